@@ -0,0 +1,65 @@
+//! Benchmarks of elaborating generated terms: `cargo bench`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use leonie::elaborator::elaborate;
+
+/// Church numerals up to `n * n`, and a proof by `refl` that two ways of
+/// computing it are equal, which unifies both normal forms.
+fn church(n: usize) -> String {
+    let mut src = String::from(
+        "let Nat : U1 := (N : U0) → (N → N) → N → N
+let zero : Nat := λ N. λ s. λ z. z
+let suc : Nat → Nat := λ a. λ N. λ s. λ z. s (a N s z)
+let add : Nat → Nat → Nat := λ a. λ b. λ N. λ s. λ z. a N s (b N s z)
+let mul : Nat → Nat → Nat := λ a. λ b. λ N. λ s. λ z. a N (b N s) z
+let Eq : {A : U1} → A → A → U2 := λ {A}. λ x. λ y. (P : A → U1) → P x → P y
+let refl : {A : U1} {x : A} → Eq x x := λ {A}. λ {x}. λ P. λ px. px
+let n0 : Nat := zero
+",
+    );
+    for i in 1..=n {
+        src += &format!("let n{i} : Nat := suc n{}\n", i - 1);
+    }
+    src += &format!("let sq : Nat := mul n{n} n{n}\n");
+    src += &format!("let sum : Nat := add (mul n{n} n{}) n{n}\n", n - 1);
+    src += "let p : Eq sq sum := refl\nsq";
+    src
+}
+
+/// A function of `n` type parameters, applied to all of them.
+fn telescope(n: usize) -> String {
+    let params: String = (0..n).map(|i| format!("(A{i} : U) ")).collect();
+    let lams: String = (0..n).map(|i| format!("λ A{i}. ")).collect();
+    let args: String = (0..n).map(|_| " U0").collect();
+    format!("let f : {params}→ A0 → A0 := {lams}λ x. x\nf{args}")
+}
+
+/// `n` nested lets, each defined as the one before.
+fn let_chain(n: usize) -> String {
+    let mut src = String::from("let x0 : U1 := U0\n");
+    for i in 1..n {
+        src += &format!("let x{i} : U1 := x{}\n", i - 1);
+    }
+    src += &format!("x{}", n - 1);
+    src
+}
+
+fn bench(c: &mut Criterion, name: &str, gen: fn(usize) -> String, sizes: &[usize]) {
+    let mut group = c.benchmark_group(name);
+    for &n in sizes {
+        let src = gen(n);
+        group.bench_with_input(BenchmarkId::from_parameter(n), &src, |b, src| {
+            b.iter(|| elaborate(black_box(src.as_str())).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn benches(c: &mut Criterion) {
+    bench(c, "church", church, &[10, 30]);
+    bench(c, "telescope", telescope, &[50, 200]);
+    bench(c, "let_chain", let_chain, &[100, 1000]);
+}
+
+criterion_group!(elaboration, benches);
+criterion_main!(elaboration);
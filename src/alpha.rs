@@ -0,0 +1,270 @@
+//! Syntactic equality of terms up to the names of binders, and the other
+//! questions about their structure: common subterms, weakening and
+//! occurrences of variables and metas.
+//!
+//! With de Bruijn indices, alpha-equivalent terms only differ in the names
+//! kept for printing, so equality and hashing ignore them. Data types and
+//! records are compared by identity, and the spans kept with
+//! [`crate::ElabOptions::keep_spans`] are skipped. They all walk the terms
+//! with an explicit stack, like evaluation.
+
+use std::{
+    collections::HashSet,
+    hash::{Hash, Hasher},
+};
+
+use crate::{
+    data::Data, metas::MetaVar, record::Record, Icit, IntOp, Ix, Level, Term, Unfolds, BD,
+};
+
+/// What a term is made of besides its subterms and the names it binds
+#[derive(PartialEq, Eq, Hash)]
+enum Node<'a> {
+    V(usize),
+    Lam(Icit),
+    Pi(Icit),
+    Pair,
+    Sigma,
+    Let,
+    Meta(MetaVar),
+    InsertedMeta(MetaVar, &'a [BD]),
+    App(Icit),
+    Proj1,
+    Proj2,
+    U(Level),
+    Nat,
+    NatLit(u64),
+    Suc,
+    NatElim,
+    Bool,
+    True,
+    False,
+    BoolElim,
+    String,
+    StrLit(&'a str),
+    Char,
+    CharLit(char),
+    Append,
+    Length,
+    Int,
+    IntLit(i64),
+    IntOp(IntOp),
+    Eq,
+    Refl,
+    J,
+    Lazy,
+    Delay,
+    Force,
+    Data(*const Data),
+    Con(*const Data, usize),
+    Elim(*const Data),
+    /// when the definitions of the block unfold, and the one referred to
+    Fix(Vec<Unfolds>, usize),
+    Record(*const Record),
+    RecordLit(*const Record),
+    Field(*const Record, usize),
+}
+
+impl Term {
+    /// Whether the terms are equal up to the names of their binders.
+    pub fn alpha_eq(&self, other: &Term) -> bool {
+        let mut stack = vec![(self, other)];
+        while let Some((l, r)) = stack.pop() {
+            if std::ptr::eq(l, r) {
+                continue;
+            }
+            let (node, ts) = l.node();
+            let (node_, ts_) = r.node();
+            if node != node_ || ts.len() != ts_.len() {
+                return false;
+            }
+            stack.extend(ts.into_iter().zip(ts_));
+        }
+        true
+    }
+
+    /// The largest subterms the term has in common with `other` at the same
+    /// positions, of both, so that eliding them leaves where they differ.
+    /// Atoms are left out, as eliding them shortens nothing, and so are the
+    /// functions of applications, which say what the arguments are for.
+    pub fn common(&self, other: &Term) -> HashSet<*const Term> {
+        let mut common = HashSet::new();
+        // whether the subterms may be elided
+        let mut stack = vec![(self, other, false)];
+        while let Some((l, r, elide)) = stack.pop() {
+            let (l, r) = (l.unspanned(), r.unspanned());
+            let (node, ts) = l.node();
+            let (node_, ts_) = r.node();
+            if elide && !ts.is_empty() && l.alpha_eq(r) {
+                common.extend([l as *const Term, r as *const Term]);
+                continue;
+            }
+            if node != node_ || ts.len() != ts_.len() {
+                continue;
+            }
+            let app = matches!(l, Term::TApp(..));
+            let subterms = ts.into_iter().zip(ts_).enumerate();
+            stack.extend(subterms.map(|(k, (t, t_))| (t, t_, !(app && k == 0))));
+        }
+        common
+    }
+
+    /// Whether `other` is this term weakened by `by` variables: the same term
+    /// under `by` binders more, which it doesn't refer to.
+    pub fn weakened_eq(&self, other: &Term, by: usize) -> bool {
+        let mut stack = vec![(0, self, other)];
+        while let Some((depth, l, r)) = stack.pop() {
+            let (node, ts) = l.node();
+            let (node_, ts_) = r.node();
+            let same = match (node, node_) {
+                (Node::V(x), Node::V(x_)) if x < depth => x == x_,
+                (Node::V(x), Node::V(x_)) => x + by == x_,
+                (node, node_) => node == node_,
+            };
+            if !same || ts.len() != ts_.len() {
+                return false;
+            }
+            let scopes = (0..ts.len()).map(|k| depth + l.binds(k));
+            stack.extend(scopes.zip(ts).zip(ts_).map(|((d, t), t_)| (d, t, t_)));
+        }
+        true
+    }
+
+    /// Whether the variable `x` occurs in the term. For a meta applied to it
+    /// as the `k`th of `n` variables, `meta(m, k, n)` says whether it does.
+    pub fn occurs(&self, x: Ix, meta: &mut impl FnMut(MetaVar, usize, usize) -> bool) -> bool {
+        let mut stack = vec![(x.0, self)];
+        while let Some((x, t)) = stack.pop() {
+            match t.unspanned() {
+                Term::TV(y) if y.0 == x => return true,
+                Term::TInsertedMeta(m, bds) => {
+                    let Some(lvl) = bds.len().checked_sub(x + 1) else {
+                        continue;
+                    };
+                    let bound = |bds: &[BD]| bds.iter().filter(|bd| **bd == BD::Bound).count();
+                    if bds[lvl] == BD::Bound && meta(*m, bound(&bds[..lvl]), bound(bds)) {
+                        return true;
+                    }
+                }
+                _ => {
+                    let (_, ts) = t.node();
+                    stack.extend(ts.into_iter().enumerate().map(|(k, u)| (x + t.binds(k), u)));
+                }
+            }
+        }
+        false
+    }
+
+    /// The metas occurring in the term, each once, in the order they are met
+    /// from left to right.
+    pub fn metas(&self) -> Vec<MetaVar> {
+        let mut metas = Vec::new();
+        let mut stack = vec![self];
+        while let Some(t) = stack.pop() {
+            let (node, ts) = t.node();
+            if let Node::Meta(m) | Node::InsertedMeta(m, _) = node {
+                if !metas.contains(&m) {
+                    metas.push(m);
+                }
+            }
+            stack.extend(ts.into_iter().rev());
+        }
+        metas
+    }
+
+    /// The term under its spans.
+    pub fn unspanned(&self) -> &Term {
+        let mut t = self;
+        while let Term::TSrcPos(_, t_) = t {
+            t = t_;
+        }
+        t
+    }
+
+    /// How many variables the `k`th subterm of [`Term::node`] is under
+    fn binds(&self, k: usize) -> usize {
+        match (self.unspanned(), k) {
+            (Term::Tλ(..), 0) | (Term::TΠ(..), 1) | (Term::TΣ(..), 1) | (Term::TLet(..), 2) => 1,
+            (Term::TFix(fix, _), _) => fix.len(),
+            _ => 0,
+        }
+    }
+
+    fn node(&self) -> (Node<'_>, Vec<&Term>) {
+        match self.unspanned() {
+            Term::TV(x) => (Node::V(x.0), vec![]),
+            Term::Tλ(_, i, t) => (Node::Lam(*i), vec![t]),
+            Term::TΠ(_, i, a, b) => (Node::Pi(*i), vec![a, b]),
+            Term::Tσ(t, u) => (Node::Pair, vec![t, u]),
+            Term::TΣ(_, a, b) => (Node::Sigma, vec![a, b]),
+            Term::TLet(_, a, t, u) => (Node::Let, vec![a, t, u]),
+            Term::TMeta(m) => (Node::Meta(*m), vec![]),
+            Term::TInsertedMeta(m, bds) => (Node::InsertedMeta(*m, bds), vec![]),
+            Term::TApp(t, u, i) => (Node::App(*i), vec![t, u]),
+            Term::TProj1(t) => (Node::Proj1, vec![t]),
+            Term::TProj2(t) => (Node::Proj2, vec![t]),
+            Term::TU(l) => (Node::U(*l), vec![]),
+            Term::TNat => (Node::Nat, vec![]),
+            Term::TNatLit(n) => (Node::NatLit(*n), vec![]),
+            Term::TSuc(t) => (Node::Suc, vec![t]),
+            Term::TNatElim(p, z, s, n) => (Node::NatElim, vec![p, z, s, n]),
+            Term::TBool => (Node::Bool, vec![]),
+            Term::TTrue => (Node::True, vec![]),
+            Term::TFalse => (Node::False, vec![]),
+            Term::TBoolElim(p, t, f, b) => (Node::BoolElim, vec![p, t, f, b]),
+            Term::TString => (Node::String, vec![]),
+            Term::TStrLit(s) => (Node::StrLit(s), vec![]),
+            Term::TChar => (Node::Char, vec![]),
+            Term::TCharLit(c) => (Node::CharLit(*c), vec![]),
+            Term::TAppend(s, t) => (Node::Append, vec![s, t]),
+            Term::TLength(s) => (Node::Length, vec![s]),
+            Term::TInt => (Node::Int, vec![]),
+            Term::TIntLit(n) => (Node::IntLit(*n), vec![]),
+            Term::TIntOp(op, x, y) => (Node::IntOp(*op), vec![x, y]),
+            Term::TEq(a, t, u) => (Node::Eq, vec![a, t, u]),
+            Term::TRefl(a, t) => (Node::Refl, vec![a, t]),
+            Term::TJ(a, t, p, pr, u, e) => (Node::J, vec![a, t, p, pr, u, e]),
+            Term::TLazy(a) => (Node::Lazy, vec![a]),
+            Term::TDelay(t) => (Node::Delay, vec![t]),
+            Term::TForce(t) => (Node::Force, vec![t]),
+            Term::TData(d, args) => (Node::Data(&**d), args.iter().map(|t| &**t).collect()),
+            Term::TCon(d, c, args) => (Node::Con(&**d, *c), args.iter().map(|t| &**t).collect()),
+            Term::TElim(d, args, t) => (
+                Node::Elim(&**d),
+                args.iter().chain([t]).map(|t| &**t).collect(),
+            ),
+            Term::TFix(fix, i) => (
+                Node::Fix(fix.iter().map(|(_, k, _)| *k).collect(), *i),
+                fix.iter().map(|(_, _, t)| &**t).collect(),
+            ),
+            Term::TRecord(r, args) => (Node::Record(&**r), args.iter().map(|t| &**t).collect()),
+            Term::TRecordLit(r, args) => {
+                (Node::RecordLit(&**r), args.iter().map(|t| &**t).collect())
+            }
+            Term::TField(r, f, t) => (Node::Field(&**r, *f), vec![t]),
+            Term::TSrcPos(..) => unreachable!("spans are skipped above"),
+        }
+    }
+}
+
+/// Alpha-equivalence
+impl PartialEq for Term {
+    fn eq(&self, other: &Self) -> bool {
+        self.alpha_eq(other)
+    }
+}
+
+impl Eq for Term {}
+
+/// Hash of the structure of a term, the same for alpha-equivalent terms
+impl Hash for Term {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut stack = vec![self];
+        while let Some(t) = stack.pop() {
+            let (node, ts) = t.node();
+            node.hash(state);
+            ts.len().hash(state);
+            stack.extend(ts);
+        }
+    }
+}
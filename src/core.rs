@@ -0,0 +1,478 @@
+//! Independent checking of elaborated terms.
+//!
+//! The core checker type checks the output of elaboration again, after
+//! zonking, as a safety net against bugs of the elaborator. It shares
+//! evaluation, quotation and conversion with the elaborator, but has no
+//! metas of its own: a meta left in a term is an error, and nothing is
+//! inferred that the term doesn't spell out. Universe levels aren't checked
+//! again, every universe is taken to contain the others.
+//!
+//! Lambdas don't record the types of their parameters, so applications of a
+//! lambda, which the eta-expanded primitives elaborate to, are checked once
+//! reduced.
+//!
+//! Errors are positioned at the innermost span around the term rejected, if
+//! the term kept its spans, see [`crate::ElabOptions::keep_spans`].
+
+use std::{borrow::Cow, fmt::Display};
+
+use crate::{
+    builtin,
+    data::elim_type,
+    eval, eval_closure, force,
+    metas::{self, MetaCxt, MetaVar},
+    quote,
+    span::Span,
+    Definition, Env, Icit, Level, Lvl, Name, Rc, TPrettyPrinter, Term, Tm, Type, Value,
+};
+
+/// Term the core checker rejected, with the names of the variables in scope,
+/// which the terms of the error live in
+#[derive(Debug, Clone)]
+pub struct CoreError {
+    /// the innermost span around the term, if it kept any
+    pub pos: Option<Span>,
+    pub names: Vec<Name>,
+    pub term: Term,
+    pub kind: Box<CoreErrorKind>,
+}
+
+#[derive(Debug, Clone)]
+pub enum CoreErrorKind {
+    /// meta that zonking didn't replace
+    Meta(MetaVar),
+    /// the type of the term doesn't convert to the expected one
+    Mismatch {
+        expected: Term,
+        actual: Term,
+    },
+    /// the term is applied as a function, projected or eliminated, but its
+    /// type `actual` isn't of the kind `expected`
+    Expected {
+        expected: &'static str,
+        actual: Term,
+    },
+    IcitMismatch(Icit, Icit),
+    /// the term only checks against a type, and there is none to check it
+    /// against
+    NoType,
+}
+
+impl Display for CoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pp = |t| TPrettyPrinter(&self.names, t);
+        write!(f, "ill-typed term {}: ", pp(&self.term))?;
+        match &*self.kind {
+            CoreErrorKind::Meta(m) => write!(f, "unsolved meta ?{m}"),
+            CoreErrorKind::Mismatch { expected, actual } => {
+                write!(f, "expected type {}, found {}", pp(expected), pp(actual))
+            }
+            CoreErrorKind::Expected { expected, actual } => {
+                write!(f, "expected a {expected}, found {}", pp(actual))
+            }
+            CoreErrorKind::IcitMismatch(expected, actual) => {
+                write!(f, "expected {expected:?} application, found {actual:?}")
+            }
+            CoreErrorKind::NoType => write!(f, "can't infer its type"),
+        }
+    }
+}
+
+impl std::error::Error for CoreError {}
+
+/// Check the closed term `term` against the closed type `ty`.
+pub fn check(term: &Term, ty: &Term) -> Result<(), CoreError> {
+    let mut checker = Checker::new();
+    checker.check_type(ty)?;
+    let ty = checker.eval(ty);
+    checker.check(term, ty)
+}
+
+/// Context of the core checker: the variables and definitions in scope, with
+/// their types
+#[derive(Default)]
+pub struct Checker {
+    /// always empty, for evaluation and conversion
+    metas: MetaCxt,
+    env: Env,
+    types: Vec<Type>,
+    names: Vec<Name>,
+    /// the innermost span around the term being checked
+    pos: Option<Span>,
+}
+
+impl Checker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn lvl(&self) -> Lvl {
+        self.env.len()
+    }
+
+    /// Check elaborated definitions in order, each in the scope of the ones
+    /// before it, and bring them into scope.
+    pub fn check_definitions(&mut self, defs: &[Definition]) -> Result<(), CoreError> {
+        let mut i = 0;
+        while i < defs.len() {
+            let block = match &defs[i].tm {
+                Some(Term::TFix(fix, 0)) if fix.len() > 1 => defs.get(i..i + fix.len()),
+                _ => None,
+            };
+            match block {
+                Some(block) => self.define_block(block)?,
+                None => self.define(&defs[i])?,
+            }
+            i += block.map_or(1, |block| block.len());
+        }
+        Ok(())
+    }
+
+    /// Check `def x : a := t` and bring `x` into scope, an axiom as a
+    /// variable.
+    pub fn define(&mut self, def: &Definition) -> Result<(), CoreError> {
+        self.check_type(&def.ty)?;
+        let va = self.eval(&def.ty);
+        match &def.tm {
+            Some(tm) => {
+                self.check(tm, va.clone())?;
+                let vt = self.eval(tm);
+                self.define_global(def.name.clone(), vt, va);
+            }
+            None => {
+                let v = Value::VRigid(self.lvl(), vec![]);
+                self.define_local(def.name.clone(), v, va);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check a block of recursive definitions, the first one being the whole
+    /// `TFix` and the others its weakened copies, and bring them into scope.
+    fn define_block(&mut self, defs: &[Definition]) -> Result<(), CoreError> {
+        let Some(Term::TFix(fix, _)) = &defs[0].tm else {
+            unreachable!("block of definitions without a fix")
+        };
+        let values: Vec<Value> = (0..fix.len())
+            .map(|k| self.eval(&Term::TFix(fix.clone(), k)))
+            .collect();
+
+        // the types after the first are weakened past the definitions before
+        // them, which are in scope by then
+        let mut types = Vec::new();
+        for (def, vt) in defs.iter().zip(values) {
+            self.check_type(&def.ty)?;
+            let va = self.eval(&def.ty);
+            types.push(va.clone());
+            self.define_global(def.name.clone(), vt, va);
+        }
+
+        // the bodies see the block as the innermost variables, which the
+        // definitions are, with their values
+        for ((_, _, t), va) in fix.iter().zip(types) {
+            self.check(t, va)?;
+        }
+        Ok(())
+    }
+
+    /// Check that `t` has type `ty`.
+    pub fn check(&mut self, t: &Term, ty: Type) -> Result<(), CoreError> {
+        match (t, force(&mut self.metas, ty)) {
+            (Term::Tλ(x, i, t), Value::VΠ(_, i_, a, b)) if *i == i_ => {
+                let x_ = Value::VRigid(self.lvl(), vec![]);
+                let b = eval_closure(&mut self.metas, b, x_);
+                self.under(x, Rc::unwrap_or_clone(a), |this| this.check(t, b))
+            }
+            (Term::Tσ(t, u), Value::VΣ(_, a, b)) => {
+                self.check(t, Rc::unwrap_or_clone(a))?;
+                let vt = self.eval(t);
+                let b = eval_closure(&mut self.metas, b, vt);
+                self.check(u, b)
+            }
+            (Term::TLet(x, a, t, u), ty) => {
+                let va = self.let_def(a, t)?;
+                let vt = self.eval(t);
+                self.define_local(x.clone(), vt, va);
+                let res = self.check(u, ty);
+                self.pop();
+                res
+            }
+            (Term::TApp(..), ty) if applies_lambda(t) => {
+                let t = self.reduce(t);
+                self.check(&t, ty)
+            }
+            // a recursive definition checks against its own type
+            (Term::TFix(fix, 0), ty) if fix.len() == 1 => {
+                self.under(&fix[0].0, ty.clone(), |this| this.check(&fix[0].2, ty))
+            }
+            (Term::TDelay(t), Value::VLazy(a)) => self.check(t, Rc::unwrap_or_clone(a)),
+            (Term::TSrcPos(pos, t), ty) => self.at(pos, |this| this.check(t, ty)),
+            (Term::TRecordLit(record, ts), Value::VRecord(record_, params))
+                if Rc::ptr_eq(record, &record_) =>
+            {
+                let mut ty = record.telescope(&mut self.metas, params);
+                for t in ts {
+                    let Value::VΠ(_, _, a, b) = force(&mut self.metas, ty) else {
+                        unreachable!("record with fewer fields than its names")
+                    };
+                    self.check(t, Rc::unwrap_or_clone(a))?;
+                    let vt = self.eval(t);
+                    ty = eval_closure(&mut self.metas, b, vt);
+                }
+                Ok(())
+            }
+            (t, expected) => {
+                let actual = self.infer(t)?;
+                let lvl = self.lvl();
+                match metas::unify(&mut self.metas, lvl, expected.clone(), actual.clone()) {
+                    Ok(()) => Ok(()),
+                    Err(_) => {
+                        let kind = CoreErrorKind::Mismatch {
+                            expected: quote(&mut self.metas, lvl, expected),
+                            actual: quote(&mut self.metas, lvl, actual),
+                        };
+                        Err(self.error(t, kind))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Infer the type of `t`.
+    pub fn infer(&mut self, t: &Term) -> Result<Type, CoreError> {
+        let u = || Value::VU(Level::LConst(0));
+        let prim = |this: &mut Self, x: &str, args: &[&Tm]| {
+            let (_, a) = builtin(&mut this.metas, x).expect("missing builtin");
+            this.apply(a, args.iter().copied())
+        };
+
+        match t {
+            Term::TV(x) => Ok(self.types[self.lvl() - 1 - x.0].clone()),
+            Term::TMeta(m) | Term::TInsertedMeta(m, _) => {
+                Err(self.error(t, CoreErrorKind::Meta(*m)))
+            }
+            Term::TΠ(x, _, a, b) | Term::TΣ(x, a, b) => {
+                self.check_type(a)?;
+                let va = self.eval(a);
+                self.under(x, va, |this| this.check_type(b))?;
+                Ok(u())
+            }
+            Term::TLet(x, a, t, u) => {
+                let va = self.let_def(a, t)?;
+                let vt = self.eval(t);
+                self.define_local(x.clone(), vt, va);
+                let res = self.infer(u);
+                self.pop();
+                res
+            }
+            Term::TApp(..) if applies_lambda(t) => {
+                let t = self.reduce(t);
+                self.infer(&t)
+            }
+            Term::TApp(..) => {
+                let mut head = t;
+                let mut args = Vec::new();
+                while let Term::TApp(t, u, i) = head {
+                    args.push((u, *i));
+                    head = t;
+                }
+                let mut ty = self.infer(head)?;
+                let mut f = head.clone();
+                for (u, i) in args.into_iter().rev() {
+                    ty = match force(&mut self.metas, ty) {
+                        Value::VΠ(_, i_, _, _) if i != i_ => {
+                            return Err(self.error(&f, CoreErrorKind::IcitMismatch(i_, i)));
+                        }
+                        Value::VΠ(_, _, a, b) => {
+                            self.check(u, Rc::unwrap_or_clone(a))?;
+                            let vu = self.eval(u);
+                            eval_closure(&mut self.metas, b, vu)
+                        }
+                        ty => return Err(self.expected(&f, "function type", ty)),
+                    };
+                    f = Term::TApp(f.into(), u.clone(), i);
+                }
+                Ok(ty)
+            }
+            Term::TProj1(u) => {
+                let ty = self.infer(u)?;
+                match force(&mut self.metas, ty) {
+                    Value::VΣ(_, a, _) => Ok(Rc::unwrap_or_clone(a)),
+                    ty => Err(self.expected(u, "sigma type", ty)),
+                }
+            }
+            Term::TProj2(u) => {
+                let ty = self.infer(u)?;
+                match force(&mut self.metas, ty) {
+                    Value::VΣ(_, _, b) => {
+                        let v1 = self.eval(&Term::TProj1(u.clone()));
+                        Ok(eval_closure(&mut self.metas, b, v1))
+                    }
+                    ty => Err(self.expected(u, "sigma type", ty)),
+                }
+            }
+            Term::TU(l) => Ok(Value::VU(l.suc())),
+            Term::TNat | Term::TBool | Term::TString | Term::TChar | Term::TInt => Ok(u()),
+            Term::TNatLit(_) => Ok(Value::VNat),
+            Term::TTrue | Term::TFalse => Ok(Value::VBool),
+            Term::TStrLit(_) => Ok(Value::VString),
+            Term::TCharLit(_) => Ok(Value::VChar),
+            Term::TIntLit(_) => Ok(Value::VInt),
+            Term::TSuc(n) => prim(self, "suc", &[n]),
+            Term::TNatElim(p, z, s, n) => prim(self, "natElim", &[p, z, s, n]),
+            Term::TBoolElim(p, tt, ff, b) => prim(self, "boolElim", &[p, tt, ff, b]),
+            Term::TAppend(s, t) => prim(self, "append", &[s, t]),
+            Term::TLength(s) => prim(self, "length", &[s]),
+            Term::TIntOp(op, x, y) => prim(self, op.name(), &[x, y]),
+            Term::TEq(a, x, y) => prim(self, "Eq", &[a, x, y]),
+            Term::TRefl(a, x) => prim(self, "refl", &[a, x]),
+            Term::TJ(a, x, p, pr, y, e) => prim(self, "J", &[a, x, p, pr, y, e]),
+            Term::TLazy(a) => prim(self, "Lazy", &[a]),
+            Term::TDelay(u) => Ok(Value::VLazy(self.infer(u)?.into())),
+            Term::TForce(u) => {
+                let ty = self.infer(u)?;
+                match force(&mut self.metas, ty) {
+                    Value::VLazy(a) => Ok(Rc::unwrap_or_clone(a)),
+                    ty => Err(self.expected(u, "lazy type", ty)),
+                }
+            }
+            Term::TData(data, args) => self.apply(data.ty.clone(), args.iter()),
+            Term::TCon(data, c, args) => {
+                let a = data.con_type(&mut self.metas, *c);
+                self.apply(a, args.iter())
+            }
+            Term::TElim(data, args, u) => {
+                let a = elim_type(&mut self.metas, data, 0);
+                let a = eval(&mut self.metas, Cow::Owned(Env::default()), a);
+                self.apply(a, args.iter().chain([u]))
+            }
+            Term::TRecord(record, args) => self.apply(record.ty.clone(), args.iter()),
+            Term::TField(record, f, u) => {
+                let ty = self.infer(u)?;
+                match force(&mut self.metas, ty) {
+                    Value::VRecord(record_, params) if Rc::ptr_eq(record, &record_) => {
+                        let vu = self.eval(u);
+                        Ok(record.field_type(&mut self.metas, params, &vu, *f))
+                    }
+                    ty => Err(self.expected(u, "record type", ty)),
+                }
+            }
+            Term::TSrcPos(pos, t) => self.at(pos, |this| this.infer(t)),
+            Term::Tλ(..) | Term::Tσ(..) | Term::TFix(..) | Term::TRecordLit(..) => {
+                Err(self.error(t, CoreErrorKind::NoType))
+            }
+        }
+    }
+
+    /// Check that `a` is a type.
+    pub fn check_type(&mut self, a: &Term) -> Result<(), CoreError> {
+        let ty = self.infer(a)?;
+        match force(&mut self.metas, ty) {
+            Value::VU(_) => Ok(()),
+            ty => Err(self.expected(a, "universe", ty)),
+        }
+    }
+
+    /// Check the arguments `args` against the function type `ty`,
+    /// returning the type of the result.
+    fn apply<'a>(
+        &mut self,
+        mut ty: Type,
+        args: impl IntoIterator<Item = &'a Tm>,
+    ) -> Result<Type, CoreError> {
+        for u in args {
+            let Value::VΠ(_, _, a, b) = force(&mut self.metas, ty) else {
+                unreachable!("primitive applied to more arguments than its type takes")
+            };
+            self.check(u, Rc::unwrap_or_clone(a))?;
+            let vu = self.eval(u);
+            ty = eval_closure(&mut self.metas, b, vu);
+        }
+        Ok(ty)
+    }
+
+    /// Check the type `a` and the definition `t` of a `let`, returning the type.
+    fn let_def(&mut self, a: &Term, t: &Term) -> Result<Type, CoreError> {
+        self.check_type(a)?;
+        let va = self.eval(a);
+        self.check(t, va.clone())?;
+        Ok(va)
+    }
+
+    fn eval(&mut self, t: &Term) -> Value {
+        eval(&mut self.metas, Cow::Borrowed(&self.env), t.clone())
+    }
+
+    /// Normal form of `t`, keeping the definitions in scope folded.
+    fn reduce(&mut self, t: &Term) -> Term {
+        let v = self.eval(t);
+        let lvl = self.lvl();
+        quote(&mut self.metas, lvl, v)
+    }
+
+    /// Run `f` positioned at `pos`.
+    fn at<T>(
+        &mut self,
+        pos: &Span,
+        f: impl FnOnce(&mut Self) -> Result<T, CoreError>,
+    ) -> Result<T, CoreError> {
+        let outer = self.pos.replace(pos.clone());
+        let res = f(self);
+        self.pos = outer;
+        res
+    }
+
+    /// Run `f` with a variable `x : a` in scope.
+    fn under<T>(
+        &mut self,
+        x: &Name,
+        a: Type,
+        f: impl FnOnce(&mut Self) -> Result<T, CoreError>,
+    ) -> Result<T, CoreError> {
+        let v = Value::VRigid(self.lvl(), vec![]);
+        self.define_local(x.clone(), v, a);
+        let res = f(self);
+        self.pop();
+        res
+    }
+
+    fn define_local(&mut self, x: Name, v: Value, a: Type) {
+        self.env.push(v);
+        self.types.push(a);
+        self.names.push(x);
+    }
+
+    fn define_global(&mut self, x: Name, v: Value, a: Type) {
+        let v = Value::VGlued(x.clone(), Rc::new(v), vec![]);
+        self.define_local(x, v, a);
+    }
+
+    fn pop(&mut self) {
+        self.env.pop();
+        self.types.pop();
+        self.names.pop();
+    }
+
+    fn expected(&mut self, t: &Term, expected: &'static str, ty: Type) -> CoreError {
+        let lvl = self.lvl();
+        let actual = quote(&mut self.metas, lvl, ty);
+        self.error(t, CoreErrorKind::Expected { expected, actual })
+    }
+
+    fn error(&self, t: &Term, kind: CoreErrorKind) -> CoreError {
+        CoreError {
+            pos: self.pos.clone(),
+            names: self.names.clone(),
+            term: t.clone(),
+            kind: kind.into(),
+        }
+    }
+}
+
+/// Whether `t` is an application of a lambda, under their spans.
+fn applies_lambda(mut t: &Term) -> bool {
+    while let Term::TApp(f, _, _) = t {
+        t = f.unspanned();
+    }
+    matches!(t.unspanned(), Term::Tλ(..))
+}
@@ -0,0 +1,945 @@
+//! User-defined inductive types, declared with `data`.
+
+use std::borrow::Cow;
+#[cfg(not(feature = "sync"))]
+use std::cell::OnceCell;
+#[cfg(feature = "sync")]
+use std::sync::OnceLock as OnceCell;
+
+use crate::{
+    check, check_levels, check_type,
+    error::{Error, ErrorKind},
+    eval, eval_closure, force, insert, instance, lvl2ix,
+    metas::MetaCxt,
+    quote, unfold_env, v_app, zonk, Cxt, Definition, Elim, Env, Icit, Ix, Lvl, Name, Pattern, Raw,
+    Rc, Term, Tm, Type, Unfold, Value, Weak,
+};
+
+/// Inductive type `D : (params) → (indices) → U`
+#[derive(Debug)]
+pub struct Data {
+    pub name: Name,
+    /// name of the generated eliminator, `vecElim` for `Vec`
+    pub elim: Name,
+    pub params: usize,
+    pub indices: usize,
+    /// type of the type former
+    pub ty: Type,
+    pub cons: Vec<Con>,
+    /// the data types declared together with it, itself included, in order
+    block: OnceCell<Vec<Weak<Data>>>,
+}
+
+/// Constructor of a [`Data`] type
+#[derive(Debug)]
+pub struct Con {
+    pub name: Name,
+    /// icits of the arguments after the parameters
+    pub icits: Vec<Icit>,
+    /// `{params} → (args) → D params indices`, abstracted over the type
+    /// formers of the block of `D`
+    pub ty: (Env, Tm),
+}
+
+impl Data {
+    /// The type former `λ params indices. D params indices`.
+    pub fn former(self: &Rc<Self>, metas: &mut MetaCxt) -> Value {
+        let n = self.params + self.indices;
+        let binders = binders(metas, self.ty.clone(), 0, n);
+        let t = lams(binders, Term::TData(self.clone(), vars(n)));
+        eval(metas, Cow::Owned(Env::default()), t)
+    }
+
+    /// Type of the constructor `c`.
+    pub fn con_type(self: &Rc<Self>, metas: &mut MetaCxt, c: usize) -> Type {
+        let (mut env, t) = self.cons[c].ty.clone();
+        for data in self.block() {
+            env.push(data.former(metas));
+        }
+        eval(metas, Cow::Owned(env), Rc::unwrap_or_clone(t))
+    }
+
+    /// The data types declared together with this one in a `mutual` block,
+    /// itself included, in order. Each is kept alive by the context that
+    /// declared the block.
+    pub fn block(&self) -> Vec<Rc<Data>> {
+        let block = self.block.get().expect("data type without its block");
+        block
+            .iter()
+            .map(|data| data.upgrade().expect("data type of a block dropped"))
+            .collect()
+    }
+
+    /// Which arguments of the constructor `c` are recursive.
+    pub fn recursive(self: &Rc<Self>, metas: &mut MetaCxt, c: usize) -> Vec<bool> {
+        let mut ty = self.con_type(metas, c);
+        let mut rec = Vec::new();
+
+        for k in 0..self.params + self.cons[c].icits.len() {
+            let Value::VΠ(_, _, a, b) = force(metas, ty) else {
+                unreachable!("constructor with fewer arguments than its icits")
+            };
+            if k >= self.params {
+                let a = force(metas, Rc::unwrap_or_clone(a));
+                rec.push(matches!(a, Value::VData(d, _) if Rc::ptr_eq(&d, self)));
+            }
+            ty = eval_closure(metas, b, Value::VRigid(k, vec![]));
+        }
+
+        rec
+    }
+
+    /// Index of the constructor named `x`.
+    pub fn con(&self, x: &str) -> Option<usize> {
+        self.cons.iter().position(|c| &*c.name == x)
+    }
+}
+
+/// Apply the eliminator of `data` to `v`, `args` being the parameters, the
+/// motive, the methods and the indices.
+pub(crate) fn v_elim(metas: &mut MetaCxt, data: Rc<Data>, args: Vec<Value>, v: Value) -> Value {
+    match v {
+        Value::VFlex(m, mut sp) => {
+            sp.push(Elim::EElim(data, args));
+            Value::VFlex(m, sp)
+        }
+        Value::VRigid(x, mut sp) => {
+            sp.push(Elim::EElim(data, args));
+            Value::VRigid(x, sp)
+        }
+        Value::VGlued(x, def, mut sp) => {
+            sp.push(Elim::EElim(data, args));
+            Value::VGlued(x, def, sp)
+        }
+        Value::VFix(x, k, t, mut sp) => {
+            sp.push(Elim::EElim(data, args));
+            Value::VFix(x, k, t, sp)
+        }
+        // the method applied to the arguments, each recursive one followed by
+        // its induction hypothesis
+        Value::VCon(_, c, con_args) => {
+            let p = data.params;
+            let methods = p + 1 + data.cons.len();
+            let mut t = args[p + 1 + c].clone();
+            let mut ty = data.con_type(metas, c);
+
+            for (k, u) in con_args.into_iter().enumerate() {
+                let Value::VΠ(_, i, a, b) = force(metas, ty) else {
+                    unreachable!("constructor applied to too many arguments")
+                };
+                ty = eval_closure(metas, b, u.clone());
+                if k < p {
+                    continue;
+                }
+
+                let ih = match force(metas, Rc::unwrap_or_clone(a)) {
+                    Value::VData(d, is) if Rc::ptr_eq(&d, &data) => {
+                        let mut args_ = args[..methods].to_vec();
+                        args_.extend(is.into_iter().skip(p));
+                        Some(v_elim(metas, data.clone(), args_, u.clone()))
+                    }
+                    _ => None,
+                };
+                t = v_app(metas, t, u, i);
+                if let Some(ih) = ih {
+                    t = v_app(metas, t, ih, Icit::Expl);
+                }
+            }
+
+            t
+        }
+        _ => panic!(),
+    }
+}
+
+/// `data x params : ty` with the constructors `cons`
+pub type DataDecl = (Name, Vec<(Name, Raw)>, Raw, Vec<(Name, Raw)>);
+
+/// Elaborate `data x params : ty` with the constructors `cons`, and bring the
+/// type former, the constructors and the eliminator into scope.
+pub fn check_data(
+    metas: &mut MetaCxt,
+    cxt: &mut Cxt,
+    x: Name,
+    params: Vec<(Name, Raw)>,
+    ty: Raw,
+    cons: Vec<(Name, Raw)>,
+) -> Result<Vec<Definition>, Error> {
+    check_data_block(metas, cxt, vec![(x, params, ty, cons)])
+}
+
+/// Elaborate a block of mutually recursive data types, the constructors of
+/// each one referring to any of them, and bring them into scope in order.
+pub fn check_data_block(
+    metas: &mut MetaCxt,
+    cxt: &mut Cxt,
+    block: Vec<DataDecl>,
+) -> Result<Vec<Definition>, Error> {
+    // left over by a definition that failed
+    metas.take_postponed();
+    metas.take_instances();
+    // the metas of earlier definitions are theirs alone
+    metas.freeze();
+
+    let lvl = cxt.lvl;
+    let n = block.len();
+    let mut formers = Vec::new();
+    let mut decls = Vec::new();
+    for (x, params, ty, cons) in block {
+        let p = params.len();
+        let a = check_params(metas, cxt, params, ty)?;
+        let va = eval(metas, Cow::Borrowed(&cxt.env), a.clone());
+
+        let (indices, level) = {
+            let mut ty = va.clone();
+            let mut n = 0;
+            loop {
+                match force(metas, ty) {
+                    Value::VΠ(_, _, _, b) => {
+                        ty = eval_closure(metas, b, Value::VRigid(lvl + n, vec![]));
+                        n += 1;
+                    }
+                    Value::VU(l) => break (n - p, l),
+                    _ => {
+                        let kind = ErrorKind::DataType { data: x, ty: a };
+                        return Err(Error::new(metas, cxt, kind));
+                    }
+                }
+            }
+        };
+        formers.push((x.clone(), va.clone()));
+        decls.push((x, a, va, p, indices, level, cons));
+    }
+
+    // the constructors are checked with the type formers and the parameters
+    // bound
+    let env = cxt.env.clone();
+    let cons = bind_all(metas, cxt, &formers, |metas, cxt| {
+        let mut block = Vec::new();
+        for (i, (_, _, va, p, indices, level, cons)) in decls.iter().enumerate() {
+            let cons = bind_params(metas, cxt, va.clone(), *p, |metas, cxt| {
+                cons.iter()
+                    .cloned()
+                    .map(|(c, raw)| {
+                        let pos = match &raw {
+                            Raw::RSrcPos(pos, _) => pos.clone(),
+                            _ => cxt.pos.clone(),
+                        };
+                        let t = check(metas, cxt, raw, Value::VU(*level))?;
+                        let t = zonk(metas, cxt, t);
+                        cxt.pos = pos;
+                        let icits =
+                            con_icits(&t, Ix(*p), n, i, *p, *indices).map_err(|(kind, j)| {
+                                let kind = kind(c.clone(), formers[j].0.clone());
+                                Error::new(metas, cxt, kind)
+                            })?;
+
+                        // abstract over the parameters, made implicit
+                        let mut t = t;
+                        for lvl_ in (lvl + n..cxt.lvl).rev() {
+                            let (y, a) = cxt.types[lvl_].clone();
+                            let a = quote(metas, lvl_, a);
+                            t = Term::TΠ(y, Icit::Impl, a.into(), t.into());
+                        }
+                        Ok((c, icits, t))
+                    })
+                    .collect::<Result<Vec<_>, Error>>()
+            })?;
+            block.push(
+                cons.into_iter()
+                    .map(|(c, icits, t)| (c, icits, zonk(metas, cxt, t)))
+                    .collect::<Vec<_>>(),
+            );
+        }
+        Ok(block)
+    })?;
+
+    instance::check_found(metas)?;
+    if let Some(p) = metas.take_postponed().into_iter().next() {
+        return Err(Error::stuck(metas, p));
+    }
+    check_levels(metas, cxt)?;
+
+    // the types are weakened past the definitions of the block before them
+    let never = unfold_env(cxt, Unfold::Never).into_owned();
+    let mut block = Vec::new();
+    for ((x, a, va, p, indices, _, _), cons) in decls.into_iter().zip(cons) {
+        let a = zonk(metas, cxt, a);
+        let data = Rc::new(Data {
+            name: x.clone(),
+            elim: elim_name(&x),
+            params: p,
+            indices,
+            ty: va,
+            cons: cons
+                .into_iter()
+                .map(|(name, icits, t)| Con {
+                    name,
+                    icits,
+                    ty: (env.clone(), t.into()),
+                })
+                .collect(),
+            block: OnceCell::new(),
+        });
+        block.push((data, a));
+    }
+    let weak: Vec<_> = block.iter().map(|(d, _)| Rc::downgrade(d)).collect();
+    for (data, _) in &block {
+        data.block.set(weak.clone()).unwrap();
+    }
+
+    let mut defs = Vec::new();
+    for (data, a) in block {
+        let a = eval(metas, Cow::Borrowed(&never), a);
+        let a = quote(metas, cxt.lvl, a);
+        defs.extend(define_data(metas, cxt, data, a));
+    }
+
+    Ok(defs)
+}
+
+/// Bind the variables `xs` of the given types in `cxt` around `f`.
+pub(crate) fn bind_all<T>(
+    metas: &mut MetaCxt,
+    cxt: &mut Cxt,
+    xs: &[(Name, Type)],
+    f: impl FnOnce(&mut MetaCxt, &mut Cxt) -> T,
+) -> T {
+    match xs {
+        [] => f(metas, cxt),
+        [(x, a), xs @ ..] => {
+            cxt.bind(x.clone(), a.clone(), |cxt| bind_all(metas, cxt, xs, f))
+                .0
+        }
+    }
+}
+
+/// Bring the type former of type `a`, the constructors and the eliminator of
+/// `data` into scope.
+fn define_data(metas: &mut MetaCxt, cxt: &mut Cxt, data: Rc<Data>, a: Term) -> Vec<Definition> {
+    let mut defs = Vec::new();
+
+    let va = data.ty.clone();
+    let n = data.params + data.indices;
+    let t = lams(
+        binders(metas, va.clone(), cxt.lvl, n),
+        Term::TData(data.clone(), vars(n)),
+    );
+    let vt = eval(metas, Cow::Borrowed(&cxt.env), t.clone());
+    cxt.define_global(data.name.clone(), vt, va);
+    defs.push(Definition {
+        name: data.name.clone(),
+        ty: a,
+        tm: Some(t),
+    });
+
+    let p = data.params;
+    for (c, con) in data.cons.iter().enumerate() {
+        let va = data.con_type(metas, c);
+        let n = p + con.icits.len();
+        let t = lams(
+            binders(metas, va.clone(), cxt.lvl, n),
+            Term::TCon(data.clone(), c, vars(n)),
+        );
+        let vt = eval(metas, Cow::Borrowed(&cxt.env), t.clone());
+        let a = quote(metas, cxt.lvl, va.clone());
+        cxt.define_global(con.name.clone(), vt, va);
+        defs.push(Definition {
+            name: con.name.clone(),
+            ty: a,
+            tm: Some(t),
+        });
+    }
+
+    let a = elim_type(metas, &data, cxt.lvl);
+    let va = eval(metas, Cow::Borrowed(&cxt.env), a.clone());
+    let n = p + 1 + data.cons.len() + data.indices + 1;
+    let t = lams(
+        binders(metas, va.clone(), cxt.lvl, n),
+        Term::TElim(
+            data.clone(),
+            vars(n)[..n - 1].to_vec(),
+            Term::TV(Ix(0)).into(),
+        ),
+    );
+    let vt = eval(metas, Cow::Borrowed(&cxt.env), t.clone());
+    cxt.define_global(data.elim.clone(), vt, va);
+    defs.push(Definition {
+        name: data.elim.clone(),
+        ty: a,
+        tm: Some(t),
+    });
+
+    cxt.data.push(data);
+
+    defs
+}
+
+/// Elaborate `match t with branches` against `ty` to an application of the
+/// eliminator of the type of `t`. Every constructor needs a branch, or a `_`
+/// one covering the rest. Where `t` and its indices are distinct variables,
+/// the motive abstracts over them in `ty`, so that each branch sees them
+/// replaced by its constructor and indices.
+pub(crate) fn check_match(
+    metas: &mut MetaCxt,
+    cxt: &mut Cxt,
+    t: Raw,
+    branches: Vec<(Pattern, Raw)>,
+    ty: Type,
+) -> Result<Term, Error> {
+    let pos = cxt.pos.clone();
+    let (t, a) = insert(metas, cxt, t)?;
+    cxt.pos = pos.clone();
+
+    let (data, args) = match force(metas, a) {
+        Value::VData(d, args) => (d, args),
+        a => {
+            let ty = quote(metas, cxt.lvl, a);
+            return Err(Error::new(metas, cxt, ErrorKind::NotData { ty }));
+        }
+    };
+    let p = data.params;
+    let vt = eval(metas, Cow::Borrowed(&cxt.env), t.clone());
+
+    let motive = motive(metas, cxt, &data, &args[p..], vt, ty);
+    let vmotive = eval(metas, Cow::Borrowed(&cxt.env), motive.clone());
+
+    // the branch of each constructor
+    let mut cases: Vec<Option<(Pattern, Raw)>> = vec![None; data.cons.len()];
+    let mut wild = None;
+    for (pat, body) in branches {
+        let redundant = |metas: &mut MetaCxt, cxt: &Cxt, con: Name| {
+            Err(Error::new(metas, cxt, ErrorKind::RedundantCase { con }))
+        };
+        if wild.is_some() {
+            return redundant(metas, cxt, pat.con());
+        }
+
+        match &pat {
+            Pattern::PCon(con, _) => {
+                let Some(c) = data.con(con) else {
+                    let con = con.clone();
+                    let data = data.name.clone();
+                    let kind = ErrorKind::NotAConstructor { con, data };
+                    return Err(Error::new(metas, cxt, kind));
+                };
+                if cases[c].is_some() {
+                    return redundant(metas, cxt, con.clone());
+                }
+                cases[c] = Some((pat, body));
+            }
+            Pattern::PWild => {
+                if cases.iter().all(Option::is_some) {
+                    return redundant(metas, cxt, "_".into());
+                }
+                wild = Some(body);
+            }
+        }
+    }
+
+    let mut methods = Vec::new();
+    for (c, case) in cases.into_iter().enumerate() {
+        let con = data.cons[c].name.clone();
+        let (pat, body) = match (case, &wild) {
+            (Some(case), _) => case,
+            (None, Some(body)) => (Pattern::PWild, body.clone()),
+            (None, None) => return Err(Error::new(metas, cxt, ErrorKind::MissingCase { con })),
+        };
+
+        // the arguments named by the pattern, with `_` for the induction
+        // hypotheses and the implicit arguments left out
+        let rec = data.recursive(metas, c);
+        let icits = &data.cons[c].icits;
+        let mut names = Vec::new();
+        let mut xs = match pat {
+            Pattern::PCon(_, xs) => Some(xs.into_iter()),
+            Pattern::PWild => None,
+        };
+        for (i, rec) in icits.iter().zip(rec) {
+            let x = match xs.as_mut().map(|xs| xs.as_slice().first().cloned()) {
+                None => "_".into(),
+                Some(Some((x, i_))) if i_ == *i => {
+                    xs.as_mut().unwrap().next();
+                    x
+                }
+                Some(_) if *i != Icit::Expl => "_".into(),
+                Some(_) => {
+                    let expected = icits.iter().filter(|i| **i == Icit::Expl).count();
+                    let kind = ErrorKind::PatternArity { con, expected };
+                    return Err(Error::new(metas, cxt, kind));
+                }
+            };
+            names.push(x);
+            if rec {
+                names.push("_".into());
+            }
+        }
+        if xs.is_some_and(|mut xs| xs.next().is_some()) {
+            let expected = icits.iter().filter(|i| **i == Icit::Expl).count();
+            let kind = ErrorKind::PatternArity { con, expected };
+            return Err(Error::new(metas, cxt, kind));
+        }
+
+        let ty = {
+            let mut env = cxt.env.clone();
+            for a in &args[..p] {
+                env.push(a.clone());
+            }
+            env.push(vmotive.clone());
+            let m = method(metas, &data, cxt.lvl, cxt.lvl + p, c, cxt.lvl + p + 1);
+            eval(metas, Cow::Owned(env), m)
+        };
+        methods.push(check_branch(metas, cxt, &names, ty, body)?);
+        cxt.pos = pos.clone();
+    }
+
+    let mut elim_args: Vec<Tm> = Vec::new();
+    for a in &args[..p] {
+        elim_args.push(quote(metas, cxt.lvl, a.clone()).into());
+    }
+    elim_args.push(motive.into());
+    elim_args.extend(methods.into_iter().map(Rc::new));
+    for a in &args[p..] {
+        elim_args.push(quote(metas, cxt.lvl, a.clone()).into());
+    }
+
+    Ok(Term::TElim(data, elim_args, t.into()))
+}
+
+/// `λ is x. ty`, with the indices `is` and the scrutinee `t` replaced by the
+/// bound variables where they are distinct variables of `cxt`.
+fn motive(
+    metas: &mut MetaCxt,
+    cxt: &Cxt,
+    data: &Rc<Data>,
+    is: &[Value],
+    t: Value,
+    ty: Type,
+) -> Term {
+    let mut subst: Vec<(Lvl, Lvl)> = Vec::new();
+    for (k, v) in is.iter().cloned().chain([t]).enumerate() {
+        if let Value::VRigid(x, sp) = force(metas, v) {
+            if sp.is_empty() && subst.iter().all(|(y, _)| *y != x) {
+                subst.push((x, cxt.lvl + k));
+            }
+        }
+    }
+
+    let ty = quote(metas, cxt.lvl, ty);
+    let mut env = Env::default();
+    for (x, v) in unfold_env(cxt, Unfold::Never).iter().enumerate() {
+        env.push(match subst.iter().find(|(y, _)| *y == x) {
+            Some((_, x_)) => Value::VRigid(*x_, vec![]),
+            None => v.clone(),
+        });
+    }
+    let ty = eval(metas, Cow::Owned(env), ty);
+    let ty = quote(metas, cxt.lvl + is.len() + 1, ty);
+
+    let mut binders = binders(metas, data.ty.clone(), 0, data.params + data.indices);
+    binders.drain(..data.params);
+    binders.push(("x".into(), Icit::Expl));
+    lams(
+        binders.into_iter().map(|(x, _)| (x, Icit::Expl)).collect(),
+        ty,
+    )
+}
+
+/// Bind `names` for the leading binders of the method type `ty` and check
+/// `body` against the rest.
+fn check_branch(
+    metas: &mut MetaCxt,
+    cxt: &mut Cxt,
+    names: &[Name],
+    ty: Type,
+    body: Raw,
+) -> Result<Term, Error> {
+    let Some((x, names)) = names.split_first() else {
+        return check(metas, cxt, body, ty);
+    };
+
+    let Value::VΠ(_, i, a, b) = force(metas, ty) else {
+        unreachable!("method with fewer binders than the constructor")
+    };
+    let b = eval_closure(metas, b, Value::VRigid(cxt.lvl, vec![]));
+    let t = cxt
+        .bind(x.clone(), Rc::unwrap_or_clone(a), |cxt| {
+            check_branch(metas, cxt, names, b, body)
+        })
+        .0?;
+    Ok(Term::Tλ(x.clone(), i, t.into()))
+}
+
+/// Elaborate `(params) → ty`, which has to be a type.
+pub(crate) fn check_params(
+    metas: &mut MetaCxt,
+    cxt: &mut Cxt,
+    mut params: Vec<(Name, Raw)>,
+    ty: Raw,
+) -> Result<Term, Error> {
+    if params.is_empty() {
+        return Ok(check_type(metas, cxt, ty)?.0);
+    }
+
+    let (x, a) = params.remove(0);
+    let (a, _) = check_type(metas, cxt, a)?;
+    let va = eval(metas, Cow::Borrowed(&cxt.env), a.clone());
+    let b = cxt
+        .bind(x.clone(), va, |cxt| check_params(metas, cxt, params, ty))
+        .0?;
+    Ok(Term::TΠ(x, Icit::Expl, a.into(), b.into()))
+}
+
+/// Bind the first `n` binders of the Π type `ty` in `cxt` around `f`.
+pub(crate) fn bind_params<T>(
+    metas: &mut MetaCxt,
+    cxt: &mut Cxt,
+    ty: Type,
+    n: usize,
+    f: impl FnOnce(&mut MetaCxt, &mut Cxt) -> T,
+) -> T {
+    if n == 0 {
+        return f(metas, cxt);
+    }
+
+    let Value::VΠ(x, _, a, b) = force(metas, ty) else {
+        unreachable!("fewer than {n} binders")
+    };
+    let b = eval_closure(metas, b, Value::VRigid(cxt.lvl, vec![]));
+    cxt.bind(x, Rc::unwrap_or_clone(a), |cxt| {
+        bind_params(metas, cxt, b, n - 1, f)
+    })
+    .0
+}
+
+/// error about a constructor, and the data type of the block it is about
+type ConError = (fn(Name, Name) -> ErrorKind, usize);
+
+/// Icits of the arguments of the constructor type `t` of the `i`th data type
+/// of a block of `n`, in which the type formers of the block are the variables
+/// up to `d` followed by the `p` parameters. The type has to end in the data
+/// type applied to the parameters, and the data types of the block may only
+/// occur the same way as the types of recursive arguments, possibly `Lazy`.
+fn con_icits(
+    mut t: &Term,
+    mut d: Ix,
+    n: usize,
+    i: usize,
+    p: usize,
+    indices: usize,
+) -> Result<Vec<Icit>, ConError> {
+    let mut icits = Vec::new();
+
+    while let Term::TΠ(_, icit, a, b) = t.unspanned() {
+        // suspended recursive arguments, of infinite values
+        let a = match a.unspanned() {
+            Term::TLazy(a) => a,
+            a => a,
+        };
+        let negative = (0..n).find(|j| {
+            let e = Ix(d.0 + n - 1 - j);
+            !match data_args(a, e) {
+                Some(args) if *j == i => uniform(&args, d, n, p, indices),
+                Some(args) => args.iter().all(|t| !mentions_block(t, d, n)),
+                None => !mentions(a, e),
+            }
+        });
+        if let Some(j) = negative {
+            return Err((|con, data| ErrorKind::Positivity { con, data }, j));
+        }
+
+        icits.push(*icit);
+        d = Ix(d.0 + 1);
+        t = b;
+    }
+
+    match data_args(t, Ix(d.0 + n - 1 - i)) {
+        Some(args) if uniform(&args, d, n, p, indices) => Ok(icits),
+        _ => Err((|con, data| ErrorKind::ConstructorType { con, data }, i)),
+    }
+}
+
+/// Explicit arguments of `t` if it is an application of the variable `d`.
+fn data_args(mut t: &Term, d: Ix) -> Option<Vec<&Term>> {
+    let mut args = Vec::new();
+
+    loop {
+        match t.unspanned() {
+            Term::TApp(t_, u, Icit::Expl) => {
+                args.push(u.unspanned());
+                t = t_;
+            }
+            Term::TV(x) if x.0 == d.0 => {
+                args.reverse();
+                return Some(args);
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// Whether `args` are the `p` parameters bound after `d` followed by indices
+/// not mentioning the `n` variables up to `d`.
+fn uniform(args: &[&Term], d: Ix, n: usize, p: usize, indices: usize) -> bool {
+    args.len() == p + indices
+        && args[..p]
+            .iter()
+            .enumerate()
+            .all(|(j, t)| matches!(t, Term::TV(x) if x.0 + j + 1 == d.0))
+        && args[p..].iter().all(|t| !mentions_block(t, d, n))
+}
+
+/// Whether any of the `n` variables up to `d` occurs in `t`.
+fn mentions_block(t: &Term, d: Ix, n: usize) -> bool {
+    (0..n).any(|j| mentions(t, Ix(d.0 + j)))
+}
+
+/// Whether the variable `x` occurs in `t`.
+fn mentions(t: &Term, x: Ix) -> bool {
+    let under = |t: &Term| mentions(t, Ix(x.0 + 1));
+
+    match t {
+        Term::TV(y) => y.0 == x.0,
+        Term::Tλ(_, _, t) => under(t),
+        Term::TFix(fix, _) => fix.iter().any(|(_, _, t)| mentions(t, Ix(x.0 + fix.len()))),
+        Term::TΠ(_, _, a, b) | Term::TΣ(_, a, b) => mentions(a, x) || under(b),
+        Term::TLet(_, a, t, u) => mentions(a, x) || mentions(t, x) || under(u),
+        Term::Tσ(t, u) | Term::TApp(t, u, _) | Term::TRefl(t, u) => {
+            mentions(t, x) || mentions(u, x)
+        }
+        Term::TProj1(t) | Term::TProj2(t) | Term::TSuc(t) | Term::TLength(t) => mentions(t, x),
+        Term::TLazy(t) | Term::TDelay(t) | Term::TForce(t) => mentions(t, x),
+        Term::TAppend(t, u) | Term::TIntOp(_, t, u) => mentions(t, x) || mentions(u, x),
+        Term::TNatElim(a, b, c, d) | Term::TBoolElim(a, b, c, d) => {
+            [a, b, c, d].iter().any(|t| mentions(t, x))
+        }
+        Term::TEq(a, t, u) => [a, t, u].iter().any(|t| mentions(t, x)),
+        Term::TJ(a, t, p, pr, u, e) => [a, t, p, pr, u, e].iter().any(|t| mentions(t, x)),
+        Term::TData(_, args)
+        | Term::TCon(_, _, args)
+        | Term::TRecord(_, args)
+        | Term::TRecordLit(_, args) => args.iter().any(|t| mentions(t, x)),
+        Term::TField(_, _, t) | Term::TSrcPos(_, t) => mentions(t, x),
+        Term::TElim(_, args, t) => mentions(t, x) || args.iter().any(|t| mentions(t, x)),
+        // inserted metas are applied to every bound variable
+        Term::TInsertedMeta(..) => true,
+        Term::TMeta(_)
+        | Term::TU(_)
+        | Term::TNat
+        | Term::TNatLit(_)
+        | Term::TBool
+        | Term::TTrue
+        | Term::TFalse
+        | Term::TString
+        | Term::TStrLit(_)
+        | Term::TChar
+        | Term::TCharLit(_)
+        | Term::TInt
+        | Term::TIntLit(_) => false,
+    }
+}
+
+/// Type of the eliminator of `data` at `lvl`:
+///
+/// ```text
+/// {params} → (P : (indices) → D params indices → U)
+///   → (methods) → {indices} → (x : D params indices) → P indices x
+/// ```
+pub(crate) fn elim_type(metas: &mut MetaCxt, data: &Rc<Data>, lvl: Lvl) -> Term {
+    let u = Term::TU(metas.fresh_level());
+
+    telescope(
+        metas,
+        lvl,
+        data.ty.clone(),
+        data.params,
+        Some(Icit::Impl),
+        &mut |metas, lp, ty| {
+            let motive = telescope(metas, lp, ty.clone(), data.indices, None, &mut |_, l, _| {
+                let x = Term::TData(data.clone(), levels(l, lvl..l));
+                Term::TΠ("x".into(), Icit::Expl, x.into(), u.clone().into())
+            });
+            let methods = methods(metas, data, lvl, lp, 0, lp + 1, ty);
+            Term::TΠ("P".into(), Icit::Expl, motive.into(), methods.into())
+        },
+    )
+}
+
+/// Types of the methods from the constructor `c` on at `lvl`, followed by the
+/// rest of the eliminator type. The parameters are bound at `l0`, the motive
+/// at `lp`, and `ty` are the indices of the data type.
+fn methods(
+    metas: &mut MetaCxt,
+    data: &Rc<Data>,
+    l0: Lvl,
+    lp: Lvl,
+    c: usize,
+    lvl: Lvl,
+    ty: Type,
+) -> Term {
+    if c == data.cons.len() {
+        return telescope(
+            metas,
+            lvl,
+            ty,
+            data.indices,
+            Some(Icit::Impl),
+            &mut |metas, l, _| {
+                let args = levels(l, (l0..lp).chain(lvl..l));
+                let x = Term::TData(data.clone(), args);
+                let is = (lvl..l).map(|x| Value::VRigid(x, vec![])).collect();
+                let px = motive_app(metas, lp, l + 1, is, Term::TV(Ix(0)));
+                Term::TΠ("x".into(), Icit::Expl, x.into(), px.into())
+            },
+        );
+    }
+
+    let method = method(metas, data, l0, lp, c, lvl);
+    let rest = methods(metas, data, l0, lp, c + 1, lvl + 1, ty);
+    Term::TΠ(
+        data.cons[c].name.clone(),
+        Icit::Expl,
+        method.into(),
+        rest.into(),
+    )
+}
+
+/// Type of the method for the constructor `c` at `lvl`, with the parameters
+/// bound at `l0` and the motive at `lp`.
+fn method(metas: &mut MetaCxt, data: &Rc<Data>, l0: Lvl, lp: Lvl, c: usize, lvl: Lvl) -> Term {
+    let mut con_ty = data.con_type(metas, c);
+    for x in l0..lp {
+        let Value::VΠ(_, _, _, b) = force(metas, con_ty) else {
+            unreachable!("constructor without its parameters")
+        };
+        con_ty = eval_closure(metas, b, Value::VRigid(x, vec![]));
+    }
+
+    method_type(metas, data, l0, lp, c, lvl, con_ty, Vec::new())
+}
+
+/// `(args) → P indices (c params args)` at `lvl`, with an induction
+/// hypothesis after each recursive argument, for the rest `ty` of the type of
+/// the constructor `c` after the arguments at the levels `args`.
+#[allow(clippy::too_many_arguments)]
+fn method_type(
+    metas: &mut MetaCxt,
+    data: &Rc<Data>,
+    l0: Lvl,
+    lp: Lvl,
+    c: usize,
+    lvl: Lvl,
+    ty: Type,
+    mut args: Vec<Lvl>,
+) -> Term {
+    match force(metas, ty) {
+        Value::VΠ(x, i, a, b) => {
+            let a = Rc::unwrap_or_clone(a);
+            let dom = quote(metas, lvl, a.clone());
+            let b = eval_closure(metas, b, Value::VRigid(lvl, vec![]));
+            args.push(lvl);
+
+            let rest = match force(metas, a) {
+                Value::VData(d, is) if Rc::ptr_eq(&d, data) => {
+                    let is = is.into_iter().skip(data.params).collect();
+                    let ih = motive_app(metas, lp, lvl + 1, is, Term::TV(Ix(0)));
+                    let rest = method_type(metas, data, l0, lp, c, lvl + 2, b, args);
+                    Term::TΠ("ih".into(), Icit::Expl, ih.into(), rest.into())
+                }
+                _ => method_type(metas, data, l0, lp, c, lvl + 1, b, args),
+            };
+            Term::TΠ(named(x), i, dom.into(), rest.into())
+        }
+        Value::VData(_, is) => {
+            let is = is.into_iter().skip(data.params).collect();
+            let con = Term::TCon(
+                data.clone(),
+                c,
+                levels(lvl, (l0..l0 + data.params).chain(args)),
+            );
+            motive_app(metas, lp, lvl, is, con)
+        }
+        _ => unreachable!("constructor type not ending in its data type"),
+    }
+}
+
+/// The motive bound at `lp` applied to the indices `is` and `x`, at `lvl`.
+fn motive_app(metas: &mut MetaCxt, lp: Lvl, lvl: Lvl, is: Vec<Value>, x: Term) -> Term {
+    let mut t = Term::TV(lvl2ix(lvl, lp));
+    for i in is {
+        t = Term::TApp(t.into(), quote(metas, lvl, i).into(), Icit::Expl);
+    }
+    Term::TApp(t.into(), x.into(), Icit::Expl)
+}
+
+/// The first `n` binders of the Π type `ty` quoted at `lvl`, with the icit
+/// `icit` if given, around the term `f` makes of the rest of the type at the
+/// level under them.
+fn telescope(
+    metas: &mut MetaCxt,
+    lvl: Lvl,
+    ty: Type,
+    n: usize,
+    icit: Option<Icit>,
+    f: &mut dyn FnMut(&mut MetaCxt, Lvl, Type) -> Term,
+) -> Term {
+    if n == 0 {
+        return f(metas, lvl, ty);
+    }
+
+    let Value::VΠ(x, i, a, b) = force(metas, ty) else {
+        unreachable!("fewer than {n} binders")
+    };
+    let a = quote(metas, lvl, Rc::unwrap_or_clone(a));
+    let b = eval_closure(metas, b, Value::VRigid(lvl, vec![]));
+    let b = telescope(metas, lvl + 1, b, n - 1, icit, f);
+    Term::TΠ(named(x), icit.unwrap_or(i), a.into(), b.into())
+}
+
+/// Names and icits of the first `n` binders of the Π type `ty` at `lvl`.
+pub(crate) fn binders(metas: &mut MetaCxt, mut ty: Type, lvl: Lvl, n: usize) -> Vec<(Name, Icit)> {
+    let mut binders = Vec::new();
+
+    for k in 0..n {
+        let Value::VΠ(x, i, _, b) = force(metas, ty) else {
+            unreachable!("fewer than {n} binders")
+        };
+        binders.push((named(x), i));
+        ty = eval_closure(metas, b, Value::VRigid(lvl + k, vec![]));
+    }
+
+    binders
+}
+
+/// Name for a binder that was `_`, as the generated terms refer to all of
+/// their binders.
+fn named(x: Name) -> Name {
+    match &*x {
+        "_" => "x".into(),
+        _ => x,
+    }
+}
+
+pub(crate) fn lams(binders: Vec<(Name, Icit)>, t: Term) -> Term {
+    binders
+        .into_iter()
+        .rev()
+        .fold(t, |t, (x, i)| Term::Tλ(x, i, t.into()))
+}
+
+/// The `n` innermost variables, outermost first.
+pub(crate) fn vars(n: usize) -> Vec<Tm> {
+    (0..n).rev().map(|x| Rc::new(Term::TV(Ix(x)))).collect()
+}
+
+/// The variables at the levels `xs`, as terms at `lvl`.
+fn levels(lvl: Lvl, xs: impl Iterator<Item = Lvl>) -> Vec<Tm> {
+    xs.map(|x| Rc::new(Term::TV(lvl2ix(lvl, x)))).collect()
+}
+
+/// `vecElim` for `Vec`
+fn elim_name(x: &str) -> Name {
+    let mut chars = x.chars();
+    let first = chars.next().map(|c| c.to_lowercase().to_string());
+    format!("{}{}Elim", first.unwrap_or_default(), chars.as_str()).into()
+}
@@ -0,0 +1,610 @@
+use std::fmt::Write;
+
+use chumsky::error::SimpleReason;
+
+use crate::{
+    error::{Error, ErrorKind},
+    lint::Lint,
+    metas::HoleReport,
+    parser::ParseError,
+    span::{SourceMap, Span},
+    Icit, Name, TElidingPrinter, TPrettyPrinter, Term,
+};
+
+/// Render an error against the source it came from, without colors.
+pub fn render_error(src: &str, err: &Error) -> String {
+    Renderer::new(src, false, "error").render(err)
+}
+
+/// Render an error against the file of `sources` it points into, naming the
+/// file in the location.
+pub fn render_error_in(sources: &SourceMap, err: &Error) -> String {
+    let mut renderer = Renderer::new(sources.source(err.pos.file), false, "error");
+    renderer.file = Some(sources.name(err.pos.file));
+    renderer.render(err)
+}
+
+/// Like [`render_error`], but highlighted with ANSI escape codes for terminals.
+pub fn render_error_colored(src: &str, err: &Error) -> String {
+    Renderer::new(src, true, "error").render(err)
+}
+
+/// Render an error that was downgraded to a warning, e.g. an unsolved meta.
+pub fn render_warning(src: &str, err: &Error) -> String {
+    Renderer::new(src, false, "warning").render(err)
+}
+
+/// Render the goal of a hole: its expected type and local context.
+pub fn render_hole(src: &str, hole: &HoleReport) -> String {
+    Renderer::new(src, false, "goal").render_hole(hole)
+}
+
+/// The message of an error with its notes, but without the severity and the
+/// source, for editors that show it at its position themselves.
+pub fn error_message(err: &Error) -> String {
+    Renderer::plain().render(err)
+}
+
+/// Like [`error_message`], for the goal of a hole.
+pub fn hole_message(hole: &HoleReport) -> String {
+    Renderer::plain().render_hole(hole)
+}
+
+/// Like [`error_message`], for a single syntax error.
+pub fn parse_error_message(e: &ParseError) -> String {
+    parse_message(e)
+}
+
+const RED: &str = "31";
+const YELLOW: &str = "33";
+const BLUE: &str = "34";
+const BOLD: &str = "1";
+
+/// how many characters the types of a mismatch may take before what they
+/// have in common is elided
+const DIFF_LEN: usize = 40;
+
+struct Renderer<'a> {
+    src: &'a str,
+    /// name of the file `src` is the source of, if it is one
+    file: Option<&'a str>,
+    color: bool,
+    severity: &'static str,
+    /// write the messages and notes only
+    plain: bool,
+}
+
+impl<'a> Renderer<'a> {
+    fn new(src: &'a str, color: bool, severity: &'static str) -> Self {
+        Self {
+            src,
+            file: None,
+            color,
+            severity,
+            plain: false,
+        }
+    }
+
+    fn plain() -> Self {
+        Self {
+            plain: true,
+            ..Self::new("", false, "")
+        }
+    }
+
+    fn render(&self, err: &Error) -> String {
+        let mut out = String::new();
+        let names: Vec<Name> = err.cxt.iter().map(|(x, _)| x.clone()).collect();
+        let term = |t: &Term| TPrettyPrinter(&names, t).to_string();
+
+        match &err.kind {
+            ErrorKind::Parse(errors) => {
+                for (i, e) in errors.iter().enumerate() {
+                    if i > 0 {
+                        out.push('\n');
+                    }
+                    self.header(&mut out, &parse_message(e));
+                    self.snippet(&mut out, &e.span());
+                }
+                return out;
+            }
+            ErrorKind::UnboundVariable(x) => {
+                self.header(&mut out, &format!("unbound variable `{x}`"));
+                self.snippet(&mut out, &err.pos);
+            }
+            ErrorKind::Mismatch { expected, actual } => {
+                self.header(&mut out, "type mismatch");
+                self.snippet(&mut out, &err.pos);
+                self.diff(&mut out, &names, expected, actual);
+            }
+            ErrorKind::ConClash {
+                expected,
+                actual,
+                con,
+                con_,
+            } => {
+                self.header(&mut out, "type mismatch");
+                self.snippet(&mut out, &err.pos);
+                self.diff(&mut out, &names, expected, actual);
+                self.note(
+                    &mut out,
+                    "note",
+                    &format!("the constructors `{con}` and `{con_}` are distinct"),
+                );
+            }
+            ErrorKind::Occurs {
+                meta,
+                origin,
+                expected,
+                actual,
+            } => {
+                self.header(
+                    &mut out,
+                    &format!("?{meta} would occur in its own solution"),
+                );
+                self.snippet(&mut out, &err.pos);
+                self.note(&mut out, "expected", &term(expected));
+                self.note(&mut out, "  actual", &term(actual));
+                let (line, col) = line_col(self.src, origin.start);
+                self.note(
+                    &mut out,
+                    "note",
+                    &format!("?{meta} was created at {}:{}", line + 1, col + 1),
+                );
+            }
+            ErrorKind::Scope {
+                meta,
+                origin,
+                scope,
+                var,
+                expected,
+                actual,
+            } => {
+                self.header(
+                    &mut out,
+                    &format!("?{meta} would depend on `{var}`, which is out of its scope"),
+                );
+                self.snippet(&mut out, &err.pos);
+                self.note(&mut out, "expected", &term(expected));
+                self.note(&mut out, "  actual", &term(actual));
+                let (line, col) = line_col(self.src, origin.start);
+                let scope = match scope.is_empty() {
+                    true => "nothing".to_owned(),
+                    false => scope
+                        .iter()
+                        .map(|x| format!("`{x}`"))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                };
+                self.note(
+                    &mut out,
+                    "note",
+                    &format!(
+                        "?{meta} was created at {}:{}, with {scope} in scope",
+                        line + 1,
+                        col + 1
+                    ),
+                );
+            }
+            ErrorKind::Frozen {
+                meta,
+                origin,
+                expected,
+                actual,
+            } => {
+                self.header(
+                    &mut out,
+                    &format!("?{meta} belongs to an earlier definition and can't be solved here"),
+                );
+                self.snippet(&mut out, &err.pos);
+                self.note(&mut out, "expected", &term(expected));
+                self.note(&mut out, "  actual", &term(actual));
+                let (line, col) = line_col(self.src, origin.start);
+                self.note(
+                    &mut out,
+                    "note",
+                    &format!("?{meta} was created at {}:{}", line + 1, col + 1),
+                );
+            }
+            ErrorKind::IcitMismatch(i, i_) => {
+                self.header(
+                    &mut out,
+                    &format!(
+                        "expected an {} argument, got an {} one",
+                        icit(*i_),
+                        icit(*i)
+                    ),
+                );
+                self.snippet(&mut out, &err.pos);
+            }
+            ErrorKind::NotAFunction { ty, arg } => {
+                self.header(&mut out, "expected a function type");
+                self.snippet(&mut out, &err.pos);
+                self.note(&mut out, "expected", "a function type");
+                self.note(&mut out, "  actual", &term(ty));
+                self.note(
+                    &mut out,
+                    "note",
+                    &format!("applied to the argument `{arg}`"),
+                );
+            }
+            ErrorKind::NoNamedImplicitArg(x) => {
+                self.header(&mut out, &format!("no implicit argument named `{x}`"));
+                self.snippet(&mut out, &err.pos);
+            }
+            ErrorKind::UnresolvedImport(path) => {
+                self.header(
+                    &mut out,
+                    &format!("can't resolve `import {path}` without a module loader"),
+                );
+                self.snippet(&mut out, &err.pos);
+            }
+            ErrorKind::Stuck {
+                meta,
+                names,
+                expected,
+                actual,
+            } => {
+                self.header(
+                    &mut out,
+                    &format!("can't solve ?{meta} from an equation that isn't a pattern"),
+                );
+                self.snippet(&mut out, &err.pos);
+                self.note(
+                    &mut out,
+                    "expected",
+                    &TPrettyPrinter(names, expected).to_string(),
+                );
+                self.note(
+                    &mut out,
+                    "  actual",
+                    &TPrettyPrinter(names, actual).to_string(),
+                );
+            }
+            ErrorKind::Universe { lhs, rhs } => {
+                self.header(&mut out, "universe inconsistency");
+                self.snippet(&mut out, &err.pos);
+                self.note(
+                    &mut out,
+                    "note",
+                    &format!("universe levels can't satisfy {lhs} <= {rhs}"),
+                );
+            }
+            ErrorKind::DataType { data, ty } => {
+                self.header(
+                    &mut out,
+                    &format!("the type of `{data}` must end in a universe"),
+                );
+                self.snippet(&mut out, &err.pos);
+                self.note(&mut out, "type", &term(ty));
+            }
+            ErrorKind::ConstructorType { con, data } => {
+                self.header(
+                    &mut out,
+                    &format!("`{con}` must return `{data}` applied to its parameters"),
+                );
+                self.snippet(&mut out, &err.pos);
+            }
+            ErrorKind::Positivity { con, data } => {
+                self.header(
+                    &mut out,
+                    &format!("`{data}` occurs in an argument of `{con}` other than as its type"),
+                );
+                self.snippet(&mut out, &err.pos);
+                self.note(
+                    &mut out,
+                    "note",
+                    &format!("only strictly positive occurrences of `{data}` are allowed"),
+                );
+            }
+            ErrorKind::NotData { ty } => {
+                self.header(&mut out, "can only match on a value of a data type");
+                self.snippet(&mut out, &err.pos);
+                self.note(&mut out, "type", &term(ty));
+            }
+            ErrorKind::NotAConstructor { con, data } => {
+                self.header(
+                    &mut out,
+                    &format!("`{con}` isn't a constructor of `{data}`"),
+                );
+                self.snippet(&mut out, &err.pos);
+            }
+            ErrorKind::RedundantCase { con } => {
+                self.header(
+                    &mut out,
+                    &format!("the branch for `{con}` is covered by an earlier one"),
+                );
+                self.snippet(&mut out, &err.pos);
+            }
+            ErrorKind::MissingCase { con } => {
+                self.header(&mut out, &format!("missing a branch for `{con}`"));
+                self.snippet(&mut out, &err.pos);
+            }
+            ErrorKind::PatternArity { con, expected } => {
+                self.header(
+                    &mut out,
+                    &format!("`{con}` takes {expected} explicit argument(s) in a pattern"),
+                );
+                self.snippet(&mut out, &err.pos);
+            }
+            ErrorKind::RecordType { record, ty } => {
+                self.header(
+                    &mut out,
+                    &format!("the type of `{record}` must be a universe after its parameters"),
+                );
+                self.snippet(&mut out, &err.pos);
+                self.note(&mut out, "type", &term(ty));
+            }
+            ErrorKind::NotARecord { ty } => {
+                self.header(&mut out, "expected a value of a record type");
+                self.snippet(&mut out, &err.pos);
+                self.note(&mut out, "type", &term(ty));
+            }
+            ErrorKind::NoSuchField { field, record } => {
+                self.header(&mut out, &format!("`{record}` has no field `{field}`"));
+                self.snippet(&mut out, &err.pos);
+            }
+            ErrorKind::MissingField { field, record } => {
+                self.header(
+                    &mut out,
+                    &format!("missing the field `{field}` of `{record}`"),
+                );
+                self.snippet(&mut out, &err.pos);
+            }
+            ErrorKind::DuplicateField { field } => {
+                self.header(&mut out, &format!("the field `{field}` is given twice"));
+                self.snippet(&mut out, &err.pos);
+            }
+            ErrorKind::UnknownRecord { fields } => {
+                let fields: Vec<String> = fields.iter().map(|f| format!("`{f}`")).collect();
+                self.header(
+                    &mut out,
+                    &format!("no record has the fields {}", fields.join(", ")),
+                );
+                self.snippet(&mut out, &err.pos);
+            }
+            ErrorKind::Termination { def, names, call } => {
+                self.header(&mut out, &format!("`{def}` may not terminate"));
+                self.snippet(&mut out, &err.pos);
+                self.note(&mut out, "call", &TPrettyPrinter(names, call).to_string());
+                self.note(
+                    &mut out,
+                    "note",
+                    "recursive calls need an argument bound by a match on the same argument, \
+                     or to be delayed in an argument of a constructor",
+                );
+            }
+            ErrorKind::NoInstance { meta, ty } => {
+                let ty = term(ty);
+                self.header(&mut out, &format!("no instance of `{ty}` for ?{meta}"));
+                self.snippet(&mut out, &err.pos);
+            }
+            ErrorKind::UnsolvedMeta { meta, names, ty } => {
+                self.header(&mut out, &format!("unsolved meta ?{meta}"));
+                self.snippet(&mut out, &err.pos);
+                self.note(&mut out, "type", &TPrettyPrinter(names, ty).to_string());
+            }
+            ErrorKind::UnusedBinder { name } => {
+                self.header(&mut out, &format!("unused variable `{name}`"));
+                self.snippet(&mut out, &err.pos);
+                self.note(
+                    &mut out,
+                    "help",
+                    "bind it as `_` if it is meant to be unused",
+                );
+            }
+            ErrorKind::Shadowing { name } => {
+                self.header(
+                    &mut out,
+                    &format!("`{name}` shadows a variable of the same name"),
+                );
+                self.snippet(&mut out, &err.pos);
+            }
+            ErrorKind::UnknownPragma { name } => {
+                self.header(&mut out, &format!("unknown pragma `{name}`"));
+                self.snippet(&mut out, &err.pos);
+                self.note(&mut out, "note", "it is ignored");
+            }
+            ErrorKind::UnknownLint { name } => {
+                self.header(&mut out, &format!("unknown lint `{name}`"));
+                self.snippet(&mut out, &err.pos);
+                let lints: Vec<&str> = Lint::ALL.iter().map(|lint| lint.name()).collect();
+                self.note(
+                    &mut out,
+                    "help",
+                    &format!("the lints are {}", lints.join(", ")),
+                );
+            }
+            ErrorKind::NormalizationTimeout { term: t } => {
+                self.header(&mut out, "evaluation ran out of fuel");
+                self.snippet(&mut out, &err.pos);
+                self.note(&mut out, "stopped at", &term(t));
+                self.note(
+                    &mut out,
+                    "help",
+                    "a recursive definition may not terminate, or needs more fuel",
+                );
+            }
+            ErrorKind::ResourceExhausted { nodes } => {
+                self.header(
+                    &mut out,
+                    &format!("the terms grew beyond the limit of {nodes} nodes"),
+                );
+                self.snippet(&mut out, &err.pos);
+            }
+            ErrorKind::UnconstrainedHole { name } => {
+                self.header(
+                    &mut out,
+                    &format!("nothing is known about the type of the hole `?{name}`"),
+                );
+                self.snippet(&mut out, &err.pos);
+                self.note(
+                    &mut out,
+                    "help",
+                    "use it where its type is known, e.g. as the definition of a `let` with a type",
+                );
+            }
+        }
+
+        self.scope(&mut out, &err.cxt);
+        out
+    }
+
+    fn render_hole(&self, hole: &HoleReport) -> String {
+        let mut out = String::new();
+        let names: Vec<Name> = hole.cxt.iter().map(|(x, _)| x.clone()).collect();
+
+        self.header(
+            &mut out,
+            &format!("?{} : {}", hole.name, TPrettyPrinter(&names, &hole.ty)),
+        );
+        self.snippet(&mut out, &hole.pos);
+        if let Some(t) = &hole.solution {
+            self.note(&mut out, "solved", &TPrettyPrinter(&[], t).to_string());
+        }
+        self.scope(&mut out, &hole.cxt);
+        out
+    }
+
+    fn paint(&self, code: &str, text: &str) -> String {
+        if self.color {
+            format!("\x1b[{code}m{text}\x1b[0m")
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn header(&self, out: &mut String, msg: &str) {
+        if self.plain {
+            let _ = writeln!(out, "{msg}");
+            return;
+        }
+        let color = if self.severity == "error" {
+            RED
+        } else {
+            YELLOW
+        };
+        let severity = self.paint(&format!("{BOLD};{color}"), self.severity);
+        let _ = writeln!(out, "{severity}: {}", self.paint(BOLD, msg));
+    }
+
+    /// The line containing the start of `pos`, with the range underlined up to
+    /// the end of that line.
+    fn snippet(&self, out: &mut String, pos: &Span) {
+        if self.plain {
+            return;
+        }
+        let (line, col) = line_col(self.src, pos.start);
+        let text = self.src.lines().nth(line).unwrap_or("");
+        let width = text.chars().count();
+
+        let gutter = " ".repeat((line + 1).to_string().len());
+        let bar = self.paint(BLUE, "|");
+
+        let len = pos
+            .end
+            .saturating_sub(pos.start)
+            .min(width.saturating_sub(col))
+            .max(1);
+        let underline = self.paint(RED, &"^".repeat(len));
+
+        let file = self.file.map(|file| format!("{file}:")).unwrap_or_default();
+        let _ = writeln!(
+            out,
+            "{gutter}{} {file}{}:{}",
+            self.paint(BLUE, "-->"),
+            line + 1,
+            col + 1
+        );
+        let _ = writeln!(out, "{gutter} {bar}");
+        let _ = writeln!(
+            out,
+            "{} {bar} {text}",
+            self.paint(BLUE, &(line + 1).to_string())
+        );
+        let _ = writeln!(out, "{gutter} {bar} {}{underline}", " ".repeat(col));
+    }
+
+    /// The expected and actual types of a mismatch, with what they have in
+    /// common elided once either is too long to compare at a glance.
+    fn diff(&self, out: &mut String, names: &[Name], expected: &Term, actual: &Term) {
+        let full = |t| TPrettyPrinter(names, t).to_string();
+        let (mut e, mut a) = (full(expected), full(actual));
+        if e.chars().count().max(a.chars().count()) > DIFF_LEN {
+            let common = expected.common(actual);
+            e = TElidingPrinter(names, expected, &common).to_string();
+            a = TElidingPrinter(names, actual, &common).to_string();
+        }
+        self.note(out, "expected", &e);
+        self.note(out, "  actual", &a);
+    }
+
+    fn note(&self, out: &mut String, label: &str, msg: &str) {
+        let _ = writeln!(out, "  {} {label}: {msg}", self.paint(BLUE, "="));
+    }
+
+    fn scope(&self, out: &mut String, cxt: &[(Name, Term)]) {
+        if cxt.is_empty() {
+            return;
+        }
+
+        let names: Vec<Name> = cxt.iter().map(|(x, _)| x.clone()).collect();
+
+        let _ = writeln!(out, "  {} in scope:", self.paint(BLUE, "="));
+        for (lvl, (x, a)) in cxt.iter().enumerate() {
+            let _ = writeln!(out, "      {x} : {}", TPrettyPrinter(&names[..lvl], a));
+        }
+    }
+}
+
+fn icit(i: Icit) -> &'static str {
+    match i {
+        Icit::Impl => "implicit",
+        Icit::Expl => "explicit",
+        Icit::Inst => "instance",
+    }
+}
+
+fn parse_message(e: &ParseError) -> String {
+    match e.reason() {
+        SimpleReason::Custom(msg) => return msg.clone(),
+        SimpleReason::Unclosed { delimiter, .. } => return format!("unclosed {delimiter}"),
+        SimpleReason::Unexpected => {}
+    }
+
+    let mut msg = match e.found() {
+        Some(token) => format!("unexpected {token}"),
+        None => "unexpected end of input".to_string(),
+    };
+
+    let expected: Vec<String> = e
+        .expected()
+        .map(|t| match t {
+            Some(t) => format!("{t}"),
+            None => "end of input".to_string(),
+        })
+        .collect();
+
+    if !expected.is_empty() {
+        let _ = write!(msg, ", expected {}", expected.join(", "));
+    }
+
+    msg
+}
+
+/// Zero-based line and column of the `pos`th character of `src`.
+pub fn line_col(src: &str, pos: usize) -> (usize, usize) {
+    let mut line = 0;
+    let mut col = 0;
+
+    for c in src.chars().take(pos) {
+        if c == '\n' {
+            line += 1;
+            col = 0;
+        } else {
+            col += 1;
+        }
+    }
+
+    (line, col)
+}
@@ -0,0 +1,474 @@
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use chumsky::prelude::Simple;
+
+use crate::{
+    check, check_definition, check_levels, check_program,
+    error::Error,
+    eval, infer, instance,
+    lint::{Lint, Severity},
+    metas::{
+        is_def_eq, log::UnifyLog, Conversion, HoleReport, MetaCxt, MetaEntry, MetaVar,
+        UnifyObserver, UnsolvedPolicy,
+    },
+    modules::{ModuleError, ModuleLoader},
+    normalize, normalize_limited,
+    parser::parse,
+    quote,
+    span::{Pos, Span},
+    stepper::Stepper,
+    store::TermStore,
+    trace::{ElabTracer, TypeInfo, TypeRecorder},
+    whnf, zonk, zonk_local, Cxt, Definition, ElabOptions, Name, Program, Raw, Rc, Strategy, Term,
+    Type, Unfold, VPrettyPrinter, Value,
+};
+
+/// Elaboration session: the metavariables and the top-level context of
+/// everything elaborated so far.
+#[derive(Debug, Default)]
+pub struct Elaborator {
+    metas: MetaCxt,
+    cxt: Cxt,
+    unsolved: UnsolvedPolicy,
+    /// unsolved metas, with [`UnsolvedPolicy::Warn`], and the binders
+    /// warned about
+    warnings: Vec<Error>,
+    /// whether [`Elaborator::elaborate`] records the types of the subterms
+    record_types: bool,
+}
+
+/// What [`Elaborator::elaborate`] takes: the source of a term, or the term
+/// already parsed
+#[derive(Debug, Clone)]
+pub enum Input<'a> {
+    Source(&'a str),
+    Raw(Raw),
+}
+
+impl<'a> From<&'a str> for Input<'a> {
+    fn from(src: &'a str) -> Self {
+        Input::Source(src)
+    }
+}
+
+impl From<Raw> for Input<'_> {
+    fn from(raw: Raw) -> Self {
+        Input::Raw(raw)
+    }
+}
+
+/// An elaborated term with what else came out of elaborating it
+#[derive(Debug)]
+pub struct ElabResult {
+    /// the term, with the solved metas substituted
+    pub term: Term,
+    /// its type, quoted with the solved metas substituted
+    pub ty: Term,
+    /// the metas created for the term that are still unsolved, allowed by
+    /// the [`UnsolvedPolicy`]
+    pub unsolved_metas: Vec<MetaVar>,
+    /// the unsolved metas, with [`UnsolvedPolicy::Warn`], and the binders
+    /// warned about
+    pub warnings: Vec<Error>,
+    /// the subterms at source spans with their types, zonked, in the order
+    /// their elaboration finished; only recorded with
+    /// [`Elaborator::set_record_types`]
+    pub types: Vec<TypeInfo>,
+    pub timings: Timings,
+}
+
+/// Time spent in each phase of [`Elaborator::elaborate`], all zero on
+/// `wasm32-unknown-unknown`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timings {
+    /// zero for input already parsed
+    pub parse: Duration,
+    /// inferring the type, and checking the instances and universe levels
+    pub elaborate: Duration,
+    /// substituting the solved metas
+    pub zonk: Duration,
+}
+
+impl Timings {
+    pub fn total(&self) -> Duration {
+        self.parse + self.elaborate + self.zonk
+    }
+}
+
+/// Measures the phases of [`Elaborator::elaborate`]. There is no clock on
+/// `wasm32-unknown-unknown`, where the timings stay zero.
+struct Stopwatch {
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    start: Instant,
+}
+
+impl Stopwatch {
+    fn start() -> Self {
+        Stopwatch {
+            #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+            start: Instant::now(),
+        }
+    }
+
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    fn elapsed(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+/// The innermost subterm of `elab` whose span contains `pos`, with its type,
+/// if the types were recorded. Like those of spans, `pos` counts characters
+/// rather than bytes.
+pub fn type_at(pos: Pos, elab: &ElabResult) -> Option<TypeInfo> {
+    innermost_type(pos, &elab.types).cloned()
+}
+
+/// The innermost of the recorded `types` whose span contains `pos`, the
+/// outermost of those with the same span.
+pub fn innermost_type(pos: Pos, types: &[TypeInfo]) -> Option<&TypeInfo> {
+    types
+        .iter()
+        .rev()
+        .filter(|info| info.pos.range().contains(&pos))
+        .min_by_key(|info| info.pos.range().len())
+}
+
+/// Elaborate a closed term in a session of its own.
+pub fn elaborate<'a>(input: impl Into<Input<'a>>) -> Result<ElabResult, Error> {
+    Elaborator::new().elaborate(input)
+}
+
+impl Elaborator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Session in the context of the axioms `x : A`, see [`Cxt::with_axioms`].
+    pub fn with_axioms(axioms: &[(&str, &str)]) -> Result<Self, Error> {
+        let mut metas = MetaCxt::default();
+        let cxt = Cxt::with_axioms(&mut metas, axioms)?;
+        Ok(Elaborator {
+            metas,
+            cxt,
+            ..Self::default()
+        })
+    }
+
+    pub fn metas(&self) -> &MetaCxt {
+        &self.metas
+    }
+
+    pub fn cxt(&self) -> &Cxt {
+        &self.cxt
+    }
+
+    /// The store the elaborated definitions are interned in.
+    pub fn store(&self) -> &TermStore {
+        self.metas.store()
+    }
+
+    /// What to do with the metas a definition leaves unsolved.
+    pub fn set_unsolved_policy(&mut self, policy: UnsolvedPolicy) {
+        self.unsolved = policy;
+    }
+
+    /// Warnings of the definitions elaborated since the last call.
+    pub fn take_warnings(&mut self) -> Vec<Error> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Goals of the holes `?x` elaborated since the last call.
+    pub fn take_holes(&mut self) -> Vec<HoleReport> {
+        self.metas.take_holes(0)
+    }
+
+    /// Trace every following `check` and `infer` with `tracer`.
+    pub fn set_tracer(&mut self, tracer: std::rc::Rc<RefCell<dyn ElabTracer>>) {
+        self.cxt.set_tracer(tracer);
+    }
+
+    /// Record the types of the subterms of the terms elaborated from now on,
+    /// for [`type_at`].
+    pub fn set_record_types(&mut self, on: bool) {
+        self.record_types = on;
+    }
+
+    /// Report the unification problems, solutions and postponed problems of
+    /// everything that follows to `observer`.
+    pub fn set_observer(&mut self, observer: std::rc::Rc<RefCell<dyn UnifyObserver>>) {
+        self.metas.set_observer(observer);
+    }
+
+    /// Log the unification problems of everything that follows, see
+    /// [`MetaCxt::start_log`].
+    pub fn start_unify_log(&mut self) {
+        self.metas.start_log();
+    }
+
+    /// The unification problems logged since [`Elaborator::start_unify_log`],
+    /// which stops logging.
+    pub fn take_unify_log(&mut self) -> Option<UnifyLog> {
+        self.metas.take_log()
+    }
+
+    /// Evaluate with `strategy` from now on.
+    pub fn set_strategy(&mut self, strategy: Strategy) {
+        self.metas.set_strategy(strategy);
+    }
+
+    pub fn options(&self) -> &ElabOptions {
+        self.cxt.options()
+    }
+
+    /// Elaborate everything that follows with `options`.
+    pub fn set_options(&mut self, options: ElabOptions) {
+        let shadowing = match options.warn_shadowing {
+            true => Severity::Warn,
+            false => Severity::Allow,
+        };
+        self.metas.lints_mut().set(Lint::Shadowing, shadowing);
+        self.cxt.set_options(options);
+    }
+
+    /// Give the warnings of `lint` the severity `severity` from now on, but
+    /// in the declarations whose pragmas say otherwise.
+    pub fn set_lint(&mut self, lint: Lint, severity: Severity) {
+        self.metas.lints_mut().set(lint, severity);
+    }
+
+    pub fn infer(&mut self, raw: Raw) -> Result<(Term, Type), Error> {
+        let res = infer(&mut self.metas, &mut self.cxt, raw)?;
+        instance::check_found(&mut self.metas)?;
+        check_levels(&mut self.metas, &self.cxt)?;
+        Ok(res)
+    }
+
+    /// Infer the type of a term of the top-level context one node at a time,
+    /// without the checks of the instances and universe levels that
+    /// [`Elaborator::infer`] does after.
+    pub fn stepper(&mut self, raw: Raw) -> Stepper<'_> {
+        Stepper::infer(&mut self.metas, &mut self.cxt, raw)
+    }
+
+    /// Infer the type of a term of the top-level context, parsing it first if
+    /// it is source, and zonk both.
+    pub fn elaborate<'a>(&mut self, input: impl Into<Input<'a>>) -> Result<ElabResult, Error> {
+        let mut timings = Timings::default();
+
+        let start = Stopwatch::start();
+        let raw = match input.into() {
+            Input::Source(src) => match parse(src) {
+                Ok(Some(raw)) => raw,
+                Ok(None) => {
+                    let err = Simple::custom(Span::default(), "expected a term");
+                    return Err(Error::parse(vec![err]));
+                }
+                Err(errs) => return Err(Error::parse(errs)),
+            },
+            Input::Raw(raw) => raw,
+        };
+        timings.parse = start.elapsed();
+
+        let start = Stopwatch::start();
+        let from = self.metas.len();
+        let outer = self.cxt.tracer.clone();
+        let recorder = self.record_types.then(|| {
+            let recorder = std::rc::Rc::new(RefCell::new(TypeRecorder::new(outer.clone())));
+            self.cxt.tracer = Some(recorder.clone());
+            recorder
+        });
+        let res = self.infer(raw);
+        self.cxt.tracer = outer;
+        let (t, a) = res?;
+        if self.options().strict_holes {
+            self.metas.check_holes_constrained(from)?;
+        }
+        let mut warnings = self.metas.check_all_solved(from, self.unsolved)?;
+        warnings.extend(self.metas.take_warnings());
+        let warnings = self.metas.lints_mut().apply(warnings)?;
+        timings.elaborate = start.elapsed();
+
+        let start = Stopwatch::start();
+        let term = self.zonk(t);
+        let ty = self.quote(a);
+        let ty = self.zonk(ty);
+        let types = match recorder {
+            Some(recorder) => std::mem::take(&mut recorder.borrow_mut().types)
+                .into_iter()
+                .map(|info| TypeInfo {
+                    term: zonk_local(&mut self.metas, info.names.len(), info.term),
+                    ty: zonk_local(&mut self.metas, info.names.len(), info.ty),
+                    ..info
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        timings.zonk = start.elapsed();
+
+        let unsolved_metas = (from..self.metas.len())
+            .filter(|&m| self.metas.solution(m).is_none())
+            .collect();
+
+        Ok(ElabResult {
+            term,
+            ty,
+            unsolved_metas,
+            warnings,
+            types,
+            timings,
+        })
+    }
+
+    pub fn check(&mut self, raw: Raw, ty: Type) -> Result<Term, Error> {
+        let t = check(&mut self.metas, &mut self.cxt, raw, ty)?;
+        instance::check_found(&mut self.metas)?;
+        check_levels(&mut self.metas, &self.cxt)?;
+        Ok(t)
+    }
+
+    /// Elaborate `def x : a := t`, keeping `x` in scope.
+    pub fn define(&mut self, x: Name, a: Raw, t: Raw) -> Result<Definition, Error> {
+        let from = self.metas.len();
+        let def = check_definition(&mut self.metas, &mut self.cxt, x, a, t)?;
+        self.check_solved(from)?;
+        Ok(def)
+    }
+
+    /// Elaborate the definitions of a program, keeping them in scope.
+    pub fn check_program(&mut self, program: Program) -> Result<Vec<Definition>, Error> {
+        let from = self.metas.len();
+        let defs = check_program(&mut self.metas, &mut self.cxt, program)?;
+        self.check_solved(from)?;
+        Ok(defs)
+    }
+
+    /// Like [`Elaborator::check_program`], but resolving imports with `loader`.
+    /// `file` is only used for error reporting.
+    pub fn load_program(
+        &mut self,
+        loader: &mut ModuleLoader,
+        file: &Path,
+        program: Program,
+    ) -> Result<Vec<Definition>, ModuleError> {
+        let from = self.metas.len();
+        let defs = loader.check_program(&mut self.metas, &mut self.cxt, file, program)?;
+        self.check_solved(from)
+            .map_err(|err| ModuleError::Check(file.to_path_buf(), err))?;
+        Ok(defs)
+    }
+
+    /// Like [`Elaborator::load_program`], but going on past the declarations
+    /// that fail, see [`ModuleLoader::check_program_recovering`], and failing
+    /// with every error. The warnings are kept either way.
+    pub fn load_program_recovering(
+        &mut self,
+        loader: &mut ModuleLoader,
+        file: &Path,
+        program: Program,
+    ) -> Result<Vec<Definition>, Vec<ModuleError>> {
+        let from = self.metas.len();
+        let (defs, mut errors) =
+            loader.check_program_recovering(&mut self.metas, &mut self.cxt, file, program);
+        if let Err(err) = self.check_solved(from) {
+            errors.push(ModuleError::Check(file.to_path_buf(), err));
+            // the lints check_solved didn't get to
+            let warnings = self.metas.take_warnings();
+            match self.metas.lints_mut().apply(warnings) {
+                Ok(warnings) => self.warnings.extend(warnings),
+                Err(err) => errors.push(ModuleError::Check(file.to_path_buf(), err)),
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(defs),
+            false => Err(errors),
+        }
+    }
+
+    pub fn eval(&mut self, term: Term) -> Value {
+        eval(&mut self.metas, Cow::Borrowed(self.cxt.env()), term)
+    }
+
+    pub fn quote(&mut self, val: Value) -> Term {
+        quote(&mut self.metas, self.cxt.lvl(), val)
+    }
+
+    /// Printer of a value of the top-level context.
+    pub fn display_value(&mut self, val: &Value) -> VPrettyPrinter {
+        VPrettyPrinter::new(&mut self.metas, &self.cxt, val)
+    }
+
+    /// Normal form of a term of the top-level context.
+    pub fn normalize(&mut self, term: Term, unfold: Unfold) -> Term {
+        normalize(&mut self.metas, &self.cxt, term, unfold)
+    }
+
+    /// Like [`Elaborator::normalize`], but failing beyond the limits of the
+    /// options, see [`normalize_limited`].
+    pub fn normalize_limited(&mut self, term: Term, unfold: Unfold) -> Result<Term, Error> {
+        normalize_limited(&mut self.metas, &self.cxt, term, unfold)
+    }
+
+    /// Substitute the solved metas of a term of the top-level context.
+    pub fn zonk(&mut self, term: Term) -> Term {
+        zonk(&mut self.metas, &self.cxt, term)
+    }
+
+    /// Weak head normal form of a term of the top-level context.
+    pub fn whnf(&mut self, term: Term, unfold: Unfold) -> Value {
+        whnf(&mut self.metas, &self.cxt, term, unfold)
+    }
+
+    /// Whether two values of the top-level context are definitionally equal,
+    /// leaving the metas unsolved.
+    pub fn is_def_eq(&mut self, l: Value, r: Value) -> Conversion {
+        is_def_eq(&mut self.metas, self.cxt.lvl(), l, r)
+    }
+
+    /// Solutions of the metas solved so far, as closed terms.
+    pub fn solve_metas(&mut self) -> Vec<(MetaVar, Term)> {
+        let solved: Vec<(MetaVar, Value)> = self
+            .metas
+            .iter()
+            .filter_map(|(m, _)| Some((m, self.metas.solution(m)?.clone())))
+            .collect();
+
+        solved
+            .into_iter()
+            .map(|(m, v)| (m, quote(&mut self.metas, 0, v)))
+            .collect()
+    }
+
+    pub fn unsolved_metas(&self) -> Vec<MetaVar> {
+        self.metas
+            .iter()
+            .filter(|(_, entry)| matches!(entry, MetaEntry::Unsolved))
+            .map(|(m, _)| m)
+            .collect()
+    }
+
+    /// Share the nodes of `term` with the definitions elaborated before.
+    pub fn intern(&mut self, term: &Term) -> Rc<Term> {
+        self.metas.intern(term)
+    }
+
+    fn check_solved(&mut self, from: MetaVar) -> Result<(), Error> {
+        if self.options().strict_holes {
+            self.metas.check_holes_constrained(from)?;
+        }
+        let mut warnings = self.metas.check_all_solved(from, self.unsolved)?;
+        warnings.extend(self.metas.take_warnings());
+        let warnings = self.metas.lints_mut().apply(warnings)?;
+        self.warnings.extend(warnings);
+        Ok(())
+    }
+}
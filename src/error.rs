@@ -0,0 +1,429 @@
+use std::backtrace::Backtrace;
+
+use crate::{
+    metas::{con_name, LevelConstraint, MetaCxt, MetaVar, Postponed, UnifyError},
+    modules::ModulePath,
+    parser::ParseError,
+    quote, Cxt, Icit, Level, Lvl, Name, Raw, Span, Term, Value,
+};
+
+/// An error, with its parts in [`ErrorInner`] behind a box so that results
+/// carrying it stay as small as a pointer
+#[derive(Debug)]
+pub struct Error(Box<ErrorInner>);
+
+const _: () = assert!(std::mem::size_of::<Error>() == std::mem::size_of::<usize>());
+
+#[derive(Debug)]
+pub struct ErrorInner {
+    pub backtrace: Backtrace,
+    /// where the error happened
+    pub pos: Span,
+    /// the local context at `pos`, each type quoted at its own level; the terms
+    /// in `kind` live in this context
+    pub cxt: Vec<(Name, Term)>,
+    pub kind: ErrorKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum ErrorKind {
+    Parse(Vec<ParseError>),
+    UnboundVariable(Name),
+    /// `expected` and `actual` don't unify
+    Mismatch {
+        expected: Term,
+        actual: Term,
+    },
+    /// solving `meta`, created at `origin`, while unifying `expected` with
+    /// `actual` would make it occur in its own solution
+    Occurs {
+        meta: MetaVar,
+        origin: Span,
+        expected: Term,
+        actual: Term,
+    },
+    /// solving `meta`, created at `origin` with the variables `scope` in
+    /// scope, while unifying `expected` with `actual` would need the variable
+    /// `var`, which isn't one of them
+    Scope {
+        meta: MetaVar,
+        origin: Span,
+        scope: Vec<Name>,
+        var: Name,
+        expected: Term,
+        actual: Term,
+    },
+    /// unifying `expected` with `actual` would solve `meta`, which belongs to
+    /// the earlier definition at `origin`
+    Frozen {
+        meta: MetaVar,
+        origin: Span,
+        expected: Term,
+        actual: Term,
+    },
+    /// `expected` and `actual` don't unify because somewhere in them the
+    /// distinct constructors `con` and `con_` are compared
+    ConClash {
+        expected: Term,
+        actual: Term,
+        con: Name,
+        con_: Name,
+    },
+    IcitMismatch(Icit, Icit),
+    /// an application to `arg` of a term of the type `ty`, which is neither a
+    /// function type nor could become one
+    NotAFunction {
+        ty: Term,
+        arg: Raw,
+    },
+    NoNamedImplicitArg(Name),
+    UnresolvedImport(ModulePath),
+    /// `expected =? actual` is still waiting for `meta` to be solved; the
+    /// sides live in the context `names` rather than `Error::cxt`
+    Stuck {
+        meta: MetaVar,
+        names: Vec<Name>,
+        expected: Term,
+        actual: Term,
+    },
+    /// the universe `lhs` can't be below `rhs` without making the levels
+    /// inconsistent
+    Universe {
+        lhs: Level,
+        rhs: Level,
+    },
+    /// the type `ty` of the data type `data` doesn't end in a universe
+    DataType {
+        data: Name,
+        ty: Term,
+    },
+    /// the constructor `con` doesn't return its data type `data` applied to
+    /// the parameters
+    ConstructorType {
+        con: Name,
+        data: Name,
+    },
+    /// `data` occurs in an argument of its constructor `con` other than as the
+    /// type of a recursive argument
+    Positivity {
+        con: Name,
+        data: Name,
+    },
+    /// the scrutinee of a `match` has the type `ty`, which isn't a data type
+    NotData {
+        ty: Term,
+    },
+    /// a `match` branch for `con`, which isn't a constructor of `data`
+    NotAConstructor {
+        con: Name,
+        data: Name,
+    },
+    /// a `match` branch for `con`, which an earlier branch covers
+    RedundantCase {
+        con: Name,
+    },
+    /// a `match` without a branch for `con`
+    MissingCase {
+        con: Name,
+    },
+    /// a pattern for `con` with other than `expected` explicit arguments
+    PatternArity {
+        con: Name,
+        expected: usize,
+    },
+    /// the type of the record `record` isn't a universe after its parameters
+    RecordType {
+        record: Name,
+        ty: Term,
+    },
+    /// a projection from, or a record literal of, the type `ty`, which isn't
+    /// a record
+    NotARecord {
+        ty: Term,
+    },
+    /// `field` isn't a field of `record`
+    NoSuchField {
+        field: Name,
+        record: Name,
+    },
+    /// a record literal without a value for `field` of `record`
+    MissingField {
+        field: Name,
+        record: Name,
+    },
+    /// `field` is given twice in a record declaration or literal
+    DuplicateField {
+        field: Name,
+    },
+    /// no record has exactly the fields of a record literal
+    UnknownRecord {
+        fields: Vec<Name>,
+    },
+    /// `def` calls itself as `call`, on no smaller argument; `call` lives in
+    /// the context `names`
+    Termination {
+        def: Name,
+        names: Vec<Name>,
+        call: Term,
+    },
+    /// no instance in scope fits the instance argument `meta : ty`
+    NoInstance {
+        meta: MetaVar,
+        ty: Term,
+    },
+    /// `meta : ty` was left unsolved, `ty` lives in the context `names`
+    UnsolvedMeta {
+        meta: MetaVar,
+        names: Vec<Name>,
+        ty: Term,
+    },
+    /// the variable `name` of a binder is never used, a warning
+    UnusedBinder {
+        name: Name,
+    },
+    /// a binder of `name` shadows a variable of the same name, a warning
+    /// of the lint [`crate::lint::Lint::Shadowing`]
+    Shadowing {
+        name: Name,
+    },
+    /// the pragma `name` isn't one the elaborator knows, a warning
+    UnknownPragma {
+        name: Name,
+    },
+    /// a pragma sets the severity of `name`, which isn't a lint, a warning
+    UnknownLint {
+        name: Name,
+    },
+    /// the type of the hole `?name` is still an unsolved meta, with
+    /// [`crate::ElabOptions::strict_holes`]
+    UnconstrainedHole {
+        name: Name,
+    },
+    /// evaluation ran out of fuel unfolding recursive definitions, see
+    /// [`crate::ElabOptions::fuel`]; `term` is as far as it got
+    NormalizationTimeout {
+        term: Term,
+    },
+    /// the terms quoted would have had more than `nodes` nodes, see
+    /// [`crate::ElabOptions::max_nodes`]
+    ResourceExhausted {
+        nodes: usize,
+    },
+}
+
+macro_rules! error {
+    ($metas:expr, $cxt:expr, $error_kind:expr) => {
+        Err($crate::error::Error::new($metas, $cxt, $error_kind))
+    };
+}
+
+impl std::ops::Deref for Error {
+    type Target = ErrorInner;
+
+    fn deref(&self) -> &ErrorInner {
+        &self.0
+    }
+}
+
+impl std::ops::DerefMut for Error {
+    fn deref_mut(&mut self) -> &mut ErrorInner {
+        &mut self.0
+    }
+}
+
+impl Error {
+    pub fn new(metas: &mut MetaCxt, cxt: &Cxt, kind: ErrorKind) -> Self {
+        let cxt_ = cxt
+            .types()
+            .iter()
+            .enumerate()
+            .map(|(lvl, (x, a))| (x.clone(), quote(metas, lvl, a.clone())))
+            .collect();
+
+        Error(Box::new(ErrorInner {
+            backtrace: Backtrace::capture(),
+            pos: cxt.pos().clone(),
+            cxt: cxt_,
+            kind,
+        }))
+    }
+
+    /// Errors of the parser, positioned at the first one
+    pub fn parse(errors: Vec<ParseError>) -> Self {
+        Error(Box::new(ErrorInner {
+            backtrace: Backtrace::capture(),
+            pos: errors.first().map(|e| e.span()).unwrap_or_default(),
+            cxt: Vec::new(),
+            kind: ErrorKind::Parse(errors),
+        }))
+    }
+
+    /// Turn a failed unification of `expected` with `actual` into an error in `cxt`.
+    pub fn unify(
+        metas: &mut MetaCxt,
+        cxt: &Cxt,
+        expected: Value,
+        actual: Value,
+        err: UnifyError,
+    ) -> Self {
+        let expected = quote(metas, cxt.lvl(), expected);
+        let actual = quote(metas, cxt.lvl(), actual);
+
+        let kind = match err {
+            // the sides may only differ as they are partially evaluated
+            _ if metas.exhausted() => ErrorKind::NormalizationTimeout { term: actual },
+            _ if metas.out_of_nodes() => ErrorKind::ResourceExhausted {
+                nodes: cxt.options().max_nodes.unwrap_or_default(),
+            },
+            UnifyError::MetaOccurs(meta, _) => ErrorKind::Occurs {
+                meta,
+                origin: metas.origin(meta).pos.clone(),
+                expected,
+                actual,
+            },
+            UnifyError::MetaScope(meta, x) => {
+                let origin = metas.origin(meta);
+                ErrorKind::Scope {
+                    meta,
+                    origin: origin.pos.clone(),
+                    scope: origin.names.clone(),
+                    var: var_name(cxt, x),
+                    expected,
+                    actual,
+                }
+            }
+            UnifyError::MetaFrozen(meta) => ErrorKind::Frozen {
+                meta,
+                origin: metas.origin(meta).pos.clone(),
+                expected,
+                actual,
+            },
+            UnifyError::ConClash(l, r) => ErrorKind::ConClash {
+                expected,
+                actual,
+                con: con_name(&l).unwrap_or_default(),
+                con_: con_name(&r).unwrap_or_default(),
+            },
+            UnifyError::MetaSpine(..) | UnifyError::MetaInvert(_) | UnifyError::MetaUnify(..) => {
+                ErrorKind::Mismatch { expected, actual }
+            }
+        };
+
+        Error::new(metas, cxt, kind)
+    }
+
+    /// Level constraint that made the universe levels inconsistent
+    pub fn universe(c: LevelConstraint) -> Self {
+        Error(Box::new(ErrorInner {
+            backtrace: Backtrace::capture(),
+            pos: c.pos.unwrap_or_default(),
+            cxt: Vec::new(),
+            kind: ErrorKind::Universe {
+                lhs: c.lhs,
+                rhs: c.rhs,
+            },
+        }))
+    }
+
+    /// An unsolved meta, positioned where it was created, in the local context
+    /// it was created in
+    pub fn unsolved(metas: &mut MetaCxt, meta: MetaVar) -> Self {
+        let cxt = metas.origin_cxt(meta);
+        let origin = metas.origin(meta);
+        Error(Box::new(ErrorInner {
+            backtrace: Backtrace::capture(),
+            pos: origin.pos.clone(),
+            cxt,
+            kind: ErrorKind::UnsolvedMeta {
+                meta,
+                names: origin.names.clone(),
+                ty: origin.ty.clone(),
+            },
+        }))
+    }
+
+    /// The binder of `name` at `pos`, whose variable is never used
+    pub fn unused_binder(pos: Span, name: Name) -> Self {
+        Error(Box::new(ErrorInner {
+            backtrace: Backtrace::capture(),
+            pos,
+            cxt: Vec::new(),
+            kind: ErrorKind::UnusedBinder { name },
+        }))
+    }
+
+    /// The binder of `name` at `pos`, which shadows a variable of the same
+    /// name
+    pub fn shadowing(pos: Span, name: Name) -> Self {
+        Error(Box::new(ErrorInner {
+            backtrace: Backtrace::capture(),
+            pos,
+            cxt: Vec::new(),
+            kind: ErrorKind::Shadowing { name },
+        }))
+    }
+
+    /// The pragma `name` at `pos`, which isn't known
+    pub fn unknown_pragma(pos: Span, name: Name) -> Self {
+        Error(Box::new(ErrorInner {
+            backtrace: Backtrace::capture(),
+            pos,
+            cxt: Vec::new(),
+            kind: ErrorKind::UnknownPragma { name },
+        }))
+    }
+
+    /// The pragma at `pos` setting the severity of `name`, which isn't a lint
+    pub fn unknown_lint(pos: Span, name: Name) -> Self {
+        Error(Box::new(ErrorInner {
+            backtrace: Backtrace::capture(),
+            pos,
+            cxt: Vec::new(),
+            kind: ErrorKind::UnknownLint { name },
+        }))
+    }
+
+    /// The hole of `meta`, positioned where it was written, in the local
+    /// context it was written in, whose type is unknown
+    pub fn unconstrained_hole(metas: &mut MetaCxt, meta: MetaVar) -> Self {
+        let cxt = metas.origin_cxt(meta);
+        let origin = metas.origin(meta);
+        Error(Box::new(ErrorInner {
+            backtrace: Backtrace::capture(),
+            pos: origin.pos.clone(),
+            cxt,
+            kind: ErrorKind::UnconstrainedHole {
+                name: origin.hole.clone().unwrap_or_else(|| "_".into()),
+            },
+        }))
+    }
+
+    /// A postponed unification problem that was never solved
+    pub fn stuck(metas: &mut MetaCxt, p: Postponed) -> Self {
+        let mut names = p.names;
+        for x in names.len()..p.lvl {
+            names.push(format!("x{x}").into());
+        }
+
+        Error(Box::new(ErrorInner {
+            backtrace: Backtrace::capture(),
+            pos: p.pos.unwrap_or_default(),
+            cxt: Vec::new(),
+            kind: ErrorKind::Stuck {
+                meta: p.blocker,
+                names,
+                expected: quote(metas, p.lvl, p.lhs),
+                actual: quote(metas, p.lvl, p.rhs),
+            },
+        }))
+    }
+}
+
+/// Name of the variable of level `x` of `cxt` the way the printers write it,
+/// or `x{x}` for a variable bound inside the sides of a unification
+fn var_name(cxt: &Cxt, x: Lvl) -> Name {
+    match cxt.types().get(x) {
+        Some((name, _)) => name.clone(),
+        None => format!("x{x}").into(),
+    }
+}
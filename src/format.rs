@@ -0,0 +1,455 @@
+//! Source formatter: files parsed and printed back with a canonical layout.
+//!
+//! Declarations are printed the way they are written rather than the way
+//! [`Raw`]'s `Display` shows them, so that the result parses to the same
+//! program. Line breaks outside of parentheses and braces end declarations
+//! and `let`s, so long terms are only broken inside them.
+//!
+//! Comments are kept: those between declarations and at the ends of their
+//! last lines are moved along with them, and a declaration with comments
+//! inside of it is left as it was written.
+
+use std::fmt::Write;
+
+use crate::{
+    incremental::chunks,
+    named_binder,
+    parser::{parse_program_at, strip_comments, ParseError},
+    pretty::{DocBuilder, INDENT},
+    span::FileId,
+    Binder, Decl, Icit, PrintOptions, Raw,
+};
+
+const ATOM_P: u8 = 3;
+const APP_P: u8 = 2;
+const PI_P: u8 = 1;
+const LET_P: u8 = 0;
+
+/// Format a source file, or return its syntax errors.
+pub fn format_source(src: &str) -> Result<String, Vec<ParseError>> {
+    let stripped: Vec<char> = strip_comments(src).chars().collect();
+    let chars: Vec<char> = src.chars().collect();
+    let width = PrintOptions::default().width;
+
+    let mut out = Output::default();
+    let mut errors = vec![];
+
+    for chunk in chunks(src) {
+        let text: String = chars[chunk.clone()].iter().collect();
+        let code: String = stripped[chunk.clone()].iter().collect();
+        let (decls, errs) = parse_program_at(FileId::default(), &text, chunk.start);
+        errors.extend(errs);
+
+        let lines: Vec<&str> = text.lines().collect();
+        let code_lines: Vec<&str> = code.lines().collect();
+        let first_code = code_lines.iter().position(|l| !l.trim().is_empty());
+        let last_code = code_lines.iter().rposition(|l| !l.trim().is_empty());
+
+        // the comments before the first declaration
+        for line in &lines[..first_code.unwrap_or(lines.len())] {
+            out.comment_or_blank(line);
+        }
+
+        if let (Some(first), Some(last)) = (first_code, last_code) {
+            match decl_comments(&lines[first..=last], &code_lines[first..=last]) {
+                Some(comment) => {
+                    let mut printed = vec![];
+                    for d in &decls {
+                        let mut doc = DocBuilder::new();
+                        decl(d, &mut doc).unwrap();
+                        let mut s = String::new();
+                        doc.finish().render(width, &mut s).unwrap();
+                        printed.push(s);
+                    }
+                    let mut printed = printed.join("\n\n");
+                    if let Some(comment) = comment {
+                        write!(printed, " {comment}").unwrap();
+                    }
+                    out.decl(printed.lines());
+                }
+                // left as it is
+                None => out.decl(lines[first..=last].iter().copied()),
+            }
+        }
+
+        let rest = last_code.map_or(lines.len(), |last| last + 1);
+        for line in &lines[rest..] {
+            out.comment_or_blank(line);
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok(out.finish()),
+        false => Err(errors),
+    }
+}
+
+/// The comment at the end of the last line of a declaration, or `None` if it
+/// has comments anywhere else.
+fn decl_comments<'a>(lines: &[&'a str], code_lines: &[&str]) -> Option<Option<&'a str>> {
+    let (last, lines) = lines.split_last().unwrap();
+    let (last_code, code_lines) = code_lines.split_last().unwrap();
+    if lines.iter().zip(code_lines).any(|(l, c)| l != c) {
+        return None;
+    }
+
+    let Some(start) = last
+        .chars()
+        .zip(last_code.chars())
+        .position(|(l, c)| l != c)
+    else {
+        return Some(None);
+    };
+    let (code, comment) = last.split_at(last.char_indices().nth(start).unwrap().0);
+    let rest: String = last_code.chars().skip(code.chars().count()).collect();
+    rest.trim().is_empty().then(|| Some(comment.trim_end()))
+}
+
+/// Lines of formatted source, with single blank lines between declarations
+/// and where there were blank lines before.
+#[derive(Default)]
+struct Output {
+    lines: Vec<String>,
+    /// whether the last line written was part of a declaration
+    after_decl: bool,
+    /// whether there were blank lines since the last line written
+    blank: bool,
+}
+
+impl Output {
+    fn decl<'a>(&mut self, lines: impl Iterator<Item = &'a str>) {
+        self.separate(self.blank || self.after_decl);
+        self.lines.extend(lines.map(|l| l.trim_end().to_owned()));
+        self.after_decl = true;
+    }
+
+    fn comment_or_blank(&mut self, line: &str) {
+        if line.trim().is_empty() {
+            self.blank = true;
+            return;
+        }
+        self.separate(self.blank);
+        self.lines.push(line.trim_end().to_owned());
+        self.after_decl = false;
+    }
+
+    fn separate(&mut self, blank: bool) {
+        if blank && !self.lines.is_empty() {
+            self.lines.push(String::new());
+        }
+        self.blank = false;
+    }
+
+    fn finish(self) -> String {
+        let mut out = self.lines.join("\n");
+        out.push('\n');
+        out
+    }
+}
+
+fn decl(d: &Decl, f: &mut DocBuilder) -> std::fmt::Result {
+    match d {
+        Decl::DDef(x, a, t) => def("def", x, a, t, f),
+        Decl::DInstance(x, a, t) => def("instance", x, a, t, f),
+        Decl::DOpaque(x, a, t) => def("opaque", x, a, t, f),
+        Decl::DAxiom(x, a) => f.nest(INDENT, |f| -> std::fmt::Result {
+            write!(f, "axiom {x} : ")?;
+            raw(LET_P, a, f, false)
+        }),
+        Decl::DImport(path) => write!(f, "import {path}"),
+        Decl::DPragma(p, d) => {
+            write!(f, "#[{p}]")?;
+            f.hard_line();
+            decl(d, f)
+        }
+        Decl::DFilePragma(p) => write!(f, "#![{p}]"),
+        Decl::DData(x, params, a, cons) | Decl::DRecord(x, params, a, cons) => {
+            let keyword = match d {
+                Decl::DData(..) => "data",
+                _ => "record",
+            };
+            write!(f, "{keyword} {x}")?;
+            for (y, b) in params {
+                write!(f, " ({y} : ")?;
+                raw(LET_P, b, f, true)?;
+                write!(f, ")")?;
+            }
+            write!(f, " : ")?;
+            raw(LET_P, a, f, false)?;
+            f.nest(INDENT, |f| -> std::fmt::Result {
+                for (c, b) in cons {
+                    f.hard_line();
+                    write!(f, "| {c} : ")?;
+                    raw(LET_P, b, f, false)?;
+                }
+                Ok(())
+            })
+        }
+        Decl::DMutual(data, defs) => {
+            write!(f, "mutual")?;
+            f.nest(INDENT, |f| -> std::fmt::Result {
+                for (x, params, a, cons) in data {
+                    f.hard_line();
+                    let data = Decl::DData(x.clone(), params.clone(), a.clone(), cons.clone());
+                    decl(&data, f)?;
+                }
+                for (x, a, t) in defs {
+                    f.hard_line();
+                    def("def", x, a, t, f)?;
+                }
+                Ok(())
+            })
+        }
+    }
+}
+
+fn def(keyword: &str, x: &str, a: &Raw, t: &Raw, f: &mut DocBuilder) -> std::fmt::Result {
+    f.nest(INDENT, |f| -> std::fmt::Result {
+        write!(f, "{keyword} {x} : ")?;
+        raw(LET_P, a, f, false)?;
+        write!(f, " := ")?;
+        raw(LET_P, t, f, false)
+    })
+}
+
+fn prec(raw: &Raw) -> u8 {
+    match raw {
+        Raw::RSrcPos(_, raw) => prec(raw),
+        Raw::RLam(..) | Raw::RLet(..) | Raw::RLetRec(..) | Raw::RIf(..) | Raw::RMatch(..) => LET_P,
+        Raw::RPi(..) | Raw::RSigma(..) => PI_P,
+        Raw::RApp(..) | Raw::RAppNamed(..) => APP_P,
+        _ => ATOM_P,
+    }
+}
+
+/// Print `raw` at precedence `p`, in parentheses if it binds more loosely.
+/// Lines are only broken `inside` parentheses or braces.
+fn raw(p: u8, raw: &Raw, f: &mut DocBuilder, inside: bool) -> std::fmt::Result {
+    if prec(raw) >= p {
+        return print(raw, f, inside);
+    }
+    write!(f, "(")?;
+    f.nest(1, |f| print(raw, f, true))?;
+    write!(f, ")")
+}
+
+/// A space, or a line break where one is allowed
+fn space(f: &mut DocBuilder, inside: bool) -> std::fmt::Result {
+    match inside {
+        true => f.line(),
+        false => write!(f, " ")?,
+    }
+    Ok(())
+}
+
+fn print(t: &Raw, f: &mut DocBuilder, inside: bool) -> std::fmt::Result {
+    match t {
+        Raw::RSrcPos(_, t) => print(t, f, inside),
+        Raw::RVar(x) => write!(f, "{x}"),
+        Raw::RHole(None) => write!(f, "_"),
+        Raw::RHole(Some(x)) => write!(f, "?{x}"),
+        Raw::RU(None) => write!(f, "U"),
+        Raw::RU(Some(n)) => write!(f, "U{n}"),
+        Raw::RNatLit(n) => write!(f, "{n}"),
+        Raw::RStrLit(s) => write!(f, "{s:?}"),
+        Raw::RCharLit(c) => write!(f, "{c:?}"),
+        Raw::RIntLit(n) => write!(f, "{n}"),
+        // the binders of nested lambdas after one `λ`
+        Raw::RLam(x, i, t) => f.block(|f| -> std::fmt::Result {
+            write!(f, "λ {}", Binder(x, *i))?;
+            let mut t = t;
+            loop {
+                match &**t {
+                    Raw::RSrcPos(_, t_) => t = t_,
+                    Raw::RLam(x, i, t_) => {
+                        write!(f, " {}", Binder(x, *i))?;
+                        t = t_;
+                    }
+                    _ => break,
+                }
+            }
+            write!(f, ".")?;
+            space(f, inside)?;
+            raw(LET_P, t, f, inside)
+        }),
+        Raw::RPi(..) => f.block(|f| -> std::fmt::Result {
+            let mut t = t;
+            // whether the last domain was a binder with a name
+            let mut named = None;
+
+            loop {
+                match t {
+                    Raw::RSrcPos(_, t_) => t = t_,
+                    Raw::RPi(x, i, a, b) => {
+                        let dependent = named_binder(x, *i);
+                        match named {
+                            None => {}
+                            Some(true) if dependent => {}
+                            Some(_) => {
+                                space(f, inside)?;
+                                write!(f, "→ ")?;
+                            }
+                        }
+
+                        if dependent {
+                            let (xs, b) = t.pi_group();
+                            let (l, r) = i.delims();
+                            write!(f, "{l}{} : ", xs.join(" "))?;
+                            raw(LET_P, a, f, true)?;
+                            write!(f, "{r}")?;
+                            t = b;
+                        } else {
+                            raw(APP_P, a, f, inside)?;
+                            t = b;
+                        }
+
+                        named = Some(dependent);
+                    }
+                    _ => {
+                        space(f, inside)?;
+                        write!(f, "→ ")?;
+                        break raw(PI_P, t, f, inside);
+                    }
+                }
+            }
+        }),
+        Raw::RSigma(x, a, b) => f.block(|f| -> std::fmt::Result {
+            match &**x {
+                "_" => raw(APP_P, a, f, inside)?,
+                _ => {
+                    write!(f, "({x} : ")?;
+                    raw(LET_P, a, f, true)?;
+                    write!(f, ")")?;
+                }
+            }
+            space(f, inside)?;
+            write!(f, "× ")?;
+            raw(PI_P, b, f, inside)
+        }),
+        Raw::RPair(t, u) => {
+            write!(f, "(")?;
+            f.group(|f| {
+                f.nest(1, |f| -> std::fmt::Result {
+                    raw(LET_P, t, f, true)?;
+
+                    // right-nested pairs are one tuple
+                    let mut u = u;
+                    while let Raw::RPair(t, u_) = &**u {
+                        write!(f, ",")?;
+                        f.line();
+                        raw(LET_P, t, f, true)?;
+                        u = u_;
+                    }
+
+                    write!(f, ",")?;
+                    f.line();
+                    raw(LET_P, u, f, true)
+                })
+            })?;
+            write!(f, ")")
+        }
+        // the body on the next line
+        Raw::RLet(x, a, d, u) | Raw::RLetRec(x, a, d, u) => {
+            let rec = match t {
+                Raw::RLetRec(..) => "rec ",
+                _ => "",
+            };
+            write!(f, "let {rec}{x} : ")?;
+            raw(LET_P, a, f, inside)?;
+            write!(f, " := ")?;
+            raw(LET_P, d, f, inside)?;
+            f.hard_line();
+            raw(LET_P, u, f, inside)
+        }
+        Raw::RApp(..) | Raw::RAppNamed(..) => {
+            // the whole spine, so that its arguments break alike
+            let mut spine = vec![];
+            let mut t = t;
+            loop {
+                match t {
+                    Raw::RSrcPos(_, t_) => t = t_,
+                    Raw::RApp(t_, _, _) | Raw::RAppNamed(t_, _, _) => {
+                        spine.push(t);
+                        t = t_;
+                    }
+                    _ => break,
+                }
+            }
+
+            f.block(|f| -> std::fmt::Result {
+                raw(APP_P, t, f, inside)?;
+                for app in spine.into_iter().rev() {
+                    space(f, inside)?;
+                    match app {
+                        Raw::RApp(_, u, Icit::Expl) => raw(ATOM_P, u, f, inside)?,
+                        Raw::RApp(_, u, i) => {
+                            let (l, r) = i.delims();
+                            write!(f, "{l}")?;
+                            raw(LET_P, u, f, true)?;
+                            write!(f, "{r}")?;
+                        }
+                        Raw::RAppNamed(_, x, u) => {
+                            write!(f, "{{{x} = ")?;
+                            raw(LET_P, u, f, true)?;
+                            write!(f, "}}")?;
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+                Ok(())
+            })
+        }
+        Raw::RProj1(t) => {
+            raw(ATOM_P, t, f, inside)?;
+            write!(f, ".1")
+        }
+        Raw::RProj2(t) => {
+            raw(ATOM_P, t, f, inside)?;
+            write!(f, ".2")
+        }
+        Raw::RField(t, x) => {
+            raw(ATOM_P, t, f, inside)?;
+            write!(f, ".{x}")
+        }
+        Raw::RRecordLit(fields) => f.group(|f| -> std::fmt::Result {
+            write!(f, "record {{")?;
+            f.nest(INDENT, |f| -> std::fmt::Result {
+                for (k, (x, t)) in fields.iter().enumerate() {
+                    if k > 0 {
+                        write!(f, ",")?;
+                    }
+                    f.line();
+                    write!(f, "{x} = ")?;
+                    raw(LET_P, t, f, true)?;
+                }
+                Ok(())
+            })?;
+            f.line();
+            write!(f, "}}")
+        }),
+        Raw::RIf(b, t, e) => f.block(|f| -> std::fmt::Result {
+            write!(f, "if ")?;
+            raw(LET_P, b, f, inside)?;
+            space(f, inside)?;
+            write!(f, "then ")?;
+            raw(LET_P, t, f, inside)?;
+            space(f, inside)?;
+            write!(f, "else ")?;
+            raw(LET_P, e, f, inside)
+        }),
+        // a branch to a line
+        Raw::RMatch(t, branches) => {
+            write!(f, "match ")?;
+            raw(LET_P, t, f, inside)?;
+            write!(f, " with")?;
+            f.nest(INDENT, |f| -> std::fmt::Result {
+                for (pat, u) in branches {
+                    f.hard_line();
+                    write!(f, "| {pat} => ")?;
+                    raw(LET_P, u, f, inside)?;
+                }
+                Ok(())
+            })
+        }
+    }
+}
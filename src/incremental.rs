@@ -0,0 +1,235 @@
+//! Incremental parsing of source files, for editors.
+//!
+//! A source file is split into chunks at the lines that start without
+//! indentation, one for each top-level declaration. After an edit only the
+//! chunks it touches are parsed again, along with those that had syntax
+//! errors; the others are kept, moved along by the change in length.
+
+use std::ops::Range;
+
+use crate::{
+    parser::{parse_program_at, strip_comments, ParseError},
+    span::{FileId, Span},
+    Decl, Name, Pragma, Program, Raw,
+};
+
+/// Replacement of the characters `range` of a source by `text`
+#[derive(Debug, Clone)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+/// Parsed source file that can be parsed again after an edit
+#[derive(Debug, Clone)]
+pub struct ParseResult {
+    file: FileId,
+    source: String,
+    chunks: Vec<Chunk>,
+}
+
+#[derive(Debug, Clone)]
+struct Chunk {
+    /// characters of the source it covers
+    span: Range<usize>,
+    decls: Program,
+    errors: Vec<ParseError>,
+}
+
+impl ParseResult {
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The declarations that parsed, in order.
+    pub fn program(&self) -> Program {
+        self.chunks.iter().flat_map(|c| c.decls.clone()).collect()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &ParseError> {
+        self.chunks.iter().flat_map(|c| &c.errors)
+    }
+}
+
+/// Parse a whole source file, recovering from syntax errors.
+pub fn parse(file: FileId, source: String) -> ParseResult {
+    let chunks = chunks(&source)
+        .into_iter()
+        .map(|span| parse_chunk(file, &source, span))
+        .collect();
+    ParseResult {
+        file,
+        source,
+        chunks,
+    }
+}
+
+/// Parse the source of `old` with `edit` applied, reusing the declarations
+/// that it doesn't touch.
+pub fn reparse(old: &ParseResult, edit: TextEdit) -> ParseResult {
+    let TextEdit { range, text } = edit;
+    let mut source: String = old.source.chars().take(range.start).collect();
+    source.push_str(&text);
+    source.extend(old.source.chars().skip(range.end));
+
+    let end = range.start + text.chars().count();
+    let delta = end as isize - range.end as isize;
+
+    let chunks = chunks(&source)
+        .into_iter()
+        .map(|span| {
+            // where the chunk was before the edit, if it is outside of it
+            let old_span = if span.end <= range.start {
+                Some(span.clone())
+            } else if span.start >= end {
+                Some(shift_range(&span, -delta))
+            } else {
+                None
+            };
+            let reused = old_span.and_then(|old_span| {
+                let i = old
+                    .chunks
+                    .binary_search_by_key(&old_span.start, |c| c.span.start)
+                    .ok()?;
+                let chunk = &old.chunks[i];
+                (chunk.span == old_span && chunk.errors.is_empty()).then_some(chunk)
+            });
+
+            match reused {
+                Some(chunk) => Chunk {
+                    decls: chunk.decls.iter().map(|d| shift_decl(d, delta)).collect(),
+                    span,
+                    errors: Vec::new(),
+                },
+                None => parse_chunk(old.file, &source, span),
+            }
+        })
+        .collect();
+
+    ParseResult {
+        file: old.file,
+        source,
+        chunks,
+    }
+}
+
+fn parse_chunk(file: FileId, source: &str, span: Range<usize>) -> Chunk {
+    let text: String = source.chars().take(span.end).skip(span.start).collect();
+    let (decls, errors) = parse_program_at(file, &text, span.start);
+    Chunk {
+        span,
+        decls,
+        errors,
+    }
+}
+
+/// The chunks of `source`: from the start of each line that starts without
+/// indentation and outside of a comment to the start of the next one. A
+/// pragma `#[..]` stays in the chunk of the declaration after it.
+pub(crate) fn chunks(source: &str) -> Vec<Range<usize>> {
+    let stripped: Vec<char> = strip_comments(source).chars().collect();
+    let mut starts = vec![0];
+    let mut line_start = true;
+    for (i, &c) in stripped.iter().enumerate() {
+        let after_pragma = stripped[starts[starts.len() - 1]..].starts_with(&['#', '[']);
+        if line_start && i > 0 && !c.is_whitespace() && !after_pragma {
+            starts.push(i);
+        }
+        line_start = c == '\n';
+    }
+
+    let len = stripped.len();
+    let ends = starts.iter().skip(1).copied().chain([len]);
+    starts.iter().zip(ends).map(|(&s, e)| s..e).collect()
+}
+
+fn shift_range(range: &Range<usize>, delta: isize) -> Range<usize> {
+    range.start.wrapping_add_signed(delta)..range.end.wrapping_add_signed(delta)
+}
+
+fn shift_decl(d: &Decl, delta: isize) -> Decl {
+    let go = |t: &Raw| shift_raw(t, delta);
+    let go_all = |ts: &Vec<(Name, Raw)>| -> Vec<(Name, Raw)> {
+        ts.iter().map(|(x, t)| (x.clone(), go(t))).collect()
+    };
+
+    match d {
+        Decl::DDef(x, a, t) => Decl::DDef(x.clone(), go(a), go(t)),
+        Decl::DInstance(x, a, t) => Decl::DInstance(x.clone(), go(a), go(t)),
+        Decl::DOpaque(x, a, t) => Decl::DOpaque(x.clone(), go(a), go(t)),
+        Decl::DAxiom(x, a) => Decl::DAxiom(x.clone(), go(a)),
+        Decl::DImport(path) => Decl::DImport(path.clone()),
+        Decl::DPragma(p, d) => {
+            let shift = |pos: &Span| Span::new(pos.file, shift_range(&pos.range(), delta));
+            let p = Pragma {
+                pos: shift(&p.pos),
+                scope: shift(&p.scope),
+                ..p.clone()
+            };
+            Decl::DPragma(p, Box::new(shift_decl(d, delta)))
+        }
+        // the scope is the whole file, wherever the pragma is
+        Decl::DFilePragma(p) => Decl::DFilePragma(Pragma {
+            pos: Span::new(p.pos.file, shift_range(&p.pos.range(), delta)),
+            ..p.clone()
+        }),
+        Decl::DData(x, params, a, cons) => {
+            Decl::DData(x.clone(), go_all(params), go(a), go_all(cons))
+        }
+        Decl::DRecord(x, params, a, fields) => {
+            Decl::DRecord(x.clone(), go_all(params), go(a), go_all(fields))
+        }
+        Decl::DMutual(data, defs) => Decl::DMutual(
+            data.iter()
+                .map(|(x, params, a, cons)| (x.clone(), go_all(params), go(a), go_all(cons)))
+                .collect(),
+            defs.iter()
+                .map(|(x, a, t)| (x.clone(), go(a), go(t)))
+                .collect(),
+        ),
+    }
+}
+
+/// `t` with its source positions moved by `delta` characters.
+fn shift_raw(t: &Raw, delta: isize) -> Raw {
+    let go = |t: &Raw| Box::new(shift_raw(t, delta));
+
+    match t {
+        Raw::RSrcPos(pos, t) => {
+            let pos = Span::new(pos.file, shift_range(&pos.range(), delta));
+            Raw::RSrcPos(pos, go(t))
+        }
+        Raw::RLam(x, i, t) => Raw::RLam(x.clone(), *i, go(t)),
+        Raw::RApp(t, u, i) => Raw::RApp(go(t), go(u), *i),
+        Raw::RAppNamed(t, x, u) => Raw::RAppNamed(go(t), x.clone(), go(u)),
+        Raw::RPi(x, i, a, b) => Raw::RPi(x.clone(), *i, go(a), go(b)),
+        Raw::RSigma(x, a, b) => Raw::RSigma(x.clone(), go(a), go(b)),
+        Raw::RPair(t, u) => Raw::RPair(go(t), go(u)),
+        Raw::RLet(x, a, t, u) => Raw::RLet(x.clone(), go(a), go(t), go(u)),
+        Raw::RLetRec(x, a, t, u) => Raw::RLetRec(x.clone(), go(a), go(t), go(u)),
+        Raw::RProj1(t) => Raw::RProj1(go(t)),
+        Raw::RProj2(t) => Raw::RProj2(go(t)),
+        Raw::RIf(b, t, f) => Raw::RIf(go(b), go(t), go(f)),
+        Raw::RMatch(t, branches) => Raw::RMatch(
+            go(t),
+            branches
+                .iter()
+                .map(|(pat, u)| (pat.clone(), shift_raw(u, delta)))
+                .collect(),
+        ),
+        Raw::RField(t, x) => Raw::RField(go(t), x.clone()),
+        Raw::RRecordLit(fields) => Raw::RRecordLit(
+            fields
+                .iter()
+                .map(|(x, t)| (x.clone(), shift_raw(t, delta)))
+                .collect(),
+        ),
+        Raw::RVar(_)
+        | Raw::RU(_)
+        | Raw::RHole(_)
+        | Raw::RNatLit(_)
+        | Raw::RStrLit(_)
+        | Raw::RCharLit(_)
+        | Raw::RIntLit(_) => t.clone(),
+    }
+}
@@ -0,0 +1,168 @@
+//! Instance arguments `{{x : C a}}`, found by searching the instances in scope
+//! rather than by unification.
+//!
+//! The instances are the definitions declared with `instance` and the instance
+//! arguments bound around the meta. An instance fits when its type, after
+//! inserting its own implicit and instance arguments, unifies with the goal.
+//! The goal's metas have to be solved by the rest of the definition first, so
+//! the search is retried whenever a meta is solved, and only takes an
+//! instance that fits without solving any of them until the definition is
+//! done.
+
+use std::borrow::Cow;
+
+use crate::{
+    error::{Error, ErrorKind},
+    eval, eval_closure, force, force_metas,
+    metas::{unify, MetaCxt, MetaEntry, MetaVar},
+    quote, v_app, zonk, Cxt, Icit, Term, Type, Value,
+};
+
+/// How deep instances needing instances in turn are searched for before
+/// giving up, so that instances needing themselves don't loop
+const MAX_DEPTH: usize = 32;
+
+/// Instance argument `meta : goal` waiting to be found
+#[derive(Debug, Clone)]
+pub struct InstanceProblem {
+    pub meta: MetaVar,
+    pub goal: Type,
+    /// the meta applied to the bound variables of `cxt`
+    val: Value,
+    /// the context the meta was inserted in
+    cxt: Cxt,
+    /// number of instances this one is needed by
+    depth: usize,
+}
+
+/// New meta for an instance argument of type `ty`, found right away if its
+/// type is known.
+pub(crate) fn fresh_instance(metas: &mut MetaCxt, cxt: &Cxt, ty: Type) -> Term {
+    let m = fresh(metas, cxt, ty, 0);
+    resolve(metas);
+    m
+}
+
+fn fresh(metas: &mut MetaCxt, cxt: &Cxt, goal: Type, depth: usize) -> Term {
+    let m = metas.fresh_meta(cxt, goal.clone());
+    let Term::TInsertedMeta(meta, _) = m else {
+        unreachable!("fresh metas are inserted ones")
+    };
+    let val = eval(metas, Cow::Borrowed(&cxt.env), m.clone());
+    metas.push_instance(InstanceProblem {
+        meta,
+        goal,
+        val,
+        cxt: cxt.clone(),
+        depth,
+    });
+    m
+}
+
+/// Find the instance arguments that an instance fits without solving metas of
+/// their goals, until there are no more.
+pub fn resolve(metas: &mut MetaCxt) {
+    if !metas.start_search() {
+        return;
+    }
+
+    let mut found = true;
+    while found {
+        found = false;
+        for p in metas.take_instances() {
+            if matches!(metas[p.meta], MetaEntry::Solved(_)) {
+                continue;
+            }
+            match search(metas, &p, false) {
+                Some(solved) => {
+                    *metas = solved;
+                    found = true;
+                }
+                None => metas.push_instance(p),
+            }
+        }
+    }
+
+    metas.end_search();
+}
+
+/// Find the instance arguments left once a definition is done, now with
+/// instances that solve metas of their goals too.
+pub fn check_found(metas: &mut MetaCxt) -> Result<(), Error> {
+    resolve(metas);
+
+    while let Some(p) = metas.instances().first().cloned() {
+        if !matches!(metas[p.meta], MetaEntry::Solved(_)) {
+            metas.start_search();
+            let solved = search(metas, &p, true);
+            metas.end_search();
+
+            let Some(mut solved) = solved else {
+                // the rest are given up on along with `p`
+                metas.take_instances();
+                let ty = quote(metas, p.cxt.lvl, p.goal);
+                let ty = zonk(metas, &p.cxt, ty);
+                let kind = ErrorKind::NoInstance { meta: p.meta, ty };
+                return Err(Error::new(metas, &p.cxt, kind));
+            };
+            solved.end_search();
+            *metas = solved;
+        }
+        // drops `p` along with the others found by now
+        resolve(metas);
+    }
+
+    Ok(())
+}
+
+/// The metas with `p` solved by the latest instance in its scope that fits
+/// it, if any. Unless `refine`, the instance mustn't solve metas created
+/// before. The instance arguments the instance needs are waiting in them.
+/// The instances that don't fit are rolled back, as their copies of the
+/// metas share the thunks they forced.
+fn search(metas: &MetaCxt, p: &InstanceProblem, refine: bool) -> Option<MetaCxt> {
+    p.cxt.instances.iter().rev().find_map(|&x| {
+        let snapshot = metas.snapshot();
+        let mut trial = metas.clone();
+        let val = force_metas(&mut trial, p.cxt.env[x].clone());
+        let ty = p.cxt.types[x].1.clone();
+
+        let fits = fits(&mut trial, p, val, ty)
+            && (refine || (0..metas.len()).all(|m| solves(metas, &trial, m) == (m == p.meta)));
+        if !fits {
+            trial.rollback(snapshot);
+            return None;
+        }
+        Some(trial)
+    })
+}
+
+/// Whether `m`, unsolved in `before`, is solved in `after`.
+fn solves(before: &MetaCxt, after: &MetaCxt, m: MetaVar) -> bool {
+    matches!(before[m], MetaEntry::Unsolved) && matches!(after[m], MetaEntry::Solved(_))
+}
+
+/// Solve `p` with the instance `val : ty` applied to fresh metas for its
+/// implicit and instance arguments, if its type unifies with the goal.
+fn fits(metas: &mut MetaCxt, p: &InstanceProblem, mut val: Value, mut ty: Type) -> bool {
+    loop {
+        match force(metas, ty) {
+            Value::VΠ(_, Icit::Inst, _, _) if p.depth == MAX_DEPTH => return false,
+            Value::VΠ(_, i @ (Icit::Impl | Icit::Inst), a, b) => {
+                let a = (*a).clone();
+                let m = match i {
+                    Icit::Inst => fresh(metas, &p.cxt, a, p.depth + 1),
+                    _ => metas.fresh_meta(&p.cxt, a),
+                };
+                let m = eval(metas, Cow::Borrowed(&p.cxt.env), m);
+                val = v_app(metas, val, m.clone(), i);
+                ty = eval_closure(metas, b, m);
+            }
+            ty => {
+                let lvl = p.cxt.lvl;
+                return unify(metas, lvl, ty, p.goal.clone()).is_ok()
+                    && unify(metas, lvl, p.val.clone(), val).is_ok();
+            }
+        }
+    }
+}
@@ -0,0 +1,723 @@
+//! Interface files: a compact binary encoding of the elaborated definitions of
+//! a module, so that a module whose source didn't change can be loaded
+//! without elaborating it again.
+//!
+//! A file starts with the magic bytes `LEOI`, the format version and a
+//! checksum of the source it was produced from, followed by the zonked
+//! definitions. Numbers are LEB128 varints and terms are written as their
+//! number of nodes followed by the nodes in postorder, each after its
+//! subterms. The body of a definition follows a byte that is 0 for an axiom,
+//! which has none, and 1 otherwise. Data types and records are
+//! written by name and looked up in the context of the module on decoding.
+//! The spans kept in terms aren't written, as the files they are in mean
+//! nothing to another session.
+
+use std::fmt::Display;
+
+use crate::{
+    data::Data, record::Record, Cxt, Definition, Icit, IntOp, Ix, Level, Name, Rc, Term, Tm,
+    Unfolds, BD,
+};
+
+pub const MAGIC: &[u8; 4] = b"LEOI";
+
+/// Version of the format, bumped whenever it changes
+pub const VERSION: u32 = 3;
+
+/// Decoded interface file
+#[derive(Debug, Clone)]
+pub struct Interface {
+    /// checksum of the source it was produced from
+    pub checksum: u64,
+    pub defs: Vec<Definition>,
+}
+
+impl Interface {
+    /// Whether the interface was produced from `source`.
+    pub fn is_current(&self, source: &str) -> bool {
+        self.checksum == checksum(source)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    NotAnInterface,
+    /// produced by another version of the format
+    Version(u32),
+    /// ended in the middle of something
+    Truncated,
+    /// unknown tag of a term, level or other choice
+    Tag(u8),
+    Utf8,
+    /// term with fewer subterms than its nodes need
+    Malformed,
+    UnknownData(Name),
+    UnknownRecord(Name),
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::NotAnInterface => write!(f, "not an interface file"),
+            DecodeError::Version(v) => {
+                write!(f, "interface file of version {v}, expected {VERSION}")
+            }
+            DecodeError::Truncated => write!(f, "truncated interface file"),
+            DecodeError::Tag(t) => write!(f, "unknown tag {t} in interface file"),
+            DecodeError::Utf8 => write!(f, "invalid UTF-8 in interface file"),
+            DecodeError::Malformed => write!(f, "malformed term in interface file"),
+            DecodeError::UnknownData(x) => write!(f, "unknown data type `{x}`"),
+            DecodeError::UnknownRecord(x) => write!(f, "unknown record `{x}`"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// FNV-1a hash of a source file.
+pub fn checksum(source: &str) -> u64 {
+    source.bytes().fold(0xcbf29ce484222325, |h, b| {
+        (h ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Encode the definitions elaborated from `source`.
+pub fn encode(source: &str, defs: &[Definition]) -> Vec<u8> {
+    let mut w = Writer(Vec::new());
+    w.0.extend(MAGIC);
+    w.uint(VERSION as u64);
+    w.0.extend(checksum(source).to_le_bytes());
+    w.uint(defs.len() as u64);
+    for def in defs {
+        w.str(&def.name);
+        w.term(&def.ty);
+        match &def.tm {
+            Some(tm) => {
+                w.0.push(1);
+                w.term(tm);
+            }
+            None => w.0.push(0),
+        }
+    }
+    w.0
+}
+
+/// Decode an interface file, resolving the data types and records it refers
+/// to in `cxt`.
+pub fn decode(cxt: &Cxt, bytes: &[u8]) -> Result<Interface, DecodeError> {
+    let mut r = Reader { cxt, bytes, pos: 0 };
+    if r.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        return Err(DecodeError::NotAnInterface);
+    }
+    let version = r.uint()?;
+    if version != VERSION as u64 {
+        return Err(DecodeError::Version(version as u32));
+    }
+    let checksum = u64::from_le_bytes(r.take(8)?.try_into().unwrap());
+
+    let n = r.uint()?;
+    let mut defs = Vec::new();
+    for _ in 0..n {
+        let name = r.str()?;
+        let ty = r.term()?;
+        let tm = match r.byte()? {
+            0 => None,
+            1 => Some(r.term()?),
+            t => return Err(DecodeError::Tag(t)),
+        };
+        defs.push(Definition { name, ty, tm });
+    }
+    Ok(Interface { checksum, defs })
+}
+
+mod tag {
+    pub const V: u8 = 0;
+    pub const LAM: u8 = 1;
+    pub const PI: u8 = 2;
+    pub const PAIR: u8 = 3;
+    pub const SIGMA: u8 = 4;
+    pub const LET: u8 = 5;
+    pub const META: u8 = 6;
+    pub const INSERTED_META: u8 = 7;
+    pub const APP: u8 = 8;
+    pub const PROJ1: u8 = 9;
+    pub const PROJ2: u8 = 10;
+    pub const U: u8 = 11;
+    pub const NAT: u8 = 12;
+    pub const NAT_LIT: u8 = 13;
+    pub const SUC: u8 = 14;
+    pub const NAT_ELIM: u8 = 15;
+    pub const BOOL: u8 = 16;
+    pub const TRUE: u8 = 17;
+    pub const FALSE: u8 = 18;
+    pub const BOOL_ELIM: u8 = 19;
+    pub const STRING: u8 = 20;
+    pub const STR_LIT: u8 = 21;
+    pub const CHAR: u8 = 22;
+    pub const CHAR_LIT: u8 = 23;
+    pub const APPEND: u8 = 24;
+    pub const LENGTH: u8 = 25;
+    pub const INT: u8 = 26;
+    pub const INT_LIT: u8 = 27;
+    pub const INT_OP: u8 = 28;
+    pub const EQ: u8 = 29;
+    pub const REFL: u8 = 30;
+    pub const J: u8 = 31;
+    pub const DATA: u8 = 32;
+    pub const CON: u8 = 33;
+    pub const ELIM: u8 = 34;
+    pub const FIX: u8 = 35;
+    pub const RECORD: u8 = 36;
+    pub const RECORD_LIT: u8 = 37;
+    pub const FIELD: u8 = 38;
+    pub const LAZY: u8 = 39;
+    pub const DELAY: u8 = 40;
+    pub const FORCE: u8 = 41;
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn uint(&mut self, mut n: u64) {
+        loop {
+            let byte = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                self.0.push(byte);
+                return;
+            }
+            self.0.push(byte | 0x80);
+        }
+    }
+
+    fn int(&mut self, n: i64) {
+        self.uint(((n << 1) ^ (n >> 63)) as u64);
+    }
+
+    fn str(&mut self, s: &str) {
+        self.uint(s.len() as u64);
+        self.0.extend(s.as_bytes());
+    }
+
+    fn icit(&mut self, i: Icit) {
+        self.0.push(match i {
+            Icit::Impl => 0,
+            Icit::Expl => 1,
+            Icit::Inst => 2,
+        });
+    }
+
+    fn level(&mut self, l: Level) {
+        match l {
+            Level::LConst(n) => {
+                self.0.push(0);
+                self.uint(n as u64);
+            }
+            Level::LMeta(l, k) => {
+                self.0.push(1);
+                self.uint(l as u64);
+                self.uint(k as u64);
+            }
+        }
+    }
+
+    fn term(&mut self, t: &Term) {
+        let mut nodes = Writer(Vec::new());
+        let n = nodes.nodes(t);
+        self.uint(n);
+        self.0.extend(nodes.0);
+    }
+
+    /// Write the nodes of `t` in postorder, with an explicit stack like
+    /// evaluation, returning their number.
+    fn nodes(&mut self, t: &Term) -> u64 {
+        enum Step<'a> {
+            Visit(&'a Term),
+            Emit(&'a Term),
+        }
+        let mut n = 0;
+        let mut stack = vec![Step::Visit(t)];
+
+        while let Some(step) = stack.pop() {
+            let t = match step {
+                Step::Visit(Term::TSrcPos(_, t)) => {
+                    stack.push(Step::Visit(t));
+                    continue;
+                }
+                Step::Visit(t) => {
+                    stack.push(Step::Emit(t));
+                    stack.extend(subterms(t).into_iter().rev().map(|t| Step::Visit(t)));
+                    continue;
+                }
+                Step::Emit(t) => t,
+            };
+            n += 1;
+
+            match t {
+                Term::TV(x) => {
+                    self.0.push(tag::V);
+                    self.uint(x.0 as u64);
+                }
+                Term::Tλ(x, i, _) => {
+                    self.0.push(tag::LAM);
+                    self.str(x);
+                    self.icit(*i);
+                }
+                Term::TΠ(x, i, _, _) => {
+                    self.0.push(tag::PI);
+                    self.str(x);
+                    self.icit(*i);
+                }
+                Term::Tσ(..) => self.0.push(tag::PAIR),
+                Term::TΣ(x, _, _) => {
+                    self.0.push(tag::SIGMA);
+                    self.str(x);
+                }
+                Term::TLet(x, _, _, _) => {
+                    self.0.push(tag::LET);
+                    self.str(x);
+                }
+                Term::TMeta(m) => {
+                    self.0.push(tag::META);
+                    self.uint(*m as u64);
+                }
+                Term::TInsertedMeta(m, bds) => {
+                    self.0.push(tag::INSERTED_META);
+                    self.uint(*m as u64);
+                    self.uint(bds.len() as u64);
+                    self.0.extend(bds.iter().map(|bd| match bd {
+                        BD::Bound => 0,
+                        BD::Defined => 1,
+                    }));
+                }
+                Term::TApp(_, _, i) => {
+                    self.0.push(tag::APP);
+                    self.icit(*i);
+                }
+                Term::TProj1(_) => self.0.push(tag::PROJ1),
+                Term::TProj2(_) => self.0.push(tag::PROJ2),
+                Term::TU(l) => {
+                    self.0.push(tag::U);
+                    self.level(*l);
+                }
+                Term::TNat => self.0.push(tag::NAT),
+                Term::TNatLit(n) => {
+                    self.0.push(tag::NAT_LIT);
+                    self.uint(*n);
+                }
+                Term::TSuc(_) => self.0.push(tag::SUC),
+                Term::TNatElim(..) => self.0.push(tag::NAT_ELIM),
+                Term::TBool => self.0.push(tag::BOOL),
+                Term::TTrue => self.0.push(tag::TRUE),
+                Term::TFalse => self.0.push(tag::FALSE),
+                Term::TBoolElim(..) => self.0.push(tag::BOOL_ELIM),
+                Term::TString => self.0.push(tag::STRING),
+                Term::TStrLit(s) => {
+                    self.0.push(tag::STR_LIT);
+                    self.str(s);
+                }
+                Term::TChar => self.0.push(tag::CHAR),
+                Term::TCharLit(c) => {
+                    self.0.push(tag::CHAR_LIT);
+                    self.uint(*c as u64);
+                }
+                Term::TAppend(..) => self.0.push(tag::APPEND),
+                Term::TLength(_) => self.0.push(tag::LENGTH),
+                Term::TInt => self.0.push(tag::INT),
+                Term::TIntLit(n) => {
+                    self.0.push(tag::INT_LIT);
+                    self.int(*n);
+                }
+                Term::TIntOp(op, _, _) => {
+                    self.0.push(tag::INT_OP);
+                    self.0
+                        .push(IntOp::ALL.iter().position(|o| o == op).unwrap() as u8);
+                }
+                Term::TEq(..) => self.0.push(tag::EQ),
+                Term::TRefl(..) => self.0.push(tag::REFL),
+                Term::TJ(..) => self.0.push(tag::J),
+                Term::TLazy(_) => self.0.push(tag::LAZY),
+                Term::TDelay(_) => self.0.push(tag::DELAY),
+                Term::TForce(_) => self.0.push(tag::FORCE),
+                Term::TData(d, args) => {
+                    self.0.push(tag::DATA);
+                    self.str(&d.name);
+                    self.uint(args.len() as u64);
+                }
+                Term::TCon(d, c, args) => {
+                    self.0.push(tag::CON);
+                    self.str(&d.name);
+                    self.uint(*c as u64);
+                    self.uint(args.len() as u64);
+                }
+                Term::TElim(d, args, _) => {
+                    self.0.push(tag::ELIM);
+                    self.str(&d.name);
+                    self.uint(args.len() as u64);
+                }
+                Term::TFix(fix, i) => {
+                    self.0.push(tag::FIX);
+                    self.uint(fix.len() as u64);
+                    for (x, unfolds, _) in fix.iter() {
+                        self.str(x);
+                        // zero for right away, one once eliminated, the
+                        // argument plus two otherwise
+                        self.uint(match unfolds {
+                            Unfolds::Now => 0,
+                            Unfolds::OnElim => 1,
+                            Unfolds::OnArg(k) => *k as u64 + 2,
+                        });
+                    }
+                    self.uint(*i as u64);
+                }
+                Term::TRecord(r, args) => {
+                    self.0.push(tag::RECORD);
+                    self.str(&r.name);
+                    self.uint(args.len() as u64);
+                }
+                Term::TRecordLit(r, args) => {
+                    self.0.push(tag::RECORD_LIT);
+                    self.str(&r.name);
+                    self.uint(args.len() as u64);
+                }
+                Term::TField(r, f, _) => {
+                    self.0.push(tag::FIELD);
+                    self.str(&r.name);
+                    self.uint(*f as u64);
+                }
+                Term::TSrcPos(..) => unreachable!("spans are skipped when visited"),
+            }
+        }
+        n
+    }
+}
+
+/// The subterms of `t`, in order.
+fn subterms(t: &Term) -> Vec<&Tm> {
+    match t {
+        Term::Tλ(_, _, t)
+        | Term::TProj1(t)
+        | Term::TProj2(t)
+        | Term::TSuc(t)
+        | Term::TLength(t)
+        | Term::TLazy(t)
+        | Term::TDelay(t)
+        | Term::TForce(t)
+        | Term::TField(_, _, t)
+        | Term::TSrcPos(_, t) => vec![t],
+        Term::TΠ(_, _, a, b)
+        | Term::TΣ(_, a, b)
+        | Term::Tσ(a, b)
+        | Term::TApp(a, b, _)
+        | Term::TAppend(a, b)
+        | Term::TIntOp(_, a, b)
+        | Term::TRefl(a, b) => vec![a, b],
+        Term::TLet(_, a, t, u) | Term::TEq(a, t, u) => vec![a, t, u],
+        Term::TNatElim(a, b, c, d) | Term::TBoolElim(a, b, c, d) => vec![a, b, c, d],
+        Term::TJ(a, t, p, pr, u, e) => vec![a, t, p, pr, u, e],
+        Term::TData(_, args)
+        | Term::TCon(_, _, args)
+        | Term::TRecord(_, args)
+        | Term::TRecordLit(_, args) => args.iter().collect(),
+        Term::TElim(_, args, t) => args.iter().chain([t]).collect(),
+        Term::TFix(fix, _) => fix.iter().map(|(_, _, t)| t).collect(),
+        Term::TV(_)
+        | Term::TMeta(_)
+        | Term::TInsertedMeta(..)
+        | Term::TU(_)
+        | Term::TNat
+        | Term::TNatLit(_)
+        | Term::TBool
+        | Term::TTrue
+        | Term::TFalse
+        | Term::TString
+        | Term::TStrLit(_)
+        | Term::TChar
+        | Term::TCharLit(_)
+        | Term::TInt
+        | Term::TIntLit(_) => vec![],
+    }
+}
+
+struct Reader<'a> {
+    cxt: &'a Cxt,
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], DecodeError> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos + n)
+            .ok_or(DecodeError::Truncated)?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn uint(&mut self) -> Result<u64, DecodeError> {
+        let mut n = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            n |= ((byte & 0x7f) as u64).checked_shl(shift).unwrap_or(0);
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+            shift += 7;
+        }
+    }
+
+    fn usize(&mut self) -> Result<usize, DecodeError> {
+        Ok(self.uint()? as usize)
+    }
+
+    fn int(&mut self) -> Result<i64, DecodeError> {
+        let n = self.uint()?;
+        Ok((n >> 1) as i64 ^ -((n & 1) as i64))
+    }
+
+    fn str(&mut self) -> Result<Name, DecodeError> {
+        let n = self.usize()?;
+        let bytes = self.take(n)?;
+        let s = std::str::from_utf8(bytes).map_err(|_| DecodeError::Utf8)?;
+        Ok(s.into())
+    }
+
+    fn icit(&mut self) -> Result<Icit, DecodeError> {
+        match self.byte()? {
+            0 => Ok(Icit::Impl),
+            1 => Ok(Icit::Expl),
+            2 => Ok(Icit::Inst),
+            t => Err(DecodeError::Tag(t)),
+        }
+    }
+
+    fn level(&mut self) -> Result<Level, DecodeError> {
+        match self.byte()? {
+            0 => Ok(Level::LConst(self.uint()? as u32)),
+            1 => Ok(Level::LMeta(self.usize()?, self.uint()? as u32)),
+            t => Err(DecodeError::Tag(t)),
+        }
+    }
+
+    fn data(&mut self) -> Result<Rc<Data>, DecodeError> {
+        let x = self.str()?;
+        let data = self.cxt.data().iter().rev().find(|d| d.name == x);
+        data.cloned().ok_or(DecodeError::UnknownData(x))
+    }
+
+    fn record(&mut self) -> Result<Rc<Record>, DecodeError> {
+        let x = self.str()?;
+        let record = self.cxt.records().iter().rev().find(|r| r.name == x);
+        record.cloned().ok_or(DecodeError::UnknownRecord(x))
+    }
+
+    /// Read a term, building each node from the subterms read before it.
+    fn term(&mut self) -> Result<Term, DecodeError> {
+        let mut stack: Vec<Tm> = Vec::new();
+
+        for _ in 0..self.uint()? {
+            let tag = self.byte()?;
+            // the last `n` terms read, in order
+            let mut pop = |n: usize| -> Result<Vec<Tm>, DecodeError> {
+                let from = stack.len().checked_sub(n).ok_or(DecodeError::Malformed)?;
+                Ok(stack.split_off(from))
+            };
+            macro_rules! pop {
+                ($n:literal) => {{
+                    let ts: [Tm; $n] = pop($n)?.try_into().unwrap();
+                    ts
+                }};
+            }
+
+            let t = match tag {
+                tag::V => Term::TV(Ix(self.usize()?)),
+                tag::LAM => {
+                    let (x, i) = (self.str()?, self.icit()?);
+                    let [t] = pop!(1);
+                    Term::Tλ(x, i, t)
+                }
+                tag::PI => {
+                    let (x, i) = (self.str()?, self.icit()?);
+                    let [a, b] = pop!(2);
+                    Term::TΠ(x, i, a, b)
+                }
+                tag::PAIR => {
+                    let [t, u] = pop!(2);
+                    Term::Tσ(t, u)
+                }
+                tag::SIGMA => {
+                    let x = self.str()?;
+                    let [a, b] = pop!(2);
+                    Term::TΣ(x, a, b)
+                }
+                tag::LET => {
+                    let x = self.str()?;
+                    let [a, t, u] = pop!(3);
+                    Term::TLet(x, a, t, u)
+                }
+                tag::META => Term::TMeta(self.usize()?),
+                tag::INSERTED_META => {
+                    let m = self.usize()?;
+                    let n = self.usize()?;
+                    let bds = self
+                        .take(n)?
+                        .iter()
+                        .map(|bd| match bd {
+                            0 => Ok(BD::Bound),
+                            1 => Ok(BD::Defined),
+                            t => Err(DecodeError::Tag(*t)),
+                        })
+                        .collect::<Result<_, _>>()?;
+                    Term::TInsertedMeta(m, bds)
+                }
+                tag::APP => {
+                    let i = self.icit()?;
+                    let [t, u] = pop!(2);
+                    Term::TApp(t, u, i)
+                }
+                tag::PROJ1 => {
+                    let [t] = pop!(1);
+                    Term::TProj1(t)
+                }
+                tag::PROJ2 => {
+                    let [t] = pop!(1);
+                    Term::TProj2(t)
+                }
+                tag::U => Term::TU(self.level()?),
+                tag::NAT => Term::TNat,
+                tag::NAT_LIT => Term::TNatLit(self.uint()?),
+                tag::SUC => {
+                    let [t] = pop!(1);
+                    Term::TSuc(t)
+                }
+                tag::NAT_ELIM => {
+                    let [p, z, s, n] = pop!(4);
+                    Term::TNatElim(p, z, s, n)
+                }
+                tag::BOOL => Term::TBool,
+                tag::TRUE => Term::TTrue,
+                tag::FALSE => Term::TFalse,
+                tag::BOOL_ELIM => {
+                    let [p, t, f, b] = pop!(4);
+                    Term::TBoolElim(p, t, f, b)
+                }
+                tag::STRING => Term::TString,
+                tag::STR_LIT => Term::TStrLit(self.str()?),
+                tag::CHAR => Term::TChar,
+                tag::CHAR_LIT => {
+                    let c = char::from_u32(self.uint()? as u32).ok_or(DecodeError::Utf8)?;
+                    Term::TCharLit(c)
+                }
+                tag::APPEND => {
+                    let [s, t] = pop!(2);
+                    Term::TAppend(s, t)
+                }
+                tag::LENGTH => {
+                    let [s] = pop!(1);
+                    Term::TLength(s)
+                }
+                tag::INT => Term::TInt,
+                tag::INT_LIT => Term::TIntLit(self.int()?),
+                tag::INT_OP => {
+                    let op = self.byte()?;
+                    let op = *IntOp::ALL.get(op as usize).ok_or(DecodeError::Tag(op))?;
+                    let [x, y] = pop!(2);
+                    Term::TIntOp(op, x, y)
+                }
+                tag::EQ => {
+                    let [a, t, u] = pop!(3);
+                    Term::TEq(a, t, u)
+                }
+                tag::REFL => {
+                    let [a, t] = pop!(2);
+                    Term::TRefl(a, t)
+                }
+                tag::J => {
+                    let [a, t, p, pr, u, e] = pop!(6);
+                    Term::TJ(a, t, p, pr, u, e)
+                }
+                tag::LAZY => {
+                    let [a] = pop!(1);
+                    Term::TLazy(a)
+                }
+                tag::DELAY => {
+                    let [t] = pop!(1);
+                    Term::TDelay(t)
+                }
+                tag::FORCE => {
+                    let [t] = pop!(1);
+                    Term::TForce(t)
+                }
+                tag::DATA => {
+                    let (d, n) = (self.data()?, self.usize()?);
+                    Term::TData(d, pop(n)?)
+                }
+                tag::CON => {
+                    let (d, c, n) = (self.data()?, self.usize()?, self.usize()?);
+                    if c >= d.cons.len() {
+                        return Err(DecodeError::Malformed);
+                    }
+                    Term::TCon(d, c, pop(n)?)
+                }
+                tag::ELIM => {
+                    let (d, n) = (self.data()?, self.usize()?);
+                    let mut args = pop(n + 1)?;
+                    let t = args.pop().unwrap();
+                    Term::TElim(d, args, t)
+                }
+                tag::FIX => {
+                    let n = self.usize()?;
+                    let mut heads = Vec::new();
+                    for _ in 0..n {
+                        let x = self.str()?;
+                        let unfolds = match self.usize()? {
+                            0 => Unfolds::Now,
+                            1 => Unfolds::OnElim,
+                            k => Unfolds::OnArg(k - 2),
+                        };
+                        heads.push((x, unfolds));
+                    }
+                    let i = self.usize()?;
+                    if i >= n {
+                        return Err(DecodeError::Malformed);
+                    }
+                    let fix = heads
+                        .into_iter()
+                        .zip(pop(n)?)
+                        .map(|((x, k), t)| (x, k, t))
+                        .collect();
+                    Term::TFix(fix, i)
+                }
+                tag::RECORD => {
+                    let (r, n) = (self.record()?, self.usize()?);
+                    Term::TRecord(r, pop(n)?)
+                }
+                tag::RECORD_LIT => {
+                    let (r, n) = (self.record()?, self.usize()?);
+                    Term::TRecordLit(r, pop(n)?)
+                }
+                tag::FIELD => {
+                    let (r, f) = (self.record()?, self.usize()?);
+                    if f >= r.fields.len() {
+                        return Err(DecodeError::Malformed);
+                    }
+                    let [t] = pop!(1);
+                    Term::TField(r, f, t)
+                }
+                t => return Err(DecodeError::Tag(t)),
+            };
+            stack.push(t.into());
+        }
+
+        match &mut stack[..] {
+            [t] => Ok(Rc::unwrap_or_clone(t.clone())),
+            _ => Err(DecodeError::Malformed),
+        }
+    }
+}
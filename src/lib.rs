@@ -1,39 +1,182 @@
 use std::{
     borrow::Cow,
-    fmt::{Debug, Display},
+    cell::RefCell,
+    collections::HashSet,
+    fmt::{Debug, Display, Write},
     ops::Deref,
-    rc::Rc,
-    sync::atomic::{AtomicUsize, Ordering},
 };
 
-use metas::{unify, Error, MetaCxt, MetaEntry, MetaVar};
-
+use data::{Data, DataDecl};
+use error::{Error, ErrorKind};
+use metas::{LevelVar, MetaCxt, MetaEntry, MetaVar};
+use modules::ModulePath;
+use pretty::{DocBuilder, INDENT};
+use record::Record;
+use span::Span;
+use trace::{ElabTracer, PrintTracer};
+
+pub mod alpha;
+pub mod core;
+pub mod data;
+pub mod diagnostics;
+pub mod elaborator;
+#[macro_use]
+pub mod error;
+pub mod format;
+pub mod incremental;
+pub mod instance;
+pub mod interface;
+pub mod lint;
+pub mod lsp;
 pub mod metas;
+pub mod modules;
 pub mod parser;
+pub mod pretty;
+pub mod record;
+pub mod repl;
+#[cfg(feature = "serde")]
+pub mod serialize;
+pub mod span;
+pub mod stepper;
+pub mod store;
+pub mod subst;
+pub mod termination;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod trace;
+pub mod visit;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+/// The pointer terms, values and names are shared with: [`std::rc::Rc`], or
+/// [`std::sync::Arc`] with the `sync` feature, which makes them `Send` and
+/// `Sync` so that elaboration results can be shared between threads.
+#[cfg(not(feature = "sync"))]
+pub use std::rc::{Rc, Weak};
+#[cfg(feature = "sync")]
+pub use std::sync::{Arc as Rc, Weak};
 
 pub type Name = Rc<str>;
 
-pub type SourcePos = std::ops::Range<usize>;
-
-static LEVEL: AtomicUsize = AtomicUsize::new(0);
+/// Implicit or explicit binder/application
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Icit {
+    Impl,
+    Expl,
+    /// instance argument `{{x : C a}}`, found by searching the instances in
+    /// scope
+    Inst,
+}
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Raw {
     RVar(Name),
-    RLam(Name, Box<Raw>),
-    RApp(Box<Raw>, Box<Raw>),
-    RU,
-    RPi(Name, Box<Raw>, Box<Raw>),
+    RLam(Name, Icit, Box<Raw>),
+    RApp(Box<Raw>, Box<Raw>, Icit),
+    /// application to an implicit argument given by name, `t {x = u}`
+    RAppNamed(Box<Raw>, Name, Box<Raw>),
+    /// `U`, or `Ui` for an explicit level
+    RU(Option<u32>),
+    RPi(Name, Icit, Box<Raw>, Box<Raw>),
+    RSigma(Name, Box<Raw>, Box<Raw>),
+    RPair(Box<Raw>, Box<Raw>),
     RLet(Name, Box<Raw>, Box<Raw>, Box<Raw>),
-    RSrcPos(SourcePos, Box<Raw>),
-    RHole,
+    /// `let rec x : a := t; u`, where `x` is in scope in `t`
+    RLetRec(Name, Box<Raw>, Box<Raw>, Box<Raw>),
+    RSrcPos(Span, Box<Raw>),
+    /// `_`, or the hole `?x` whose goal is reported
+    RHole(Option<Name>),
+    RProj1(Box<Raw>),
+    RProj2(Box<Raw>),
+    /// natural number literal
+    RNatLit(u64),
+    /// `"s"`
+    RStrLit(Rc<str>),
+    /// `'c'`
+    RCharLit(char),
+    /// `-n`; the other integer literals are natural number literals checked
+    /// against `Int`
+    RIntLit(i64),
+    /// `if b then t else f`
+    RIf(Box<Raw>, Box<Raw>, Box<Raw>),
+    /// `match t with | c x y => u | _ => v`
+    RMatch(Box<Raw>, Vec<(Pattern, Raw)>),
+    /// field projection `t.x`
+    RField(Box<Raw>, Name),
+    /// `record { x = t, y = u }`
+    RRecordLit(Vec<(Name, Raw)>),
+}
+
+/// Pattern of a `match` branch
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Pattern {
+    /// constructor applied to variables, `cons {n} x xs`
+    PCon(Name, Vec<(Name, Icit)>),
+    /// `_`, covering the constructors without a branch of their own
+    PWild,
+}
+
+impl Pattern {
+    /// name of the constructor, `_` for a wildcard
+    pub fn con(&self) -> Name {
+        match self {
+            Pattern::PCon(c, _) => c.clone(),
+            Pattern::PWild => "_".into(),
+        }
+    }
+}
+
+/// Top-level declaration
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Decl {
+    /// `def x : A := t`
+    DDef(Name, Raw, Raw),
+    /// `import A.B`
+    DImport(ModulePath),
+    /// `data D (x : A) : T | c : C`
+    DData(Name, Vec<(Name, Raw)>, Raw, Vec<(Name, Raw)>),
+    /// `record R (x : A) : U | f : B`
+    DRecord(Name, Vec<(Name, Raw)>, Raw, Vec<(Name, Raw)>),
+    /// `mutual` followed by data types and definitions that may refer to each
+    /// other
+    DMutual(Vec<DataDecl>, Vec<(Name, Raw, Raw)>),
+    /// `instance x : A := t`
+    DInstance(Name, Raw, Raw),
+    /// `opaque x : A := t`
+    DOpaque(Name, Raw, Raw),
+    /// `axiom x : A`
+    DAxiom(Name, Raw),
+    /// declaration with a pragma `#[..]` in front of it
+    DPragma(Pragma, Box<Decl>),
+    /// `#![..]`, a pragma for the rest of the file
+    DFilePragma(Pragma),
+}
+
+/// `name(arg, ..)` of a pragma `#[..]` in front of a declaration or `#![..]`
+/// for a file
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pragma {
+    pub name: Name,
+    pub args: Vec<Name>,
+    /// where the pragma is written
+    pub pos: Span,
+    /// the pragma and the declaration, which it applies to, or the whole file
+    pub scope: Span,
 }
 
-type Tm = Box<Term>;
-type Ty = Box<Term>;
+pub type Program = Vec<Decl>;
+
+type Tm = Rc<Term>;
+type Ty = Rc<Term>;
 
 /// De Bruijn index
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Ix(pub usize);
 
 impl std::fmt::Debug for Ix {
@@ -46,84 +189,893 @@ impl std::fmt::Debug for Ix {
 type Lvl = usize;
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Term {
     TV(Ix),
-    Tλ(Name, Tm),
-    TΠ(Name, Ty, Ty),
+    Tλ(Name, Icit, Tm),
+    TΠ(Name, Icit, Ty, Ty),
     Tσ(Tm, Tm),
     TΣ(Name, Ty, Ty),
     TLet(Name, Ty, Tm, Tm),
     TMeta(MetaVar),
     TInsertedMeta(MetaVar, Vec<BD>),
-    TApp(Tm, Tm),
-    TU,
+    TApp(Tm, Tm, Icit),
+    TProj1(Tm),
+    TProj2(Tm),
+    TU(Level),
+    TNat,
+    TNatLit(u64),
+    TSuc(Tm),
+    /// `natElim P z s n`
+    TNatElim(Tm, Tm, Tm, Tm),
+    TBool,
+    TTrue,
+    TFalse,
+    /// `boolElim P t f b`
+    TBoolElim(Tm, Tm, Tm, Tm),
+    TString,
+    TStrLit(Rc<str>),
+    TChar,
+    TCharLit(char),
+    /// `append s t`
+    TAppend(Tm, Tm),
+    /// `length s`, the number of characters of `s`
+    TLength(Tm),
+    TInt,
+    TIntLit(i64),
+    /// `intAdd x y` and the other operations on integers
+    TIntOp(IntOp, Tm, Tm),
+    /// `Eq A x y`
+    TEq(Ty, Tm, Tm),
+    /// `refl {A} {x}`
+    TRefl(Ty, Tm),
+    /// `J {A} {x} P pr y e`
+    TJ(Ty, Tm, Tm, Tm, Tm, Tm),
+    /// `Lazy A`, the type of the suspended computations of an `A`
+    TLazy(Ty),
+    /// `delay t`, which evaluates `t` only once it is forced
+    TDelay(Tm),
+    /// `force t`
+    TForce(Tm),
+    /// data type applied to its parameters and indices
+    TData(
+        #[cfg_attr(feature = "serde", serde(with = "serialize::data"))] Rc<Data>,
+        Vec<Tm>,
+    ),
+    /// constructor applied to the parameters and its arguments
+    TCon(
+        #[cfg_attr(feature = "serde", serde(with = "serialize::data"))] Rc<Data>,
+        usize,
+        Vec<Tm>,
+    ),
+    /// eliminator applied to the parameters, the motive, the methods and the
+    /// indices, then to the scrutinee
+    TElim(
+        #[cfg_attr(feature = "serde", serde(with = "serialize::data"))] Rc<Data>,
+        Vec<Tm>,
+        Tm,
+    ),
+    /// the `usize`th of a block of recursive definitions, which unfolds as
+    /// its [`Unfolds`] says
+    TFix(Fix, usize),
+    /// record type applied to its parameters
+    TRecord(
+        #[cfg_attr(feature = "serde", serde(with = "serialize::record"))] Rc<Record>,
+        Vec<Tm>,
+    ),
+    /// record literal, with the fields in the order they were declared
+    TRecordLit(
+        #[cfg_attr(feature = "serde", serde(with = "serialize::record"))] Rc<Record>,
+        Vec<Tm>,
+    ),
+    /// projection of the `usize`th field
+    TField(
+        #[cfg_attr(feature = "serde", serde(with = "serialize::record"))] Rc<Record>,
+        usize,
+        Tm,
+    ),
+    /// the term elaborated from the source at the span, kept with
+    /// [`ElabOptions::keep_spans`]; evaluation and conversion ignore it
+    TSrcPos(Span, Tm),
+}
+
+/// Primitive operation on two integers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum IntOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Lt,
+    Le,
+    Eq,
+}
+
+impl IntOp {
+    pub const ALL: [IntOp; 8] = [
+        IntOp::Add,
+        IntOp::Sub,
+        IntOp::Mul,
+        IntOp::Div,
+        IntOp::Mod,
+        IntOp::Lt,
+        IntOp::Le,
+        IntOp::Eq,
+    ];
+
+    /// name of the builtin for the operation
+    pub fn name(self) -> &'static str {
+        match self {
+            IntOp::Add => "intAdd",
+            IntOp::Sub => "intSub",
+            IntOp::Mul => "intMul",
+            IntOp::Div => "intDiv",
+            IntOp::Mod => "intMod",
+            IntOp::Lt => "intLt",
+            IntOp::Le => "intLe",
+            IntOp::Eq => "intEq",
+        }
+    }
+
+    /// whether the operation returns a `Bool` rather than an `Int`
+    pub fn is_comparison(self) -> bool {
+        matches!(self, IntOp::Lt | IntOp::Le | IntOp::Eq)
+    }
+
+    /// The operation on two literals. Arithmetic wraps around on overflow,
+    /// and dividing by zero gives zero with the dividend as remainder.
+    fn apply(self, x: i64, y: i64) -> Value {
+        let bool = |b| if b { Value::VTrue } else { Value::VFalse };
+        match self {
+            IntOp::Add => Value::VIntLit(x.wrapping_add(y)),
+            IntOp::Sub => Value::VIntLit(x.wrapping_sub(y)),
+            IntOp::Mul => Value::VIntLit(x.wrapping_mul(y)),
+            IntOp::Div if y == 0 => Value::VIntLit(0),
+            IntOp::Div => Value::VIntLit(x.wrapping_div(y)),
+            IntOp::Mod if y == 0 => Value::VIntLit(x),
+            IntOp::Mod => Value::VIntLit(x.wrapping_rem(y)),
+            IntOp::Lt => bool(x < y),
+            IntOp::Le => bool(x <= y),
+            IntOp::Eq => bool(x == y),
+        }
+    }
+}
+
+/// Universe level: a constant, or a level meta plus an offset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Level {
+    LConst(u32),
+    LMeta(LevelVar, u32),
+}
+
+impl Level {
+    /// level of the universe above this one
+    pub fn suc(self) -> Level {
+        match self {
+            Level::LConst(n) => Level::LConst(n + 1),
+            Level::LMeta(l, k) => Level::LMeta(l, k + 1),
+        }
+    }
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Level::LConst(n) => write!(f, "{n}"),
+            Level::LMeta(l, 0) => write!(f, "?u{l}"),
+            Level::LMeta(l, k) => write!(f, "?u{l}+{k}"),
+        }
+    }
 }
 
-type VTy = Box<Value>;
+type VTy = Rc<Value>;
 
-type VTm = Box<Value>;
+type VTm = Rc<Value>;
 
-type Spine = Vec<Value>;
+/// The eliminators a neutral value is stuck under, the first one applied
+/// first
+pub type Spine = Vec<Elim>;
 
+/// eliminator in the spine of a neutral value
 #[derive(Debug, Clone)]
+pub enum Elim {
+    EApp(Value, Icit),
+    EProj1,
+    EProj2,
+    /// `natElim P z s` of a neutral natural number
+    ENatElim(Value, Value, Value),
+    /// `boolElim P t f` of a neutral boolean
+    EBoolElim(Value, Value, Value),
+    /// `append s t` of a neutral string `s`
+    EAppend(Value),
+    /// `append s t` of a literal `s` and a neutral string `t`
+    EPrepend(Rc<str>),
+    /// `length s` of a neutral string
+    ELength,
+    /// `op x y` of a neutral integer `x`
+    EIntOp(IntOp, Value),
+    /// `op x y` of a literal `x` and a neutral integer `y`
+    EIntOpLit(IntOp, i64),
+    /// `J {A} {x} P pr y` of a neutral equality proof
+    EJ(Value, Value, Value, Value, Value),
+    /// `force t` of a neutral suspended computation
+    EForce,
+    /// eliminator of a data type applied to everything but a neutral scrutinee
+    EElim(Rc<Data>, Vec<Value>),
+    /// projection of a field of a neutral record
+    EField(Rc<Record>, usize),
+}
+
+/// Values print as they quote, see [`Value::scope`], and so does their
+/// `Debug`, rather than with their environments.
+#[derive(Clone)]
 pub enum Value {
     /// unsolved meta variabel
     VFlex(MetaVar, Spine),
     /// bound variable applied to zero or more arguments
     VRigid(Lvl, Spine),
+    /// top-level definition applied to zero or more arguments, with the value
+    /// of the definition to unfold it lazily; the `Rc` identifies the definition
+    VGlued(Name, Rc<Value>, Spine),
     // lambda closure
-    Vλ(Name, Closure),
+    Vλ(Name, Icit, Closure),
     // pi type
-    VΠ(Name, VTy, Closure),
+    VΠ(Name, Icit, VTy, Closure),
     // sigma type
     VΣ(Name, VTy, Closure),
     // pair
     Vσ(VTm, VTm),
     // universe
-    VU,
+    VU(Level),
+    VNat,
+    VNatLit(u64),
+    /// successor of a natural number that isn't a literal
+    VSuc(VTm),
+    VBool,
+    VTrue,
+    VFalse,
+    VString,
+    VStrLit(Rc<str>),
+    VChar,
+    VCharLit(char),
+    VInt,
+    VIntLit(i64),
+    VEq(VTy, VTm, VTm),
+    VRefl(VTy, VTm),
+    VLazy(VTy),
+    /// computation suspended by `delay`, the term evaluated in the
+    /// environment once it is forced
+    VDelay(Closure),
+    VData(Rc<Data>, Vec<Value>),
+    VCon(Rc<Data>, usize, Vec<Value>),
+    /// recursive definition applied to arguments that don't let it unfold yet,
+    /// with the environment of its block
+    VFix(Fix, usize, Env, Spine),
+    VRecord(Rc<Record>, Vec<Value>),
+    VRecordLit(Rc<Record>, Vec<Value>),
+    /// argument or `let` definition suspended by [`Strategy::CallByNeed`],
+    /// only in environments and spines; [`force_metas`] evaluates it
+    VThunk(Rc<Thunk>),
+}
+
+/// Term suspended in its environment, evaluated the first time it is needed
+/// and then shared by every use. Forcing it is recorded on the metas, so that
+/// rolling them back suspends it again, as its value may have solutions of
+/// theirs inlined.
+pub struct Thunk(ThunkCell<ThunkState>);
+
+#[cfg(not(feature = "sync"))]
+type ThunkCell<T> = RefCell<T>;
+#[cfg(feature = "sync")]
+type ThunkCell<T> = std::sync::Mutex<T>;
+
+enum ThunkState {
+    Suspended(Env, Tm),
+    Evaluated(Value),
+}
+
+impl Thunk {
+    fn suspended(env: Env, t: Tm) -> Value {
+        let state = ThunkState::Suspended(env, t);
+        Value::VThunk(Rc::new(Thunk(ThunkCell::new(state))))
+    }
+
+    #[cfg(not(feature = "sync"))]
+    fn state(&self) -> impl std::ops::DerefMut<Target = ThunkState> + '_ {
+        self.0.borrow_mut()
+    }
+
+    #[cfg(feature = "sync")]
+    fn state(&self) -> impl std::ops::DerefMut<Target = ThunkState> + '_ {
+        self.0
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// The value of the term, evaluating it unless it was already.
+    pub fn force(self: &Rc<Self>, metas: &mut MetaCxt) -> Value {
+        let (env, t) = match &*self.state() {
+            ThunkState::Evaluated(v) => return v.clone(),
+            ThunkState::Suspended(env, t) => (env.clone(), t.clone()),
+        };
+        let v = eval(metas, Cow::Owned(env.clone()), (*t).clone());
+        *self.state() = ThunkState::Evaluated(v.clone());
+        metas.forced(self.clone(), env, t);
+        v
+    }
+
+    /// Suspend `t` in `env` again, undoing forcing it.
+    pub(crate) fn suspend(&self, env: Env, t: Tm) {
+        *self.state() = ThunkState::Suspended(env, t);
+    }
+}
+
+impl Debug for Thunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &*self.state() {
+            ThunkState::Suspended(_, t) => write!(f, "thunk {t:?}"),
+            ThunkState::Evaluated(v) => Debug::fmt(v, f),
+        }
+    }
+}
+
+/// With the `sync` feature, terms and values can be shared between threads.
+#[cfg(feature = "sync")]
+const _: () = {
+    fn assert_send_sync<T: Send + Sync>() {}
+    let _ = assert_send_sync::<Term>;
+    let _ = assert_send_sync::<Value>;
+    let _ = assert_send_sync::<Thunk>;
+};
+
+/// How [`eval`] evaluates the arguments of functions and the definitions of
+/// `let`s
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strategy {
+    /// before the body, even when it doesn't use them
+    #[default]
+    CallByValue,
+    /// the first time the body uses them, once
+    CallByNeed,
 }
 
-fn v_app(metas: &mut MetaCxt, v1: Value, v2: Value) -> Value {
+fn v_app(metas: &mut MetaCxt, v1: Value, v2: Value, icit: Icit) -> Value {
     match v1 {
         Value::VFlex(m, mut sp) => {
-            sp.push(v2);
+            sp.push(Elim::EApp(v2, icit));
             Value::VFlex(m, sp)
         }
         Value::VRigid(x, mut sp) => {
-            sp.push(v2);
+            sp.push(Elim::EApp(v2, icit));
             Value::VRigid(x, sp)
         }
-        Value::Vλ(_, (mut env, t)) => {
+        Value::VGlued(x, def, mut sp) => {
+            sp.push(Elim::EApp(v2, icit));
+            Value::VGlued(x, def, sp)
+        }
+        Value::VFix(x, k, t, mut sp) => {
+            sp.push(Elim::EApp(v2, icit));
+            Value::VFix(x, k, t, sp)
+        }
+        Value::Vλ(_, _, (mut env, t)) => {
             env.push(v2);
-            eval(metas, Cow::Owned(env), *t)
+            eval(metas, Cow::Owned(env), Rc::unwrap_or_clone(t))
+        }
+        _ => panic!(),
+    }
+}
+
+fn v_proj1(v: Value) -> Value {
+    match v {
+        Value::VFlex(m, mut sp) => {
+            sp.push(Elim::EProj1);
+            Value::VFlex(m, sp)
+        }
+        Value::VRigid(x, mut sp) => {
+            sp.push(Elim::EProj1);
+            Value::VRigid(x, sp)
+        }
+        Value::VGlued(x, def, mut sp) => {
+            sp.push(Elim::EProj1);
+            Value::VGlued(x, def, sp)
+        }
+        Value::VFix(x, k, t, mut sp) => {
+            sp.push(Elim::EProj1);
+            Value::VFix(x, k, t, sp)
+        }
+        Value::Vσ(t, _) => Rc::unwrap_or_clone(t),
+        _ => panic!(),
+    }
+}
+
+fn v_proj2(v: Value) -> Value {
+    match v {
+        Value::VFlex(m, mut sp) => {
+            sp.push(Elim::EProj2);
+            Value::VFlex(m, sp)
+        }
+        Value::VRigid(x, mut sp) => {
+            sp.push(Elim::EProj2);
+            Value::VRigid(x, sp)
+        }
+        Value::VGlued(x, def, mut sp) => {
+            sp.push(Elim::EProj2);
+            Value::VGlued(x, def, sp)
+        }
+        Value::VFix(x, k, t, mut sp) => {
+            sp.push(Elim::EProj2);
+            Value::VFix(x, k, t, sp)
+        }
+        Value::Vσ(_, u) => Rc::unwrap_or_clone(u),
+        _ => panic!(),
+    }
+}
+
+fn v_suc(v: Value) -> Value {
+    match v {
+        Value::VNatLit(n) => Value::VNatLit(n + 1),
+        v => Value::VSuc(v.into()),
+    }
+}
+
+fn v_nat_elim(metas: &mut MetaCxt, p: Value, z: Value, s: Value, v: Value) -> Value {
+    match v {
+        Value::VFlex(m, mut sp) => {
+            sp.push(Elim::ENatElim(p, z, s));
+            Value::VFlex(m, sp)
+        }
+        Value::VRigid(x, mut sp) => {
+            sp.push(Elim::ENatElim(p, z, s));
+            Value::VRigid(x, sp)
+        }
+        Value::VGlued(x, def, mut sp) => {
+            sp.push(Elim::ENatElim(p, z, s));
+            Value::VGlued(x, def, sp)
+        }
+        Value::VFix(x, k, t, mut sp) => {
+            sp.push(Elim::ENatElim(p, z, s));
+            Value::VFix(x, k, t, sp)
+        }
+        // from the bottom up, so that big literals don't recurse deeply
+        Value::VNatLit(n) => {
+            let mut acc = z;
+            for k in 0..n {
+                let s = v_app(metas, s.clone(), Value::VNatLit(k), Icit::Expl);
+                acc = v_app(metas, s, acc, Icit::Expl);
+            }
+            acc
+        }
+        Value::VSuc(n) => {
+            let n = Rc::unwrap_or_clone(n);
+            let ih = v_nat_elim(metas, p, z, s.clone(), n.clone());
+            let s = v_app(metas, s, n, Icit::Expl);
+            v_app(metas, s, ih, Icit::Expl)
+        }
+        _ => panic!(),
+    }
+}
+
+fn v_bool_elim(p: Value, t: Value, f: Value, v: Value) -> Value {
+    match v {
+        Value::VFlex(m, mut sp) => {
+            sp.push(Elim::EBoolElim(p, t, f));
+            Value::VFlex(m, sp)
+        }
+        Value::VRigid(x, mut sp) => {
+            sp.push(Elim::EBoolElim(p, t, f));
+            Value::VRigid(x, sp)
+        }
+        Value::VGlued(x, def, mut sp) => {
+            sp.push(Elim::EBoolElim(p, t, f));
+            Value::VGlued(x, def, sp)
+        }
+        Value::VFix(x, k, fix, mut sp) => {
+            sp.push(Elim::EBoolElim(p, t, f));
+            Value::VFix(x, k, fix, sp)
+        }
+        Value::VTrue => t,
+        Value::VFalse => f,
+        _ => panic!(),
+    }
+}
+
+fn v_append(s: Value, t: Value) -> Value {
+    match (s, t) {
+        (Value::VStrLit(s), Value::VStrLit(t)) => Value::VStrLit(format!("{s}{t}").into()),
+        (Value::VStrLit(s), Value::VFlex(m, mut sp)) => {
+            sp.push(Elim::EPrepend(s));
+            Value::VFlex(m, sp)
+        }
+        (Value::VStrLit(s), Value::VRigid(x, mut sp)) => {
+            sp.push(Elim::EPrepend(s));
+            Value::VRigid(x, sp)
+        }
+        (Value::VStrLit(s), Value::VGlued(x, def, mut sp)) => {
+            sp.push(Elim::EPrepend(s));
+            Value::VGlued(x, def, sp)
+        }
+        (Value::VStrLit(s), Value::VFix(x, k, fix, mut sp)) => {
+            sp.push(Elim::EPrepend(s));
+            Value::VFix(x, k, fix, sp)
+        }
+        (Value::VFlex(m, mut sp), t) => {
+            sp.push(Elim::EAppend(t));
+            Value::VFlex(m, sp)
+        }
+        (Value::VRigid(x, mut sp), t) => {
+            sp.push(Elim::EAppend(t));
+            Value::VRigid(x, sp)
+        }
+        (Value::VGlued(x, def, mut sp), t) => {
+            sp.push(Elim::EAppend(t));
+            Value::VGlued(x, def, sp)
+        }
+        (Value::VFix(x, k, fix, mut sp), t) => {
+            sp.push(Elim::EAppend(t));
+            Value::VFix(x, k, fix, sp)
+        }
+        _ => panic!(),
+    }
+}
+
+fn v_length(v: Value) -> Value {
+    match v {
+        Value::VFlex(m, mut sp) => {
+            sp.push(Elim::ELength);
+            Value::VFlex(m, sp)
+        }
+        Value::VRigid(x, mut sp) => {
+            sp.push(Elim::ELength);
+            Value::VRigid(x, sp)
+        }
+        Value::VGlued(x, def, mut sp) => {
+            sp.push(Elim::ELength);
+            Value::VGlued(x, def, sp)
+        }
+        Value::VFix(x, k, fix, mut sp) => {
+            sp.push(Elim::ELength);
+            Value::VFix(x, k, fix, sp)
+        }
+        Value::VStrLit(s) => Value::VNatLit(s.chars().count() as u64),
+        _ => panic!(),
+    }
+}
+
+fn v_int_op(op: IntOp, x: Value, y: Value) -> Value {
+    match (x, y) {
+        (Value::VIntLit(x), Value::VIntLit(y)) => op.apply(x, y),
+        (Value::VIntLit(x), Value::VFlex(m, mut sp)) => {
+            sp.push(Elim::EIntOpLit(op, x));
+            Value::VFlex(m, sp)
+        }
+        (Value::VIntLit(x), Value::VRigid(y, mut sp)) => {
+            sp.push(Elim::EIntOpLit(op, x));
+            Value::VRigid(y, sp)
+        }
+        (Value::VIntLit(x), Value::VGlued(y, def, mut sp)) => {
+            sp.push(Elim::EIntOpLit(op, x));
+            Value::VGlued(y, def, sp)
+        }
+        (Value::VIntLit(x), Value::VFix(y, k, fix, mut sp)) => {
+            sp.push(Elim::EIntOpLit(op, x));
+            Value::VFix(y, k, fix, sp)
+        }
+        (Value::VFlex(m, mut sp), y) => {
+            sp.push(Elim::EIntOp(op, y));
+            Value::VFlex(m, sp)
         }
+        (Value::VRigid(x, mut sp), y) => {
+            sp.push(Elim::EIntOp(op, y));
+            Value::VRigid(x, sp)
+        }
+        (Value::VGlued(x, def, mut sp), y) => {
+            sp.push(Elim::EIntOp(op, y));
+            Value::VGlued(x, def, sp)
+        }
+        (Value::VFix(x, k, fix, mut sp), y) => {
+            sp.push(Elim::EIntOp(op, y));
+            Value::VFix(x, k, fix, sp)
+        }
+        _ => panic!(),
+    }
+}
+
+fn v_j(a: Value, x: Value, p: Value, pr: Value, y: Value, v: Value) -> Value {
+    match v {
+        Value::VFlex(m, mut sp) => {
+            sp.push(Elim::EJ(a, x, p, pr, y));
+            Value::VFlex(m, sp)
+        }
+        Value::VRigid(x_, mut sp) => {
+            sp.push(Elim::EJ(a, x, p, pr, y));
+            Value::VRigid(x_, sp)
+        }
+        Value::VGlued(x_, def, mut sp) => {
+            sp.push(Elim::EJ(a, x, p, pr, y));
+            Value::VGlued(x_, def, sp)
+        }
+        Value::VFix(x_, k, t, mut sp) => {
+            sp.push(Elim::EJ(a, x, p, pr, y));
+            Value::VFix(x_, k, t, sp)
+        }
+        Value::VRefl(..) => pr,
+        _ => panic!(),
+    }
+}
+
+fn v_force(metas: &mut MetaCxt, v: Value) -> Value {
+    match v {
+        Value::VFlex(m, mut sp) => {
+            sp.push(Elim::EForce);
+            Value::VFlex(m, sp)
+        }
+        Value::VRigid(x, mut sp) => {
+            sp.push(Elim::EForce);
+            Value::VRigid(x, sp)
+        }
+        Value::VGlued(x, def, mut sp) => {
+            sp.push(Elim::EForce);
+            Value::VGlued(x, def, sp)
+        }
+        Value::VFix(x, k, t, mut sp) => {
+            sp.push(Elim::EForce);
+            Value::VFix(x, k, t, sp)
+        }
+        Value::VDelay((env, t)) => eval(metas, Cow::Owned(env), Rc::unwrap_or_clone(t)),
         _ => panic!(),
     }
 }
 
+fn v_spine(metas: &mut MetaCxt, mut v: Value, sp: Spine) -> Value {
+    for e in sp {
+        v = match e {
+            Elim::EApp(u, i) => v_app(metas, v, u, i),
+            Elim::EProj1 => v_proj1(v),
+            Elim::EProj2 => v_proj2(v),
+            Elim::ENatElim(p, z, s) => v_nat_elim(metas, p, z, s, v),
+            Elim::EBoolElim(p, t, f) => v_bool_elim(p, t, f, v),
+            Elim::EAppend(t) => v_append(v, t),
+            Elim::EPrepend(s) => v_append(Value::VStrLit(s), v),
+            Elim::ELength => v_length(v),
+            Elim::EIntOp(op, y) => v_int_op(op, v, y),
+            Elim::EIntOpLit(op, x) => v_int_op(op, Value::VIntLit(x), v),
+            Elim::EJ(a, x, p, pr, y) => v_j(a, x, p, pr, y, v),
+            Elim::EForce => v_force(metas, v),
+            Elim::EElim(d, args) => data::v_elim(metas, d, args, v),
+            Elim::EField(r, f) => record::v_field(r, f, v),
+        };
+    }
+    v
+}
+
+/// Unfold the top-level definition in the head of a value, if any.
+pub fn unfold(metas: &mut MetaCxt, v: Value) -> Value {
+    match v {
+        Value::VGlued(_, def, sp) => v_spine(metas, (*def).clone(), sp),
+        Value::VFix(fix, i, block_env, sp) => {
+            let mut env = block_env.clone();
+            for j in 0..fix.len() {
+                env.push(Value::VFix(fix.clone(), j, block_env.clone(), vec![]));
+            }
+            let v = eval(
+                metas,
+                Cow::Owned(env),
+                Rc::unwrap_or_clone(fix[i].2.clone()),
+            );
+            v_spine(metas, v, sp)
+        }
+        v => v,
+    }
+}
+
+/// Whether a recursive definition applied to `sp` unfolds, see [`Unfolds`],
+/// taking the fuel for it.
+pub fn fix_ready(metas: &mut MetaCxt, unfolds: Unfolds, sp: &Spine) -> bool {
+    let ready = match unfolds {
+        Unfolds::Now => true,
+        Unfolds::OnArg(k) => {
+            let mut args = sp.iter().filter_map(|e| match e {
+                Elim::EApp(u, _) => Some(u),
+                _ => None,
+            });
+            matches!(
+                args.nth(k).map(|u| force(metas, u.clone())),
+                Some(
+                    Value::VCon(..)
+                        | Value::VNatLit(_)
+                        | Value::VSuc(_)
+                        | Value::VTrue
+                        | Value::VFalse
+                )
+            )
+        }
+        Unfolds::OnElim => sp.iter().any(|e| !matches!(e, Elim::EApp(..))),
+    };
+    ready && metas.take_fuel()
+}
+
+/// Replace solved metas in the head of a value by their solutions, applied to
+/// the spine. A solution that is itself headed by a solved meta is replaced
+/// by what it forces to, so that a chain of metas solved with each other is
+/// only chased once.
+pub fn force_metas(metas: &mut MetaCxt, mut v: Value) -> Value {
+    loop {
+        v = match v {
+            Value::VThunk(t) => t.force(metas),
+            Value::VFlex(m, sp) => {
+                let solution = match &metas[m] {
+                    MetaEntry::Solved(solution) => solution.clone(),
+                    MetaEntry::Unsolved => return Value::VFlex(m, sp),
+                };
+                let solution = match solution {
+                    Value::VFlex(m_, _) if matches!(metas[m_], MetaEntry::Solved(_)) => {
+                        let solution = force_metas(metas, solution);
+                        metas.set_solution(m, solution.clone());
+                        solution
+                    }
+                    solution => solution,
+                };
+                v_spine(metas, solution, sp)
+            }
+            v => return v,
+        }
+    }
+}
+
+/// Chase solved metas and unfold top-level definitions until the head of the
+/// value is neither, or an opaque definition.
+pub fn force(metas: &mut MetaCxt, mut v: Value) -> Value {
+    loop {
+        v = match force_metas(metas, v) {
+            Value::VGlued(x, def, sp) if metas.opaque(&def).is_none() => {
+                unfold(metas, Value::VGlued(x, def, sp))
+            }
+            Value::VFix(fix, i, env, sp) if fix_ready(metas, fix[i].1, &sp) => {
+                unfold(metas, Value::VFix(fix, i, env, sp))
+            }
+            v => return v,
+        }
+    }
+}
+
 pub type Type = Value;
 
 pub type Closure = (Env, Tm);
 
+/// Block of mutually recursive definitions, each with its name, when it
+/// unfolds and its body. The whole block is bound as the innermost variables
+/// of every body, the first definition outermost.
+pub type Fix = Rc<[(Name, Unfolds, Tm)]>;
+
+/// When a definition of a recursive block unfolds, as decided by checking
+/// that it terminates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Unfolds {
+    /// right away, as it doesn't call the block after all
+    Now,
+    /// once its `k`th argument is a constructor, as it is structurally
+    /// recursive on it
+    OnArg(usize),
+    /// once it is eliminated, as it is corecursive: it only calls the block
+    /// under `delay`, and so produces a constructor before each call
+    OnElim,
+}
+
 mod env {
-    use std::{ops::Index, slice::Iter};
+    use std::{fmt::Debug, ops::Index};
+
+    use crate::{Ix, Lvl, Rc, Value};
 
-    use crate::{Ix, Lvl, Value};
+    /// Persistent list of values, innermost first. Pushing and cloning are
+    /// O(1), closures share the tail of their environment.
+    #[derive(Clone, Default)]
+    pub struct Env {
+        head: Option<Rc<Node>>,
+        len: usize,
+    }
 
-    #[derive(Debug, Clone, Default)]
-    pub struct Env(Vec<Value>);
+    struct Node {
+        value: Value,
+        next: Option<Rc<Node>>,
+    }
 
     impl Env {
         pub fn push(&mut self, value: Value) {
-            self.0.push(value)
+            let next = self.head.take();
+            self.head = Some(Rc::new(Node { value, next }));
+            self.len += 1;
         }
 
         pub fn pop(&mut self) -> Option<Value> {
-            self.0.pop()
+            let node = self.head.take()?;
+            self.len -= 1;
+
+            match Rc::try_unwrap(node) {
+                Ok(node) => {
+                    self.head = node.next;
+                    Some(node.value)
+                }
+                Err(node) => {
+                    self.head = node.next.clone();
+                    Some(node.value.clone())
+                }
+            }
+        }
+
+        pub fn len(&self) -> usize {
+            self.len
         }
 
-        pub fn iter(&self) -> Iter<Value> {
-            self.0.iter()
+        pub fn is_empty(&self) -> bool {
+            self.len == 0
+        }
+
+        /// The value of the variable `index`, if it is in scope.
+        pub fn get(&self, index: Ix) -> Option<&Value> {
+            let mut node = self.head.as_ref()?;
+            for _ in 0..index.0 {
+                node = node.next.as_ref()?;
+            }
+            Some(&node.value)
+        }
+
+        /// Whether both environments are the same list.
+        pub fn ptr_eq(&self, other: &Env) -> bool {
+            match (&self.head, &other.head) {
+                (Some(l), Some(r)) => Rc::ptr_eq(l, r),
+                (None, None) => true,
+                _ => false,
+            }
+        }
+
+        /// values from outermost to innermost, in the order of their levels
+        pub fn iter(&self) -> std::vec::IntoIter<&Value> {
+            let mut values = Vec::with_capacity(self.len);
+            let mut node = &self.head;
+            while let Some(n) = node {
+                values.push(&n.value);
+                node = &n.next;
+            }
+            values.reverse();
+            values.into_iter()
+        }
+    }
+
+    /// how many of the innermost values of an environment its `Debug` shows
+    const SHOWN: usize = 8;
+
+    impl Debug for Env {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let mut list = f.debug_list();
+            let mut node = &self.head;
+            for _ in 0..SHOWN {
+                let Some(n) = node else {
+                    return list.finish();
+                };
+                list.entry(&n.value);
+                node = &n.next;
+            }
+            match node {
+                Some(_) => list.finish_non_exhaustive(),
+                None => list.finish(),
+            }
+        }
+    }
+
+    impl Drop for Env {
+        // iteratively, long environments would overflow the stack otherwise
+        fn drop(&mut self) {
+            let mut node = self.head.take();
+            while let Some(n) = node {
+                match Rc::try_unwrap(n) {
+                    Ok(mut n) => node = n.next.take(),
+                    Err(_) => break,
+                }
+            }
         }
     }
 
@@ -131,7 +1083,7 @@ mod env {
         type Output = Value;
 
         fn index(&self, index: Ix) -> &Self::Output {
-            &self.0[self.0.len() - 1 - index.0]
+            self.get(index).expect("index out of scope")
         }
     }
 
@@ -139,14 +1091,15 @@ mod env {
         type Output = Value;
 
         fn index(&self, index: Lvl) -> &Self::Output {
-            &self.0[index]
+            &self[Ix(self.len - 1 - index)]
         }
     }
 }
 
 use env::Env;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BD {
     Bound,
     Defined,
@@ -163,17 +1116,93 @@ pub struct Cxt {
     /// used for fresh meta creation
     bds: Vec<BD>,
     /// used for error reporting
-    pos: SourcePos,
+    pos: Span,
+    /// used for tracing check and infer
+    tracer: Option<std::rc::Rc<RefCell<dyn ElabTracer>>>,
+    /// used for deciding what check and infer accept
+    options: ElabOptions,
+    /// used for looking up the data types in scope
+    data: Vec<Rc<Data>>,
+    /// used for looking up the records of record literals
+    records: Vec<Rc<Record>>,
+    /// used for instance search: the levels of the instances in scope
+    instances: Vec<Lvl>,
 }
 
-impl Cxt {
-    pub fn env(&self) -> &Env {
-        &self.env
-    }
+/// The options and the tracer of a context from before pragmas changed them,
+/// to go back to with [`Cxt::end_pragmas`]
+#[derive(Debug, Clone)]
+pub struct PragmaScope {
+    options: ElabOptions,
+    tracer: Option<std::rc::Rc<RefCell<dyn ElabTracer>>>,
+}
 
-    pub fn lvl(&self) -> Lvl {
-        self.lvl
-    }
+/// The context to go back to with [`Cxt::rollback`]
+#[derive(Debug, Clone)]
+pub struct CxtSnapshot {
+    env: Env,
+    lvl: Lvl,
+    pos: Span,
+    data: usize,
+    records: usize,
+    instances: usize,
+}
+
+/// Options of the elaborator
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "wasm", wasm_bindgen::prelude::wasm_bindgen)]
+pub struct ElabOptions {
+    /// don't check universe levels, so that `U : U` holds
+    pub type_in_type: bool,
+    /// accept recursive definitions that aren't structurally recursive, and
+    /// so may not terminate
+    pub no_termination_check: bool,
+    /// warn about binders that shadow a variable of the same name, which the
+    /// lint [`lint::Lint::Shadowing`] allows otherwise
+    pub warn_shadowing: bool,
+    /// reject holes `?x` whose type is still an unsolved meta once their
+    /// definition is elaborated, from which nothing was learned
+    pub strict_holes: bool,
+    /// abstract the metas a definition that isn't recursive leaves unsolved
+    /// over implicit arguments, like `id : {A : U} → A → A` for
+    /// `def id : _ → _ := λ x. x`, rather than failing
+    pub generalize: bool,
+    /// unfold `opaque` definitions like any other
+    pub unfold_opaque: bool,
+    /// keep the spans of the source in the elaborated terms, see
+    /// [`Term::TSrcPos`], so that what is done with them later can point
+    /// back to it
+    pub keep_spans: bool,
+    /// how many times recursive definitions may unfold while elaborating a
+    /// definition, before it fails rather than maybe looping forever
+    pub fuel: Option<usize>,
+    /// how many nodes the terms quoted while elaborating a definition may
+    /// have in all, before it fails rather than running out of memory
+    pub max_nodes: Option<usize>,
+}
+
+impl Cxt {
+    /// Context of the axioms `x : A`, given by their names and the sources of
+    /// their types, for elaborating against a theory of one's own. They are
+    /// bound like `axiom x : A` declarations, see [`check_axiom`].
+    pub fn with_axioms(metas: &mut MetaCxt, axioms: &[(&str, &str)]) -> Result<Cxt, Error> {
+        let mut cxt = Cxt::default();
+        for &(x, a) in axioms {
+            let from = metas.len();
+            let a = parser::parse(a).map_err(Error::parse)?;
+            check_axiom(metas, &mut cxt, x.into(), a.unwrap_or(Raw::RHole(None)))?;
+            metas.check_all_solved(from, metas::UnsolvedPolicy::Error)?;
+        }
+        Ok(cxt)
+    }
+
+    pub fn env(&self) -> &Env {
+        &self.env
+    }
+
+    pub fn lvl(&self) -> Lvl {
+        self.lvl
+    }
 
     pub fn types(&self) -> &Vec<(Name, Type)> {
         &self.types
@@ -183,10 +1212,89 @@ impl Cxt {
         &self.bds
     }
 
-    pub fn pos(&self) -> &SourcePos {
+    pub fn pos(&self) -> &Span {
         &self.pos
     }
 
+    pub fn options(&self) -> &ElabOptions {
+        &self.options
+    }
+
+    pub fn set_options(&mut self, options: ElabOptions) {
+        self.options = options;
+    }
+
+    /// data types in scope, in the order they were declared
+    pub fn data(&self) -> &[Rc<Data>] {
+        &self.data
+    }
+
+    /// records in scope, in the order they were declared
+    pub fn records(&self) -> &[Rc<Record>] {
+        &self.records
+    }
+
+    /// levels of the variables in scope that instance arguments can be found
+    /// among, in the order they were bound
+    pub fn instances(&self) -> &[Lvl] {
+        &self.instances
+    }
+
+    /// The context as it is now, to go back to after trying something out.
+    pub fn snapshot(&self) -> CxtSnapshot {
+        CxtSnapshot {
+            env: self.env.clone(),
+            lvl: self.lvl,
+            pos: self.pos.clone(),
+            data: self.data.len(),
+            records: self.records.len(),
+            instances: self.instances.len(),
+        }
+    }
+
+    /// Undo the bindings and declarations added since `snapshot`, which the
+    /// context mustn't have been popped past since.
+    pub fn rollback(&mut self, snapshot: CxtSnapshot) {
+        debug_assert!(self.lvl >= snapshot.lvl, "context popped past its snapshot");
+        self.env = snapshot.env;
+        self.lvl = snapshot.lvl;
+        self.types.truncate(snapshot.lvl);
+        self.bds.truncate(snapshot.lvl);
+        self.pos = snapshot.pos;
+        self.data.truncate(snapshot.data);
+        self.records.truncate(snapshot.records);
+        self.instances.truncate(snapshot.instances);
+    }
+
+    /// The data type named `x`, the latest one if several are.
+    pub fn lookup_data(&self, x: &str) -> Option<&Rc<Data>> {
+        self.data.iter().rev().find(|d| &*d.name == x)
+    }
+
+    /// Trace the elaboration done in this context with `tracer`.
+    pub fn set_tracer(&mut self, tracer: std::rc::Rc<RefCell<dyn ElabTracer>>) {
+        self.tracer = Some(tracer);
+    }
+
+    /// The options and the tracer to go back to once the pragmas that are
+    /// interpreted next are done with, see [`Cxt::end_pragmas`].
+    pub fn begin_pragmas(&self) -> PragmaScope {
+        PragmaScope {
+            options: self.options.clone(),
+            tracer: self.tracer.clone(),
+        }
+    }
+
+    pub fn end_pragmas(&mut self, scope: PragmaScope) {
+        self.options = scope.options;
+        self.tracer = scope.tracer;
+    }
+
+    /// names in scope, innermost last
+    pub fn names(&self) -> Vec<Name> {
+        self.types.iter().map(|x| x.0.clone()).collect()
+    }
+
     pub fn bind<T>(
         &mut self,
         name: Name,
@@ -205,6 +1313,49 @@ impl Cxt {
         (res, (name, r#type))
     }
 
+    /// Like `bind`, but the variable is an instance for the instance arguments
+    /// in `f`.
+    pub fn bind_instance<T>(
+        &mut self,
+        name: Name,
+        r#type: Type,
+        f: impl FnOnce(&mut Self) -> T,
+    ) -> (T, (Name, Type)) {
+        self.instances.push(self.lvl);
+        let res = self.bind(name, r#type, f);
+        self.instances.pop();
+        res
+    }
+
+    /// Bring a variable into scope until [`Cxt::unbind`], as an instance if
+    /// `i` is one.
+    fn push_bound(&mut self, name: Name, i: Icit, r#type: Type) {
+        if i == Icit::Inst {
+            self.instances.push(self.lvl);
+        }
+        self.env.push(Value::VRigid(self.lvl, vec![]));
+        self.lvl += 1;
+        self.types.push((name, r#type));
+        self.bds.push(BD::Bound);
+    }
+
+    /// Bring a definition into scope until [`Cxt::unbind`].
+    fn push_defined(&mut self, name: Name, val: Value, r#type: Type) {
+        self.env.push(val);
+        self.lvl += 1;
+        self.types.push((name, r#type));
+        self.bds.push(BD::Defined);
+    }
+
+    /// Take the innermost variable or definition out of scope.
+    fn unbind(&mut self) -> (Name, Type, Value) {
+        self.lvl -= 1;
+        if self.instances.last() == Some(&self.lvl) {
+            self.instances.pop();
+        }
+        self.pop()
+    }
+
     pub fn define<T>(
         &mut self,
         name: Name,
@@ -222,6 +1373,19 @@ impl Cxt {
         (res, self.pop())
     }
 
+    /// Like `define`, but the definition stays in scope, as for top-level
+    /// definitions. References to it evaluate to glued values.
+    pub fn define_global(&mut self, name: Name, val: Value, r#type: Type) {
+        let val = match val {
+            Value::VGlued(x, def, sp) if sp.is_empty() => Value::VGlued(x, def, sp),
+            val => Value::VGlued(name.clone(), Rc::new(val), vec![]),
+        };
+        self.env.push(val);
+        self.lvl += 1;
+        self.types.push((name, r#type));
+        self.bds.push(BD::Defined);
+    }
+
     fn pop(&mut self) -> (Name, Value, Value) {
         self.bds.pop();
         let value = self.env.pop().unwrap();
@@ -231,132 +1395,644 @@ impl Cxt {
     }
 }
 
-pub fn eval(metas: &mut MetaCxt, mut env: Cow<'_, Env>, tm: Term) -> Value {
-    match tm {
-        Term::TV(x) => env[x].clone(),
-        Term::Tλ(x, t) => Value::Vλ(x, (env.into_owned(), t)),
-        Term::TΠ(x, a, b) => {
-            let a = eval(metas, env.clone(), *a);
-
-            let closure = (env.into_owned(), b);
+/// Evaluation continuations, kept on an explicit stack so that deeply nested
+/// terms don't overflow the native one.
+enum EvalFrame<'a> {
+    /// evaluate the argument of an application once the function is done
+    AppFun(Cow<'a, Env>, Tm, Icit),
+    /// apply the evaluated function to the argument
+    AppArg(Value, Icit),
+    PiDom(Name, Icit, Cow<'a, Env>, Ty),
+    SigmaDom(Name, Cow<'a, Env>, Ty),
+    PairFst(Cow<'a, Env>, Tm),
+    PairSnd(Value),
+    LetDef(Cow<'a, Env>, Tm),
+    Proj1,
+    Proj2,
+    Suc,
+    NatElim(Value, Value, Value),
+    BoolElim(Value, Value, Value),
+    /// evaluate the second string of an `append` once the first is done
+    AppendFst(Cow<'a, Env>, Tm),
+    AppendSnd(Value),
+    Length,
+    /// evaluate the second argument of an integer operation once the first
+    /// is done
+    IntOpFst(IntOp, Cow<'a, Env>, Tm),
+    IntOpSnd(IntOp, Value),
+    J(Value, Value, Value, Value, Value),
+    Force,
+    Elim(Rc<Data>, Vec<Value>),
+    Field(Rc<Record>, usize),
+}
 
-            Value::VΠ(x, a.into(), closure)
-        }
-        Term::Tσ(a, b) => {
-            let a = eval(metas, env.clone(), *a);
-            let b = eval(metas, env, *b);
+/// A variable of a core term that isn't bound in the `lvl` values of its
+/// environment, which the elaborator never produces.
+#[cold]
+fn ill_scoped(x: Ix, lvl: Lvl) -> ! {
+    panic!("ill-scoped core term, this is a bug: variable {x:?} in an environment of {lvl} values")
+}
 
-            Value::Vσ(a.into(), b.into())
-        }
-        Term::TΣ(name, a, b) => {
-            let a = eval(metas, env.clone(), *a);
-            let closure = (env.into_owned(), b);
-            Value::VΣ(name, a.into(), closure)
-        }
-        Term::TLet(_, _, t, u) => {
-            let val = eval(metas, env.clone(), *t);
-            env.to_mut().push(val);
-            eval(metas, env, *u)
-        }
-        Term::TMeta(m) => match metas[m].clone() {
-            MetaEntry::Solved(v) => v,
-            MetaEntry::Unsolved => Value::VFlex(m, vec![]),
+/// `t` suspended in `env`, unless it is a variable, whose value is shared.
+fn suspend(env: Cow<'_, Env>, t: Tm) -> Value {
+    match &*t {
+        Term::TV(x) => match env.get(*x) {
+            Some(v) => v.clone(),
+            None => ill_scoped(*x, env.len()),
         },
-        Term::TApp(t, u) => {
-            let t = eval(metas, env.clone(), *t);
-            let u = eval(metas, env, *u);
-
-            v_app(metas, t, u)
-        }
-        Term::TU => Value::VU,
-        Term::TInsertedMeta(m, bds) => {
-            let mut args = Vec::new();
-
-            match &metas[m] {
-                MetaEntry::Solved(val) => {
-                    let mut val = val.clone();
-                    for (t, bds) in env.iter().zip(bds.into_iter()) {
-                        if let BD::Bound = bds {
-                            val = v_app(metas, val, t.clone());
+        _ => Thunk::suspended(env.into_owned(), t),
+    }
+}
+
+/// Value of an argument or a `let` definition, suspended with
+/// [`Strategy::CallByNeed`].
+fn eval_arg(metas: &mut MetaCxt, env: &Env, t: Term) -> Value {
+    match metas.strategy() {
+        Strategy::CallByValue => eval(metas, Cow::Borrowed(env), t),
+        Strategy::CallByNeed => suspend(Cow::Borrowed(env), t.into()),
+    }
+}
+
+pub fn eval(metas: &mut MetaCxt, mut env: Cow<'_, Env>, mut tm: Term) -> Value {
+    let mut stack = Vec::new();
+
+    'eval: loop {
+        let mut val = match tm {
+            Term::TV(x) => match env.get(x) {
+                Some(Value::VThunk(t)) => t.clone().force(metas),
+                Some(v) => v.clone(),
+                None => ill_scoped(x, env.len()),
+            },
+            Term::Tλ(x, i, t) => Value::Vλ(x, i, (env.into_owned(), t)),
+            Term::TFix(fix, i) => Value::VFix(fix, i, env.into_owned(), vec![]),
+            Term::TΠ(x, i, a, b) => {
+                stack.push(EvalFrame::PiDom(x, i, env.clone(), b));
+                tm = Rc::unwrap_or_clone(a);
+                continue;
+            }
+            Term::Tσ(a, b) => {
+                stack.push(EvalFrame::PairFst(env.clone(), b));
+                tm = Rc::unwrap_or_clone(a);
+                continue;
+            }
+            Term::TΣ(x, a, b) => {
+                stack.push(EvalFrame::SigmaDom(x, env.clone(), b));
+                tm = Rc::unwrap_or_clone(a);
+                continue;
+            }
+            Term::TLet(_, _, t, u) if metas.strategy() == Strategy::CallByNeed => {
+                let t = suspend(env.clone(), t);
+                env.to_mut().push(t);
+                tm = Rc::unwrap_or_clone(u);
+                continue;
+            }
+            Term::TLet(_, _, t, u) => {
+                stack.push(EvalFrame::LetDef(env.clone(), u));
+                tm = Rc::unwrap_or_clone(t);
+                continue;
+            }
+            Term::TMeta(m) => match metas[m].clone() {
+                MetaEntry::Solved(v) => v,
+                MetaEntry::Unsolved => Value::VFlex(m, vec![]),
+            },
+            Term::TApp(t, u, i) => {
+                stack.push(EvalFrame::AppFun(env.clone(), u, i));
+                tm = Rc::unwrap_or_clone(t);
+                continue;
+            }
+            Term::TProj1(t) => {
+                stack.push(EvalFrame::Proj1);
+                tm = Rc::unwrap_or_clone(t);
+                continue;
+            }
+            Term::TProj2(t) => {
+                stack.push(EvalFrame::Proj2);
+                tm = Rc::unwrap_or_clone(t);
+                continue;
+            }
+            Term::TU(l) => Value::VU(l),
+            Term::TNat => Value::VNat,
+            Term::TNatLit(n) => Value::VNatLit(n),
+            Term::TSuc(t) => {
+                stack.push(EvalFrame::Suc);
+                tm = Rc::unwrap_or_clone(t);
+                continue;
+            }
+            Term::TNatElim(p, z, s, n) => {
+                let p = eval(metas, env.clone(), Rc::unwrap_or_clone(p));
+                let z = eval(metas, env.clone(), Rc::unwrap_or_clone(z));
+                let s = eval(metas, env.clone(), Rc::unwrap_or_clone(s));
+                stack.push(EvalFrame::NatElim(p, z, s));
+                tm = Rc::unwrap_or_clone(n);
+                continue;
+            }
+            Term::TBool => Value::VBool,
+            Term::TTrue => Value::VTrue,
+            Term::TFalse => Value::VFalse,
+            Term::TBoolElim(p, t, f, b) => {
+                let p = eval(metas, env.clone(), Rc::unwrap_or_clone(p));
+                let t = eval(metas, env.clone(), Rc::unwrap_or_clone(t));
+                let f = eval(metas, env.clone(), Rc::unwrap_or_clone(f));
+                stack.push(EvalFrame::BoolElim(p, t, f));
+                tm = Rc::unwrap_or_clone(b);
+                continue;
+            }
+            Term::TString => Value::VString,
+            Term::TStrLit(s) => Value::VStrLit(s),
+            Term::TChar => Value::VChar,
+            Term::TCharLit(c) => Value::VCharLit(c),
+            Term::TAppend(s, t) => {
+                stack.push(EvalFrame::AppendFst(env.clone(), t));
+                tm = Rc::unwrap_or_clone(s);
+                continue;
+            }
+            Term::TLength(s) => {
+                stack.push(EvalFrame::Length);
+                tm = Rc::unwrap_or_clone(s);
+                continue;
+            }
+            Term::TInt => Value::VInt,
+            Term::TIntLit(n) => Value::VIntLit(n),
+            Term::TIntOp(op, x, y) => {
+                stack.push(EvalFrame::IntOpFst(op, env.clone(), y));
+                tm = Rc::unwrap_or_clone(x);
+                continue;
+            }
+            Term::TEq(a, x, y) => Value::VEq(
+                eval(metas, env.clone(), Rc::unwrap_or_clone(a)).into(),
+                eval(metas, env.clone(), Rc::unwrap_or_clone(x)).into(),
+                eval(metas, env.clone(), Rc::unwrap_or_clone(y)).into(),
+            ),
+            Term::TRefl(a, x) => Value::VRefl(
+                eval(metas, env.clone(), Rc::unwrap_or_clone(a)).into(),
+                eval(metas, env.clone(), Rc::unwrap_or_clone(x)).into(),
+            ),
+            Term::TJ(a, x, p, pr, y, e) => {
+                let [a, x, p, pr, y] =
+                    [a, x, p, pr, y].map(|t| eval(metas, env.clone(), Rc::unwrap_or_clone(t)));
+                stack.push(EvalFrame::J(a, x, p, pr, y));
+                tm = Rc::unwrap_or_clone(e);
+                continue;
+            }
+            Term::TLazy(a) => Value::VLazy(eval(metas, env, Rc::unwrap_or_clone(a)).into()),
+            Term::TDelay(t) => Value::VDelay((env.into_owned(), t)),
+            Term::TForce(t) => {
+                stack.push(EvalFrame::Force);
+                tm = Rc::unwrap_or_clone(t);
+                continue;
+            }
+            Term::TData(d, args) => Value::VData(
+                d,
+                args.into_iter()
+                    .map(|t| eval(metas, env.clone(), Rc::unwrap_or_clone(t)))
+                    .collect(),
+            ),
+            Term::TCon(d, c, args) => Value::VCon(
+                d,
+                c,
+                args.into_iter()
+                    .map(|t| eval(metas, env.clone(), Rc::unwrap_or_clone(t)))
+                    .collect(),
+            ),
+            Term::TElim(d, args, t) => {
+                let args = args
+                    .into_iter()
+                    .map(|t| eval(metas, env.clone(), Rc::unwrap_or_clone(t)))
+                    .collect();
+                stack.push(EvalFrame::Elim(d, args));
+                tm = Rc::unwrap_or_clone(t);
+                continue;
+            }
+            Term::TRecord(r, args) => Value::VRecord(
+                r,
+                args.into_iter()
+                    .map(|t| eval(metas, env.clone(), Rc::unwrap_or_clone(t)))
+                    .collect(),
+            ),
+            Term::TRecordLit(r, fields) => Value::VRecordLit(
+                r,
+                fields
+                    .into_iter()
+                    .map(|t| eval(metas, env.clone(), Rc::unwrap_or_clone(t)))
+                    .collect(),
+            ),
+            Term::TField(r, f, t) => {
+                stack.push(EvalFrame::Field(r, f));
+                tm = Rc::unwrap_or_clone(t);
+                continue;
+            }
+            Term::TSrcPos(_, t) => {
+                tm = Rc::unwrap_or_clone(t);
+                continue;
+            }
+            Term::TInsertedMeta(m, bds) => {
+                let mut args = Vec::new();
+
+                match &metas[m] {
+                    MetaEntry::Solved(val) => {
+                        let mut val = val.clone();
+                        for (t, bds) in env.iter().zip(bds) {
+                            if let BD::Bound = bds {
+                                val = v_app(metas, val, t.clone(), Icit::Expl);
+                            }
                         }
+                        val
                     }
-                    val
-                }
-                MetaEntry::Unsolved => {
-                    for (t, bds) in env.iter().cloned().zip(bds.into_iter()) {
-                        if let BD::Bound = bds {
-                            args.push(t.clone());
+                    MetaEntry::Unsolved => {
+                        for (t, bds) in env.iter().cloned().zip(bds) {
+                            if let BD::Bound = bds {
+                                args.push(Elim::EApp(t.clone(), Icit::Expl));
+                            }
                         }
-                    }
 
-                    Value::VFlex(m, args)
+                        Value::VFlex(m, args)
+                    }
                 }
             }
+        };
+
+        loop {
+            val = match stack.pop() {
+                None => return val,
+                Some(EvalFrame::AppFun(env_, u, i)) if metas.strategy() == Strategy::CallByNeed => {
+                    stack.push(EvalFrame::AppArg(val, i));
+                    suspend(env_, u)
+                }
+                Some(EvalFrame::AppFun(env_, u, i)) => {
+                    stack.push(EvalFrame::AppArg(val, i));
+                    (env, tm) = (env_, Rc::unwrap_or_clone(u));
+                    continue 'eval;
+                }
+                Some(EvalFrame::AppArg(Value::Vλ(_, _, (mut env_, t)), _)) => {
+                    env_.push(val);
+                    (env, tm) = (Cow::Owned(env_), Rc::unwrap_or_clone(t));
+                    continue 'eval;
+                }
+                Some(EvalFrame::AppArg(t, i)) => v_app(metas, t, val, i),
+                Some(EvalFrame::PiDom(x, i, env_, b)) => {
+                    Value::VΠ(x, i, val.into(), (env_.into_owned(), b))
+                }
+                Some(EvalFrame::SigmaDom(x, env_, b)) => {
+                    Value::VΣ(x, val.into(), (env_.into_owned(), b))
+                }
+                Some(EvalFrame::PairFst(env_, u)) => {
+                    stack.push(EvalFrame::PairSnd(val));
+                    (env, tm) = (env_, Rc::unwrap_or_clone(u));
+                    continue 'eval;
+                }
+                Some(EvalFrame::PairSnd(t)) => Value::Vσ(t.into(), val.into()),
+                Some(EvalFrame::LetDef(mut env_, u)) => {
+                    env_.to_mut().push(val);
+                    (env, tm) = (env_, Rc::unwrap_or_clone(u));
+                    continue 'eval;
+                }
+                Some(EvalFrame::Proj1) => v_proj1(val),
+                Some(EvalFrame::Proj2) => v_proj2(val),
+                Some(EvalFrame::Suc) => v_suc(val),
+                Some(EvalFrame::NatElim(p, z, s)) => v_nat_elim(metas, p, z, s, val),
+                Some(EvalFrame::BoolElim(p, t, f)) => v_bool_elim(p, t, f, val),
+                Some(EvalFrame::AppendFst(env_, t)) => {
+                    stack.push(EvalFrame::AppendSnd(val));
+                    (env, tm) = (env_, Rc::unwrap_or_clone(t));
+                    continue 'eval;
+                }
+                Some(EvalFrame::AppendSnd(s)) => v_append(s, val),
+                Some(EvalFrame::Length) => v_length(val),
+                Some(EvalFrame::IntOpFst(op, env_, y)) => {
+                    stack.push(EvalFrame::IntOpSnd(op, val));
+                    (env, tm) = (env_, Rc::unwrap_or_clone(y));
+                    continue 'eval;
+                }
+                Some(EvalFrame::IntOpSnd(op, x)) => v_int_op(op, x, val),
+                Some(EvalFrame::J(a, x, p, pr, y)) => v_j(a, x, p, pr, y, val),
+                Some(EvalFrame::Force) => v_force(metas, val),
+                Some(EvalFrame::Elim(d, args)) => data::v_elim(metas, d, args, val),
+                Some(EvalFrame::Field(r, f)) => record::v_field(r, f, val),
+            };
         }
     }
 }
 
 pub fn check(metas: &mut MetaCxt, cxt: &mut Cxt, raw: Raw, ty: Type) -> Result<Term, Error> {
-    fn check_(metas: &mut MetaCxt, cxt: &mut Cxt, raw: Raw, ty: Type) -> Result<Term, Error> {
-        Ok(match (raw, ty) {
+    match ElabMachine::new(cxt, Node::Check(raw, ty)).run(metas, cxt)? {
+        Elaborated::Checked(t) => Ok(t),
+        Elaborated::Inferred(..) => unreachable!(),
+    }
+}
+
+pub fn infer(metas: &mut MetaCxt, cxt: &mut Cxt, raw: Raw) -> Result<(Term, Type), Error> {
+    match ElabMachine::new(cxt, Node::Infer(raw)).run(metas, cxt)? {
+        Elaborated::Inferred(t, a) => Ok((t, a)),
+        Elaborated::Checked(_) => unreachable!(),
+    }
+}
+
+/// A `check` or `infer` call
+#[derive(Debug, Clone)]
+pub(crate) enum Node {
+    Check(Raw, Type),
+    Infer(Raw),
+}
+
+/// What a [`Node`] elaborates to
+#[derive(Debug, Clone)]
+pub(crate) enum Elaborated {
+    Checked(Term),
+    Inferred(Term, Type),
+}
+
+/// The node to check `raw` against `expected`, or to infer it without
+fn node(raw: Raw, expected: Option<Type>) -> Node {
+    match expected {
+        Some(a) => Node::Check(raw, a),
+        None => Node::Infer(raw),
+    }
+}
+
+/// Elaboration continuations: what to do with the result of the node
+/// elaborated next. The frames that bind a variable keep it in `cxt` until
+/// they are resumed.
+enum ElabFrame {
+    /// leave a traced node, at the position, checked against the type if it
+    /// is a `check`
+    Traced(Span, Option<Type>),
+    /// check later that the variable of the binder written at the position
+    /// is used in the term elaborated from it, and whether to warn that it
+    /// shadows another one
+    Binder(Span, bool),
+    /// keep the span of the term elaborated, with [`ElabOptions::keep_spans`]
+    SrcPos(Span),
+    /// the body of a lambda, checked or inferred
+    Lam(Icit),
+    /// check the second component of a pair against the codomain of its Σ
+    /// type once the first one is done
+    PairFst(Raw, Closure),
+    PairSnd(Term),
+    /// the type of `let x : a := t; u`, then the definition and the body,
+    /// checked against the type if there is one and inferred otherwise
+    LetType(Name, Raw, Raw, Option<Type>),
+    LetDef(Name, Term, Type, Raw, Option<Type>),
+    /// like `LetType` for `let rec`, whose definition is checked with `x`
+    /// bound, and the position of the definition restored after it
+    LetRecType(Name, Raw, Raw, Option<Type>),
+    LetRecDef(Name, Term, Type, Raw, Option<Type>, Span),
+    LetBody(Term, Term),
+    /// the branches of an `if`, the first one checked against the type if
+    /// there is one and inferred otherwise
+    IfCond(Raw, Raw, Option<Type>),
+    IfThen(Term, Raw, Option<Type>),
+    IfElse(Term, Term, Type, bool),
+    /// insert the implicit arguments of an inferred term, unless it is an
+    /// implicit lambda
+    Insert(bool),
+    /// check an inferred term by unifying its type with the expected one
+    Unify(Type),
+    /// check the argument once the function is inferred
+    AppFun(Raw, Icit),
+    AppNamedFun(Name, Raw),
+    AppArg(Term, Closure, Icit),
+    /// infer a `match` checked against a fresh type
+    Match(Type),
+    PiDom(Name, Icit, Raw, Level),
+    PiCod(Icit, Term, Level, Level),
+    SigmaDom(Name, Raw, Level),
+    SigmaCod(Term, Level, Level),
+    /// infer the second component of a pair once the first one is done
+    InferPairFst(Raw),
+    InferPairSnd(Term, Type),
+    Proj1,
+    Proj2,
+    /// suspend the argument of `delay` applied to it, elaborated unevaluated
+    Delay,
+    /// the type of `Lazy` applied to it, in the universe of the level, so
+    /// that strict positivity sees the suspended type
+    Lazy(Level),
+}
+
+/// What comes after starting a node or resuming a frame: another node to
+/// start, or the result of the innermost node being elaborated
+enum ElabStep {
+    Start(Node),
+    Done(Elaborated),
+}
+
+/// `check` and `infer` with their continuations kept on an explicit stack,
+/// so that elaboration can be stopped before each node. Data types and
+/// records elaborate their `match`es, fields and record literals in one
+/// node.
+pub(crate) struct ElabMachine {
+    /// the node to start next, unless elaboration is done
+    next: Option<Node>,
+    stack: Vec<ElabFrame>,
+}
+
+impl ElabMachine {
+    pub(crate) fn new(cxt: &mut Cxt, node: Node) -> Self {
+        let mut machine = ElabMachine {
+            next: None,
+            stack: Vec::new(),
+        };
+        machine.set_next(cxt, node);
+        machine
+    }
+
+    /// The node to start next, unless elaboration is done.
+    pub(crate) fn next(&self) -> Option<&Node> {
+        self.next.as_ref()
+    }
+
+    fn set_next(&mut self, cxt: &mut Cxt, mut node: Node) {
+        loop {
+            node = match node {
+                Node::Check(Raw::RSrcPos(pos, t), a) => {
+                    self.enter(cxt, pos);
+                    Node::Check(*t, a)
+                }
+                Node::Infer(Raw::RSrcPos(pos, t)) => {
+                    self.enter(cxt, pos);
+                    Node::Infer(*t)
+                }
+                node => break self.next = Some(node),
+            };
+        }
+    }
+
+    /// Elaborate everything that is left.
+    pub(crate) fn run(mut self, metas: &mut MetaCxt, cxt: &mut Cxt) -> Result<Elaborated, Error> {
+        loop {
+            if let Some(res) = self.step(metas, cxt) {
+                return res;
+            }
+        }
+    }
+
+    /// Start the next node, and resume the frames it returns to until there
+    /// is another node to start. Returns the result of the elaboration once
+    /// it is done.
+    pub(crate) fn step(
+        &mut self,
+        metas: &mut MetaCxt,
+        cxt: &mut Cxt,
+    ) -> Option<Result<Elaborated, Error>> {
+        let node = self.next.take()?;
+        let mut step = self.start(metas, cxt, node);
+
+        loop {
+            match step {
+                Ok(ElabStep::Start(node)) => {
+                    self.set_next(cxt, node);
+                    return None;
+                }
+                Ok(ElabStep::Done(res)) => match self.stack.pop() {
+                    None => return Some(Ok(res)),
+                    Some(frame) => step = self.resume(metas, cxt, frame, res),
+                },
+                Err(err) => {
+                    self.unwind(cxt);
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+
+    /// Move to the span `pos` of the node starting next, which the term
+    /// elaborated from it keeps if the options say so.
+    fn enter(&mut self, cxt: &mut Cxt, pos: Span) {
+        if cxt.options.keep_spans {
+            self.stack.push(ElabFrame::SrcPos(pos.clone()));
+        }
+        cxt.pos = pos;
+    }
+
+    /// Check later that the variable of the binder of `x` starting at the
+    /// node is used, unless it is `_`, and that it doesn't shadow another one
+    /// if the options ask for it.
+    fn push_binder(&mut self, cxt: &Cxt, x: &str) {
+        if x != "_" {
+            let shadows = cxt.types.iter().any(|(y, _)| &**y == x);
+            self.stack.push(ElabFrame::Binder(cxt.pos.clone(), shadows));
+        }
+    }
+
+    /// Leave the nodes being elaborated after an error.
+    fn unwind(&mut self, cxt: &mut Cxt) {
+        while let Some(frame) = self.stack.pop() {
+            match frame {
+                ElabFrame::Traced(..) => cxt.tracer.as_ref().unwrap().borrow_mut().exit(),
+                ElabFrame::Lam(_)
+                | ElabFrame::LetRecDef(..)
+                | ElabFrame::LetBody(..)
+                | ElabFrame::PiCod(..)
+                | ElabFrame::SigmaCod(..) => {
+                    cxt.unbind();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn start(&mut self, metas: &mut MetaCxt, cxt: &mut Cxt, node: Node) -> Result<ElabStep, Error> {
+        if let Some(tracer) = cxt.tracer.clone() {
+            let ty = match &node {
+                Node::Check(raw, a) => {
+                    let quotation = quote(metas, cxt.lvl, a.clone());
+                    tracer.borrow_mut().check(&cxt.names(), raw, &quotation);
+                    Some(a.clone())
+                }
+                Node::Infer(raw) => {
+                    tracer.borrow_mut().infer(&cxt.names(), raw);
+                    None
+                }
+            };
+            self.stack.push(ElabFrame::Traced(cxt.pos.clone(), ty));
+        }
+
+        match node {
+            Node::Check(raw, a) => self.start_check(metas, cxt, raw, a),
+            Node::Infer(raw) => self.start_infer(metas, cxt, raw),
+        }
+    }
+
+    fn start_check(
+        &mut self,
+        metas: &mut MetaCxt,
+        cxt: &mut Cxt,
+        raw: Raw,
+        ty: Type,
+    ) -> Result<ElabStep, Error> {
+        use ElabStep::{Done, Start};
+
+        Ok(match (raw, force(metas, ty)) {
             (Raw::RSrcPos(pos, t), a) => {
-                cxt.pos = pos;
-                check(metas, cxt, *t, a)?
+                self.enter(cxt, pos);
+                Start(Node::Check(*t, a))
+            }
+            (Raw::RLam(x, i, t), Value::VΠ(_, i_, a, b)) if i == i_ => {
+                let b = eval_closure(metas, b, Value::VRigid(cxt.lvl, vec![]));
+                self.push_binder(cxt, &x);
+                cxt.push_bound(x, i, Rc::unwrap_or_clone(a));
+                self.stack.push(ElabFrame::Lam(i));
+                Start(Node::Check(*t, b))
             }
-            (Raw::RLam(x, t), Value::VΠ(_, a, b)) => {
+            // insert an implicit or instance lambda
+            (t, Value::VΠ(x, i @ (Icit::Impl | Icit::Inst), a, b)) => {
                 let b = eval_closure(metas, b, Value::VRigid(cxt.lvl, vec![]));
-                let body = cxt.bind(x.clone(), *a, |cxt| check(metas, cxt, *t, b)).0?;
-                Term::Tλ(x, body.into())
+                cxt.push_bound(x, i, Rc::unwrap_or_clone(a));
+                self.stack.push(ElabFrame::Lam(i));
+                Start(Node::Check(t, b))
+            }
+            (Raw::RNatLit(n), Value::VInt) if i64::try_from(n).is_ok() => {
+                Done(Elaborated::Checked(Term::TIntLit(n as i64)))
+            }
+            (Raw::RPair(t, u), Value::VΣ(_, a, b)) => {
+                self.stack.push(ElabFrame::PairFst(*u, b));
+                Start(Node::Check(*t, Rc::unwrap_or_clone(a)))
             }
             (Raw::RLet(x, a, t, u), a_) => {
-                let a = check(metas, cxt, *a, Value::VU)?;
-                let va = eval(metas, Cow::Borrowed(&cxt.env), a.clone());
-                let t = check(metas, cxt, *t, va.clone())?;
-                let vt = eval(metas, Cow::Borrowed(&cxt.env), t.clone());
-                let u = cxt
-                    .define(x.clone(), vt, va, |cxt| check(metas, cxt, *u, a_))
-                    .0?;
-                Term::TLet(x, a.into(), t.into(), u.into())
+                let l = metas.fresh_level();
+                self.push_binder(cxt, &x);
+                self.stack.push(ElabFrame::LetType(x, *t, *u, Some(a_)));
+                Start(Node::Check(*a, Value::VU(l)))
+            }
+            (Raw::RLetRec(x, a, t, u), a_) => {
+                let l = metas.fresh_level();
+                self.push_binder(cxt, &x);
+                self.stack.push(ElabFrame::LetRecType(x, *t, *u, Some(a_)));
+                Start(Node::Check(*a, Value::VU(l)))
+            }
+            (Raw::RHole(None), a) => Done(Elaborated::Checked(metas.fresh_meta(cxt, a))),
+            (Raw::RHole(Some(x)), a) => Done(Elaborated::Checked(metas.fresh_hole(cxt, x, a))),
+            (Raw::RMatch(t, branches), a) => Done(Elaborated::Checked(data::check_match(
+                metas, cxt, *t, branches, a,
+            )?)),
+            (Raw::RRecordLit(fields), Value::VRecord(r, params)) => Done(Elaborated::Checked(
+                record::check_record_lit(metas, cxt, r, params, fields)?,
+            )),
+            (Raw::RIf(b, t, f), a) => {
+                self.stack.push(ElabFrame::IfCond(*t, *f, Some(a)));
+                Start(Node::Check(*b, Value::VBool))
+            }
+            (Raw::RApp(f, u, Icit::Expl), Value::VLazy(a)) if is_prim(cxt, &f, "delay") => {
+                self.stack.push(ElabFrame::Delay);
+                Start(Node::Check(*u, Rc::unwrap_or_clone(a)))
             }
-            (Raw::RHole, _) => metas.fresh_meta(cxt),
             (t, expected) => {
-                let (t, inferred) = infer(metas, cxt, t)?;
-                unify(metas, cxt.lvl, expected, inferred)?;
-                t
+                self.stack.push(ElabFrame::Unify(expected));
+                self.stack.push(ElabFrame::Insert(implicit_lam(&t)));
+                Start(Node::Infer(t))
             }
         })
     }
 
-    match raw {
-        Raw::RSrcPos(pos, t) => {
-            cxt.pos = pos;
-            check(metas, cxt, *t, ty)
-        }
-        raw => {
-            let level = LEVEL.fetch_add(1, Ordering::Relaxed);
-            let quotation = quote(metas, cxt.lvl, ty.clone());
-            println!(
-                "{}check {raw}: {}",
-                " ".repeat(level),
-                TPrettyPrinter(cxt, &quotation)
-            );
-            let res = check_(metas, cxt, raw, ty);
-            LEVEL.swap(level, Ordering::Relaxed);
-            res
-        }
-    }
-}
-
-pub fn close_val(metas: &mut MetaCxt, cxt: &Cxt, val: Value) -> Closure {
-    let lvl = cxt.lvl;
-    let env = cxt.env.clone();
-    let t = quote(metas, lvl + 1, val);
-    (env, t.into())
-}
+    fn start_infer(
+        &mut self,
+        metas: &mut MetaCxt,
+        cxt: &mut Cxt,
+        raw: Raw,
+    ) -> Result<ElabStep, Error> {
+        use ElabStep::{Done, Start};
 
-pub fn infer(metas: &mut MetaCxt, cxt: &mut Cxt, raw: Raw) -> Result<(Term, Type), Error> {
-    fn infer_(metas: &mut MetaCxt, cxt: &mut Cxt, raw: Raw) -> Result<(Term, Type), Error> {
-        Ok(match raw {
+        let (t, a) = match raw {
             Raw::RVar(x) => {
                 let mut res = Err(());
                 for (ix, (x_, r#type)) in cxt.types.iter().rev().enumerate() {
@@ -367,154 +2043,1822 @@ pub fn infer(metas: &mut MetaCxt, cxt: &mut Cxt, raw: Raw) -> Result<(Term, Type
                 }
                 match res {
                     Ok(res) => res,
-                    Err(_) => panic!("unbound variable {x}"),
+                    Err(_) => match builtin(metas, &x) {
+                        Some(res) => res,
+                        None => return error!(metas, cxt, ErrorKind::UnboundVariable(x)),
+                    },
                 }
             }
-            Raw::RLam(mut x, t) => {
-                let mut a = {
-                    let m = metas.fresh_meta(cxt);
+            Raw::RLam(x, i, t) => {
+                let a = {
+                    let m = metas.fresh_type_meta(cxt);
                     eval(metas, Cow::Borrowed(&cxt.env), m)
                 };
-
-                let (t, b) = {
-                    let (res, (x_, a_)) = cxt.bind(x, a, |cxt| infer(metas, cxt, *t));
-                    (x, a) = (x_, a_);
-                    res?
-                };
-
-                (
-                    Term::Tλ(x.clone(), t.into()),
-                    Type::VΠ(x, a.into(), close_val(metas, cxt, b)),
-                )
+                self.push_binder(cxt, &x);
+                cxt.push_bound(x, i, a);
+                self.stack.push(ElabFrame::Lam(i));
+                self.stack.push(ElabFrame::Insert(implicit_lam(&t)));
+                return Ok(Start(Node::Infer(*t)));
             }
-            Raw::RApp(t, u) => {
-                let (t, tty) = infer(metas, cxt, *t)?;
-                let (a, b) = match metas.force(tty) {
-                    Value::VΠ(_, a, b) => (*a, b),
-                    tty => {
-                        let mut a = {
-                            let m = metas.fresh_meta(cxt);
-                            eval(metas, Cow::Borrowed(&cxt.env), m)
-                        };
-                        let (x, b) = {
-                            let (m, (x, a_)) = cxt.bind("a".into(), a, |cxt| metas.fresh_meta(cxt));
-                            a = a_;
-                            (x, (cxt.env.clone(), Box::new(m)))
-                        };
-
-                        unify(
-                            metas,
-                            cxt.lvl,
-                            Value::VΠ(x, a.clone().into(), b.clone()),
-                            tty,
-                        )?;
-                        (a, b)
-                    }
-                };
-                let u = check(metas, cxt, *u, a)?;
-
-                let ty = {
-                    let ty = eval(metas, Cow::Borrowed(&cxt.env), u.clone());
-                    eval_closure(metas, b, ty)
-                };
-
-                (Term::TApp(t.into(), u.into()), ty)
+            Raw::RApp(f, u, Icit::Expl) if is_prim(cxt, &f, "delay") => {
+                self.stack.push(ElabFrame::Delay);
+                self.stack.push(ElabFrame::Insert(implicit_lam(&u)));
+                return Ok(Start(Node::Infer(*u)));
             }
-            Raw::RU => (Term::TU, Value::VU),
-            Raw::RPi(mut x, a, b) => {
-                let a = check(metas, cxt, *a, Value::VU)?;
-                let b = {
-                    let va = eval(metas, Cow::Borrowed(&cxt.env), a.clone());
-                    let (b, (x_, _)) = cxt.bind(x, va, |cxt| check(metas, cxt, *b, Value::VU));
-                    x = x_;
-                    b?
+            Raw::RApp(f, a, Icit::Expl) if is_prim(cxt, &f, "Lazy") => {
+                let l = metas.fresh_level();
+                self.stack.push(ElabFrame::Lazy(l));
+                return Ok(Start(Node::Check(*a, Value::VU(l))));
+            }
+            Raw::RApp(t, u, i) => {
+                self.stack.push(ElabFrame::AppFun(*u, i));
+                return Ok(Start(Node::Infer(*t)));
+            }
+            Raw::RAppNamed(t, x, u) => {
+                self.stack.push(ElabFrame::AppNamedFun(x, *u));
+                return Ok(Start(Node::Infer(*t)));
+            }
+            Raw::RNatLit(n) => (Term::TNatLit(n), Value::VNat),
+            Raw::RStrLit(s) => (Term::TStrLit(s), Value::VString),
+            Raw::RCharLit(c) => (Term::TCharLit(c), Value::VChar),
+            Raw::RIntLit(n) => (Term::TIntLit(n), Value::VInt),
+            Raw::RField(t, x) => record::infer_field(metas, cxt, *t, x)?,
+            Raw::RRecordLit(fields) => record::infer_record_lit(metas, cxt, fields)?,
+            raw @ Raw::RMatch(..) => {
+                let a = metas.fresh_type_meta(cxt);
+                let a = eval(metas, Cow::Borrowed(&cxt.env), a);
+                self.stack.push(ElabFrame::Match(a.clone()));
+                return Ok(Start(Node::Check(raw, a)));
+            }
+            Raw::RIf(b, t, f) => {
+                self.stack.push(ElabFrame::IfCond(*t, *f, None));
+                return Ok(Start(Node::Check(*b, Value::VBool)));
+            }
+            Raw::RU(l) => {
+                let l = match l {
+                    Some(n) => Level::LConst(n),
+                    None => metas.fresh_level(),
                 };
-
-                (Term::TΠ(x, a.into(), b.into()), Value::VU)
+                (Term::TU(l), Value::VU(l.suc()))
+            }
+            Raw::RPi(x, i, a, b) => {
+                let la = metas.fresh_level();
+                self.push_binder(cxt, &x);
+                self.stack.push(ElabFrame::PiDom(x, i, *b, la));
+                return Ok(Start(Node::Check(*a, Value::VU(la))));
+            }
+            Raw::RSigma(x, a, b) => {
+                let la = metas.fresh_level();
+                self.stack.push(ElabFrame::SigmaDom(x, *b, la));
+                return Ok(Start(Node::Check(*a, Value::VU(la))));
+            }
+            Raw::RPair(t, u) => {
+                self.stack.push(ElabFrame::InferPairFst(*u));
+                return Ok(Start(Node::Infer(*t)));
             }
             Raw::RLet(x, a, t, u) => {
-                let a = check(metas, cxt, *a, Value::VU)?;
-
-                let va = eval(metas, Cow::Borrowed(&cxt.env), a.clone());
-                let t = check(metas, cxt, *t, va.clone())?;
-
-                let vt = eval(metas, Cow::Borrowed(&cxt.env), t.clone());
-                let (u, b) = cxt
-                    .define(x.clone(), vt, va, |cxt| infer(metas, cxt, *u))
-                    .0?;
-
-                (Term::TLet(x, a.into(), t.into(), u.into()), b)
+                let l = metas.fresh_level();
+                self.push_binder(cxt, &x);
+                self.stack.push(ElabFrame::LetType(x, *t, *u, None));
+                return Ok(Start(Node::Check(*a, Value::VU(l))));
+            }
+            Raw::RLetRec(x, a, t, u) => {
+                let l = metas.fresh_level();
+                self.push_binder(cxt, &x);
+                self.stack.push(ElabFrame::LetRecType(x, *t, *u, None));
+                return Ok(Start(Node::Check(*a, Value::VU(l))));
             }
             Raw::RSrcPos(pos, t) => {
-                cxt.pos = pos;
-                infer(metas, cxt, *t)?
+                self.enter(cxt, pos);
+                return Ok(Start(Node::Infer(*t)));
             }
-            Raw::RHole => {
+            Raw::RHole(x) => {
                 let a = {
-                    let m = metas.fresh_meta(cxt);
+                    let m = metas.fresh_type_meta(cxt);
                     eval(metas, Cow::Borrowed(&cxt.env), m)
                 };
-                let t = metas.fresh_meta(cxt);
+                let t = match x {
+                    Some(x) => metas.fresh_hole(cxt, x, a.clone()),
+                    None => metas.fresh_meta(cxt, a.clone()),
+                };
                 (t, a)
             }
-        })
+            Raw::RProj1(t) => {
+                self.stack.push(ElabFrame::Proj1);
+                return Ok(Start(Node::Infer(*t)));
+            }
+            Raw::RProj2(t) => {
+                self.stack.push(ElabFrame::Proj2);
+                return Ok(Start(Node::Infer(*t)));
+            }
+        };
+        Ok(Done(Elaborated::Inferred(t, a)))
     }
 
-    match raw {
-        Raw::RSrcPos(pos, raw) => {
-            cxt.pos = pos;
-            infer(metas, cxt, *raw)
-        }
-        raw => {
-            let level = LEVEL.fetch_add(1, Ordering::Relaxed);
-            println!("{}infer {}", " ".repeat(level), &raw);
-            let res = infer_(metas, cxt, raw);
-            LEVEL.swap(level, Ordering::Relaxed);
-
-            if let Ok((term, value)) = &res {
-                let quotation = quote(metas, cxt.lvl, value.clone());
-                print!("{}|- {}: ", " ".repeat(level), TPrettyPrinter(cxt, term));
-                println!("{}", TPrettyPrinter(cxt, &quotation));
+    fn resume(
+        &mut self,
+        metas: &mut MetaCxt,
+        cxt: &mut Cxt,
+        frame: ElabFrame,
+        res: Elaborated,
+    ) -> Result<ElabStep, Error> {
+        use ElabStep::{Done, Start};
+        use Elaborated::{Checked, Inferred};
+
+        Ok(match (frame, res) {
+            (ElabFrame::Traced(pos, ty), res) => {
+                let tracer = cxt.tracer.clone().unwrap();
+                tracer.borrow_mut().exit();
+                let names = cxt.names();
+                let (term, quotation) = match (&res, ty) {
+                    (Inferred(term, value), _) => {
+                        let quotation = quote(metas, cxt.lvl, value.clone());
+                        tracer.borrow_mut().inferred(&names, term, &quotation);
+                        (term, quotation)
+                    }
+                    (Checked(term), Some(ty)) => (term, quote(metas, cxt.lvl, ty)),
+                    (Checked(_), None) => unreachable!(),
+                };
+                tracer.borrow_mut().typed(&names, &pos, term, &quotation);
+                Done(res)
             }
-
-            res
+            (ElabFrame::Binder(pos, shadows), res) => {
+                let (Checked(t) | Inferred(t, _)) = &res;
+                if let Term::Tλ(x, _, body) | Term::TΠ(x, _, _, body) | Term::TLet(x, _, _, body) =
+                    t
+                {
+                    metas.record_binder(pos, x.clone(), body.clone(), shadows);
+                }
+                Done(res)
+            }
+            (ElabFrame::SrcPos(pos), Checked(t)) => Done(Checked(Term::TSrcPos(pos, t.into()))),
+            (ElabFrame::SrcPos(pos), Inferred(t, a)) => {
+                Done(Inferred(Term::TSrcPos(pos, t.into()), a))
+            }
+            (ElabFrame::Lam(i), Checked(t)) => {
+                let (x, _, _) = cxt.unbind();
+                Done(Checked(Term::Tλ(x, i, t.into())))
+            }
+            (ElabFrame::Lam(i), Inferred(t, b)) => {
+                let (x, a, _) = cxt.unbind();
+                Done(Inferred(
+                    Term::Tλ(x.clone(), i, t.into()),
+                    Type::VΠ(x, i, a.into(), close_val(metas, cxt, b)),
+                ))
+            }
+            (ElabFrame::PairFst(u, b), Checked(t)) => {
+                let b = {
+                    let vt = eval(metas, Cow::Borrowed(&cxt.env), t.clone());
+                    eval_closure(metas, b, vt)
+                };
+                self.stack.push(ElabFrame::PairSnd(t));
+                Start(Node::Check(u, b))
+            }
+            (ElabFrame::PairSnd(t), Checked(u)) => Done(Checked(Term::Tσ(t.into(), u.into()))),
+            (ElabFrame::LetType(x, t, u, expected), Checked(a)) => {
+                let va = eval(metas, Cow::Borrowed(&cxt.env), a.clone());
+                self.stack
+                    .push(ElabFrame::LetDef(x, a, va.clone(), u, expected));
+                Start(Node::Check(t, va))
+            }
+            (ElabFrame::LetDef(x, a, va, u, expected), Checked(t)) => {
+                let vt = eval_arg(metas, &cxt.env, t.clone());
+                cxt.push_defined(x, vt, va);
+                self.stack.push(ElabFrame::LetBody(a, t));
+                Start(node(u, expected))
+            }
+            (ElabFrame::LetRecType(x, t, u, expected), Checked(a)) => {
+                let va = eval(metas, Cow::Borrowed(&cxt.env), a.clone());
+                let pos = cxt.pos.clone();
+                cxt.push_bound(x.clone(), Icit::Expl, va.clone());
+                self.stack
+                    .push(ElabFrame::LetRecDef(x, a, va.clone(), u, expected, pos));
+                Start(Node::Check(t, va))
+            }
+            (ElabFrame::LetRecDef(x, a, va, u, expected, pos), Checked(t)) => {
+                cxt.unbind();
+                cxt.pos = pos;
+                let fix = Term::TFix(vec![(x.clone(), Unfolds::Now, t.into())].into(), 0);
+                let t = termination::check(metas, cxt, fix)?;
+                let vt = eval(metas, Cow::Borrowed(&cxt.env), t.clone());
+                cxt.push_defined(x, vt, va);
+                self.stack.push(ElabFrame::LetBody(a, t));
+                Start(node(u, expected))
+            }
+            (ElabFrame::LetBody(a, t), Checked(u)) => {
+                let (x, _, _) = cxt.unbind();
+                Done(Checked(Term::TLet(x, a.into(), t.into(), u.into())))
+            }
+            (ElabFrame::LetBody(a, t), Inferred(u, b)) => {
+                let (x, _, _) = cxt.unbind();
+                Done(Inferred(Term::TLet(x, a.into(), t.into(), u.into()), b))
+            }
+            (ElabFrame::IfCond(t, f, Some(a)), Checked(b)) => {
+                self.stack.push(ElabFrame::IfThen(b, f, Some(a.clone())));
+                Start(Node::Check(t, a))
+            }
+            (ElabFrame::IfCond(t, f, None), Checked(b)) => {
+                self.stack.push(ElabFrame::IfThen(b, f, None));
+                self.stack.push(ElabFrame::Insert(implicit_lam(&t)));
+                Start(Node::Infer(t))
+            }
+            (ElabFrame::IfThen(b, f, Some(a)), Checked(t)) => {
+                self.stack.push(ElabFrame::IfElse(b, t, a.clone(), false));
+                Start(Node::Check(f, a))
+            }
+            (ElabFrame::IfThen(b, f, None), Inferred(t, a)) => {
+                self.stack.push(ElabFrame::IfElse(b, t, a.clone(), true));
+                Start(Node::Check(f, a))
+            }
+            (ElabFrame::IfElse(b, t, a, inferred), Checked(f)) => {
+                let p = Term::Tλ(
+                    "_".into(),
+                    Icit::Expl,
+                    quote(metas, cxt.lvl + 1, a.clone()).into(),
+                );
+                let t = Term::TBoolElim(p.into(), t.into(), f.into(), b.into());
+                match inferred {
+                    true => Done(Inferred(t, a)),
+                    false => Done(Checked(t)),
+                }
+            }
+            (ElabFrame::Insert(true), res @ Inferred(..)) => Done(res),
+            (ElabFrame::Insert(false), Inferred(t, a)) => {
+                let (t, a) = insert_implicits(metas, cxt, (t, a));
+                Done(Inferred(t, a))
+            }
+            (ElabFrame::Unify(expected), Inferred(t, inferred)) => {
+                subtype(metas, cxt, expected, inferred)?;
+                Done(Checked(t))
+            }
+            (ElabFrame::AppFun(u, i), Inferred(t, tty)) => {
+                let (t, tty) = match i {
+                    Icit::Impl => (t, tty),
+                    Icit::Inst => insert_until_instance(metas, cxt, (t, tty)),
+                    Icit::Expl => insert_implicits(metas, cxt, (t, tty)),
+                };
+                let (a, b) = app_domain(metas, cxt, tty, i, &u)?;
+                self.stack.push(ElabFrame::AppArg(t, b, i));
+                Start(Node::Check(u, a))
+            }
+            (ElabFrame::AppNamedFun(x, u), Inferred(t, tty)) => {
+                let (t, tty) = insert_until_name(metas, cxt, &x, (t, tty))?;
+                // named arguments are implicit or instance ones
+                let i = match &tty {
+                    Value::VΠ(_, i, _, _) => *i,
+                    _ => Icit::Impl,
+                };
+                let (a, b) = app_domain(metas, cxt, tty, i, &u)?;
+                self.stack.push(ElabFrame::AppArg(t, b, i));
+                Start(Node::Check(u, a))
+            }
+            (ElabFrame::AppArg(t, b, i), Checked(u)) => {
+                let ty = {
+                    let u = eval_arg(metas, &cxt.env, u.clone());
+                    eval_closure(metas, b, u)
+                };
+                Done(Inferred(Term::TApp(t.into(), u.into(), i), ty))
+            }
+            (ElabFrame::Match(a), Checked(t)) => Done(Inferred(t, a)),
+            (ElabFrame::PiDom(x, i, b, la), Checked(a)) => {
+                let va = eval(metas, Cow::Borrowed(&cxt.env), a.clone());
+                cxt.push_bound(x, Icit::Expl, va);
+                let lb = metas.fresh_level();
+                self.stack.push(ElabFrame::PiCod(i, a, la, lb));
+                Start(Node::Check(b, Value::VU(lb)))
+            }
+            (ElabFrame::PiCod(i, a, la, lb), Checked(b)) => {
+                let (x, _, _) = cxt.unbind();
+                let l = max_level(metas, cxt, la, lb);
+                Done(Inferred(Term::TΠ(x, i, a.into(), b.into()), Value::VU(l)))
+            }
+            (ElabFrame::SigmaDom(x, b, la), Checked(a)) => {
+                let va = eval(metas, Cow::Borrowed(&cxt.env), a.clone());
+                cxt.push_bound(x, Icit::Expl, va);
+                let lb = metas.fresh_level();
+                self.stack.push(ElabFrame::SigmaCod(a, la, lb));
+                Start(Node::Check(b, Value::VU(lb)))
+            }
+            (ElabFrame::SigmaCod(a, la, lb), Checked(b)) => {
+                let (x, _, _) = cxt.unbind();
+                let l = max_level(metas, cxt, la, lb);
+                Done(Inferred(Term::TΣ(x, a.into(), b.into()), Value::VU(l)))
+            }
+            (ElabFrame::InferPairFst(u), Inferred(t, a)) => {
+                self.stack.push(ElabFrame::InferPairSnd(t, a));
+                Start(Node::Infer(u))
+            }
+            (ElabFrame::InferPairSnd(t, a), Inferred(u, b)) => Done(Inferred(
+                Term::Tσ(t.into(), u.into()),
+                Type::VΣ("_".into(), a.into(), close_val(metas, cxt, b)),
+            )),
+            (ElabFrame::Proj1, Inferred(t, tty)) => {
+                let (a, _) = sigma_components(metas, cxt, tty)?;
+                Done(Inferred(Term::TProj1(t.into()), a))
+            }
+            (ElabFrame::Proj2, Inferred(t, tty)) => {
+                let (_, b) = sigma_components(metas, cxt, tty)?;
+                let ty = {
+                    let t = eval(metas, Cow::Borrowed(&cxt.env), t.clone());
+                    eval_closure(metas, b, v_proj1(t))
+                };
+                Done(Inferred(Term::TProj2(t.into()), ty))
+            }
+            (ElabFrame::Delay, Checked(t)) => Done(Checked(Term::TDelay(t.into()))),
+            (ElabFrame::Delay, Inferred(t, a)) => {
+                Done(Inferred(Term::TDelay(t.into()), Value::VLazy(a.into())))
+            }
+            (ElabFrame::Lazy(l), Checked(a)) => Done(Inferred(Term::TLazy(a.into()), Value::VU(l))),
+            // every frame is resumed by the kind of node it starts
+            _ => unreachable!(),
+        })
+    }
+}
+
+/// Elaborated top-level definition
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Definition {
+    pub name: Name,
+    pub ty: Term,
+    /// the body, which an axiom has none of
+    pub tm: Option<Term>,
+}
+
+/// Elaborated program: its declarations and the definitions they elaborate to
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CheckedModule {
+    pub program: Program,
+    pub defs: Vec<Definition>,
+}
+
+/// Elaborate `def x : a := t` and bring `x` into scope for the rest of the program.
+pub fn check_definition(
+    metas: &mut MetaCxt,
+    cxt: &mut Cxt,
+    x: Name,
+    a: Raw,
+    t: Raw,
+) -> Result<Definition, Error> {
+    let mut defs = check_definitions(metas, cxt, vec![(x, a, t)])?;
+    Ok(defs.pop().unwrap())
+}
+
+/// Elaborate `instance x : a := t`, a definition that instance arguments can
+/// be found among from then on.
+pub fn check_instance(
+    metas: &mut MetaCxt,
+    cxt: &mut Cxt,
+    x: Name,
+    a: Raw,
+    t: Raw,
+) -> Result<Definition, Error> {
+    let def = check_definition(metas, cxt, x, a, t)?;
+    cxt.instances.push(cxt.lvl - 1);
+    Ok(def)
+}
+
+/// Elaborate `opaque x : a := t`, a definition that unification and normal
+/// forms don't unfold from then on, unless [`ElabOptions::unfold_opaque`].
+pub fn check_opaque(
+    metas: &mut MetaCxt,
+    cxt: &mut Cxt,
+    x: Name,
+    a: Raw,
+    t: Raw,
+) -> Result<Definition, Error> {
+    let def = check_definition(metas, cxt, x.clone(), a, t)?;
+    if !cxt.options.unfold_opaque {
+        // glued anew, as the value may be shared with the definition it is
+        // an alias of
+        let v = Rc::new(cxt.env.pop().unwrap());
+        cxt.env.push(Value::VGlued(x.clone(), v.clone(), vec![]));
+        metas.set_opaque(x, v, cxt.lvl - 1);
+    }
+    Ok(def)
+}
+
+/// Elaborate a block of definitions `def x : a := t` that may refer to each
+/// other, the types first and then the bodies, and bring them into scope in
+/// order.
+pub fn check_definitions(
+    metas: &mut MetaCxt,
+    cxt: &mut Cxt,
+    defs: Vec<(Name, Raw, Raw)>,
+) -> Result<Vec<Definition>, Error> {
+    metas.set_fuel(cxt.options.fuel);
+    metas.set_max_nodes(cxt.options.max_nodes);
+    let defs = check_block(metas, cxt, defs);
+    let out_of_nodes = metas.out_of_nodes();
+    metas.set_fuel(None);
+    metas.set_max_nodes(None);
+    match (defs, cxt.options.max_nodes) {
+        // whatever went wrong, it may be as the terms were cut short
+        (_, Some(nodes)) if out_of_nodes => {
+            error!(metas, cxt, ErrorKind::ResourceExhausted { nodes })
+        }
+        (defs, _) => defs,
+    }
+}
+
+fn check_block(
+    metas: &mut MetaCxt,
+    cxt: &mut Cxt,
+    defs: Vec<(Name, Raw, Raw)>,
+) -> Result<Vec<Definition>, Error> {
+    // left over by a definition that failed
+    metas.take_postponed();
+    metas.take_instances();
+    // the metas of earlier definitions are theirs alone
+    metas.freeze();
+
+    let mut types = Vec::new();
+    let mut block = Vec::new();
+    for (x, a, _) in &defs {
+        let (a, _) = check_type(metas, cxt, a.clone())?;
+        let va = eval(metas, Cow::Borrowed(&cxt.env), a.clone());
+        types.push(a);
+        block.push((x.clone(), va));
+    }
+
+    let pos = cxt.pos.clone();
+    // recursive definitions refer to the block as bound variables
+    let t = match &defs[..] {
+        [(x, _, t)] if !termination::mentions(t, x) => {
+            let (_, _, t) = defs.into_iter().next().unwrap();
+            check(metas, cxt, t, block[0].1.clone())?
+        }
+        _ => {
+            let bodies = data::bind_all(metas, cxt, &block, |metas, cxt| {
+                defs.into_iter()
+                    .zip(&block)
+                    .map(|((_, _, t), (_, va))| check(metas, cxt, t, va.clone()))
+                    .collect::<Result<Vec<_>, _>>()
+            })?;
+            let fix = block
+                .iter()
+                .zip(bodies)
+                .map(|((x, _), t)| (x.clone(), Unfolds::Now, t.into()))
+                .collect();
+            Term::TFix(fix, 0)
+        }
+    };
+
+    instance::check_found(metas)?;
+    if let Some(p) = metas.take_postponed().into_iter().next() {
+        return Err(Error::stuck(metas, p));
+    }
+    check_levels(metas, cxt)?;
+
+    let types: Vec<_> = types.into_iter().map(|a| zonk(metas, cxt, a)).collect();
+    let t = zonk(metas, cxt, t);
+    cxt.pos = pos;
+    let t = termination::check(metas, cxt, t)?;
+    let (types, t) = match t {
+        Term::TFix(..) => (types, t),
+        t if cxt.options.generalize => {
+            let (a, t) = generalize(metas, cxt, types[0].clone(), t);
+            block[0].1 = eval(metas, Cow::Borrowed(&cxt.env), a.clone());
+            (vec![a], t)
+        }
+        t => (types, t),
+    };
+
+    // interned before they are evaluated, so that the closures of their
+    // values share the nodes of equal terms
+    let t = Rc::unwrap_or_clone(metas.intern(&t));
+    let types: Vec<_> = types
+        .iter()
+        .map(|a| Rc::unwrap_or_clone(metas.intern(a)))
+        .collect();
+
+    // the definitions after the first are weakened past the ones before them
+    let env = cxt.env.clone();
+    let never = unfold_env(cxt, Unfold::Never).into_owned();
+    let mut defs = Vec::new();
+    for (i, ((x, va), a)) in block.into_iter().zip(types).enumerate() {
+        let t = match &t {
+            Term::TFix(fix, _) => Term::TFix(fix.clone(), i),
+            t => t.clone(),
+        };
+        let vt = eval(metas, Cow::Borrowed(&env), t.clone());
+        let (a, t) = match i {
+            0 => (a, t),
+            _ => {
+                let a = eval(metas, Cow::Borrowed(&never), a);
+                let t = eval(metas, Cow::Borrowed(&never), t);
+                let [a, t] = [a, t].map(|v| {
+                    let t = quote(metas, cxt.lvl, v);
+                    Rc::unwrap_or_clone(metas.intern(&t))
+                });
+                (a, t)
+            }
+        };
+        cxt.define_global(x.clone(), vt, va);
+        defs.push(Definition {
+            name: x,
+            ty: a,
+            tm: Some(t),
+        });
+    }
+
+    Ok(defs)
+}
+
+/// Elaborate `axiom x : a`, a constant of type `a` with no definition, and
+/// bring it into scope for the rest of the program. It never unfolds, and is
+/// equal only to itself.
+pub fn check_axiom(
+    metas: &mut MetaCxt,
+    cxt: &mut Cxt,
+    x: Name,
+    a: Raw,
+) -> Result<Definition, Error> {
+    // left over by a definition that failed
+    metas.take_postponed();
+    metas.take_instances();
+    metas.freeze();
+
+    let (a, _) = check_type(metas, cxt, a)?;
+    instance::check_found(metas)?;
+    if let Some(p) = metas.take_postponed().into_iter().next() {
+        return Err(Error::stuck(metas, p));
+    }
+    check_levels(metas, cxt)?;
+
+    let a = zonk(metas, cxt, a);
+    let va = eval(metas, Cow::Borrowed(&cxt.env), a.clone());
+    // there is nothing to unfold to, the constant stands for itself
+    let def = Rc::new(Value::VRigid(cxt.lvl, vec![]));
+    metas.set_axiom(x.clone(), def.clone(), cxt.lvl);
+    cxt.define_global(x.clone(), Value::VGlued(x.clone(), def, vec![]), va);
+    Ok(Definition {
+        name: x,
+        ty: a,
+        tm: None,
+    })
+}
+
+/// With [`ElabOptions::generalize`], abstract the unsolved metas of the
+/// zonked type `a` and body `t` of a definition over implicit arguments,
+/// giving `{A : U} → a` and `λ {A}. t`, see [`generalizable`] for which
+/// are. Those are marked generalized, the others stay unsolved.
+fn generalize(metas: &mut MetaCxt, cxt: &Cxt, a: Term, t: Term) -> (Term, Term) {
+    let mut order = Vec::new();
+    let mut rejected = Vec::new();
+    for m in a.metas().into_iter().chain(t.metas()) {
+        generalizable(metas, cxt, m, &mut order, &mut rejected);
+    }
+
+    let gens: Vec<MetaVar> = order.iter().map(|(m, _)| *m).collect();
+    let n = gens.len() as isize;
+    let mut a = rebind(a, &gens, n, 0);
+    let mut t = rebind(t, &gens, n, 0);
+    for (j, (m, ty)) in order.into_iter().enumerate().rev() {
+        let x = generalized_name(j, &ty);
+        let ty = rebind(ty, &gens[..j], j as isize, 0);
+        a = Term::TΠ(x.clone(), Icit::Impl, ty.into(), a.into());
+        t = Term::Tλ(x, Icit::Impl, t.into());
+        metas.set_generalized(m);
+    }
+    (a, t)
+}
+
+/// Whether `m` can be generalized over, adding it to `order` with its zonked
+/// type after the metas of its type if so: it must be an unsolved meta of the
+/// definition, not a hole, created in the top-level context `cxt` or under
+/// binders of the definition, but not under `let`s.
+fn generalizable(
+    metas: &mut MetaCxt,
+    cxt: &Cxt,
+    m: MetaVar,
+    order: &mut Vec<(MetaVar, Term)>,
+    rejected: &mut Vec<MetaVar>,
+) -> bool {
+    if order.iter().any(|(m_, _)| *m_ == m) {
+        return true;
+    }
+    if rejected.contains(&m) || metas.is_frozen(m) || metas.origin(m).hole.is_some() {
+        return false;
+    }
+    // rejected while its type is visited, in case it mentions `m`
+    rejected.push(m);
+    let Some(ty) = meta_type(metas, cxt, m) else {
+        return false;
+    };
+    // solved metas are gone once zonked
+    if !ty
+        .metas()
+        .into_iter()
+        .all(|m_| generalizable(metas, cxt, m_, order, rejected))
+    {
+        return false;
+    }
+    rejected.retain(|m_| *m_ != m);
+    order.push((m, ty));
+    true
+}
+
+/// The zonked type of `m` in the top-level context `cxt`, a function type of
+/// the variables the meta is applied to, if it is known.
+fn meta_type(metas: &mut MetaCxt, cxt: &Cxt, m: MetaVar) -> Option<Term> {
+    let origin = metas.origin(m).clone();
+    if let Some((m_, keep)) = origin.pruned {
+        let ty = meta_type(metas, cxt, m_)?;
+        return prune_pis(ty, &keep);
+    }
+    if origin.bds.len() < cxt.lvl
+        || origin.bds[..cxt.lvl].contains(&BD::Bound)
+        || origin.bds[cxt.lvl..].contains(&BD::Defined)
+    {
+        return None;
+    }
+
+    let mut ty = origin.ty;
+    for (lvl, (x, a)) in origin.types.into_iter().enumerate().skip(cxt.lvl).rev() {
+        let a = quote(metas, lvl, a);
+        ty = Term::TΠ(x, Icit::Expl, a.into(), ty.into());
+    }
+    Some(zonk(metas, cxt, ty))
+}
+
+/// Keep the domains of the function type `ty` that `keep` says, if the rest
+/// of it doesn't depend on the others.
+fn prune_pis(ty: Term, keep: &[bool]) -> Option<Term> {
+    let Some((kept, keep)) = keep.split_first() else {
+        return Some(ty);
+    };
+    let Term::TΠ(x, i, a, b) = ty else {
+        return None;
+    };
+    let b = Rc::unwrap_or_clone(b);
+    match kept {
+        true => Some(Term::TΠ(x, i, a, prune_pis(b, keep)?.into())),
+        false if b.occurs(Ix(0), &mut |_, _, _| true) => None,
+        false => prune_pis(rebind(b, &[], -1, 0), keep),
+    }
+}
+
+/// Name of the `j`th argument of a generalized definition: `A`, `B`, ... for
+/// types and type families and `a`, `b`, ... for the others.
+fn generalized_name(j: usize, mut ty: &Term) -> Name {
+    while let Term::TΠ(_, _, _, b) = ty {
+        ty = b;
+    }
+    let first = match ty {
+        Term::TU(_) => b'A',
+        _ => b'a',
+    };
+    let letter = (first + (j % 26) as u8) as char;
+    match j / 26 {
+        0 => letter.to_string().into(),
+        k => format!("{letter}{k}").into(),
+    }
+}
+
+/// Shift the variables of a term under `depth` binders bound outside of it
+/// by `by`, and replace the metas `gens` by the variables of the `gens.len()`
+/// binders just outside, the last one innermost.
+fn rebind(term: Term, gens: &[MetaVar], by: isize, depth: usize) -> Term {
+    let go = |t: Tm| Rc::new(rebind(Rc::unwrap_or_clone(t), gens, by, depth));
+    let under = |t: Tm| Rc::new(rebind(Rc::unwrap_or_clone(t), gens, by, depth + 1));
+
+    match term {
+        Term::TV(x) if x.0 >= depth => Term::TV(Ix(x.0.wrapping_add_signed(by))),
+        Term::TV(x) => Term::TV(x),
+        Term::TMeta(m) | Term::TInsertedMeta(m, _) if gens.contains(&m) => {
+            let j = gens.iter().position(|m_| *m_ == m).unwrap();
+            Term::TV(Ix(depth + gens.len() - 1 - j))
+        }
+        t @ (Term::TMeta(_) | Term::TInsertedMeta(..)) => t,
+        Term::Tλ(x, i, t) => Term::Tλ(x, i, under(t)),
+        Term::TFix(fix, i) => {
+            let len = fix.len();
+            let fix = fix
+                .iter()
+                .map(|(x, k, t)| {
+                    let t = rebind(Rc::unwrap_or_clone(t.clone()), gens, by, depth + len);
+                    (x.clone(), *k, Rc::new(t))
+                })
+                .collect();
+            Term::TFix(fix, i)
+        }
+        Term::TΠ(x, i, a, b) => Term::TΠ(x, i, go(a), under(b)),
+        Term::Tσ(t, u) => Term::Tσ(go(t), go(u)),
+        Term::TΣ(x, a, b) => Term::TΣ(x, go(a), under(b)),
+        Term::TLet(x, a, t, u) => Term::TLet(x, go(a), go(t), under(u)),
+        Term::TApp(t, u, i) => Term::TApp(go(t), go(u), i),
+        Term::TU(l) => Term::TU(l),
+        Term::TProj1(t) => Term::TProj1(go(t)),
+        Term::TProj2(t) => Term::TProj2(go(t)),
+        Term::TNat => Term::TNat,
+        Term::TNatLit(n) => Term::TNatLit(n),
+        Term::TSuc(t) => Term::TSuc(go(t)),
+        Term::TNatElim(p, z, s, n) => Term::TNatElim(go(p), go(z), go(s), go(n)),
+        Term::TBool => Term::TBool,
+        Term::TTrue => Term::TTrue,
+        Term::TFalse => Term::TFalse,
+        Term::TBoolElim(p, t, f, b) => Term::TBoolElim(go(p), go(t), go(f), go(b)),
+        Term::TString => Term::TString,
+        Term::TStrLit(s) => Term::TStrLit(s),
+        Term::TChar => Term::TChar,
+        Term::TCharLit(c) => Term::TCharLit(c),
+        Term::TAppend(s, t) => Term::TAppend(go(s), go(t)),
+        Term::TLength(s) => Term::TLength(go(s)),
+        Term::TLazy(a) => Term::TLazy(go(a)),
+        Term::TDelay(t) => Term::TDelay(go(t)),
+        Term::TForce(t) => Term::TForce(go(t)),
+        Term::TInt => Term::TInt,
+        Term::TIntLit(n) => Term::TIntLit(n),
+        Term::TIntOp(op, x, y) => Term::TIntOp(op, go(x), go(y)),
+        Term::TEq(a, x, y) => Term::TEq(go(a), go(x), go(y)),
+        Term::TRefl(a, x) => Term::TRefl(go(a), go(x)),
+        Term::TJ(a, x, p, pr, y, e) => {
+            let [a, x, p, pr, y, e] = [a, x, p, pr, y, e].map(go);
+            Term::TJ(a, x, p, pr, y, e)
+        }
+        Term::TData(d, args) => Term::TData(d, args.into_iter().map(go).collect()),
+        Term::TCon(d, c, args) => Term::TCon(d, c, args.into_iter().map(go).collect()),
+        Term::TElim(d, args, t) => Term::TElim(d, args.into_iter().map(go).collect(), go(t)),
+        Term::TRecord(r, args) => Term::TRecord(r, args.into_iter().map(go).collect()),
+        Term::TRecordLit(r, fields) => Term::TRecordLit(r, fields.into_iter().map(go).collect()),
+        Term::TField(r, f, t) => Term::TField(r, f, go(t)),
+        Term::TSrcPos(pos, t) => Term::TSrcPos(pos, go(t)),
+    }
+}
+
+/// Elaborate a `mutual` block: the data types, whose constructors may refer to
+/// each other, then the definitions.
+pub fn check_mutual(
+    metas: &mut MetaCxt,
+    cxt: &mut Cxt,
+    data: Vec<DataDecl>,
+    defs: Vec<(Name, Raw, Raw)>,
+) -> Result<Vec<Definition>, Error> {
+    let mut block = data::check_data_block(metas, cxt, data)?;
+    if !defs.is_empty() {
+        block.extend(check_definitions(metas, cxt, defs)?);
+    }
+    Ok(block)
+}
+
+/// Check that the universe levels are consistent, unless the options of
+/// `cxt` make `U : U` hold. Then the constraints that make them inconsistent
+/// are dropped all the same, so that they aren't held against the
+/// definitions checked without, e.g. after a pragma `#[type_in_type]`.
+pub fn check_levels(metas: &mut MetaCxt, cxt: &Cxt) -> Result<(), Error> {
+    let res = metas.check_levels();
+    if cxt.options.type_in_type {
+        return Ok(());
+    }
+    res
+}
+
+/// Elaborate the declarations of a program in order, each one in the scope of
+/// the definitions before it.
+///
+/// Programs with imports have to go through a [`modules::ModuleLoader`].
+pub fn check_program(
+    metas: &mut MetaCxt,
+    cxt: &mut Cxt,
+    program: Program,
+) -> Result<Vec<Definition>, Error> {
+    // what the pragmas of the program set ends with it, even if it fails
+    let scope = cxt.begin_pragmas();
+    let res = check_decls(metas, cxt, program);
+    cxt.end_pragmas(scope);
+    res
+}
+
+fn check_decls(
+    metas: &mut MetaCxt,
+    cxt: &mut Cxt,
+    program: Program,
+) -> Result<Vec<Definition>, Error> {
+    let mut defs = Vec::new();
+
+    for decl in program {
+        let (decl, scope) = check_pragmas(metas, cxt, decl);
+        match decl {
+            Decl::DDef(x, a, t) => defs.push(check_definition(metas, cxt, x, a, t)?),
+            Decl::DData(x, params, a, cons) => {
+                defs.extend(data::check_data(metas, cxt, x, params, a, cons)?)
+            }
+            Decl::DRecord(x, params, a, fields) => {
+                defs.extend(record::check_record(metas, cxt, x, params, a, fields)?)
+            }
+            Decl::DMutual(data, defs_) => defs.extend(check_mutual(metas, cxt, data, defs_)?),
+            Decl::DInstance(x, a, t) => defs.push(check_instance(metas, cxt, x, a, t)?),
+            Decl::DOpaque(x, a, t) => defs.push(check_opaque(metas, cxt, x, a, t)?),
+            Decl::DAxiom(x, a) => defs.push(check_axiom(metas, cxt, x, a)?),
+            Decl::DImport(path) => return error!(metas, cxt, ErrorKind::UnresolvedImport(path)),
+            Decl::DPragma(..) => unreachable!("interpreted by check_pragmas"),
+            // which holds for the rest of the program
+            Decl::DFilePragma(p) => {
+                pragma(metas, cxt, &p);
+                continue;
+            }
+        }
+        cxt.end_pragmas(scope);
+    }
+
+    Ok(defs)
+}
+
+/// Interpret the pragmas in front of a declaration, giving the declaration
+/// and what to go back to with [`Cxt::end_pragmas`] once it is elaborated.
+pub fn check_pragmas(metas: &mut MetaCxt, cxt: &mut Cxt, mut decl: Decl) -> (Decl, PragmaScope) {
+    let scope = cxt.begin_pragmas();
+    while let Decl::DPragma(p, d) = decl {
+        pragma(metas, cxt, &p);
+        decl = *d;
+    }
+    (decl, scope)
+}
+
+/// Interpret the pragma `p`: the options it names are turned on, `trace`
+/// prints a trace of the elaboration to stderr, and the others set the
+/// severities of lints, see [`lint::Lints::pragma`].
+pub fn pragma(metas: &mut MetaCxt, cxt: &mut Cxt, p: &Pragma) {
+    match &*p.name {
+        "type_in_type" => cxt.options.type_in_type = true,
+        "no_termination_check" => cxt.options.no_termination_check = true,
+        "generalize" => cxt.options.generalize = true,
+        "unfold_opaque" => cxt.options.unfold_opaque = true,
+        "trace" => cxt.tracer = Some(std::rc::Rc::new(RefCell::new(PrintTracer::default()))),
+        _ => metas.lints_mut().pragma(p),
+    }
+}
+
+/// Elaborate a program like [`check_program`], keeping its declarations with
+/// the definitions.
+pub fn check_module(
+    metas: &mut MetaCxt,
+    cxt: &mut Cxt,
+    program: Program,
+) -> Result<CheckedModule, Error> {
+    let defs = check_program(metas, cxt, program.clone())?;
+    Ok(CheckedModule { program, defs })
+}
+
+pub fn close_val(metas: &mut MetaCxt, cxt: &Cxt, val: Value) -> Closure {
+    let lvl = cxt.lvl;
+    let env = cxt.env.clone();
+    let t = quote(metas, lvl + 1, val);
+    (env, t.into())
+}
+
+/// Unify `expected` with `actual`, reporting a failure in terms of both sides.
+fn unify(metas: &mut MetaCxt, cxt: &Cxt, expected: Value, actual: Value) -> Result<(), Error> {
+    convert(metas, cxt, expected, actual, metas::unify)
+}
+
+/// Like [`unify`], but `actual` may be a subtype of `expected`, see
+/// [`metas::subtype`].
+fn subtype(metas: &mut MetaCxt, cxt: &Cxt, expected: Value, actual: Value) -> Result<(), Error> {
+    convert(metas, cxt, expected, actual, metas::subtype)
+}
+
+fn convert(
+    metas: &mut MetaCxt,
+    cxt: &Cxt,
+    expected: Value,
+    actual: Value,
+    f: fn(&mut MetaCxt, Lvl, Value, Value) -> Result<(), metas::UnifyError>,
+) -> Result<(), Error> {
+    let from = metas.postponed().len();
+    let levels_from = metas.level_constraints().len();
+    match f(metas, cxt.lvl, expected.clone(), actual.clone()) {
+        Ok(()) => {
+            metas.locate_postponed(from, &cxt.names(), &cxt.pos);
+            metas.locate_levels(levels_from, &cxt.pos);
+            Ok(())
+        }
+        Err(err) => Err(Error::unify(metas, cxt, expected, actual, err)),
+    }
+}
+
+/// Built-in constants, in scope unless shadowed by a local name. The
+/// primitives that take arguments are eta-expanded.
+pub(crate) fn builtin(metas: &mut MetaCxt, x: &str) -> Option<(Term, Type)> {
+    let v = |ix| Rc::new(Term::TV(Ix(ix)));
+    let app = |t: Tm, u: Tm| Rc::new(Term::TApp(t, u, Icit::Expl));
+    let pi = |x: &str, a: Tm, b: Tm| Rc::new(Term::TΠ(x.into(), Icit::Expl, a, b));
+    let lam = |x: &str, t: Tm| Term::Tλ(x.into(), Icit::Expl, t);
+    let pi_impl = |x: &str, a: Tm, b: Tm| Rc::new(Term::TΠ(x.into(), Icit::Impl, a, b));
+    let lam_impl = |x: &str, t: Tm| Term::Tλ(x.into(), Icit::Impl, t);
+    let nat = || Rc::new(Term::TNat);
+
+    let (t, a) = match x {
+        "Nat" => (Term::TNat, Term::TU(Level::LConst(0))),
+        "zero" => (Term::TNatLit(0), Term::TNat),
+        "suc" => (
+            lam("n", Term::TSuc(v(0)).into()),
+            Rc::unwrap_or_clone(pi("n", nat(), nat())),
+        ),
+        // natElim : (P : Nat → U) → P zero → ((n : Nat) → P n → P (suc n))
+        //         → (n : Nat) → P n
+        "natElim" => {
+            let motive = pi("_", nat(), Term::TU(metas.fresh_level()).into());
+            let zero = app(v(0), Term::TNatLit(0).into());
+            let step = pi(
+                "n",
+                nat(),
+                pi("ih", app(v(2), v(0)), app(v(3), Term::TSuc(v(1)).into())),
+            );
+            let ty = pi(
+                "P",
+                motive,
+                pi("z", zero, pi("s", step, pi("n", nat(), app(v(3), v(0))))),
+            );
+            let t = Term::TNatElim(v(3), v(2), v(1), v(0));
+            let t = lam(
+                "P",
+                lam("z", lam("s", lam("n", t.into()).into()).into()).into(),
+            );
+            (t, Rc::unwrap_or_clone(ty))
+        }
+        "String" => (Term::TString, Term::TU(Level::LConst(0))),
+        "Char" => (Term::TChar, Term::TU(Level::LConst(0))),
+        // append : String → String → String
+        "append" => {
+            let string = || Rc::new(Term::TString);
+            let t = Term::TAppend(v(1), v(0));
+            (
+                lam("s", lam("t", t.into()).into()),
+                Rc::unwrap_or_clone(pi("s", string(), pi("t", string(), string()))),
+            )
+        }
+        // length : String → Nat
+        "length" => (
+            lam("s", Term::TLength(v(0)).into()),
+            Rc::unwrap_or_clone(pi("s", Term::TString.into(), nat())),
+        ),
+        "Int" => (Term::TInt, Term::TU(Level::LConst(0))),
+        "Bool" => (Term::TBool, Term::TU(Level::LConst(0))),
+        "true" => (Term::TTrue, Term::TBool),
+        "false" => (Term::TFalse, Term::TBool),
+        // boolElim : (P : Bool → U) → P true → P false → (b : Bool) → P b
+        "boolElim" => {
+            let bool = || Rc::new(Term::TBool);
+            let motive = pi("_", bool(), Term::TU(metas.fresh_level()).into());
+            let ty = pi(
+                "P",
+                motive,
+                pi(
+                    "t",
+                    app(v(0), Term::TTrue.into()),
+                    pi(
+                        "f",
+                        app(v(1), Term::TFalse.into()),
+                        pi("b", bool(), app(v(3), v(0))),
+                    ),
+                ),
+            );
+            let t = Term::TBoolElim(v(3), v(2), v(1), v(0));
+            let t = lam(
+                "P",
+                lam("t", lam("f", lam("b", t.into()).into()).into()).into(),
+            );
+            (t, Rc::unwrap_or_clone(ty))
+        }
+        // Eq : (A : U) → A → A → U
+        "Eq" => {
+            let l = Rc::new(Term::TU(metas.fresh_level()));
+            let ty = pi("A", l.clone(), pi("x", v(0), pi("y", v(1), l)));
+            let t = Term::TEq(v(2), v(1), v(0));
+            let t = lam("A", lam("x", lam("y", t.into()).into()).into());
+            (t, Rc::unwrap_or_clone(ty))
+        }
+        // refl : {A : U} → {x : A} → Eq A x x
+        "refl" => {
+            let ty = pi_impl(
+                "A",
+                Term::TU(metas.fresh_level()).into(),
+                pi_impl("x", v(0), Term::TEq(v(1), v(0), v(0)).into()),
+            );
+            let t = Term::TRefl(v(1), v(0));
+            let t = lam_impl("A", lam_impl("x", t.into()).into());
+            (t, Rc::unwrap_or_clone(ty))
+        }
+        // J : {A : U} → {x : A} → (P : (y : A) → Eq A x y → U) → P x refl
+        //   → (y : A) → (e : Eq A x y) → P y e
+        "J" => {
+            let motive = pi(
+                "y",
+                v(1),
+                pi(
+                    "_",
+                    Term::TEq(v(2), v(1), v(0)).into(),
+                    Term::TU(metas.fresh_level()).into(),
+                ),
+            );
+            let base = app(app(v(0), v(1)), Term::TRefl(v(2), v(1)).into());
+            let ty = pi_impl(
+                "A",
+                Term::TU(metas.fresh_level()).into(),
+                pi_impl(
+                    "x",
+                    v(0),
+                    pi(
+                        "P",
+                        motive,
+                        pi(
+                            "pr",
+                            base,
+                            pi(
+                                "y",
+                                v(3),
+                                pi(
+                                    "e",
+                                    Term::TEq(v(4), v(3), v(0)).into(),
+                                    app(app(v(3), v(1)), v(0)),
+                                ),
+                            ),
+                        ),
+                    ),
+                ),
+            );
+            let t = Term::TJ(v(5), v(4), v(3), v(2), v(1), v(0));
+            let t = lam("e", t.into());
+            let t = lam_impl(
+                "A",
+                lam_impl(
+                    "x",
+                    lam("P", lam("pr", lam("y", t.into()).into()).into()).into(),
+                )
+                .into(),
+            );
+            (t, Rc::unwrap_or_clone(ty))
+        }
+        // Lazy : U → U
+        "Lazy" => {
+            let l = Rc::new(Term::TU(metas.fresh_level()));
+            let t = lam("A", Term::TLazy(v(0)).into());
+            (t, Rc::unwrap_or_clone(pi("A", l.clone(), l)))
+        }
+        // delay : {A : U} → A → Lazy A, lazy in its argument when applied
+        // directly, see `ElabFrame::Delay`
+        "delay" => {
+            let ty = pi_impl(
+                "A",
+                Term::TU(metas.fresh_level()).into(),
+                pi("x", v(0), Term::TLazy(v(1)).into()),
+            );
+            let t = lam_impl("A", lam("x", Term::TDelay(v(0)).into()).into());
+            (t, Rc::unwrap_or_clone(ty))
+        }
+        // force : {A : U} → Lazy A → A
+        "force" => {
+            let ty = pi_impl(
+                "A",
+                Term::TU(metas.fresh_level()).into(),
+                pi("x", Term::TLazy(v(0)).into(), v(1)),
+            );
+            let t = lam_impl("A", lam("x", Term::TForce(v(0)).into()).into());
+            (t, Rc::unwrap_or_clone(ty))
+        }
+        // intAdd : Int → Int → Int, intLt : Int → Int → Bool, ...
+        _ => {
+            let op = IntOp::ALL.into_iter().find(|op| op.name() == x)?;
+            let int = || Rc::new(Term::TInt);
+            let b = match op.is_comparison() {
+                true => Term::TBool,
+                false => Term::TInt,
+            };
+            let t = Term::TIntOp(op, v(1), v(0));
+            (
+                lam("x", lam("y", t.into()).into()),
+                Rc::unwrap_or_clone(pi("x", int(), pi("y", int(), b.into()))),
+            )
+        }
+    };
+
+    Some((t, eval(metas, Cow::Owned(Env::default()), a)))
+}
+
+/// Whether `x` names a built-in constant of [`builtin`].
+pub(crate) fn is_builtin(x: &str) -> bool {
+    matches!(
+        x,
+        "Nat"
+            | "zero"
+            | "suc"
+            | "natElim"
+            | "String"
+            | "Char"
+            | "append"
+            | "length"
+            | "Int"
+            | "Bool"
+            | "true"
+            | "false"
+            | "boolElim"
+            | "Eq"
+            | "refl"
+            | "J"
+            | "Lazy"
+            | "delay"
+            | "force"
+    ) || IntOp::ALL.iter().any(|op| op.name() == x)
+}
+
+/// Elaborate a type, returning the level of the universe it lives in.
+pub(crate) fn check_type(
+    metas: &mut MetaCxt,
+    cxt: &mut Cxt,
+    raw: Raw,
+) -> Result<(Term, Level), Error> {
+    let l = metas.fresh_level();
+    let a = check(metas, cxt, raw, Value::VU(l))?;
+    Ok((a, l))
+}
+
+/// Fresh level above both `l1` and `l2`, for a type built from types of
+/// these levels.
+fn max_level(metas: &mut MetaCxt, cxt: &Cxt, l1: Level, l2: Level) -> Level {
+    let l = metas.fresh_level();
+    metas.level_le(l1, l, Some(cxt.pos.clone()));
+    metas.level_le(l2, l, Some(cxt.pos.clone()));
+    l
+}
+
+/// Split the type of a function into its domain and codomain, refining it
+/// with fresh metas when it isn't a Π yet.
+fn app_domain(
+    metas: &mut MetaCxt,
+    cxt: &mut Cxt,
+    tty: Type,
+    i: Icit,
+    arg: &Raw,
+) -> Result<(Type, Closure), Error> {
+    match force(metas, tty) {
+        Value::VΠ(_, i_, a, b) => {
+            if i != i_ {
+                return error!(metas, cxt, ErrorKind::IcitMismatch(i, i_));
+            }
+            Ok((Rc::unwrap_or_clone(a), b))
+        }
+        // only a meta or a recursive definition stuck on one may still turn
+        // out to be a function type
+        tty @ (Value::VFlex(..) | Value::VFix(..)) => {
+            let mut a = {
+                let m = metas.fresh_type_meta(cxt);
+                eval(metas, Cow::Borrowed(&cxt.env), m)
+            };
+            let (x, b) = {
+                let (m, (x, a_)) = cxt.bind("a".into(), a, |cxt| metas.fresh_type_meta(cxt));
+                a = a_;
+                (x, (cxt.env.clone(), Rc::new(m)))
+            };
+
+            unify(
+                metas,
+                cxt,
+                Value::VΠ(x, i, a.clone().into(), b.clone()),
+                tty,
+            )?;
+            Ok((a, b))
+        }
+        tty => {
+            let ty = quote(metas, cxt.lvl, tty);
+            let arg = arg.clone();
+            error!(metas, cxt, ErrorKind::NotAFunction { ty, arg })
+        }
+    }
+}
+
+/// Apply a term to fresh metas for all of its leading implicit and instance
+/// arguments.
+fn insert_implicits(metas: &mut MetaCxt, cxt: &Cxt, (mut t, mut ty): (Term, Type)) -> (Term, Type) {
+    loop {
+        match force(metas, ty) {
+            Value::VΠ(_, i @ (Icit::Impl | Icit::Inst), a, b) => {
+                let m = fresh_arg(metas, cxt, i, Rc::unwrap_or_clone(a));
+                let mv = eval(metas, Cow::Borrowed(&cxt.env), m.clone());
+                t = Term::TApp(t.into(), m.into(), i);
+                ty = eval_closure(metas, b, mv);
+            }
+            ty => return (t, ty),
+        }
+    }
+}
+
+/// Apply a term to fresh metas for the implicit arguments before its first
+/// instance argument, which is given explicitly.
+fn insert_until_instance(
+    metas: &mut MetaCxt,
+    cxt: &Cxt,
+    (mut t, mut ty): (Term, Type),
+) -> (Term, Type) {
+    loop {
+        match force(metas, ty) {
+            Value::VΠ(_, Icit::Impl, a, b) => {
+                let m = metas.fresh_meta(cxt, Rc::unwrap_or_clone(a));
+                let mv = eval(metas, Cow::Borrowed(&cxt.env), m.clone());
+                t = Term::TApp(t.into(), m.into(), Icit::Impl);
+                ty = eval_closure(metas, b, mv);
+            }
+            ty => return (t, ty),
+        }
+    }
+}
+
+/// Fresh meta for an inserted argument of type `a`, found by instance search
+/// if it is an instance argument.
+fn fresh_arg(metas: &mut MetaCxt, cxt: &Cxt, i: Icit, a: Type) -> Term {
+    match i {
+        Icit::Inst => instance::fresh_instance(metas, cxt, a),
+        Icit::Impl | Icit::Expl => metas.fresh_meta(cxt, a),
+    }
+}
+
+/// Infer `raw` and insert its leading implicit arguments, unless it is an
+/// implicit lambda. Built-ins like `refl` elaborate to implicit lambdas too, so
+/// this looks at the raw term rather than the elaborated one.
+fn insert(metas: &mut MetaCxt, cxt: &mut Cxt, raw: Raw) -> Result<(Term, Type), Error> {
+    let implicit_lam = implicit_lam(&raw);
+
+    let res = infer(metas, cxt, raw)?;
+    Ok(match implicit_lam {
+        true => res,
+        false => insert_implicits(metas, cxt, res),
+    })
+}
+
+/// Whether `raw` is an implicit or instance lambda, under its positions.
+fn implicit_lam(mut raw: &Raw) -> bool {
+    while let Raw::RSrcPos(_, t) = raw {
+        raw = t;
+    }
+    matches!(raw, Raw::RLam(_, Icit::Impl | Icit::Inst, _))
+}
+
+/// Whether `raw` is the builtin `x`, under its positions, which isn't
+/// shadowed in `cxt`.
+fn is_prim(cxt: &Cxt, mut raw: &Raw, x: &str) -> bool {
+    while let Raw::RSrcPos(_, t) = raw {
+        raw = t;
+    }
+    matches!(raw, Raw::RVar(y) if &**y == x) && !cxt.types.iter().any(|(y, _)| &**y == x)
+}
+
+/// Insert fresh metas for the implicit arguments that come before the one named `x`.
+fn insert_until_name(
+    metas: &mut MetaCxt,
+    cxt: &Cxt,
+    x: &Name,
+    (mut t, mut ty): (Term, Type),
+) -> Result<(Term, Type), Error> {
+    loop {
+        match force(metas, ty) {
+            Value::VΠ(x_, i @ (Icit::Impl | Icit::Inst), a, b) => {
+                if &x_ == x {
+                    return Ok((t, Value::VΠ(x_, i, a, b)));
+                }
+
+                let m = fresh_arg(metas, cxt, i, Rc::unwrap_or_clone(a));
+                let mv = eval(metas, Cow::Borrowed(&cxt.env), m.clone());
+                t = Term::TApp(t.into(), m.into(), i);
+                ty = eval_closure(metas, b, mv);
+            }
+            _ => return error!(metas, cxt, ErrorKind::NoNamedImplicitArg(x.clone())),
+        }
+    }
+}
+
+/// Split the type of a projected term into the components of a sigma type,
+/// refining it with fresh metas when it isn't one yet.
+fn sigma_components(
+    metas: &mut MetaCxt,
+    cxt: &mut Cxt,
+    tty: Type,
+) -> Result<(Type, Closure), Error> {
+    match force(metas, tty) {
+        Value::VΣ(_, a, b) => Ok((Rc::unwrap_or_clone(a), b)),
+        tty => {
+            let mut a = {
+                let m = metas.fresh_type_meta(cxt);
+                eval(metas, Cow::Borrowed(&cxt.env), m)
+            };
+            let (x, b) = {
+                let (m, (x, a_)) = cxt.bind("a".into(), a, |cxt| metas.fresh_type_meta(cxt));
+                a = a_;
+                (x, (cxt.env.clone(), Rc::new(m)))
+            };
+
+            unify(metas, cxt, Value::VΣ(x, a.clone().into(), b.clone()), tty)?;
+            Ok((a, b))
+        }
+    }
+}
+
+/// Quotation continuations, the counterpart of [`EvalFrame`].
+enum QuoteFrame {
+    /// the body of a lambda quoted at one level above `Lvl`
+    Lam(Name, Icit, Lvl),
+    PiDom(Name, Icit, Closure, Lvl),
+    Pi(Name, Icit, Term, Lvl),
+    SigmaDom(Name, Closure, Lvl),
+    Sigma(Name, Term, Lvl),
+    PairFst(Value),
+    Pair(Term),
+    Suc,
+    /// the bodies of a stuck block of recursive definitions one after the
+    /// other, the block bound in the environment, then the spine of the
+    /// `usize`th
+    Fix(Fix, usize, Env, Vec<Tm>, Spine, Lvl),
+    /// an argument of the neutral `Term`, followed by the rest of its spine
+    SpineArg(Term, Icit, std::vec::IntoIter<Elim>),
+}
+
+/// Next step of quotation: either a finished term, or a value to quote first.
+enum QuoteStep {
+    Done(Term),
+    Quote(Value),
+}
+
+pub fn quote(metas: &mut MetaCxt, lvl: Lvl, val: Value) -> Term {
+    quote_with(metas, lvl, Vec::new(), QuoteStep::Quote(val))
+}
+
+pub fn quote_spine(metas: &mut MetaCxt, lvl: Lvl, tm: Term, spine: Spine) -> Term {
+    let mut stack = Vec::new();
+    let step = quote_elims(metas, lvl, &mut stack, tm, spine.into_iter());
+    quote_with(metas, lvl, stack, step)
+}
+
+/// Apply eliminators to `tm` until an argument needs quoting.
+fn quote_elims(
+    metas: &mut MetaCxt,
+    lvl: Lvl,
+    stack: &mut Vec<QuoteFrame>,
+    mut tm: Term,
+    mut spine: std::vec::IntoIter<Elim>,
+) -> QuoteStep {
+    while let Some(e) = spine.next() {
+        tm = match e {
+            Elim::EApp(u, i) => {
+                stack.push(QuoteFrame::SpineArg(tm, i, spine));
+                return QuoteStep::Quote(u);
+            }
+            Elim::EProj1 => Term::TProj1(tm.into()),
+            Elim::EProj2 => Term::TProj2(tm.into()),
+            Elim::ENatElim(p, z, s) => Term::TNatElim(
+                quote(metas, lvl, p).into(),
+                quote(metas, lvl, z).into(),
+                quote(metas, lvl, s).into(),
+                tm.into(),
+            ),
+            Elim::EBoolElim(p, t, f) => Term::TBoolElim(
+                quote(metas, lvl, p).into(),
+                quote(metas, lvl, t).into(),
+                quote(metas, lvl, f).into(),
+                tm.into(),
+            ),
+            Elim::EAppend(t) => Term::TAppend(tm.into(), quote(metas, lvl, t).into()),
+            Elim::EPrepend(s) => Term::TAppend(Term::TStrLit(s).into(), tm.into()),
+            Elim::ELength => Term::TLength(tm.into()),
+            Elim::EForce => Term::TForce(tm.into()),
+            Elim::EIntOp(op, y) => Term::TIntOp(op, tm.into(), quote(metas, lvl, y).into()),
+            Elim::EIntOpLit(op, x) => Term::TIntOp(op, Term::TIntLit(x).into(), tm.into()),
+            Elim::EJ(a, x, p, pr, y) => {
+                let [a, x, p, pr, y] = [a, x, p, pr, y].map(|v| Rc::new(quote(metas, lvl, v)));
+                Term::TJ(a, x, p, pr, y, tm.into())
+            }
+            Elim::EElim(d, args) => {
+                let args = args
+                    .into_iter()
+                    .map(|v| Rc::new(quote(metas, lvl, v)))
+                    .collect();
+                Term::TElim(d, args, tm.into())
+            }
+            Elim::EField(r, f) => Term::TField(r, f, tm.into()),
+        };
+    }
+
+    QuoteStep::Done(tm)
+}
+
+fn quote_with(
+    metas: &mut MetaCxt,
+    mut lvl: Lvl,
+    mut stack: Vec<QuoteFrame>,
+    mut step: QuoteStep,
+) -> Term {
+    loop {
+        let mut tm = match step {
+            QuoteStep::Done(tm) => tm,
+            // cut short, to be reported by whoever set the limit
+            QuoteStep::Quote(_) if !metas.take_node() => Term::TU(Level::LConst(0)),
+            QuoteStep::Quote(val) => match force_metas(metas, val) {
+                Value::VThunk(_) => unreachable!("thunk left by forcing"),
+                Value::VFlex(m, sp) => {
+                    step = quote_elims(metas, lvl, &mut stack, Term::TMeta(m), sp.into_iter());
+                    continue;
+                }
+                Value::VRigid(x, sp) => {
+                    let x = Term::TV(lvl2ix(lvl, x));
+                    step = quote_elims(metas, lvl, &mut stack, x, sp.into_iter());
+                    continue;
+                }
+                Value::VGlued(x, def, sp) => {
+                    step = match metas.opaque(&def) {
+                        Some(x) => {
+                            let x = Term::TV(lvl2ix(lvl, x));
+                            quote_elims(metas, lvl, &mut stack, x, sp.into_iter())
+                        }
+                        None => QuoteStep::Quote(unfold(metas, Value::VGlued(x, def, sp))),
+                    };
+                    continue;
+                }
+                Value::VFix(fix, i, mut env, sp) => {
+                    if fix_ready(metas, fix[i].1, &sp) {
+                        step = QuoteStep::Quote(unfold(metas, Value::VFix(fix, i, env, sp)));
+                        continue;
+                    }
+                    for j in 0..fix.len() {
+                        env.push(Value::VRigid(lvl + j, vec![]));
+                    }
+                    let t = Rc::unwrap_or_clone(fix[0].2.clone());
+                    let t = eval(metas, Cow::Borrowed(&env), t);
+                    lvl += fix.len();
+                    stack.push(QuoteFrame::Fix(fix, i, env, Vec::new(), sp, lvl));
+                    step = QuoteStep::Quote(t);
+                    continue;
+                }
+                Value::Vλ(x, i, t) => {
+                    let t = eval_closure(metas, t, Value::VRigid(lvl, vec![]));
+                    stack.push(QuoteFrame::Lam(x, i, lvl));
+                    lvl += 1;
+                    step = QuoteStep::Quote(t);
+                    continue;
+                }
+                Value::VΠ(x, i, a, b) => {
+                    stack.push(QuoteFrame::PiDom(x, i, b, lvl));
+                    step = QuoteStep::Quote(Rc::unwrap_or_clone(a));
+                    continue;
+                }
+                Value::VΣ(x, a, b) => {
+                    stack.push(QuoteFrame::SigmaDom(x, b, lvl));
+                    step = QuoteStep::Quote(Rc::unwrap_or_clone(a));
+                    continue;
+                }
+                Value::Vσ(t, u) => {
+                    stack.push(QuoteFrame::PairFst(Rc::unwrap_or_clone(u)));
+                    step = QuoteStep::Quote(Rc::unwrap_or_clone(t));
+                    continue;
+                }
+                Value::VU(l) => Term::TU(l),
+                Value::VNat => Term::TNat,
+                Value::VNatLit(n) => Term::TNatLit(n),
+                Value::VBool => Term::TBool,
+                Value::VTrue => Term::TTrue,
+                Value::VFalse => Term::TFalse,
+                Value::VString => Term::TString,
+                Value::VStrLit(s) => Term::TStrLit(s),
+                Value::VChar => Term::TChar,
+                Value::VCharLit(c) => Term::TCharLit(c),
+                Value::VInt => Term::TInt,
+                Value::VIntLit(n) => Term::TIntLit(n),
+                Value::VEq(a, x, y) => {
+                    let [a, x, y] =
+                        [a, x, y].map(|v| Rc::new(quote(metas, lvl, Rc::unwrap_or_clone(v))));
+                    Term::TEq(a, x, y)
+                }
+                Value::VRefl(a, x) => Term::TRefl(
+                    quote(metas, lvl, Rc::unwrap_or_clone(a)).into(),
+                    quote(metas, lvl, Rc::unwrap_or_clone(x)).into(),
+                ),
+                Value::VLazy(a) => Term::TLazy(quote(metas, lvl, Rc::unwrap_or_clone(a)).into()),
+                Value::VDelay((env, t)) => {
+                    let t = eval(metas, Cow::Owned(env), Rc::unwrap_or_clone(t));
+                    Term::TDelay(quote(metas, lvl, t).into())
+                }
+                Value::VData(d, args) => Term::TData(
+                    d,
+                    args.into_iter()
+                        .map(|v| Rc::new(quote(metas, lvl, v)))
+                        .collect(),
+                ),
+                Value::VCon(d, c, args) => Term::TCon(
+                    d,
+                    c,
+                    args.into_iter()
+                        .map(|v| Rc::new(quote(metas, lvl, v)))
+                        .collect(),
+                ),
+                Value::VRecord(r, args) => Term::TRecord(
+                    r,
+                    args.into_iter()
+                        .map(|v| Rc::new(quote(metas, lvl, v)))
+                        .collect(),
+                ),
+                Value::VRecordLit(r, fields) => Term::TRecordLit(
+                    r,
+                    fields
+                        .into_iter()
+                        .map(|v| Rc::new(quote(metas, lvl, v)))
+                        .collect(),
+                ),
+                Value::VSuc(t) => {
+                    stack.push(QuoteFrame::Suc);
+                    step = QuoteStep::Quote(Rc::unwrap_or_clone(t));
+                    continue;
+                }
+            },
+        };
+
+        step = loop {
+            tm = match stack.pop() {
+                None => return tm,
+                Some(QuoteFrame::Lam(x, i, lvl_)) => {
+                    lvl = lvl_;
+                    Term::Tλ(x, i, tm.into())
+                }
+                Some(QuoteFrame::PiDom(x, i, b, lvl_)) => {
+                    let b = eval_closure(metas, b, Value::VRigid(lvl_, vec![]));
+                    stack.push(QuoteFrame::Pi(x, i, tm, lvl_));
+                    lvl = lvl_ + 1;
+                    break QuoteStep::Quote(b);
+                }
+                Some(QuoteFrame::Pi(x, i, a, lvl_)) => {
+                    lvl = lvl_;
+                    Term::TΠ(x, i, a.into(), tm.into())
+                }
+                Some(QuoteFrame::SigmaDom(x, b, lvl_)) => {
+                    let b = eval_closure(metas, b, Value::VRigid(lvl_, vec![]));
+                    stack.push(QuoteFrame::Sigma(x, tm, lvl_));
+                    lvl = lvl_ + 1;
+                    break QuoteStep::Quote(b);
+                }
+                Some(QuoteFrame::Sigma(x, a, lvl_)) => {
+                    lvl = lvl_;
+                    Term::TΣ(x, a.into(), tm.into())
+                }
+                Some(QuoteFrame::PairFst(u)) => {
+                    stack.push(QuoteFrame::Pair(tm));
+                    break QuoteStep::Quote(u);
+                }
+                Some(QuoteFrame::Pair(t)) => Term::Tσ(t.into(), tm.into()),
+                // the argument was glued or a solved meta
+                Some(QuoteFrame::Suc) => match tm {
+                    Term::TNatLit(n) => Term::TNatLit(n + 1),
+                    tm => Term::TSuc(tm.into()),
+                },
+                Some(QuoteFrame::Fix(fix, i, env, mut bodies, sp, lvl_)) => {
+                    bodies.push(tm.into());
+                    if let Some((_, _, t)) = fix.get(bodies.len()) {
+                        let t = eval(metas, Cow::Borrowed(&env), Rc::unwrap_or_clone(t.clone()));
+                        stack.push(QuoteFrame::Fix(fix, i, env, bodies, sp, lvl_));
+                        lvl = lvl_;
+                        break QuoteStep::Quote(t);
+                    }
+                    lvl = lvl_ - fix.len();
+                    let fix = fix
+                        .iter()
+                        .zip(bodies)
+                        .map(|((x, k, _), t)| (x.clone(), *k, t))
+                        .collect();
+                    let t = Term::TFix(fix, i);
+                    match quote_elims(metas, lvl, &mut stack, t, sp.into_iter()) {
+                        QuoteStep::Done(t) => t,
+                        step => break step,
+                    }
+                }
+                Some(QuoteFrame::SpineArg(t, i, spine)) => {
+                    let t = Term::TApp(t.into(), tm.into(), i);
+                    match quote_elims(metas, lvl, &mut stack, t, spine) {
+                        QuoteStep::Done(t) => t,
+                        step => break step,
+                    }
+                }
+            };
+        };
+    }
+}
+
+/// Which definitions of the context [`normalize`] and [`whnf`] unfold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Unfold {
+    /// replace defined variables by their values
+    #[default]
+    Always,
+    /// keep the top-level and `let`-bound variables of the context as they
+    /// are; `let`s inside the term itself are still reduced
+    Never,
+    /// like `Always`, and the `opaque` definitions too
+    Opaque,
+}
+
+fn unfold_env(cxt: &Cxt, unfold: Unfold) -> Cow<'_, Env> {
+    match unfold {
+        Unfold::Always | Unfold::Opaque => Cow::Borrowed(&cxt.env),
+        Unfold::Never => {
+            let mut env = Env::default();
+            for (lvl, (v, bd)) in cxt.env.iter().zip(cxt.bds.iter()).enumerate() {
+                env.push(match bd {
+                    BD::Bound => v.clone(),
+                    BD::Defined => Value::VRigid(lvl, vec![]),
+                });
+            }
+            Cow::Owned(env)
+        }
+    }
+}
+
+/// Like [`normalize`], but within the limits of the options of `cxt`:
+/// once recursive definitions would unfold more often than
+/// [`ElabOptions::fuel`], the term as far as it got is returned in a
+/// [`ErrorKind::NormalizationTimeout`] error, and once it would have more
+/// nodes than [`ElabOptions::max_nodes`], a
+/// [`ErrorKind::ResourceExhausted`] error is.
+pub fn normalize_limited(
+    metas: &mut MetaCxt,
+    cxt: &Cxt,
+    term: Term,
+    unfold: Unfold,
+) -> Result<Term, Error> {
+    let fuel = metas.set_fuel(cxt.options.fuel);
+    let nodes = metas.set_max_nodes(cxt.options.max_nodes);
+    let term = normalize(metas, cxt, term, unfold);
+    let (exhausted, out_of_nodes) = (metas.exhausted(), metas.out_of_nodes());
+    metas.set_fuel(fuel);
+    metas.set_max_nodes(nodes);
+    match (exhausted, out_of_nodes, cxt.options.max_nodes) {
+        (_, true, Some(nodes)) => error!(metas, cxt, ErrorKind::ResourceExhausted { nodes }),
+        (true, _, _) => error!(metas, cxt, ErrorKind::NormalizationTimeout { term }),
+        _ => Ok(term),
+    }
+}
+
+/// Beta-normal form of a term in `cxt`.
+pub fn normalize(metas: &mut MetaCxt, cxt: &Cxt, term: Term, unfold: Unfold) -> Term {
+    if unfold == Unfold::Opaque {
+        return metas.transparent(|metas| normalize(metas, cxt, term, Unfold::Always));
+    }
+    let val = eval(metas, unfold_env(cxt, unfold), term);
+    quote(metas, cxt.lvl, val)
+}
+
+/// Weak head normal form of a term in `cxt`: only the head is evaluated, the
+/// arguments and binder bodies are left as closures and unevaluated values.
+pub fn whnf(metas: &mut MetaCxt, cxt: &Cxt, term: Term, unfold: Unfold) -> Value {
+    if unfold == Unfold::Opaque {
+        return metas.transparent(|metas| whnf(metas, cxt, term, Unfold::Always));
+    }
+    let val = eval(metas, unfold_env(cxt, unfold), term);
+    force(metas, val)
+}
+
+/// Replace the solved metas of a term in `cxt` by their solutions, and
+/// unsolved inserted metas by plain applications. Definitions of the context
+/// aren't unfolded in the solutions.
+pub fn zonk(metas: &mut MetaCxt, cxt: &Cxt, term: Term) -> Term {
+    let mut env = unfold_env(cxt, Unfold::Never).into_owned();
+    zonk_(metas, &mut env, cxt.lvl, term)
+}
+
+/// Substitute the solved metas of a term in a context of `lvl` variables,
+/// without unfolding the definitions among them.
+pub(crate) fn zonk_local(metas: &mut MetaCxt, lvl: usize, term: Term) -> Term {
+    let mut env = Env::default();
+    for x in 0..lvl {
+        env.push(Value::VRigid(x, vec![]));
+    }
+    zonk_(metas, &mut env, lvl, term)
+}
+
+fn zonk_(metas: &mut MetaCxt, env: &mut Env, lvl: Lvl, term: Term) -> Term {
+    let under = |metas: &mut MetaCxt, env: &mut Env, t: Tm| {
+        env.push(Value::VRigid(lvl, vec![]));
+        let t = zonk_(metas, env, lvl + 1, Rc::unwrap_or_clone(t));
+        env.pop();
+        Rc::new(t)
+    };
+    let go = |metas: &mut MetaCxt, env: &mut Env, t: Tm| {
+        Rc::new(zonk_(metas, env, lvl, Rc::unwrap_or_clone(t)))
+    };
+
+    match term {
+        Term::TV(x) => Term::TV(x),
+        Term::Tλ(x, i, t) => Term::Tλ(x, i, under(metas, env, t)),
+        Term::TFix(fix, i) => {
+            for j in 0..fix.len() {
+                env.push(Value::VRigid(lvl + j, vec![]));
+            }
+            let fix: Fix = fix
+                .iter()
+                .map(|(x, k, t)| {
+                    let t = zonk_(metas, env, lvl + fix.len(), Rc::unwrap_or_clone(t.clone()));
+                    (x.clone(), *k, Rc::new(t))
+                })
+                .collect();
+            for _ in 0..fix.len() {
+                env.pop();
+            }
+            Term::TFix(fix, i)
+        }
+        Term::TΠ(x, i, a, b) => Term::TΠ(x, i, go(metas, env, a), under(metas, env, b)),
+        Term::Tσ(t, u) => Term::Tσ(go(metas, env, t), go(metas, env, u)),
+        Term::TΣ(x, a, b) => Term::TΣ(x, go(metas, env, a), under(metas, env, b)),
+        Term::TLet(x, a, t, u) => Term::TLet(
+            x,
+            go(metas, env, a),
+            go(metas, env, t),
+            under(metas, env, u),
+        ),
+        Term::TU(l) => Term::TU(l),
+        Term::TProj1(t) => Term::TProj1(go(metas, env, t)),
+        Term::TProj2(t) => Term::TProj2(go(metas, env, t)),
+        Term::TNat => Term::TNat,
+        Term::TNatLit(n) => Term::TNatLit(n),
+        Term::TSuc(t) => Term::TSuc(go(metas, env, t)),
+        Term::TNatElim(p, z, s, n) => Term::TNatElim(
+            go(metas, env, p),
+            go(metas, env, z),
+            go(metas, env, s),
+            go(metas, env, n),
+        ),
+        Term::TBool => Term::TBool,
+        Term::TTrue => Term::TTrue,
+        Term::TFalse => Term::TFalse,
+        Term::TBoolElim(p, t, f, b) => Term::TBoolElim(
+            go(metas, env, p),
+            go(metas, env, t),
+            go(metas, env, f),
+            go(metas, env, b),
+        ),
+        Term::TString => Term::TString,
+        Term::TStrLit(s) => Term::TStrLit(s),
+        Term::TChar => Term::TChar,
+        Term::TCharLit(c) => Term::TCharLit(c),
+        Term::TAppend(s, t) => Term::TAppend(go(metas, env, s), go(metas, env, t)),
+        Term::TLength(s) => Term::TLength(go(metas, env, s)),
+        Term::TLazy(a) => Term::TLazy(go(metas, env, a)),
+        Term::TDelay(t) => Term::TDelay(go(metas, env, t)),
+        Term::TForce(t) => Term::TForce(go(metas, env, t)),
+        Term::TInt => Term::TInt,
+        Term::TIntLit(n) => Term::TIntLit(n),
+        Term::TIntOp(op, x, y) => Term::TIntOp(op, go(metas, env, x), go(metas, env, y)),
+        Term::TEq(a, x, y) => Term::TEq(go(metas, env, a), go(metas, env, x), go(metas, env, y)),
+        Term::TRefl(a, x) => Term::TRefl(go(metas, env, a), go(metas, env, x)),
+        Term::TJ(a, x, p, pr, y, e) => {
+            let [a, x, p, pr, y, e] = [a, x, p, pr, y, e].map(|t| go(metas, env, t));
+            Term::TJ(a, x, p, pr, y, e)
+        }
+        Term::TData(d, args) => {
+            Term::TData(d, args.into_iter().map(|t| go(metas, env, t)).collect())
+        }
+        Term::TCon(d, c, args) => {
+            Term::TCon(d, c, args.into_iter().map(|t| go(metas, env, t)).collect())
+        }
+        Term::TElim(d, args, t) => Term::TElim(
+            d,
+            args.into_iter().map(|t| go(metas, env, t)).collect(),
+            go(metas, env, t),
+        ),
+        Term::TRecord(r, args) => {
+            Term::TRecord(r, args.into_iter().map(|t| go(metas, env, t)).collect())
         }
+        Term::TRecordLit(r, fields) => {
+            Term::TRecordLit(r, fields.into_iter().map(|t| go(metas, env, t)).collect())
+        }
+        Term::TField(r, f, t) => Term::TField(r, f, go(metas, env, t)),
+        Term::TSrcPos(pos, t) => Term::TSrcPos(pos, go(metas, env, t)),
+        t if solved_head(metas, &t) => {
+            let v = eval(metas, Cow::Borrowed(env), t);
+            quote(metas, lvl, v)
+        }
+        Term::TApp(t, u, i) => Term::TApp(go(metas, env, t), go(metas, env, u), i),
+        Term::TMeta(m) => Term::TMeta(m),
+        Term::TInsertedMeta(m, bds) => inserted_meta_app(m, &bds, lvl),
     }
 }
 
-pub fn quote(metas: &mut MetaCxt, lvl: Lvl, val: Value) -> Term {
-    match val {
-        Value::VFlex(m, sp) => quote_spine(metas, lvl, Term::TMeta(m), sp),
-        Value::VRigid(x, sp) => quote_spine(metas, lvl, Term::TV(lvl2ix(lvl, x)), sp),
-        Value::Vλ(x, (mut env, t)) => {
-            env.push(Value::VRigid(lvl, vec![]));
-            let val = eval(metas, Cow::Owned(env), *t);
-            Term::Tλ(x, quote(metas, lvl + 1, val).into())
-        }
-        Value::VΠ(x, a, (mut env, b)) => {
-            let a = quote(metas, lvl, *a);
-            env.push(Value::VRigid(lvl, vec![]));
-
-            let b = eval(metas, Cow::Owned(env), *b);
-
-            let b = quote(metas, lvl + 1, b);
-
-            Term::TΠ(x, a.into(), b.into())
+/// The plain application an inserted meta stands for in a context of `lvl`
+/// variables: `?m` applied to the variables `bds` marks as bound.
+fn inserted_meta_app(m: MetaVar, bds: &[BD], lvl: Lvl) -> Term {
+    let mut t = Term::TMeta(m);
+    for (x, bd) in bds.iter().enumerate() {
+        if let BD::Bound = bd {
+            t = Term::TApp(t.into(), Term::TV(lvl2ix(lvl, x)).into(), Icit::Expl);
         }
-        Value::VΣ(_, _, _) => todo!(),
-        Value::Vσ(_, _) => todo!(),
-        Value::VU => Term::TU,
     }
+    t
 }
 
-pub fn quote_spine(metas: &mut MetaCxt, lvl: Lvl, tm: Term, mut spine: Spine) -> Term {
-    if let Some(u) = spine.pop() {
-        Term::TApp(
-            quote_spine(metas, lvl, tm, spine).into(),
-            quote(metas, lvl, u).into(),
-        )
-    } else {
-        tm
+/// Whether the head of an application is a solved meta.
+fn solved_head(metas: &MetaCxt, mut t: &Term) -> bool {
+    loop {
+        match t {
+            Term::TApp(t_, _, _) | Term::TSrcPos(_, t_) => t = t_,
+            Term::TMeta(m) | Term::TInsertedMeta(m, _) => {
+                return matches!(metas[*m], MetaEntry::Solved(_))
+            }
+            _ => return false,
+        }
     }
 }
 
@@ -525,20 +3869,62 @@ pub fn lvl2ix(lvl: Lvl, x: Lvl) -> Ix {
 pub fn eval_closure(mcxt: &mut MetaCxt, clos: Closure, v: Value) -> Value {
     let (mut env, t) = clos;
     env.push(v);
-    eval(mcxt, Cow::Owned(env), *t)
+    eval(mcxt, Cow::Owned(env), Rc::unwrap_or_clone(t))
 }
 
 mod fresh {
-    use std::ops::{Deref, Index};
+    use std::{
+        collections::HashSet,
+        ops::{Deref, Index},
+    };
 
-    use crate::{Ix, Lvl, Name};
+    use crate::{is_builtin, Ix, Lvl, Name, Term};
 
+    /// Names of the variables in scope while printing, how deeply nested the
+    /// term being printed is, and the subterms elided.
     #[derive(Default)]
-    pub struct Fresh(Vec<Name>);
+    pub struct Fresh(Vec<Name>, usize, HashSet<*const Term>);
 
     impl Fresh {
+        /// Names of a context, with the shadowed ones primed so that every
+        /// variable is printed with a name that resolves to it.
         pub fn new(names: Vec<Name>) -> Self {
-            Self(names)
+            let mut fresh: Vec<Name> = Vec::with_capacity(names.len());
+            let mut seen = HashSet::new();
+            for mut name in names.into_iter().rev() {
+                while name.deref() != "_" && seen.contains(&name) {
+                    name = format!("{}'", name.deref()).into_boxed_str().into();
+                }
+                seen.insert(name.clone());
+                fresh.push(name);
+            }
+            fresh.reverse();
+            Self(fresh, 0, HashSet::new())
+        }
+
+        /// Elide the subterms `elided` as `…`.
+        pub fn eliding(self, elided: HashSet<*const Term>) -> Self {
+            Self(self.0, self.1, elided)
+        }
+
+        /// Whether `t` is one of the subterms elided.
+        pub fn elided(&self, t: &Term) -> bool {
+            self.2.contains(&(t as *const Term))
+        }
+
+        /// Number of variables in scope.
+        pub fn lvl(&self) -> Lvl {
+            self.0.len()
+        }
+
+        /// How many terms the one being printed is nested in.
+        pub fn depth(&self) -> usize {
+            self.1
+        }
+
+        /// Bring `name` into scope as it is.
+        pub fn insert(&mut self, name: Name) {
+            self.0.push(name);
         }
 
         pub fn freshen_and_insert(&mut self, name: Name) -> Name {
@@ -547,20 +3933,26 @@ mod fresh {
             name
         }
 
+        /// `name`, primed until it neither shadows a name in scope nor a
+        /// built-in constant.
         fn freshen(&self, name: Name) -> Name {
-            if name.deref() == "_" || !self.0.contains(&name) {
+            if name.deref() == "_" || !(self.0.contains(&name) || is_builtin(&name)) {
                 name
             } else {
                 self.freshen(format!("{}'", name.deref()).into_boxed_str().into())
             }
         }
 
+        /// Run `f` one level deeper, and forget the names it brings into
+        /// scope after.
         pub fn eval<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
             let old_len = self.0.len();
+            self.1 += 1;
 
             let res = f(self);
 
-            while old_len > self.0.len() {
+            self.1 -= 1;
+            while self.0.len() > old_len {
                 self.0.pop();
             }
 
@@ -601,8 +3993,114 @@ mod fresh {
 
 use fresh::Fresh;
 
+impl Icit {
+    fn delims(self) -> (&'static str, &'static str) {
+        match self {
+            Icit::Impl => ("{", "}"),
+            Icit::Expl => ("(", ")"),
+            Icit::Inst => ("{{", "}}"),
+        }
+    }
+}
+
+/// Whether a Π type binder is printed with its name, rather than as the
+/// domain of an arrow
+fn named_binder(x: &str, i: Icit) -> bool {
+    x != "_" || i != Icit::Expl
+}
+
+/// The name of a binder of `body` to print: `x` rather than `_` when the
+/// variable is used after all, as in a quoted motive, so that it can be
+/// referred to.
+fn binder_name(x: &Name, body: &Term) -> Name {
+    if x.deref() == "_" && body.occurs(Ix(0), &mut |_, _, _| true) {
+        "x".into()
+    } else {
+        x.clone()
+    }
+}
+
+impl Raw {
+    /// The binders at the start of the Π type `self` printed as one group
+    /// `(x y : A)`, the ones of the same icit and domain where the domain
+    /// doesn't mention the names before, and the rest of the type.
+    pub(crate) fn pi_group(&self) -> (Vec<Name>, &Raw) {
+        let mut t = self;
+        while let Raw::RSrcPos(_, t_) = t {
+            t = t_;
+        }
+        let Raw::RPi(x, i, a, b) = t else {
+            return (vec![], self);
+        };
+        let (mut xs, mut b): (_, &Raw) = (vec![x.clone()], b);
+        let a_ = a.to_string();
+        loop {
+            match b {
+                Raw::RSrcPos(_, b_) => b = b_,
+                Raw::RPi(y, i_, a, b_)
+                    if i_ == i
+                        && named_binder(y, *i_)
+                        && !xs.iter().any(|x| termination::mentions(a, x))
+                        && a.to_string() == a_ =>
+                {
+                    xs.push(y.clone());
+                    b = b_;
+                }
+                _ => break (xs, b),
+            }
+        }
+    }
+}
+
+impl Term {
+    /// The binders at the start of the Π type `self` printed as one group
+    /// `(x y : A)`, the ones of the same icit and domain, and the rest of the
+    /// type.
+    fn pi_group(&self) -> (Vec<Name>, &Term) {
+        let Term::TΠ(x, i, a, b) = self else {
+            return (vec![], self);
+        };
+        let (mut xs, mut b): (_, &Term) = (vec![binder_name(x, b)], b);
+        while let Term::TΠ(y, i_, a_, b_) = b {
+            let y = binder_name(y, b_);
+            if i_ != i || !named_binder(&y, *i_) || !a.weakened_eq(a_, xs.len()) {
+                break;
+            }
+            xs.push(y);
+            b = b_;
+        }
+        (xs, b)
+    }
+}
+
+/// A lambda binder, printed in braces when implicit.
+struct Binder<'a>(&'a Name, Icit);
+
+impl<'a> Display for Binder<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.1 {
+            Icit::Impl => write!(f, "{{{}}}", self.0),
+            Icit::Expl => write!(f, "{}", self.0),
+            Icit::Inst => write!(f, "{{{{{}}}}}", self.0),
+        }
+    }
+}
+
+impl Raw {
+    pub fn display(&self, options: PrintOptions) -> WithOptions<&Self> {
+        WithOptions(self, options)
+    }
+}
+
 impl Display for Raw {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.display(PrintOptions::default()).fmt(f)
+    }
+}
+
+impl Display for WithOptions<&Raw> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let WithOptions(raw, options) = self;
         const ATOM_P: u8 = 3;
         const APP_P: u8 = 2;
         const PI_P: u8 = 1;
@@ -612,7 +4110,7 @@ impl Display for Raw {
             p_curr < p_old
         }
 
-        fn open(p_old: u8, p_curr: u8, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn open(p_old: u8, p_curr: u8, f: &mut DocBuilder) -> std::fmt::Result {
             if show_parens(p_old, p_curr) {
                 write!(f, "(")?;
             }
@@ -620,7 +4118,7 @@ impl Display for Raw {
             Ok(())
         }
 
-        fn close(p_old: u8, p_curr: u8, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn close(p_old: u8, p_curr: u8, f: &mut DocBuilder) -> std::fmt::Result {
             if show_parens(p_old, p_curr) {
                 write!(f, ")")?;
             }
@@ -628,98 +4126,434 @@ impl Display for Raw {
             Ok(())
         }
 
-        fn print(prec: u8, raw: &Raw, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn print(prec: u8, raw: &Raw, f: &mut DocBuilder, o: &PrintOptions) -> std::fmt::Result {
             match &raw {
-                Raw::RSrcPos(_, raw) => print(prec, raw, f),
+                Raw::RSrcPos(_, raw) => print(prec, raw, f, o),
                 Raw::RVar(x) => write!(f, "{x}"),
-                Raw::RLam(x, ref t) => {
-                    write!(f, "λ {x}")?;
-
-                    let mut t = t;
+                Raw::RLam(x, i, ref t) => {
+                    open(prec, LET_P, f)?;
+                    f.block(|f| -> std::fmt::Result {
+                        write!(f, "{}{}", o.lambda(), Binder(x, *i))?;
 
-                    loop {
-                        match &**t {
-                            Raw::RLam(x, t_) => {
-                                write!(f, " {x}")?;
-                                t = t_;
-                            }
-                            other => {
-                                write!(f, ". ")?;
-                                print(LET_P, other, f)?;
+                        let mut t = t;
 
-                                break;
+                        loop {
+                            match &**t {
+                                Raw::RSrcPos(_, t_) => t = t_,
+                                Raw::RLam(x, i, t_) => {
+                                    write!(f, " {}", Binder(x, *i))?;
+                                    t = t_;
+                                }
+                                other => {
+                                    write!(f, ".")?;
+                                    f.line();
+                                    break print(LET_P, other, f, o);
+                                }
                             }
                         }
-                    }
+                    })?;
 
                     close(prec, LET_P, f)
                 }
-                Raw::RPi(x, a, ref b) => {
+                Raw::RPi(..) => {
                     open(prec, PI_P, f)?;
 
-                    if x.deref() == "_" {
-                        print(APP_P, a, f)?;
-                        write!(f, " → ")?;
-                        print(PI_P, b, f)?;
-                    } else {
-                        write!(f, "({} : ", x.deref())?;
-                        print(LET_P, a, f)?;
-                        write!(f, ")")?;
-
-                        let mut b = b;
+                    // the whole chain of binders and arrows, so that they
+                    // break alike
+                    f.block(|f| -> std::fmt::Result {
+                        let mut b = raw;
+                        // whether the last domain was a binder with a name
+                        let mut named = None;
 
                         loop {
-                            match &**b {
-                                Raw::RPi(x, a, b_) if x.deref() != "_" => {
-                                    write!(f, "({} : ", x.deref())?;
-                                    print(LET_P, a, f)?;
-                                    write!(f, ")")?;
-
-                                    b = b_;
+                            match b {
+                                Raw::RPi(x, i, a, b_) => {
+                                    let dependent = named_binder(x, *i);
+                                    match named {
+                                        None => {}
+                                        Some(true) if dependent => f.line_break(),
+                                        Some(_) => {
+                                            f.line();
+                                            write!(f, "{} ", o.arrow())?;
+                                        }
+                                    }
+
+                                    if dependent {
+                                        let (xs, b_) = b.pi_group();
+                                        let (l, r) = i.delims();
+                                        write!(f, "{l}{} : ", xs.join(" "))?;
+                                        print(LET_P, a, f, o)?;
+                                        write!(f, "{r}")?;
+                                        b = b_;
+                                    } else {
+                                        print(APP_P, a, f, o)?;
+                                        b = b_;
+                                    }
+
+                                    named = Some(dependent);
                                 }
                                 other => {
-                                    write!(f, " → ")?;
-                                    print(PI_P, other, f)?;
-                                    break;
+                                    f.line();
+                                    write!(f, "{} ", o.arrow())?;
+                                    break print(PI_P, other, f, o);
                                 }
                             }
                         }
-                    }
+                    })?;
 
                     close(prec, PI_P, f)
                 }
-                Raw::RLet(x, a, b, c) => {
-                    write!(f, "let {} : ", x)?;
+                Raw::RSigma(x, a, ref b) => {
+                    open(prec, PI_P, f)?;
+
+                    f.block(|f| -> std::fmt::Result {
+                        if x.deref() == "_" {
+                            print(APP_P, a, f, o)?;
+                        } else {
+                            write!(f, "({} : ", x.deref())?;
+                            print(LET_P, a, f, o)?;
+                            write!(f, ")")?;
+                        }
 
-                    print(LET_P, a, f)?;
-                    write!(f, " := ")?;
-                    print(LET_P, b, f)?;
+                        f.line();
+                        write!(f, "{} ", o.times())?;
+                        print(PI_P, b, f, o)
+                    })?;
 
-                    writeln!(f, ";")?;
+                    close(prec, PI_P, f)
+                }
+                Raw::RPair(t, u) => {
+                    write!(f, "(")?;
+                    f.block(|f| -> std::fmt::Result {
+                        print(LET_P, t, f, o)?;
+
+                        // right-nested pairs are one tuple
+                        let mut u = u;
+                        while let Raw::RPair(t, u_) = &**u {
+                            write!(f, ",")?;
+                            f.line();
+                            print(LET_P, t, f, o)?;
+                            u = u_;
+                        }
 
-                    print(LET_P, c, f)
+                        write!(f, ",")?;
+                        f.line();
+                        print(LET_P, u, f, o)
+                    })?;
+                    write!(f, ")")
+                }
+                Raw::RLet(x, a, b, c) | Raw::RLetRec(x, a, b, c) => {
+                    let rec = match raw {
+                        Raw::RLetRec(..) => "rec ",
+                        _ => "",
+                    };
+                    f.block(|f| -> std::fmt::Result {
+                        write!(f, "let {rec}{} : ", x)?;
+
+                        print(LET_P, a, f, o)?;
+                        write!(f, " :=")?;
+                        f.line();
+                        print(LET_P, b, f, o)
+                    })?;
+
+                    write!(f, ";")?;
+                    f.hard_line();
+
+                    print(LET_P, c, f, o)
                 }
-                Raw::RHole => write!(f, "_"),
-                Raw::RApp(t, u) => {
+                Raw::RHole(None) => write!(f, "_"),
+                Raw::RHole(Some(x)) => write!(f, "?{x}"),
+                Raw::RApp(..) | Raw::RAppNamed(..) => {
+                    // the whole spine, so that its arguments break alike
+                    let mut spine = vec![];
+                    let mut t = raw;
+                    while let Raw::RApp(t_, _, _) | Raw::RAppNamed(t_, _, _) = t {
+                        spine.push(t);
+                        t = t_;
+                    }
+
                     open(prec, APP_P, f)?;
-                    print(APP_P, t, f)?;
-                    write!(f, " ")?;
-                    print(ATOM_P, u, f)?;
+                    f.block(|f| -> std::fmt::Result {
+                        print(APP_P, t, f, o)?;
+                        for app in spine.into_iter().rev() {
+                            f.line();
+                            match app {
+                                Raw::RApp(_, u, Icit::Expl) => print(ATOM_P, u, f, o)?,
+                                Raw::RApp(_, u, i) => {
+                                    let (l, r) = i.delims();
+                                    write!(f, "{l}")?;
+                                    print(LET_P, u, f, o)?;
+                                    write!(f, "{r}")?;
+                                }
+                                Raw::RAppNamed(_, x, u) => {
+                                    write!(f, "{{{x} = ")?;
+                                    print(LET_P, u, f, o)?;
+                                    write!(f, "}}")?;
+                                }
+                                _ => unreachable!(),
+                            }
+                        }
+                        Ok(())
+                    })?;
                     close(prec, APP_P, f)
                 }
-                Raw::RU => write!(f, "U"),
+                Raw::RU(None) => write!(f, "U"),
+                Raw::RU(Some(n)) => write!(f, "U{n}"),
+                Raw::RProj1(t) => {
+                    print(ATOM_P, t, f, o)?;
+                    write!(f, ".1")
+                }
+                Raw::RProj2(t) => {
+                    print(ATOM_P, t, f, o)?;
+                    write!(f, ".2")
+                }
+                Raw::RField(t, x) => {
+                    print(ATOM_P, t, f, o)?;
+                    write!(f, ".{x}")
+                }
+                Raw::RRecordLit(fields) => f.group(|f| -> std::fmt::Result {
+                    write!(f, "record {{")?;
+                    f.nest(INDENT, |f| -> std::fmt::Result {
+                        for (k, (x, t)) in fields.iter().enumerate() {
+                            if k > 0 {
+                                write!(f, ",")?;
+                            }
+                            f.line();
+                            write!(f, "{x} = ")?;
+                            print(LET_P, t, f, o)?;
+                        }
+                        Ok(())
+                    })?;
+                    f.line();
+                    write!(f, "}}")
+                }),
+                Raw::RNatLit(n) => write!(f, "{n}"),
+                Raw::RStrLit(s) => write!(f, "{s:?}"),
+                Raw::RCharLit(c) => write!(f, "{c:?}"),
+                Raw::RIntLit(n) => write!(f, "{n}"),
+                Raw::RIf(b, t, e) => {
+                    open(prec, LET_P, f)?;
+                    f.block(|f| -> std::fmt::Result {
+                        write!(f, "if ")?;
+                        print(LET_P, b, f, o)?;
+                        f.line();
+                        write!(f, "then ")?;
+                        print(LET_P, t, f, o)?;
+                        f.line();
+                        write!(f, "else ")?;
+                        print(LET_P, e, f, o)
+                    })?;
+                    close(prec, LET_P, f)
+                }
+                Raw::RMatch(t, branches) => {
+                    open(prec, LET_P, f)?;
+                    f.block(|f| -> std::fmt::Result {
+                        write!(f, "match ")?;
+                        print(LET_P, t, f, o)?;
+                        write!(f, " with")?;
+                        for (pat, u) in branches {
+                            f.line();
+                            write!(f, "| {pat} => ")?;
+                            print(LET_P, u, f, o)?;
+                        }
+                        Ok(())
+                    })?;
+                    close(prec, LET_P, f)
+                }
+            }
+        }
+
+        let mut doc = DocBuilder::new();
+        print(0, raw, &mut doc, options)?;
+        doc.finish().render(options.width, f)
+    }
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Pattern::PCon(c, xs) => {
+                write!(f, "{c}")?;
+                for (x, i) in xs {
+                    write!(f, " {}", Binder(x, *i))?;
+                }
+                Ok(())
+            }
+            Pattern::PWild => write!(f, "_"),
+        }
+    }
+}
+
+impl Display for Pragma {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        if !self.args.is_empty() {
+            write!(f, "({})", self.args.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Decl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Decl::DDef(x, a, t) => write!(f, "def {x} : {a} := {t}"),
+            Decl::DInstance(x, a, t) => write!(f, "instance {x} : {a} := {t}"),
+            Decl::DOpaque(x, a, t) => write!(f, "opaque {x} : {a} := {t}"),
+            Decl::DAxiom(x, a) => write!(f, "axiom {x} : {a}"),
+            Decl::DPragma(p, d) => write!(f, "#[{p}]\n{d}"),
+            Decl::DFilePragma(p) => write!(f, "#![{p}]"),
+            Decl::DImport(path) => write!(f, "import {path}"),
+            Decl::DData(x, params, a, cons) | Decl::DRecord(x, params, a, cons) => {
+                let keyword = match self {
+                    Decl::DData(..) => "data",
+                    _ => "record",
+                };
+                write!(f, "{keyword} {x}")?;
+                for (y, b) in params {
+                    write!(f, " ({y} : {b})")?;
+                }
+                write!(f, " : {a}")?;
+                for (c, b) in cons {
+                    write!(f, " | {c} : {b}")?;
+                }
+                Ok(())
             }
+            Decl::DMutual(data, defs) => {
+                write!(f, "mutual")?;
+                for (x, params, a, cons) in data {
+                    let data = Decl::DData(x.clone(), params.clone(), a.clone(), cons.clone());
+                    write!(f, "\n  {data}")?;
+                }
+                for (x, a, t) in defs {
+                    write!(f, "\n  def {x} : {a} := {t}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// How terms and raw syntax are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrintOptions {
+    /// `λ`, `→` and `×` rather than `\`, `->` and `*`
+    pub unicode: bool,
+    /// whether inserted metas are shown applied to the bound variables
+    pub meta_spines: bool,
+    /// whether the `let`s of terms are printed as their definitions in place
+    /// of the variables they bind
+    pub inline_lets: bool,
+    /// how many columns printed lines fit in where they can, broken at
+    /// binders, arguments and definitions
+    pub width: usize,
+    /// how deeply nested core terms are printed, the ones nested deeper are
+    /// elided as `…`
+    pub max_depth: Option<usize>,
+}
+
+impl Default for PrintOptions {
+    fn default() -> Self {
+        PrintOptions {
+            unicode: true,
+            meta_spines: true,
+            inline_lets: false,
+            width: 80,
+            max_depth: None,
+        }
+    }
+}
+
+impl PrintOptions {
+    /// The default options with ASCII symbols.
+    pub fn ascii() -> Self {
+        PrintOptions {
+            unicode: false,
+            ..Self::default()
+        }
+    }
+
+    fn lambda(&self) -> &'static str {
+        match self.unicode {
+            true => "λ ",
+            false => "\\",
+        }
+    }
+
+    fn ellipsis(&self) -> &'static str {
+        match self.unicode {
+            true => "…",
+            false => "...",
+        }
+    }
+
+    fn arrow(&self) -> &'static str {
+        match self.unicode {
+            true => "→",
+            false => "->",
+        }
+    }
+
+    fn times(&self) -> &'static str {
+        match self.unicode {
+            true => "×",
+            false => "*",
         }
+    }
+}
+
+/// Printer with options other than the default ones
+pub struct WithOptions<T>(pub T, pub PrintOptions);
 
-        print(0, self, f)
+/// Prints a term in a context with the given names, innermost last.
+pub struct TPrettyPrinter<'a>(pub &'a [Name], pub &'a Term);
+
+impl<'a> TPrettyPrinter<'a> {
+    pub fn with_options(self, options: PrintOptions) -> WithOptions<Self> {
+        WithOptions(self, options)
+    }
+}
+
+impl Display for TPrettyPrinter<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let TPrettyPrinter(names, t) = *self;
+        TPrettyPrinter(names, t)
+            .with_options(PrintOptions::default())
+            .fmt(f)
+    }
+}
+
+impl Display for WithOptions<TPrettyPrinter<'_>> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let WithOptions(TPrettyPrinter(names, t), options) = *self;
+        TElidingPrinter(names, t, &HashSet::new())
+            .with_options(options)
+            .fmt(f)
+    }
+}
+
+/// Prints a term like [`TPrettyPrinter`], with the subterms in the set elided
+/// as `…`, such as the ones [`Term::common`] to it and another term.
+pub struct TElidingPrinter<'a>(pub &'a [Name], pub &'a Term, pub &'a HashSet<*const Term>);
+
+impl<'a> TElidingPrinter<'a> {
+    pub fn with_options(self, options: PrintOptions) -> WithOptions<Self> {
+        WithOptions(self, options)
     }
 }
 
-struct TPrettyPrinter<'a>(&'a Cxt, &'a Term);
+impl Display for TElidingPrinter<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let TElidingPrinter(names, t, elided) = *self;
+        TElidingPrinter(names, t, elided)
+            .with_options(PrintOptions::default())
+            .fmt(f)
+    }
+}
 
-impl<'a> Display for TPrettyPrinter<'a> {
+impl Display for WithOptions<TElidingPrinter<'_>> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let TPrettyPrinter(cxt, t) = self;
+        let WithOptions(TElidingPrinter(names, t, elided), options) = self;
 
         const ATOM_P: u8 = 3;
         const APP_P: u8 = 2;
@@ -730,7 +4564,7 @@ impl<'a> Display for TPrettyPrinter<'a> {
             p_curr < p_old
         }
 
-        fn open(p_old: u8, p_curr: u8, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn open(p_old: u8, p_curr: u8, f: &mut DocBuilder) -> std::fmt::Result {
             if show_parens(p_old, p_curr) {
                 write!(f, "(")?;
             }
@@ -738,7 +4572,7 @@ impl<'a> Display for TPrettyPrinter<'a> {
             Ok(())
         }
 
-        fn close(p_old: u8, p_curr: u8, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn close(p_old: u8, p_curr: u8, f: &mut DocBuilder) -> std::fmt::Result {
             if show_parens(p_old, p_curr) {
                 write!(f, ")")?;
             }
@@ -746,154 +4580,499 @@ impl<'a> Display for TPrettyPrinter<'a> {
             Ok(())
         }
 
+        /// `head` applied to atoms, one per line if they don't fit on one
+        fn print_app<'t>(
+            prec: u8,
+            head: impl Display,
+            args: impl IntoIterator<Item = &'t Term>,
+            f: &mut DocBuilder,
+            fresh: &mut Fresh,
+            o: &PrintOptions,
+        ) -> std::fmt::Result {
+            open(prec, APP_P, f)?;
+            f.block(|f| -> std::fmt::Result {
+                write!(f, "{head}")?;
+                for t in args {
+                    f.line();
+                    print(ATOM_P, t, f, fresh, o)?;
+                }
+                Ok(())
+            })?;
+            close(prec, APP_P, f)
+        }
+
         fn print(
             prec: u8,
             term: &Term,
-            f: &mut std::fmt::Formatter<'_>,
+            f: &mut DocBuilder,
             fresh: &mut Fresh,
+            o: &PrintOptions,
         ) -> std::fmt::Result {
-            match &term {
+            if let Term::TSrcPos(_, t) = term {
+                return print(prec, t, f, fresh, o);
+            }
+            if o.max_depth.is_some_and(|max| fresh.depth() >= max) || fresh.elided(term) {
+                return write!(f, "{}", o.ellipsis());
+            }
+
+            // the variables bound inside the term go out of scope after it
+            fresh.eval(|fresh| match &term {
                 Term::TV(x) => {
                     write!(f, "{}", fresh[*x])
                 }
-                Term::Tλ(x, ref t) => {
-                    let x = fresh.freshen_and_insert(x.clone());
+                Term::Tλ(x, i, ref t) => {
+                    let x = fresh.freshen_and_insert(binder_name(x, t));
                     open(prec, LET_P, f)?;
-                    write!(f, "λ {x}")?;
+                    f.block(|f| -> std::fmt::Result {
+                        write!(f, "{}{}", o.lambda(), Binder(&x, *i))?;
 
-                    let mut t = t;
+                        let mut t = t;
 
-                    loop {
-                        match &**t {
-                            Term::Tλ(x, t_) => {
-                                let x = fresh.freshen_and_insert(x.clone());
-                                write!(f, " {x}")?;
-                                t = t_;
-                            }
-                            other => {
-                                write!(f, ". ")?;
-                                print(LET_P, other, f, fresh)?;
+                        loop {
+                            match &**t {
+                                Term::Tλ(x, i, t_) => {
+                                    let x = fresh.freshen_and_insert(binder_name(x, t_));
+                                    write!(f, " {}", Binder(&x, *i))?;
+                                    t = t_;
+                                }
+                                other => {
+                                    write!(f, ".")?;
+                                    f.line();
+                                    print(LET_P, other, f, fresh, o)?;
 
-                                break;
+                                    break Ok(());
+                                }
                             }
                         }
-                    }
+                    })?;
 
                     close(prec, LET_P, f)
                 }
-                Term::TΠ(x, a, ref b) => {
+                Term::TΠ(..) => {
                     open(prec, PI_P, f)?;
 
-                    if x.deref() == "_" {
-                        print(APP_P, a, f, fresh)?;
-                        write!(f, " → ")?;
-                        fresh.freshen_and_insert(x.clone());
-                        print(PI_P, b, f, fresh)?;
-                    } else {
-                        fresh.freshen_and_insert_after(
-                            x.clone(),
-                            |fresh, x| -> std::fmt::Result {
-                                write!(f, "({x} : ")?;
-                                print(LET_P, a, f, fresh)?;
-                                write!(f, ")")
-                            },
-                        )?;
-
-                        let mut b = b;
+                    // the whole chain of binders and arrows, so that they
+                    // break alike
+                    f.block(|f| -> std::fmt::Result {
+                        let mut b = term;
+                        // whether the last domain was a binder with a name
+                        let mut named = None;
 
                         loop {
-                            match &**b {
-                                Term::TΠ(x, a, b_) if x.deref() != "_" => {
-                                    fresh.freshen_and_insert_after(
-                                        x.clone(),
-                                        |fresh, x| -> std::fmt::Result {
-                                            write!(f, "({x} : ")?;
-                                            print(LET_P, a, f, fresh)?;
-                                            write!(f, ")")
-                                        },
-                                    )?;
-
-                                    b = b_;
+                            match b {
+                                Term::TΠ(x, i, a, b_) => {
+                                    let dependent = named_binder(&binder_name(x, b_), *i);
+                                    match named {
+                                        None => {}
+                                        Some(true) if dependent => f.line_break(),
+                                        Some(_) => {
+                                            f.line();
+                                            write!(f, "{} ", o.arrow())?;
+                                        }
+                                    }
+
+                                    if dependent {
+                                        let (xs, b_) = b.pi_group();
+                                        let xs = fresh.eval(|fresh| {
+                                            let xs = xs.into_iter();
+                                            xs.map(|x| fresh.freshen_and_insert(x))
+                                                .collect::<Vec<_>>()
+                                        });
+                                        let (l, r) = i.delims();
+                                        write!(f, "{l}{} : ", xs.join(" "))?;
+                                        print(LET_P, a, f, fresh, o)?;
+                                        write!(f, "{r}")?;
+                                        xs.into_iter().for_each(|x| fresh.insert(x));
+                                        b = b_;
+                                    } else {
+                                        print(APP_P, a, f, fresh, o)?;
+                                        fresh.freshen_and_insert(x.clone());
+                                        b = b_;
+                                    }
+
+                                    named = Some(dependent);
                                 }
                                 other => {
-                                    write!(f, " → ")?;
-                                    print(PI_P, other, f, fresh)?;
-                                    break;
+                                    f.line();
+                                    write!(f, "{} ", o.arrow())?;
+                                    break print(PI_P, other, f, fresh, o);
                                 }
                             }
                         }
-                    }
+                    })?;
+
+                    close(prec, PI_P, f)
+                }
+                Term::Tσ(t, u) => {
+                    write!(f, "(")?;
+                    f.block(|f| -> std::fmt::Result {
+                        print(LET_P, t, f, fresh, o)?;
+
+                        // right-nested pairs are one tuple
+                        let mut u = u;
+                        while let Term::Tσ(t, u_) = &**u {
+                            write!(f, ",")?;
+                            f.line();
+                            print(LET_P, t, f, fresh, o)?;
+                            u = u_;
+                        }
+
+                        write!(f, ",")?;
+                        f.line();
+                        print(LET_P, u, f, fresh, o)
+                    })?;
+                    write!(f, ")")
+                }
+                Term::TΣ(x, a, b) => {
+                    open(prec, PI_P, f)?;
+
+                    f.block(|f| -> std::fmt::Result {
+                        let x = binder_name(x, b);
+                        if x.deref() == "_" {
+                            print(APP_P, a, f, fresh, o)?;
+                            fresh.freshen_and_insert(x);
+                        } else {
+                            fresh.freshen_and_insert_after(x, |fresh, x| -> std::fmt::Result {
+                                write!(f, "({x} : ")?;
+                                print(LET_P, a, f, fresh, o)?;
+                                write!(f, ")")
+                            })?;
+                        }
+
+                        f.line();
+                        write!(f, "{} ", o.times())?;
+                        print(PI_P, b, f, fresh, o)
+                    })?;
 
                     close(prec, PI_P, f)
                 }
-                Term::Tσ(_, _) => todo!(),
-                Term::TΣ(_, _, _) => todo!(),
+                // the definition is printed in place of the variable
+                Term::TLet(_, _, b, c) if o.inline_lets => {
+                    let mut def = DocBuilder::new();
+                    print(ATOM_P, b, &mut def, fresh, o)?;
+                    fresh.insert(def.finish().flat().into());
+                    print(prec, c, f, fresh, o)
+                }
                 Term::TLet(x, a, b, c) => {
                     fresh.freshen_and_insert_after(
-                        x.clone(),
+                        binder_name(x, c),
                         |fresh, name| -> std::fmt::Result {
-                            write!(f, "let {} : ", name)?;
+                            f.block(|f| -> std::fmt::Result {
+                                write!(f, "let {} : ", name)?;
 
-                            print(LET_P, a, f, fresh)?;
-                            write!(f, " := ")?;
-                            print(LET_P, b, f, fresh)?;
+                                print(LET_P, a, f, fresh, o)?;
+                                write!(f, " :=")?;
+                                f.line();
+                                print(LET_P, b, f, fresh, o)
+                            })?;
 
-                            writeln!(f, ";")?;
+                            write!(f, ";")?;
+                            f.hard_line();
 
                             Ok(())
                         },
                     )?;
 
-                    print(LET_P, c, f, fresh)
+                    print(LET_P, c, f, fresh, o)
                 }
                 Term::TMeta(m) => write!(f, "?{m}"),
+                Term::TInsertedMeta(m, _) if !o.meta_spines => write!(f, "?{m}"),
+                // printed like the application it stands for
                 Term::TInsertedMeta(m, bds) => {
-                    let mut braces = false;
+                    print(prec, &inserted_meta_app(*m, bds, fresh.lvl()), f, fresh, o)
+                }
+                Term::TApp(..) => {
+                    // the whole spine, so that its arguments break alike
+                    let mut spine = vec![];
+                    let mut t = term;
+                    while let Term::TApp(t_, u, i) = t {
+                        spine.push((u, *i));
+                        t = t_;
+                    }
 
-                    for bd in bds {
-                        match bd {
-                            BD::Bound => {
-                                braces = true;
-                                break;
+                    open(prec, APP_P, f)?;
+                    f.block(|f| -> std::fmt::Result {
+                        print(APP_P, t, f, fresh, o)?;
+                        for (u, i) in spine.into_iter().rev() {
+                            f.line();
+                            match i {
+                                Icit::Expl => print(ATOM_P, u, f, fresh, o)?,
+                                Icit::Impl | Icit::Inst => {
+                                    let (l, r) = i.delims();
+                                    write!(f, "{l}")?;
+                                    print(LET_P, u, f, fresh, o)?;
+                                    write!(f, "{r}")?;
+                                }
                             }
-                            BD::Defined => {}
                         }
-                    }
-
-                    braces = braces && show_parens(prec, APP_P);
-                    if braces {
-                        write!(f, "(?{m}")?;
-                    } else {
-                        write!(f, "?{m} ")?;
-                    }
-                    for (lvl, bd) in bds.iter().enumerate() {
-                        match bd {
-                            BD::Bound => {
-                                write!(f, " {}", fresh[lvl])?;
+                        Ok(())
+                    })?;
+                    close(prec, APP_P, f)
+                }
+                Term::TProj1(t) => {
+                    print(ATOM_P, t, f, fresh, o)?;
+                    write!(f, ".1")
+                }
+                Term::TProj2(t) => {
+                    print(ATOM_P, t, f, fresh, o)?;
+                    write!(f, ".2")
+                }
+                Term::TField(r, x, t) => {
+                    print(ATOM_P, t, f, fresh, o)?;
+                    write!(f, ".{}", r.fields[*x])
+                }
+                Term::TRecordLit(r, fields) => f.group(|f| -> std::fmt::Result {
+                    write!(f, "record {{")?;
+                    f.nest(INDENT, |f| -> std::fmt::Result {
+                        for (k, (x, t)) in r.fields.iter().zip(fields).enumerate() {
+                            if k > 0 {
+                                write!(f, ",")?;
                             }
-                            BD::Defined => {}
+                            f.line();
+                            write!(f, "{x} = ")?;
+                            print(LET_P, t, f, fresh, o)?;
                         }
-                    }
-
-                    if braces {
-                        write!(f, ")")?;
-                    }
-
-                    Ok(())
+                        Ok(())
+                    })?;
+                    f.line();
+                    write!(f, "}}")
+                }),
+                Term::TRecord(r, args) if args.is_empty() => write!(f, "{}", r.name),
+                Term::TRecord(r, args) => {
+                    print_app(prec, &r.name, args.iter().map(|t| &**t), f, fresh, o)
                 }
-                Term::TApp(t, u) => {
+                Term::TU(Level::LConst(n)) => write!(f, "U{n}"),
+                Term::TU(Level::LMeta(..)) => write!(f, "U"),
+                Term::TNat => write!(f, "Nat"),
+                Term::TNatLit(n) => write!(f, "{n}"),
+                Term::TString => write!(f, "String"),
+                Term::TStrLit(s) => write!(f, "{s:?}"),
+                Term::TChar => write!(f, "Char"),
+                Term::TCharLit(c) => write!(f, "{c:?}"),
+                Term::TAppend(s, t) => print_app(prec, "append", [&**s, t], f, fresh, o),
+                Term::TLength(s) => print_app(prec, "length", [&**s], f, fresh, o),
+                Term::TLazy(a) => print_app(prec, "Lazy", [&**a], f, fresh, o),
+                Term::TDelay(t) => print_app(prec, "delay", [&**t], f, fresh, o),
+                Term::TForce(t) => print_app(prec, "force", [&**t], f, fresh, o),
+                Term::TSrcPos(..) => unreachable!("spans are skipped above"),
+                Term::TInt => write!(f, "Int"),
+                Term::TIntLit(n) => write!(f, "{n}"),
+                Term::TIntOp(op, x, y) => print_app(prec, op.name(), [&**x, y], f, fresh, o),
+                Term::TSuc(t) => print_app(prec, "suc", [&**t], f, fresh, o),
+                Term::TNatElim(p, z, s, n) => {
+                    print_app(prec, "natElim", [&**p, z, s, n], f, fresh, o)
+                }
+                Term::TBool => write!(f, "Bool"),
+                Term::TTrue => write!(f, "true"),
+                Term::TFalse => write!(f, "false"),
+                Term::TBoolElim(p, t, e, b) => {
+                    print_app(prec, "boolElim", [&**p, t, e, b], f, fresh, o)
+                }
+                Term::TEq(a, x, y) => print_app(prec, "Eq", [&**a, x, y], f, fresh, o),
+                // the implicit arguments are left out
+                Term::TRefl(..) => write!(f, "refl"),
+                Term::TJ(_, _, p, pr, y, e) => print_app(prec, "J", [&**p, pr, y, e], f, fresh, o),
+                Term::TData(d, args) if args.is_empty() => write!(f, "{}", d.name),
+                Term::TData(d, args) => {
+                    print_app(prec, &d.name, args.iter().map(|t| &**t), f, fresh, o)
+                }
+                // the parameters are left out
+                Term::TCon(d, c, args) if args.len() == d.params => {
+                    write!(f, "{}", d.cons[*c].name)
+                }
+                Term::TCon(d, c, args) => {
                     open(prec, APP_P, f)?;
-                    print(APP_P, t, f, fresh)?;
-                    write!(f, " ")?;
-                    print(ATOM_P, u, f, fresh)?;
+                    f.block(|f| -> std::fmt::Result {
+                        write!(f, "{}", d.cons[*c].name)?;
+                        for (t, i) in args[d.params..].iter().zip(&d.cons[*c].icits) {
+                            f.line();
+                            match i {
+                                Icit::Expl => print(ATOM_P, t, f, fresh, o)?,
+                                Icit::Impl | Icit::Inst => {
+                                    let (l, r) = i.delims();
+                                    write!(f, "{l}")?;
+                                    print(LET_P, t, f, fresh, o)?;
+                                    write!(f, "{r}")?;
+                                }
+                            }
+                        }
+                        Ok(())
+                    })?;
                     close(prec, APP_P, f)
                 }
-                Term::TU => write!(f, "U"),
+                // `fix f. t with g. u for g`, every name bound in every body
+                Term::TFix(fix, i) => {
+                    let xs: Vec<_> = fix
+                        .iter()
+                        .map(|(x, _, _)| fresh.freshen_and_insert(x.clone()))
+                        .collect();
+                    open(prec, LET_P, f)?;
+                    f.group(|f| -> std::fmt::Result {
+                        for (j, (x, (_, _, t))) in xs.iter().zip(fix.iter()).enumerate() {
+                            if j > 0 {
+                                f.line();
+                                write!(f, "with ")?;
+                            }
+                            f.block(|f| -> std::fmt::Result {
+                                write!(f, "{}{x}.", if j == 0 { "fix " } else { "" })?;
+                                f.line();
+                                print(LET_P, t, f, fresh, o)
+                            })?;
+                        }
+                        if fix.len() > 1 {
+                            f.line();
+                            write!(f, "for {}", xs[*i])?;
+                        }
+                        Ok(())
+                    })?;
+                    close(prec, LET_P, f)
+                }
+                // the parameters and indices are left out
+                Term::TElim(d, args, t) => {
+                    let args = &args[d.params..d.params + 1 + d.cons.len()];
+                    let args = args.iter().chain([t]).map(|t| &**t);
+                    print_app(prec, &d.elim, args, f, fresh, o)
+                }
+            })
+        }
+
+        let mut doc = DocBuilder::new();
+        let mut fresh = Fresh::new(names.to_vec()).eliding((*elided).clone());
+        print(0, t, &mut doc, &mut fresh, options)?;
+        doc.finish().render(options.width, f)
+    }
+}
+
+/// Prints a value of a context, quoted with the solved metas substituted.
+pub struct VPrettyPrinter {
+    names: Vec<Name>,
+    term: Term,
+}
+
+impl VPrettyPrinter {
+    pub fn new(metas: &mut MetaCxt, cxt: &Cxt, val: &Value) -> Self {
+        let term = quote(metas, cxt.lvl, val.clone());
+        VPrettyPrinter {
+            names: cxt.names(),
+            term: zonk(metas, cxt, term),
+        }
+    }
+
+    /// The value quoted.
+    pub fn term(&self) -> &Term {
+        &self.term
+    }
+}
+
+impl Display for VPrettyPrinter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        TPrettyPrinter(&self.names, &self.term).fmt(f)
+    }
+}
+
+/// how deeply nested the values printed without their context are
+const VALUE_DEPTH: usize = 12;
+
+impl Value {
+    /// How many variables the value lives under: one more than its outermost
+    /// free variable, and at least as many as the environments of its
+    /// closures have values, those being made in its context or an outer one.
+    /// The environments aren't walked, so that this stays cheap on huge ones.
+    pub fn scope(&self) -> Lvl {
+        let spine = |sp: &Spine| sp.iter().map(Elim::scope).max().unwrap_or(0);
+        let all = |vs: &[Value]| vs.iter().map(Value::scope).max().unwrap_or(0);
+        match self {
+            Value::VFlex(_, sp) | Value::VGlued(_, _, sp) => spine(sp),
+            Value::VRigid(x, sp) => (x + 1).max(spine(sp)),
+            Value::Vλ(_, _, (env, _)) | Value::VΣ(_, _, (env, _)) => env.len(),
+            Value::VΠ(_, _, a, (env, _)) => a.scope().max(env.len()),
+            Value::Vσ(t, u) => t.scope().max(u.scope()),
+            Value::VSuc(t) | Value::VLazy(t) => t.scope(),
+            Value::VDelay((env, _)) => env.len(),
+            Value::VThunk(t) => match &*t.state() {
+                ThunkState::Suspended(env, _) => env.len(),
+                ThunkState::Evaluated(v) => v.scope(),
+            },
+            Value::VEq(a, t, u) => a.scope().max(t.scope()).max(u.scope()),
+            Value::VRefl(a, t) => a.scope().max(t.scope()),
+            Value::VData(_, vs)
+            | Value::VCon(_, _, vs)
+            | Value::VRecord(_, vs)
+            | Value::VRecordLit(_, vs) => all(vs),
+            Value::VFix(_, _, env, sp) => env.len().max(spine(sp)),
+            Value::VU(_)
+            | Value::VNat
+            | Value::VNatLit(_)
+            | Value::VBool
+            | Value::VTrue
+            | Value::VFalse
+            | Value::VString
+            | Value::VStrLit(_)
+            | Value::VChar
+            | Value::VCharLit(_)
+            | Value::VInt
+            | Value::VIntLit(_) => 0,
+        }
+    }
+}
+
+impl Elim {
+    fn scope(&self) -> Lvl {
+        match self {
+            Elim::EApp(v, _) | Elim::EAppend(v) | Elim::EIntOp(_, v) => v.scope(),
+            Elim::ENatElim(p, z, s) | Elim::EBoolElim(p, z, s) => {
+                p.scope().max(z.scope()).max(s.scope())
             }
+            Elim::EJ(a, x, p, pr, y) => [a, x, p, pr, y]
+                .iter()
+                .map(|v| v.scope())
+                .max()
+                .unwrap_or(0),
+            Elim::EElim(_, vs) => vs.iter().map(Value::scope).max().unwrap_or(0),
+            Elim::EProj1
+            | Elim::EProj2
+            | Elim::EPrepend(_)
+            | Elim::ELength
+            | Elim::EIntOpLit(..)
+            | Elim::EForce
+            | Elim::EField(..) => 0,
         }
+    }
+}
+
+/// Without its context, a value is quoted under [`Value::scope`] variables
+/// named after their levels, `x0` outermost, with its metas unsolved and
+/// printed [`VALUE_DEPTH`] deep. Use a [`VPrettyPrinter`] to print it in its
+/// context.
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let lvl = self.scope();
+        let term = quote(&mut MetaCxt::default(), lvl, self.clone());
+        let names: Vec<Name> = (0..lvl).map(|x| format!("x{x}").into()).collect();
+        let options = PrintOptions {
+            max_depth: Some(VALUE_DEPTH),
+            ..PrintOptions::default()
+        };
+        TPrettyPrinter(&names, &term).with_options(options).fmt(f)
+    }
+}
+
+impl Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
 
-        let names: Vec<Rc<str>> = cxt.types.iter().map(|x| x.0.clone()).collect();
+/// Prints a closure as the lambda it is the body of, like a [`Value`].
+pub struct ClosurePrinter<'a>(pub &'a Closure);
 
-        print(0, t, f, &mut Fresh::new(names))
+impl Display for ClosurePrinter<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&Value::Vλ("x".into(), Icit::Expl, self.0.clone()), f)
     }
 }
+
+/// Show a value of `cxt`, see [`VPrettyPrinter`].
+pub fn display_value(metas: &mut MetaCxt, cxt: &Cxt, val: &Value) -> String {
+    VPrettyPrinter::new(metas, cxt, val).to_string()
+}
@@ -0,0 +1,181 @@
+//! Lints: the warnings of elaboration, each of which is allowed, warned about
+//! or denied.
+//!
+//! Their severities are set for a whole session with [`Lints::set`], and for
+//! a single declaration by the pragmas `#[allow(..)]`, `#[warn(..)]` and
+//! `#[deny(..)]` in front of it, or for a file by `#![allow(..)]` and the
+//! like, which win over the ones around them. The warnings are raised
+//! regardless and sorted out by [`Lints::apply`] once a declaration or term
+//! is done.
+
+use crate::{
+    error::{Error, ErrorKind},
+    span::Span,
+    Name, Pragma,
+};
+
+/// Kind of warning whose severity can be set
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    /// the variable of a binder is never used
+    UnusedVariable,
+    /// a binder shadows a variable of the same name
+    Shadowing,
+    /// a meta was left unsolved, with [`crate::metas::UnsolvedPolicy::Warn`]
+    UnsolvedMeta,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 3] = [Lint::UnusedVariable, Lint::Shadowing, Lint::UnsolvedMeta];
+
+    /// The name of the lint in pragmas.
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::UnusedVariable => "unused_variable",
+            Lint::Shadowing => "shadowing",
+            Lint::UnsolvedMeta => "unsolved_meta",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Lint::ALL.into_iter().find(|lint| lint.name() == name)
+    }
+
+    /// The lint `warning` is raised by, if any; the others can't be allowed.
+    pub fn of(warning: &Error) -> Option<Lint> {
+        match warning.kind {
+            ErrorKind::UnusedBinder { .. } => Some(Lint::UnusedVariable),
+            ErrorKind::Shadowing { .. } => Some(Lint::Shadowing),
+            ErrorKind::UnsolvedMeta { .. } => Some(Lint::UnsolvedMeta),
+            _ => None,
+        }
+    }
+
+    /// The severity unless something says otherwise: shadowing is only
+    /// warned about when asked for, see [`crate::ElabOptions::warn_shadowing`].
+    pub fn default_severity(self) -> Severity {
+        match self {
+            Lint::Shadowing => Severity::Allow,
+            Lint::UnusedVariable | Lint::UnsolvedMeta => Severity::Warn,
+        }
+    }
+}
+
+/// What becomes of the warnings of a lint
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Severity {
+    /// they are dropped
+    Allow,
+    Warn,
+    /// they are errors
+    Deny,
+}
+
+impl Severity {
+    /// The severity the pragma `name` sets.
+    pub fn from_pragma(name: &str) -> Option<Severity> {
+        match name {
+            "allow" => Some(Severity::Allow),
+            "warn" => Some(Severity::Warn),
+            "deny" => Some(Severity::Deny),
+            _ => None,
+        }
+    }
+}
+
+/// Severities of the lints, and the warnings about pragmas not reported yet
+#[derive(Debug, Clone, Default)]
+pub struct Lints {
+    /// the severities set for the whole session
+    levels: Vec<(Lint, Severity)>,
+    /// the severities set by pragmas for the declarations they span,
+    /// outermost first
+    scopes: Vec<(Span, Lint, Severity)>,
+    /// the pragmas and the lints in pragmas that aren't known, not warned
+    /// about yet
+    unknown: Vec<(Span, Unknown)>,
+}
+
+#[derive(Debug, Clone)]
+enum Unknown {
+    Pragma(Name),
+    Lint(Name),
+}
+
+impl Lints {
+    /// Give `lint` the severity `severity` outside of the declarations whose
+    /// pragmas set it.
+    pub fn set(&mut self, lint: Lint, severity: Severity) {
+        self.levels.retain(|(l, _)| *l != lint);
+        self.levels.push((lint, severity));
+    }
+
+    /// The severity of `lint` for a warning at `pos`.
+    pub fn severity(&self, lint: Lint, pos: &Span) -> Severity {
+        let scope = self.scopes.iter().rev().find(|(span, l, _)| {
+            *l == lint && span.file == pos.file && span.start <= pos.start && pos.end <= span.end
+        });
+        let level = || self.levels.iter().find(|(l, _)| *l == lint);
+        match scope {
+            Some(&(_, _, severity)) => severity,
+            None => level().map_or(lint.default_severity(), |&(_, severity)| severity),
+        }
+    }
+
+    /// Interpret the pragma `p` of a declaration. Unknown pragmas and lints
+    /// are warned about rather than rejected.
+    pub fn pragma(&mut self, p: &Pragma) {
+        let Some(severity) = Severity::from_pragma(&p.name) else {
+            self.unknown
+                .push((p.pos.clone(), Unknown::Pragma(p.name.clone())));
+            return;
+        };
+        for name in &p.args {
+            match Lint::from_name(name) {
+                Some(lint) => self.scopes.push((p.scope.clone(), lint, severity)),
+                None => self
+                    .unknown
+                    .push((p.pos.clone(), Unknown::Lint(name.clone()))),
+            }
+        }
+    }
+
+    /// The warnings about pragmas raised since the last call.
+    pub fn take_raised(&mut self) -> Vec<Error> {
+        std::mem::take(&mut self.unknown)
+            .into_iter()
+            .map(|(pos, unknown)| match unknown {
+                Unknown::Pragma(name) => Error::unknown_pragma(pos, name),
+                Unknown::Lint(name) => Error::unknown_lint(pos, name),
+            })
+            .collect()
+    }
+
+    /// Sort out `warnings` by the severities of their lints: the allowed ones
+    /// are dropped, and the first denied one is an error. The declarations
+    /// are done with then, so the severities their pragmas set are dropped
+    /// too, as the spans of later ones may be the same, e.g. in the REPL.
+    pub fn apply(&mut self, warnings: Vec<Error>) -> Result<Vec<Error>, Error> {
+        let mut kept = Vec::new();
+        let mut denied = None;
+        for warning in warnings {
+            let severity = match Lint::of(&warning) {
+                Some(lint) => self.severity(lint, &warning.pos),
+                None => Severity::Warn,
+            };
+            match severity {
+                Severity::Allow => {}
+                Severity::Warn => kept.push(warning),
+                Severity::Deny => {
+                    denied = Some(warning);
+                    break;
+                }
+            }
+        }
+        self.scopes.clear();
+        match denied {
+            Some(warning) => Err(warning),
+            None => Ok(kept),
+        }
+    }
+}
@@ -0,0 +1,452 @@
+//! Language server, speaking the Language Server Protocol over stdin and
+//! stdout.
+//!
+//! Open documents are parsed again with the incremental parser after each
+//! change, and then elaborated from scratch, their imports resolved next to
+//! them, from the other open documents as they are edited rather than saved.
+//! The syntax errors, the first elaboration error and the goals of the holes
+//! are published as diagnostics, and hovering over a term shows its type, or
+//! the goal of a hole.
+
+pub mod json;
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io::{self, BufRead, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use json::Json;
+
+use crate::{
+    diagnostics::{error_message, hole_message, parse_error_message},
+    elaborator::innermost_type,
+    incremental::{self, ParseResult, TextEdit},
+    metas::{HoleReport, MetaCxt, UnsolvedPolicy},
+    modules::{FileSystem, MemorySources, ModuleError, ModuleLoader, ModulePath, Overlay},
+    span::FileId,
+    trace::{TypeInfo, TypeRecorder},
+    zonk_local, Cxt, TPrettyPrinter,
+};
+
+/// The file of the spans of a document, the first source of the loader it is
+/// elaborated with
+const DOCUMENT: FileId = FileId(0);
+
+const PARSE_ERROR: i32 = -32700;
+const METHOD_NOT_FOUND: i32 = -32601;
+
+const ERROR: usize = 1;
+const WARNING: usize = 2;
+const INFORMATION: usize = 3;
+
+/// Serve the client talking over `input` and `output` until it exits.
+pub fn run(mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    let mut server = Server::new();
+
+    while let Some(msg) = read_message(&mut input)? {
+        let replies = match Json::parse(&msg) {
+            Ok(msg) => server.handle(&msg),
+            Err(err) => vec![error_response(Json::Null, PARSE_ERROR, &err)],
+        };
+        for reply in replies {
+            write_message(&mut output, &reply)?;
+        }
+        if server.exited {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Read a message after its `Content-Length` header, or `None` at the end of
+/// the input.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut len = None;
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        match line.trim_end().split_once(':') {
+            Some((name, value)) if name.eq_ignore_ascii_case("content-length") => {
+                len = value.trim().parse().ok();
+            }
+            None if line.trim_end().is_empty() && len.is_some() => break,
+            _ => {}
+        }
+    }
+
+    let mut buf = vec![0; len.unwrap()];
+    input.read_exact(&mut buf)?;
+    String::from_utf8(buf)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn write_message(output: &mut impl Write, msg: &Json) -> io::Result<()> {
+    let body = msg.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    output.flush()
+}
+
+/// The open documents, and what the client asked of them
+#[derive(Debug, Default)]
+pub struct Server {
+    documents: HashMap<String, Document>,
+    /// whether the client sent `exit`
+    exited: bool,
+}
+
+#[derive(Debug)]
+struct Document {
+    parsed: ParseResult,
+    /// the terms at source positions with their types, zonked, in the order
+    /// their elaboration finished
+    types: Vec<TypeInfo>,
+    holes: Vec<HoleReport>,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the client sent `exit`, after which the server should stop.
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    /// Answer a request or notification, returning the messages to send
+    /// back.
+    pub fn handle(&mut self, msg: &Json) -> Vec<Json> {
+        let params = msg.get("params").unwrap_or(&Json::Null);
+
+        match (msg.get("method").and_then(Json::as_str), msg.get("id")) {
+            (Some(method), Some(id)) => {
+                let id = id.clone();
+                let result = match method {
+                    "initialize" => capabilities(),
+                    "shutdown" => Json::Null,
+                    "textDocument/hover" => self.hover(params).unwrap_or(Json::Null),
+                    _ => {
+                        let msg = format!("unknown method `{method}`");
+                        return vec![error_response(id, METHOD_NOT_FOUND, &msg)];
+                    }
+                };
+                vec![response(id, result)]
+            }
+            (Some(method), None) => {
+                let diagnostics = match method {
+                    "exit" => {
+                        self.exited = true;
+                        None
+                    }
+                    "textDocument/didOpen" => self.did_open(params),
+                    "textDocument/didChange" => self.did_change(params),
+                    "textDocument/didClose" => self.did_close(params),
+                    _ => None,
+                };
+                diagnostics.into_iter().collect()
+            }
+            // responses, to the requests the server doesn't send
+            (None, _) => Vec::new(),
+        }
+    }
+
+    fn did_open(&mut self, params: &Json) -> Option<Json> {
+        let doc = params.get("textDocument")?;
+        let uri = doc.get("uri")?.as_str()?;
+        let text = doc.get("text")?.as_str()?;
+
+        let parsed = incremental::parse(DOCUMENT, text.to_owned());
+        Some(self.analyze(uri, parsed))
+    }
+
+    fn did_change(&mut self, params: &Json) -> Option<Json> {
+        let uri = params.get("textDocument")?.get("uri")?.as_str()?;
+        let mut parsed = self.documents.get(uri)?.parsed.clone();
+
+        for change in params.get("contentChanges")?.as_array()? {
+            let text = change.get("text")?.as_str()?.to_owned();
+            parsed = match change.get("range") {
+                Some(range) => {
+                    let src = parsed.source();
+                    let start = offset(src, range.get("start")?)?;
+                    let end = offset(src, range.get("end")?)?;
+                    let edit = TextEdit {
+                        range: start..end,
+                        text,
+                    };
+                    incremental::reparse(&parsed, edit)
+                }
+                None => incremental::parse(DOCUMENT, text),
+            };
+        }
+
+        Some(self.analyze(uri, parsed))
+    }
+
+    fn did_close(&mut self, params: &Json) -> Option<Json> {
+        let uri = params.get("textDocument")?.get("uri")?.as_str()?;
+        self.documents.remove(uri);
+        Some(publish_diagnostics(uri, Vec::new()))
+    }
+
+    fn hover(&self, params: &Json) -> Option<Json> {
+        let uri = params.get("textDocument")?.get("uri")?.as_str()?;
+        let doc = self.documents.get(uri)?;
+        let src = doc.parsed.source();
+        let pos = offset(src, params.get("position")?)?;
+
+        let hole = doc
+            .holes
+            .iter()
+            .find(|hole| hole.pos.range().contains(&pos));
+        let (range, value) = match hole {
+            Some(hole) => (hole.pos.range(), hole_message(hole)),
+            None => {
+                let info = innermost_type(pos, &doc.types)?;
+                let ty = TPrettyPrinter(&info.names, &info.ty).to_string();
+                (info.pos.range(), ty)
+            }
+        };
+
+        Some(Json::object([
+            (
+                "contents",
+                Json::object([
+                    ("kind", "plaintext".into()),
+                    ("value", value.trim_end().into()),
+                ]),
+            ),
+            ("range", lsp_range(src, range)),
+        ]))
+    }
+
+    /// The files read for the imports of a document in `root`, the open
+    /// documents in their unsaved states over the ones saved.
+    fn buffers(&self, root: &Path) -> Overlay<MemorySources, FileSystem> {
+        let mut top = MemorySources::new();
+        for (uri, doc) in &self.documents {
+            if let Some(path) = ModulePath::of_file(root, &uri_path(uri)) {
+                top.insert(path, doc.parsed.source());
+            }
+        }
+        let base = FileSystem {
+            root: root.to_path_buf(),
+        };
+        Overlay { top, base }
+    }
+
+    /// Elaborate a document and keep it, returning its diagnostics.
+    fn analyze(&mut self, uri: &str, parsed: ParseResult) -> Json {
+        let src = parsed.source();
+        let mut diagnostics: Vec<Json> = parsed
+            .errors()
+            .map(|e| diagnostic(src, e.span().range(), ERROR, parse_error_message(e)))
+            .collect();
+
+        let file = uri_path(uri);
+        let root = file.parent().unwrap_or(Path::new("."));
+        let mut loader = ModuleLoader::with_provider(root, self.buffers(root));
+        loader.add_source(&file, src);
+
+        let mut metas = MetaCxt::default();
+        let mut cxt = Cxt::default();
+        let recorder = Rc::new(RefCell::new(TypeRecorder::new(None)));
+        cxt.set_tracer(recorder.clone());
+
+        let res = loader
+            .check_program(&mut metas, &mut cxt, &file, parsed.program())
+            .and_then(|_| {
+                metas
+                    .check_all_solved(0, UnsolvedPolicy::Error)
+                    .map_err(|err| ModuleError::Check(file.clone(), err))
+            });
+        if let Err(err) = res {
+            let (range, msg) = module_error(&loader, err);
+            diagnostics.push(diagnostic(src, range, ERROR, msg));
+        }
+        let warnings = metas.take_warnings();
+        let (warnings, denied) = match metas.lints_mut().apply(warnings) {
+            Ok(warnings) => (warnings, None),
+            Err(denied) => (Vec::new(), Some(denied)),
+        };
+        let errors = denied.into_iter().map(|err| (err, ERROR));
+        for (warning, severity) in warnings.into_iter().map(|w| (w, WARNING)).chain(errors) {
+            if warning.pos.file == DOCUMENT {
+                let msg = error_message(&warning);
+                diagnostics.push(diagnostic(src, warning.pos.range(), severity, msg));
+            }
+        }
+
+        let holes: Vec<HoleReport> = metas
+            .take_holes(0)
+            .into_iter()
+            .filter(|hole| hole.pos.file == DOCUMENT)
+            .collect();
+        for hole in &holes {
+            diagnostics.push(diagnostic(
+                src,
+                hole.pos.range(),
+                INFORMATION,
+                hole_message(hole),
+            ));
+        }
+
+        let types = std::mem::take(&mut recorder.borrow_mut().types)
+            .into_iter()
+            .filter(|info| info.pos.file == DOCUMENT)
+            .map(|info| TypeInfo {
+                ty: zonk_local(&mut metas, info.names.len(), info.ty),
+                ..info
+            })
+            .collect();
+
+        let diagnostics = publish_diagnostics(uri, diagnostics);
+        let doc = Document {
+            parsed,
+            types,
+            holes,
+        };
+        self.documents.insert(uri.to_owned(), doc);
+        diagnostics
+    }
+}
+
+/// The range and message of an error of elaborating a document. Errors in
+/// other files are put at the start of the document.
+fn module_error(loader: &ModuleLoader, err: ModuleError) -> (Range<usize>, String) {
+    match err {
+        ModuleError::Check(_, err) if err.pos.file == DOCUMENT => {
+            (err.pos.range(), error_message(&err))
+        }
+        ModuleError::Check(_, err) => {
+            let location = loader.sources().location(&err.pos);
+            (0..0, format!("{location}: {}", error_message(&err)))
+        }
+        ModuleError::Io(file, err) => (0..0, format!("can't read {}: {err}", file.display())),
+        ModuleError::Cycle(cycle) => {
+            let cycle: Vec<String> = cycle.iter().map(ToString::to_string).collect();
+            (0..0, format!("import cycle {}", cycle.join(" -> ")))
+        }
+    }
+}
+
+/// The path of a `file:` URI, or the URI itself for other schemes.
+fn uri_path(uri: &str) -> PathBuf {
+    let Some(path) = uri.strip_prefix("file://") else {
+        return PathBuf::from(uri);
+    };
+
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = path.get(i + 1..i + 3).filter(|_| bytes[i] == b'%');
+        match escape.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(b) => {
+                decoded.push(b);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    PathBuf::from(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+/// The line and the column in UTF-16 code units of the `offset`th character
+/// of `src`, as positions are given in the protocol.
+fn position(src: &str, offset: usize) -> Json {
+    let (mut line, mut col) = (0, 0);
+    for c in src.chars().take(offset) {
+        if c == '\n' {
+            line += 1;
+            col = 0;
+        } else {
+            col += c.len_utf16();
+        }
+    }
+    Json::object([("line", line.into()), ("character", col.into())])
+}
+
+/// The offset of the character at a position of the protocol, the end of its
+/// line if it is past it.
+fn offset(src: &str, pos: &Json) -> Option<usize> {
+    let line = pos.get("line")?.as_usize()?;
+    let character = pos.get("character")?.as_usize()?;
+
+    let (mut l, mut col) = (0, 0);
+    for (i, c) in src.chars().enumerate() {
+        if l == line && (col >= character || c == '\n') {
+            return Some(i);
+        }
+        if c == '\n' {
+            l += 1;
+        } else if l == line {
+            col += c.len_utf16();
+        }
+    }
+    Some(src.chars().count())
+}
+
+fn lsp_range(src: &str, range: Range<usize>) -> Json {
+    Json::object([
+        ("start", position(src, range.start)),
+        ("end", position(src, range.end)),
+    ])
+}
+
+fn diagnostic(src: &str, range: Range<usize>, severity: usize, msg: String) -> Json {
+    Json::object([
+        ("range", lsp_range(src, range)),
+        ("severity", severity.into()),
+        ("source", "leonie".into()),
+        ("message", msg.trim_end().into()),
+    ])
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Json>) -> Json {
+    Json::object([
+        ("jsonrpc", "2.0".into()),
+        ("method", "textDocument/publishDiagnostics".into()),
+        (
+            "params",
+            Json::object([("uri", uri.into()), ("diagnostics", diagnostics.into())]),
+        ),
+    ])
+}
+
+fn capabilities() -> Json {
+    let sync = Json::object([
+        ("openClose", true.into()),
+        // incremental
+        ("change", 2.into()),
+    ]);
+    Json::object([
+        (
+            "capabilities",
+            Json::object([("textDocumentSync", sync), ("hoverProvider", true.into())]),
+        ),
+        ("serverInfo", Json::object([("name", "leonie-lsp".into())])),
+    ])
+}
+
+fn response(id: Json, result: Json) -> Json {
+    Json::object([("jsonrpc", "2.0".into()), ("id", id), ("result", result)])
+}
+
+fn error_response(id: Json, code: i32, msg: &str) -> Json {
+    let error = Json::object([("code", Json::Number(code.into())), ("message", msg.into())]);
+    Json::object([("jsonrpc", "2.0".into()), ("id", id), ("error", error)])
+}
@@ -1,27 +1,193 @@
-use chumsky::prelude::Simple;
+use std::{path::Path, process::ExitCode};
 
 use leonie::{
-    infer,
-    metas::MetaCxt,
-    parser::{parse, Token},
-    Cxt,
+    diagnostics::{render_error, render_error_in, render_hole, render_warning},
+    elaborator::Elaborator,
+    error::Error,
+    format::format_source,
+    modules::{ModuleError, ModuleLoader},
+    parser::parse_program_in,
+    repl::Repl,
+    ElabOptions, Raw, Strategy, TPrettyPrinter, Unfold,
 };
 
-fn main() -> Result<(), Vec<Simple<Token>>> {
-    let str = r#"
-  let id : (A : U) -> A -> A := λ A. (λ x. x)
-  U
-"#;
+const USAGE: &str = "\
+usage: leonie [OPTIONS]                       start a REPL
+       leonie [OPTIONS] check FILE [--nf NAME]  check FILE, printing the normal form of NAME
+       leonie fmt FILE                          print FILE formatted
 
-    if let Some(raw) = parse(str)? {
-        let mut metas = MetaCxt::default();
-        let mut cxt = Cxt::default();
+options:
+  --type-in-type          don't check universe levels
+  --no-termination-check  accept recursive definitions that may not terminate
+  --warn-shadowing        warn about binders that shadow a variable
+  --strict-holes          reject holes whose type is unknown
+  --generalize            abstract the unsolved metas of definitions over implicit arguments
+  --call-by-need          evaluate arguments only once they are used
+  --unfold-opaque         unfold opaque definitions like any other
+  --keep-spans            keep source spans in elaborated terms for later errors
+  --fuel N                fail once recursive definitions unfolded N times
+  --max-nodes N           fail once the terms of a definition grew to N nodes";
 
-        match infer(&mut metas, &mut cxt, raw) {
-            Ok((norm, ty)) => println!("success: {norm:?} {ty:?}"),
-            Err(err) => println!("error: {:?} {err:#?}", cxt.pos()),
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let (Ok(fuel), Ok(max_nodes)) = (limit(&mut args, "--fuel"), limit(&mut args, "--max-nodes"))
+    else {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    };
+    let options = ElabOptions {
+        type_in_type: args.contains(&"--type-in-type"),
+        no_termination_check: args.contains(&"--no-termination-check"),
+        warn_shadowing: args.contains(&"--warn-shadowing"),
+        strict_holes: args.contains(&"--strict-holes"),
+        generalize: args.contains(&"--generalize"),
+        unfold_opaque: args.contains(&"--unfold-opaque"),
+        keep_spans: args.contains(&"--keep-spans"),
+        fuel,
+        max_nodes,
+    };
+    let strategy = match args.contains(&"--call-by-need") {
+        true => Strategy::CallByNeed,
+        false => Strategy::CallByValue,
+    };
+    args.retain(|&arg| {
+        ![
+            "--type-in-type",
+            "--no-termination-check",
+            "--warn-shadowing",
+            "--strict-holes",
+            "--generalize",
+            "--call-by-need",
+            "--unfold-opaque",
+            "--keep-spans",
+        ]
+        .contains(&arg)
+    });
+
+    match args[..] {
+        [] => {
+            let stdin = std::io::stdin();
+            let mut repl = Repl::new();
+            repl.elaborator().set_options(options);
+            repl.elaborator().set_strategy(strategy);
+            match repl.run(stdin.lock(), std::io::stdout()) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(err) => {
+                    eprintln!("error: {err}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        ["check", file] => check(Path::new(file), None, options, strategy),
+        ["check", file, "--nf", name] | ["check", "--nf", name, file] => {
+            check(Path::new(file), Some(name), options, strategy)
+        }
+        ["fmt", file] => fmt(Path::new(file)),
+        _ => {
+            eprintln!("{USAGE}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// The number given to the option `name`, which is removed with it, if any.
+fn limit(args: &mut Vec<&str>, name: &str) -> Result<Option<usize>, ()> {
+    let Some(k) = args.iter().position(|&arg| arg == name) else {
+        return Ok(None);
+    };
+    let n = args.get(k + 1).and_then(|n| n.parse().ok()).ok_or(())?;
+    args.drain(k..k + 2);
+    Ok(Some(n))
+}
+
+fn check(file: &Path, nf: Option<&str>, options: ElabOptions, strategy: Strategy) -> ExitCode {
+    let src = match std::fs::read_to_string(file) {
+        Ok(src) => src,
+        Err(err) => {
+            eprintln!("error: can't read {}: {err}", file.display());
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let root = file.parent().unwrap_or(Path::new("."));
+    let mut loader = ModuleLoader::new(root);
+    let id = loader.add_source(file, src.clone());
+
+    let program = match parse_program_in(id, &src) {
+        Ok(program) => program,
+        Err(errs) => {
+            eprint!("{}", render_error_in(loader.sources(), &Error::parse(errs)));
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut elab = Elaborator::new();
+    elab.set_options(options);
+    elab.set_strategy(strategy);
+
+    let loaded = elab.load_program_recovering(&mut loader, file, program);
+    for warning in elab.take_warnings() {
+        eprint!("{}", render_warning(&src, &warning));
+    }
+    if let Err(errs) = loaded {
+        for err in errs {
+            report(&loader, err);
         }
+        return ExitCode::FAILURE;
+    }
+    for hole in elab.take_holes() {
+        eprint!("{}", render_hole(&src, &hole));
+    }
+
+    if let Some(name) = nf {
+        let t = match elab.infer(Raw::RVar(name.into())) {
+            Ok((t, _)) => match elab.normalize_limited(t, Unfold::Always) {
+                Ok(t) => t,
+                Err(err) => {
+                    eprint!("{}", render_error(&src, &err));
+                    return ExitCode::FAILURE;
+                }
+            },
+            Err(_) => {
+                eprintln!("error: no definition named `{name}`");
+                return ExitCode::FAILURE;
+            }
+        };
+        println!("{}", TPrettyPrinter(&elab.cxt().names(), &t));
     }
 
-    Ok(())
+    ExitCode::SUCCESS
+}
+
+fn fmt(file: &Path) -> ExitCode {
+    let src = match std::fs::read_to_string(file) {
+        Ok(src) => src,
+        Err(err) => {
+            eprintln!("error: can't read {}: {err}", file.display());
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match format_source(&src) {
+        Ok(formatted) => {
+            print!("{formatted}");
+            ExitCode::SUCCESS
+        }
+        Err(errs) => {
+            eprint!("{}", render_error(&src, &Error::parse(errs)));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn report(loader: &ModuleLoader, err: ModuleError) {
+    match err {
+        ModuleError::Io(file, err) => eprintln!("error: can't read {}: {err}", file.display()),
+        ModuleError::Check(_, err) => eprint!("{}", render_error_in(loader.sources(), &err)),
+        ModuleError::Cycle(cycle) => {
+            let cycle: Vec<String> = cycle.iter().map(ToString::to_string).collect();
+            eprintln!("error: import cycle {}", cycle.join(" -> "));
+        }
+    }
 }
@@ -1,31 +1,60 @@
-use std::collections::HashMap as Map;
-use std::{backtrace::Backtrace, borrow::Cow};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{HashMap as Map, HashSet};
+use std::fmt::{self, Debug, Display};
 
-use crate::{eval, eval_closure, lvl2ix, v_app, Cxt, Env, Lvl, Spine, Term, Value};
+use crate::{
+    error::Error,
+    eval, eval_closure, fix_ready, force, force_metas,
+    instance::{self, InstanceProblem},
+    lint::Lints,
+    lvl2ix, quote,
+    record::v_field,
+    store::TermStore,
+    unfold, v_app, v_force, v_proj1, v_proj2, Closure, Cxt, Elim, Env, Icit, Ix, Level, Lvl, Name,
+    Rc, Span, Spine, Strategy, Term, Thunk, Tm, Type, Value, BD,
+};
 
-#[derive(Debug)]
-pub struct Error {
-    pub backtrace: Backtrace,
-    pub kind: ErrorKind,
-}
+pub mod log;
+
+use log::{UnifyLog, UnifyStep};
 
+/// Why unification failed. The elaborator turns this into an
+/// [`crate::error::Error`] mentioning the sides it tried to unify.
 #[derive(Debug, Clone)]
-pub enum ErrorKind {
+pub enum UnifyError {
+    /// the meta would occur in its own solution, as this value
     MetaOccurs(MetaVar, Value),
-    MetaScope(MetaVar, Value),
+    /// the solution of the meta would need the variable of this level, which
+    /// isn't in the meta's scope
+    MetaScope(MetaVar, Lvl),
     MetaSpine(Spine, Spine),
     MetaInvert(Spine),
-    MetaUnify(Value, Value),
-    InferUnbound(),
+    MetaUnify(Box<Value>, Box<Value>),
+    /// the meta was created by an earlier definition
+    MetaFrozen(MetaVar),
+    /// the values are headed by different constructors, which are distinct
+    ConClash(Box<Value>, Box<Value>),
 }
 
-macro_rules! error {
-    ($error_kind:expr) => {
-        Err(Error {
-            backtrace: Backtrace::capture(),
-            kind: $error_kind,
-        })
-    };
+impl Display for UnifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnifyError::MetaOccurs(m, _) => write!(f, "?{m} occurs in its own solution"),
+            UnifyError::MetaScope(m, x) => write!(f, "x{x} is out of the scope of ?{m}"),
+            UnifyError::MetaSpine(..) => write!(f, "the spines differ"),
+            UnifyError::MetaInvert(_) => write!(f, "the spine isn't a pattern"),
+            UnifyError::MetaUnify(..) => write!(f, "the heads differ"),
+            UnifyError::MetaFrozen(m) => write!(f, "?{m} belongs to an earlier definition"),
+            UnifyError::ConClash(l, r) => {
+                let (c, c_) = (
+                    con_name(l).unwrap_or_default(),
+                    con_name(r).unwrap_or_default(),
+                );
+                write!(f, "the constructors `{c}` and `{c_}` differ")
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -36,39 +65,769 @@ pub enum MetaEntry {
 
 pub type MetaVar = usize;
 
+pub type LevelVar = usize;
+
+/// `lhs <= rhs` between universe levels
+#[derive(Debug, Clone)]
+pub struct LevelConstraint {
+    pub lhs: Level,
+    pub rhs: Level,
+    /// where the constraint came from, once known
+    pub pos: Option<Span>,
+}
+
+/// Unification problem `lhs =? rhs` that isn't a pattern yet, waiting for
+/// `blocker` to be solved.
+#[derive(Debug, Clone)]
+pub struct Postponed {
+    pub blocker: MetaVar,
+    pub lvl: Lvl,
+    pub lhs: Value,
+    pub rhs: Value,
+    /// names of the local context the problem came from, the variables bound
+    /// while unifying are missing
+    pub names: Vec<Name>,
+    /// where the problem came from, once known
+    pub pos: Option<Span>,
+}
+
+/// Where, in which local context and with which type a meta was created
+#[derive(Debug, Clone)]
+pub struct MetaOrigin {
+    pub pos: Span,
+    /// names of the local context of the meta
+    pub names: Vec<Name>,
+    /// which variables of the local context are bound, and so arguments of
+    /// the meta, and which are defined
+    pub bds: Vec<BD>,
+    /// types of the local context, each at its own level
+    pub types: Vec<(Name, Type)>,
+    /// type of the meta in its local context
+    pub ty: Term,
+    /// name of the hole `?x` the meta stands for
+    pub hole: Option<Name>,
+    /// the meta this one was pruned from, with which of its arguments it
+    /// kept, the type above being the one of the other meta
+    pub pruned: Option<(MetaVar, Vec<bool>)>,
+    /// whether its definition was generalized over it, see
+    /// [`crate::ElabOptions::generalize`], rather than it being solved
+    pub generalized: bool,
+}
+
+impl MetaOrigin {
+    fn new(metas: &mut MetaCxt, cxt: &Cxt, ty: Value, hole: Option<Name>) -> Self {
+        MetaOrigin {
+            pos: cxt.pos().clone(),
+            names: cxt.names(),
+            bds: cxt.bds().clone(),
+            types: cxt.types().clone(),
+            ty: quote(metas, cxt.lvl(), ty),
+            hole,
+            pruned: None,
+            generalized: false,
+        }
+    }
+}
+
+/// Named hole `?x` waiting to be reported
+#[derive(Debug, Clone)]
+struct Hole {
+    name: Name,
+    meta: MetaVar,
+    lvl: Lvl,
+    ty: Value,
+}
+
+/// Binder written in the source, kept with the term its variable is bound
+/// in, as `Ix(0)`, to check that it is used once the metas are solved
+#[derive(Debug, Clone)]
+struct BinderUse {
+    pos: Span,
+    name: Name,
+    body: Tm,
+    /// whether it shadows a variable of the same name, to warn about
+    shadows: bool,
+}
+
+/// Goal of a named hole: what it should have type and what can be used to
+/// fill it.
+#[derive(Debug, Clone)]
+pub struct HoleReport {
+    pub name: Name,
+    pub meta: MetaVar,
+    pub pos: Span,
+    /// expected type of the hole, in the context `cxt`
+    pub ty: Term,
+    /// the local context of the hole, each type quoted at its own level
+    pub cxt: Vec<(Name, Term)>,
+    /// what unification filled the hole with, if anything
+    pub solution: Option<Term>,
+}
+
+/// Observes the solver, for debuggers and visualizers. Values are given as
+/// they are, with the metas solved before not substituted.
+pub trait UnifyObserver: Debug {
+    /// entering `unify lhs rhs`, with `lvl` variables bound
+    fn on_unify(&mut self, _lhs: &Value, _rhs: &Value, _lvl: Lvl) {}
+
+    /// `m` was solved with `solution`, a closed value
+    fn on_meta_solved(&mut self, _m: MetaVar, _solution: &Value) {}
+
+    /// `problem` isn't a pattern, so it waits for its blocker to be solved
+    fn on_constraint_postponed(&mut self, _problem: &Postponed) {}
+}
+
+/// What to do with the metas a definition leaves unsolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnsolvedPolicy {
+    #[default]
+    Error,
+    Warn,
+    Allow,
+}
+
 #[derive(Debug, Clone, Default)]
-pub struct MetaCxt(Vec<MetaEntry>);
+pub struct MetaCxt {
+    metas: Vec<MetaEntry>,
+    origins: Vec<MetaOrigin>,
+    postponed: Vec<Postponed>,
+    /// named holes not reported yet
+    holes: Vec<Hole>,
+    /// binders not checked for uses yet
+    binders: Vec<BinderUse>,
+    /// severities of the lints, as pragmas set them while elaborating
+    lints: Lints,
+    /// number of level metas created so far
+    levels: LevelVar,
+    level_constraints: Vec<LevelConstraint>,
+    /// metas before this one can't be solved anymore
+    frozen: MetaVar,
+    /// instance arguments not found yet
+    instances: Vec<InstanceProblem>,
+    /// whether instances are being searched for, which solving metas doesn't
+    /// start again then
+    searching: bool,
+    observer: Option<std::rc::Rc<RefCell<dyn UnifyObserver>>>,
+    /// shared with the copies of the metas that are tried out and rolled
+    /// back, so that their problems are logged too
+    log: Option<std::rc::Rc<RefCell<UnifyLog>>>,
+    /// the entries replaced by solutions, oldest first, for rolling them back
+    trail: Vec<(MetaVar, MetaEntry)>,
+    /// the thunks forced, oldest first, with the terms they were suspended
+    /// with, for suspending them again when rolling back
+    forced: Vec<(Rc<Thunk>, Env, Tm)>,
+    /// how values are evaluated, here as everything evaluating has the metas
+    strategy: Strategy,
+    /// top-level definitions that don't unfold, by their names and values,
+    /// with the levels they are bound at and whether they are axioms, which
+    /// have nothing to unfold to
+    opaque: Vec<(Name, Rc<Value>, Lvl, bool)>,
+    /// whether the opaque definitions but the axioms unfold anyway, see
+    /// [`crate::Unfold::Opaque`]
+    transparent: bool,
+    /// how many more times recursive definitions may unfold, if limited
+    fuel: Option<usize>,
+    /// whether one didn't unfold as the fuel ran out
+    exhausted: bool,
+    /// how many more nodes quoting may build, if limited, also taken by
+    /// renaming and unifying
+    nodes: Option<usize>,
+    /// whether quoting was cut short as the nodes ran out
+    out_of_nodes: bool,
+    /// the definitions and solutions are interned here, so that unification
+    /// finds the terms of their closures equal by their shared nodes
+    store: TermStore,
+}
+
+/// The metas to go back to with [`MetaCxt::rollback`]
+#[derive(Debug, Clone)]
+pub struct MetaSnapshot {
+    metas: MetaVar,
+    trail: usize,
+    forced: usize,
+    postponed: Vec<Postponed>,
+    holes: usize,
+    binders: usize,
+    levels: LevelVar,
+    level_constraints: usize,
+    frozen: MetaVar,
+    instances: Vec<InstanceProblem>,
+    searching: bool,
+}
 
+/// A meta that isn't one of this context, e.g. of a value printed without
+/// the context it was elaborated in, is unsolved.
 impl std::ops::Index<MetaVar> for MetaCxt {
     type Output = MetaEntry;
 
     fn index(&self, index: MetaVar) -> &Self::Output {
-        &self.0[index]
+        const UNSOLVED: &MetaEntry = &MetaEntry::Unsolved;
+        self.metas.get(index).unwrap_or(UNSOLVED)
     }
 }
 
 impl std::ops::IndexMut<MetaVar> for MetaCxt {
     fn index_mut(&mut self, index: MetaVar) -> &mut Self::Output {
-        &mut self.0[index]
+        &mut self.metas[index]
     }
 }
 
 impl MetaCxt {
-    pub fn fresh_meta(&mut self, cxt: &Cxt) -> Term {
-        let m = self.0.len();
-        self.0.push(MetaEntry::Unsolved);
+    /// number of metas created so far
+    pub fn len(&self) -> usize {
+        self.metas.len()
+    }
+
+    /// Solve `m` with `solution`, or replace its solution, keeping what it
+    /// was on the trail.
+    pub(crate) fn set_solution(&mut self, m: MetaVar, solution: Value) {
+        let old = std::mem::replace(&mut self.metas[m], MetaEntry::Solved(solution));
+        self.trail.push((m, old));
+    }
+
+    /// `thunk`, suspended with `t` in `env`, was forced, maybe with solutions
+    /// inlined into its value.
+    pub(crate) fn forced(&mut self, thunk: Rc<Thunk>, env: Env, t: Tm) {
+        self.forced.push((thunk, env, t));
+    }
+
+    /// The metas as they are now, to go back to after trying something out.
+    pub fn snapshot(&self) -> MetaSnapshot {
+        MetaSnapshot {
+            metas: self.metas.len(),
+            trail: self.trail.len(),
+            forced: self.forced.len(),
+            postponed: self.postponed.clone(),
+            holes: self.holes.len(),
+            binders: self.binders.len(),
+            levels: self.levels,
+            level_constraints: self.level_constraints.len(),
+            frozen: self.frozen,
+            instances: self.instances.clone(),
+            searching: self.searching,
+        }
+    }
+
+    /// Undo everything since `snapshot`: unsolve the metas solved since, and
+    /// drop the metas, holes, levels and problems created since. The thunks
+    /// forced since are suspended again.
+    pub fn rollback(&mut self, snapshot: MetaSnapshot) {
+        for (thunk, env, t) in self.forced.drain(snapshot.forced..).rev() {
+            thunk.suspend(env, t);
+        }
+        for (m, old) in self.trail.drain(snapshot.trail..).rev() {
+            if m < snapshot.metas {
+                self.metas[m] = old;
+            }
+        }
+        self.metas.truncate(snapshot.metas);
+        self.origins.truncate(snapshot.metas);
+        self.postponed = snapshot.postponed;
+        self.holes.truncate(snapshot.holes);
+        self.binders.truncate(snapshot.binders);
+        self.levels = snapshot.levels;
+        self.level_constraints.truncate(snapshot.level_constraints);
+        self.frozen = snapshot.frozen;
+        self.instances = snapshot.instances;
+        self.searching = snapshot.searching;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.metas.is_empty()
+    }
+
+    /// The metas created so far, in order, with what is known about them.
+    pub fn iter(&self) -> impl Iterator<Item = (MetaVar, &MetaEntry)> + '_ {
+        self.metas.iter().enumerate()
+    }
+
+    /// The solution of `m`, or `None` if it is unsolved or was never
+    /// created.
+    pub fn solution(&self, m: MetaVar) -> Option<&Value> {
+        match self.metas.get(m)? {
+            MetaEntry::Solved(v) => Some(v),
+            MetaEntry::Unsolved => None,
+        }
+    }
+
+    pub fn solved_count(&self) -> usize {
+        self.len() - self.unsolved_count()
+    }
+
+    pub fn unsolved_count(&self) -> usize {
+        self.metas
+            .iter()
+            .filter(|entry| matches!(entry, MetaEntry::Unsolved))
+            .count()
+    }
+
+    /// New meta of type `ty`, applied to the bound variables of `cxt`.
+    pub fn fresh_meta(&mut self, cxt: &Cxt, ty: Value) -> Term {
+        let origin = MetaOrigin::new(self, cxt, ty, None);
+        Term::TInsertedMeta(self.new_meta(origin), cxt.bds.clone())
+    }
+
+    /// New meta standing for a type, in a universe of a fresh level.
+    pub fn fresh_type_meta(&mut self, cxt: &Cxt) -> Term {
+        let l = self.fresh_level();
+        self.fresh_meta(cxt, Value::VU(l))
+    }
+
+    /// Like [`MetaCxt::fresh_meta`], but for the hole `?name` whose goal is
+    /// reported once elaboration is done.
+    pub fn fresh_hole(&mut self, cxt: &Cxt, name: Name, ty: Value) -> Term {
+        let origin = MetaOrigin::new(self, cxt, ty.clone(), Some(name.clone()));
+        let m = self.new_meta(origin);
+        self.holes.push(Hole {
+            name,
+            meta: m,
+            lvl: cxt.lvl(),
+            ty,
+        });
         Term::TInsertedMeta(m, cxt.bds.clone())
     }
 
-    pub fn force(&self, v: Value) -> Value {
-        match v {
-            Value::VFlex(m, sp) => match &self[m] {
-                MetaEntry::Solved(v) => v.clone(),
-                MetaEntry::Unsolved => Value::VFlex(m, sp),
+    fn new_meta(&mut self, origin: MetaOrigin) -> MetaVar {
+        let m = self.metas.len();
+        self.metas.push(MetaEntry::Unsolved);
+        self.origins.push(origin);
+        m
+    }
+
+    /// Prevent the metas created so far from being solved, e.g. once the
+    /// definition that created them is done.
+    pub fn freeze(&mut self) {
+        self.frozen = self.metas.len();
+    }
+
+    pub fn is_frozen(&self, m: MetaVar) -> bool {
+        m < self.frozen
+    }
+
+    pub fn origin(&self, m: MetaVar) -> &MetaOrigin {
+        &self.origins[m]
+    }
+
+    /// Mark `m` as abstracted over by its definition, which leaves it
+    /// unsolved.
+    pub(crate) fn set_generalized(&mut self, m: MetaVar) {
+        self.origins[m].generalized = true;
+    }
+
+    /// The local context `m` was created in, each type quoted at its own
+    /// level.
+    pub fn origin_cxt(&mut self, m: MetaVar) -> Vec<(Name, Term)> {
+        let types = self.origins[m].types.clone();
+        types
+            .into_iter()
+            .enumerate()
+            .map(|(lvl, (x, a))| (x, quote(self, lvl, a)))
+            .collect()
+    }
+
+    /// Goals of the holes of the metas from `from` on that weren't taken yet,
+    /// with the metas solved so far substituted.
+    pub fn take_holes(&mut self, from: MetaVar) -> Vec<HoleReport> {
+        let keep = self.holes.iter().take_while(|h| h.meta < from).count();
+        self.holes
+            .split_off(keep)
+            .into_iter()
+            .map(|hole| {
+                let pos = self.origins[hole.meta].pos.clone();
+                let solution = match &self.metas[hole.meta] {
+                    MetaEntry::Solved(v) => Some(v.clone()),
+                    MetaEntry::Unsolved => None,
+                };
+                HoleReport {
+                    name: hole.name,
+                    meta: hole.meta,
+                    pos,
+                    ty: quote(self, hole.lvl, hole.ty),
+                    cxt: self.origin_cxt(hole.meta),
+                    solution: solution.map(|v| quote(self, 0, v)),
+                }
+            })
+            .collect()
+    }
+
+    /// With [`crate::ElabOptions::strict_holes`]: fail on the first hole of
+    /// the metas from `from` on whose type is still an unsolved meta, about
+    /// which elaboration learned nothing.
+    pub fn check_holes_constrained(&mut self, from: MetaVar) -> Result<(), Error> {
+        let holes: Vec<(MetaVar, Value)> = self
+            .holes
+            .iter()
+            .filter(|hole| hole.meta >= from)
+            .map(|hole| (hole.meta, hole.ty.clone()))
+            .collect();
+
+        for (m, ty) in holes {
+            if let Value::VFlex(..) = force(self, ty) {
+                return Err(Error::unconstrained_hole(self, m));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check later that `body` uses the variable of the binder of `name` at
+    /// `pos`, and warn that it `shadows` another one, see
+    /// [`MetaCxt::binder_warnings`].
+    pub fn record_binder(&mut self, pos: Span, name: Name, body: Tm, shadows: bool) {
+        self.binders.push(BinderUse {
+            pos,
+            name,
+            body,
+            shadows,
+        });
+    }
+
+    /// Warnings for the binders recorded since the last call, in the order
+    /// they were written: those whose variables are never used, and those
+    /// that shadow another variable. An unsolved meta that could be solved
+    /// with the variable counts as a use.
+    pub fn binder_warnings(&mut self) -> Vec<Error> {
+        let mut binders = std::mem::take(&mut self.binders);
+        binders.sort_by_key(|b| b.pos.start);
+        let mut warnings = Vec::new();
+        for b in binders {
+            if b.shadows {
+                warnings.push(Error::shadowing(b.pos.clone(), b.name.clone()));
+            }
+            if !b
+                .body
+                .occurs(Ix(0), &mut |m, k, n| meta_uses(self, m, k, n))
+            {
+                warnings.push(Error::unused_binder(b.pos, b.name));
+            }
+        }
+        warnings
+    }
+
+    /// The warnings raised since the last call, before sorting them out by
+    /// [`Lints::apply`]: the ones about pragmas, then
+    /// [`MetaCxt::binder_warnings`].
+    pub fn take_warnings(&mut self) -> Vec<Error> {
+        let mut warnings = self.lints.take_raised();
+        warnings.extend(self.binder_warnings());
+        warnings
+    }
+
+    pub fn lints(&self) -> &Lints {
+        &self.lints
+    }
+
+    pub fn lints_mut(&mut self) -> &mut Lints {
+        &mut self.lints
+    }
+
+    /// Check that the metas from `from` on are solved, except for holes and
+    /// the metas definitions were generalized over.
+    /// Depending on `policy`, an unsolved one is an error, or they are all
+    /// returned as warnings.
+    pub fn check_all_solved(
+        &mut self,
+        from: MetaVar,
+        policy: UnsolvedPolicy,
+    ) -> Result<Vec<Error>, Error> {
+        let unsolved: Vec<MetaVar> = (from..self.metas.len())
+            .filter(|&m| matches!(self.metas[m], MetaEntry::Unsolved))
+            .filter(|&m| self.origins[m].hole.is_none() && !self.origins[m].generalized)
+            .collect();
+
+        match policy {
+            UnsolvedPolicy::Error => match unsolved.first() {
+                Some(&m) => Err(Error::unsolved(self, m)),
+                None => Ok(Vec::new()),
             },
-            v => v,
+            UnsolvedPolicy::Warn => Ok(unsolved
+                .into_iter()
+                .map(|m| Error::unsolved(self, m))
+                .collect()),
+            UnsolvedPolicy::Allow => Ok(Vec::new()),
+        }
+    }
+
+    pub fn fresh_level(&mut self) -> Level {
+        self.levels += 1;
+        Level::LMeta(self.levels - 1, 0)
+    }
+
+    /// Require the universe `lhs` to be below `rhs`.
+    pub fn level_le(&mut self, lhs: Level, rhs: Level, pos: Option<Span>) {
+        self.level_constraints
+            .push(LevelConstraint { lhs, rhs, pos });
+    }
+
+    pub fn level_constraints(&self) -> &[LevelConstraint] {
+        &self.level_constraints
+    }
+
+    /// Give the level constraints added since the first `from` ones the
+    /// position they came from, unless they already have one.
+    pub fn locate_levels(&mut self, from: usize, pos: &Span) {
+        for c in self.level_constraints.iter_mut().skip(from) {
+            c.pos.get_or_insert_with(|| pos.clone());
+        }
+    }
+
+    /// Check that the level constraints have a solution. Otherwise the
+    /// constraint making them inconsistent is reported and dropped, together
+    /// with the ones added after it.
+    pub fn check_levels(&mut self) -> Result<(), Error> {
+        if solve_levels(self.levels, &self.level_constraints).is_some() {
+            return Ok(());
+        }
+
+        // the shortest inconsistent prefix of the constraints
+        let (mut lo, mut hi) = (0, self.level_constraints.len());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match solve_levels(self.levels, &self.level_constraints[..=mid]) {
+                Some(_) => lo = mid + 1,
+                None => hi = mid,
+            }
+        }
+
+        let c = self.level_constraints[lo].clone();
+        self.level_constraints.truncate(lo);
+        Err(Error::universe(c))
+    }
+
+    /// The smallest solution of the level constraints, if any.
+    pub fn solve_levels(&self) -> Option<Vec<u32>> {
+        solve_levels(self.levels, &self.level_constraints)
+    }
+
+    /// Evaluate with `strategy` from now on.
+    pub fn set_strategy(&mut self, strategy: Strategy) {
+        self.strategy = strategy;
+    }
+
+    pub fn strategy(&self) -> Strategy {
+        self.strategy
+    }
+
+    pub fn store(&self) -> &TermStore {
+        &self.store
+    }
+
+    /// Share the nodes of `term` with the terms interned before.
+    pub fn intern(&mut self, term: &Term) -> Rc<Term> {
+        self.store.intern(term)
+    }
+
+    /// Let recursive definitions unfold `fuel` more times from now on, or
+    /// any number of times, and return the fuel that was left before.
+    pub fn set_fuel(&mut self, fuel: Option<usize>) -> Option<usize> {
+        self.exhausted = false;
+        std::mem::replace(&mut self.fuel, fuel)
+    }
+
+    /// Whether a recursive definition was left folded since
+    /// [`MetaCxt::set_fuel`], as the fuel ran out, so that values are only
+    /// partially evaluated.
+    pub fn exhausted(&self) -> bool {
+        self.exhausted
+    }
+
+    /// Take a unit of fuel to unfold a recursive definition, if any is left.
+    pub(crate) fn take_fuel(&mut self) -> bool {
+        match &mut self.fuel {
+            Some(0) => {
+                self.exhausted = true;
+                false
+            }
+            Some(fuel) => {
+                *fuel -= 1;
+                true
+            }
+            None => true,
+        }
+    }
+
+    /// Let quoting build `nodes` more term nodes from now on, or any number
+    /// of them, and return the number that was left before.
+    pub fn set_max_nodes(&mut self, nodes: Option<usize>) -> Option<usize> {
+        self.out_of_nodes = false;
+        std::mem::replace(&mut self.nodes, nodes)
+    }
+
+    /// Whether quoting was cut short since [`MetaCxt::set_max_nodes`], so
+    /// that the terms quoted since are garbage.
+    pub fn out_of_nodes(&self) -> bool {
+        self.out_of_nodes
+    }
+
+    /// Take a node for quoting, renaming or unifying, if any is left.
+    pub(crate) fn take_node(&mut self) -> bool {
+        match &mut self.nodes {
+            Some(0) => {
+                self.out_of_nodes = true;
+                false
+            }
+            Some(nodes) => {
+                *nodes -= 1;
+                true
+            }
+            None => true,
+        }
+    }
+
+    /// Keep the top-level definition glued to `def` and bound at `lvl` from
+    /// unfolding, in unification and normal forms.
+    pub(crate) fn set_opaque(&mut self, x: Name, def: Rc<Value>, lvl: Lvl) {
+        self.opaque.push((x, def, lvl, false));
+    }
+
+    /// Keep the axiom glued to `def` and bound at `lvl` from ever unfolding.
+    pub(crate) fn set_axiom(&mut self, x: Name, def: Rc<Value>, lvl: Lvl) {
+        self.opaque.push((x, def, lvl, true));
+    }
+
+    /// The level of the opaque definition glued to `def`, if it doesn't
+    /// unfold.
+    pub(crate) fn opaque(&self, def: &Rc<Value>) -> Option<Lvl> {
+        self.opaque
+            .iter()
+            .rev()
+            .find(|(_, d, _, _)| Rc::ptr_eq(d, def))
+            .filter(|&&(_, _, _, axiom)| axiom || !self.transparent)
+            .map(|&(_, _, lvl, _)| lvl)
+    }
+
+    /// Whether `def` is glued to an axiom.
+    pub(crate) fn axiom(&self, def: &Rc<Value>) -> bool {
+        self.opaque
+            .iter()
+            .any(|(_, d, _, axiom)| *axiom && Rc::ptr_eq(d, def))
+    }
+
+    /// The environment solutions are evaluated in: the opaque definitions,
+    /// which solutions can't unfold, so they refer to them instead.
+    fn opaque_env(&self) -> Env {
+        let mut env = Env::default();
+        for (x, def, _, _) in &self.opaque {
+            env.push(Value::VGlued(x.clone(), def.clone(), vec![]));
+        }
+        env
+    }
+
+    /// Run `f` with the opaque definitions unfolding like the others.
+    pub(crate) fn transparent<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        let transparent = std::mem::replace(&mut self.transparent, true);
+        let res = f(self);
+        self.transparent = transparent;
+        res
+    }
+
+    /// Report what the solver does from now on to `observer`.
+    pub fn set_observer(&mut self, observer: std::rc::Rc<RefCell<dyn UnifyObserver>>) {
+        self.observer = Some(observer);
+    }
+
+    fn observe(&self, f: impl FnOnce(&mut dyn UnifyObserver)) {
+        if let Some(observer) = &self.observer {
+            f(&mut *observer.borrow_mut());
         }
     }
+
+    /// Log the unification problems from now on, with the problems they are
+    /// decomposed into and how they end.
+    pub fn start_log(&mut self) {
+        self.log = Some(Default::default());
+    }
+
+    /// The problems logged since [`MetaCxt::start_log`], which stops logging.
+    pub fn take_log(&mut self) -> Option<UnifyLog> {
+        self.log.take().map(|log| log.take())
+    }
+
+    /// unification problems waiting for a meta to be solved
+    pub fn postponed(&self) -> &[Postponed] {
+        &self.postponed
+    }
+
+    pub fn take_postponed(&mut self) -> Vec<Postponed> {
+        std::mem::take(&mut self.postponed)
+    }
+
+    /// instance arguments waiting to be found
+    pub fn instances(&self) -> &[InstanceProblem] {
+        &self.instances
+    }
+
+    pub fn take_instances(&mut self) -> Vec<InstanceProblem> {
+        std::mem::take(&mut self.instances)
+    }
+
+    pub(crate) fn push_instance(&mut self, p: InstanceProblem) {
+        self.instances.push(p);
+    }
+
+    /// Start searching for instances, unless that is already underway.
+    pub(crate) fn start_search(&mut self) -> bool {
+        !std::mem::replace(&mut self.searching, true)
+    }
+
+    pub(crate) fn end_search(&mut self) {
+        self.searching = false;
+    }
+
+    /// Give the problems postponed since the first `from` ones the context
+    /// they came from, unless they already have one.
+    pub fn locate_postponed(&mut self, from: usize, names: &[Name], pos: &Span) {
+        for p in self.postponed.iter_mut().skip(from) {
+            if p.pos.is_none() {
+                p.names = names.to_vec();
+                p.pos = Some(pos.clone());
+            }
+        }
+    }
+
+    /// Replace solved metas in the head of `v` by their solutions applied to
+    /// the spine, see [`force_metas`].
+    pub fn force(&mut self, v: Value) -> Value {
+        force_metas(self, v)
+    }
+}
+
+/// Smallest assignment of the `n` level metas satisfying `constraints`, found
+/// as longest paths from level 0 in the graph with an edge `a -k-> b` for each
+/// `a + k <= b`. A positive cycle, or a path forcing 0 above itself, means
+/// there's none.
+fn solve_levels(n: LevelVar, constraints: &[LevelConstraint]) -> Option<Vec<u32>> {
+    // node 0 is the constant level 0, node `l + 1` the meta `l`
+    let node = |l: Level| match l {
+        Level::LConst(k) => (0, k as i64),
+        Level::LMeta(l, k) => (l + 1, k as i64),
+    };
+    let edges: Vec<(usize, usize, i64)> = constraints
+        .iter()
+        .map(|c| {
+            let ((a, ka), (b, kb)) = (node(c.lhs), node(c.rhs));
+            (a, b, ka - kb)
+        })
+        .collect();
+
+    let mut val = vec![0i64; n + 1];
+    for _ in 0..=n + 1 {
+        let mut changed = false;
+        for &(a, b, k) in &edges {
+            if val[a] + k > val[b] {
+                val[b] = val[a] + k;
+                changed = true;
+            }
+        }
+
+        if val[0] > 0 {
+            return None;
+        }
+        if !changed {
+            return Some(val[1..].iter().map(|&v| v as u32).collect());
+        }
+    }
+
+    None
 }
 
 #[derive(Debug, Clone)]
@@ -94,16 +853,41 @@ impl PartialRenaming {
         self.ren.remove(&self.cod);
     }
 
-    pub fn invert(metas: &MetaCxt, gamma: Lvl, spine: Spine) -> Result<Self, Error> {
+    /// Invert a spine of bound variables. A variable that occurs more than once
+    /// can't be mapped back to one argument, so it is left out of the renaming
+    /// and the solution may not mention it.
+    pub fn invert(metas: &mut MetaCxt, gamma: Lvl, spine: Spine) -> Result<Self, UnifyError> {
         let mut ren = Map::new();
-        let dom = spine.len();
+        let mut nonlinear = HashSet::new();
+        // the opaque definitions are bound outside the solution
+        let opaque = metas.opaque.len();
+        let dom = opaque + spine.len();
 
-        for (dom, t) in spine.iter().cloned().enumerate() {
-            match metas.force(t) {
-                Value::VRigid(x, y) if !ren.contains_key(&x) && y.is_empty() => {
-                    ren.insert(x, dom);
-                }
-                _ => return error!(ErrorKind::MetaInvert(spine)),
+        for (dom, t) in (opaque..).zip(spine.iter().cloned()) {
+            match t {
+                Elim::EApp(t, _) => match force(metas, t) {
+                    Value::VRigid(x, y) if y.is_empty() => {
+                        if nonlinear.contains(&x) || ren.remove(&x).is_some() {
+                            nonlinear.insert(x);
+                        } else {
+                            ren.insert(x, dom);
+                        }
+                    }
+                    _ => return Err(UnifyError::MetaInvert(spine)),
+                },
+                Elim::EProj1
+                | Elim::EProj2
+                | Elim::ENatElim(..)
+                | Elim::EBoolElim(..)
+                | Elim::EAppend(_)
+                | Elim::EPrepend(_)
+                | Elim::ELength
+                | Elim::EIntOp(..)
+                | Elim::EIntOpLit(..)
+                | Elim::EJ(..)
+                | Elim::EForce
+                | Elim::EElim(..)
+                | Elim::EField(..) => return Err(UnifyError::MetaInvert(spine)),
             }
         }
 
@@ -120,45 +904,148 @@ pub fn rename(
     m: MetaVar,
     pren: &mut PartialRenaming,
     v: Value,
-) -> Result<Term, Error> {
+) -> Result<Term, UnifyError> {
     fn go(
         metas: &mut MetaCxt,
         m: MetaVar,
         pren: &mut PartialRenaming,
         v: Value,
-    ) -> Result<Term, Error> {
-        match metas.force(v) {
+    ) -> Result<Term, UnifyError> {
+        if !metas.take_node() {
+            return Err(UnifyError::MetaUnify(
+                Value::VFlex(m, vec![]).into(),
+                v.into(),
+            ));
+        }
+        match force(metas, v) {
             Value::VFlex(m_, sp) => {
                 if m == m_ {
-                    return error!(ErrorKind::MetaOccurs(m, Value::VFlex(m_, sp)));
+                    return Err(UnifyError::MetaOccurs(m, Value::VFlex(m_, sp)));
                 }
 
-                go_sp(metas, m, pren, Term::TMeta(m_), sp)
+                prune_flex(metas, m, pren, m_, sp)
             }
+            Value::VThunk(_) => unreachable!("thunk left by forcing"),
             Value::VRigid(x, sp) => match pren.ren.get(&x) {
                 Some(x_) => go_sp(metas, m, pren, Term::TV(lvl2ix(pren.dom, *x_)), sp),
-                None => error!(ErrorKind::MetaScope(m, Value::VRigid(x, sp))),
+                None => Err(UnifyError::MetaScope(m, x)),
             },
-            Value::Vλ(x, t) => {
+            Value::Vλ(x, i, t) => {
                 let t = eval_closure(metas, t, Value::VRigid(pren.cod, vec![]));
                 pren.lift();
                 let t = go(metas, m, pren, t);
                 pren.unlift();
 
-                Ok(Term::Tλ(x, t?.into()))
+                Ok(Term::Tλ(x, i, t?.into()))
             }
-            Value::VΠ(x, a, b) => {
-                let a = go(metas, m, pren, *a)?;
+            Value::VΠ(x, i, a, b) => {
+                let a = go(metas, m, pren, Rc::unwrap_or_clone(a))?;
                 let b = eval_closure(metas, b, Value::VRigid(pren.cod, vec![]));
                 pren.lift();
                 let b = go(metas, m, pren, b);
                 pren.unlift();
 
-                Ok(Term::TΠ(x, a.into(), b?.into()))
+                Ok(Term::TΠ(x, i, a.into(), b?.into()))
+            }
+            Value::VΣ(x, a, b) => {
+                let a = go(metas, m, pren, Rc::unwrap_or_clone(a))?;
+                let b = eval_closure(metas, b, Value::VRigid(pren.cod, vec![]));
+                pren.lift();
+                let b = go(metas, m, pren, b);
+                pren.unlift();
+
+                Ok(Term::TΣ(x, a.into(), b?.into()))
+            }
+            Value::Vσ(t, u) => {
+                let t = go(metas, m, pren, Rc::unwrap_or_clone(t))?;
+                let u = go(metas, m, pren, Rc::unwrap_or_clone(u))?;
+
+                Ok(Term::Tσ(t.into(), u.into()))
+            }
+            Value::VU(l) => Ok(Term::TU(l)),
+            Value::VNat => Ok(Term::TNat),
+            Value::VNatLit(n) => Ok(Term::TNatLit(n)),
+            Value::VBool => Ok(Term::TBool),
+            Value::VTrue => Ok(Term::TTrue),
+            Value::VFalse => Ok(Term::TFalse),
+            Value::VString => Ok(Term::TString),
+            Value::VStrLit(s) => Ok(Term::TStrLit(s)),
+            Value::VChar => Ok(Term::TChar),
+            Value::VCharLit(c) => Ok(Term::TCharLit(c)),
+            Value::VInt => Ok(Term::TInt),
+            Value::VIntLit(n) => Ok(Term::TIntLit(n)),
+            Value::VEq(a, x, y) => Ok(Term::TEq(
+                go(metas, m, pren, Rc::unwrap_or_clone(a))?.into(),
+                go(metas, m, pren, Rc::unwrap_or_clone(x))?.into(),
+                go(metas, m, pren, Rc::unwrap_or_clone(y))?.into(),
+            )),
+            Value::VRefl(a, x) => Ok(Term::TRefl(
+                go(metas, m, pren, Rc::unwrap_or_clone(a))?.into(),
+                go(metas, m, pren, Rc::unwrap_or_clone(x))?.into(),
+            )),
+            Value::VSuc(t) => Ok(Term::TSuc(
+                go(metas, m, pren, Rc::unwrap_or_clone(t))?.into(),
+            )),
+            Value::VLazy(a) => Ok(Term::TLazy(
+                go(metas, m, pren, Rc::unwrap_or_clone(a))?.into(),
+            )),
+            Value::VDelay((env, t)) => {
+                let t = eval(metas, Cow::Owned(env), Rc::unwrap_or_clone(t));
+                Ok(Term::TDelay(go(metas, m, pren, t)?.into()))
+            }
+            Value::VData(d, args) => Ok(Term::TData(
+                d,
+                args.into_iter()
+                    .map(|u| Ok(go(metas, m, pren, u)?.into()))
+                    .collect::<Result<_, _>>()?,
+            )),
+            Value::VCon(d, c, args) => Ok(Term::TCon(
+                d,
+                c,
+                args.into_iter()
+                    .map(|u| Ok(go(metas, m, pren, u)?.into()))
+                    .collect::<Result<_, _>>()?,
+            )),
+            Value::VRecord(r, args) => Ok(Term::TRecord(
+                r,
+                args.into_iter()
+                    .map(|u| Ok(go(metas, m, pren, u)?.into()))
+                    .collect::<Result<_, _>>()?,
+            )),
+            Value::VRecordLit(r, fields) => Ok(Term::TRecordLit(
+                r,
+                fields
+                    .into_iter()
+                    .map(|u| Ok(go(metas, m, pren, u)?.into()))
+                    .collect::<Result<_, _>>()?,
+            )),
+            Value::VFix(fix, i, mut env, sp) => {
+                for j in 0..fix.len() {
+                    env.push(Value::VRigid(pren.cod + j, vec![]));
+                }
+                (0..fix.len()).for_each(|_| pren.lift());
+                let bodies = fix
+                    .iter()
+                    .map(|(x, k, t)| {
+                        let t = eval(metas, Cow::Borrowed(&env), Rc::unwrap_or_clone(t.clone()));
+                        Ok((x.clone(), *k, go(metas, m, pren, t)?.into()))
+                    })
+                    .collect::<Result<_, _>>();
+                (0..fix.len()).for_each(|_| pren.unlift());
+
+                go_sp(metas, m, pren, Term::TFix(bodies?, i), sp)
+            }
+            // opaque: forcing doesn't unfold it
+            Value::VGlued(x, def, sp) => {
+                let k = metas
+                    .opaque
+                    .iter()
+                    .position(|(_, d, _, _)| Rc::ptr_eq(d, &def));
+                match k {
+                    Some(k) => go_sp(metas, m, pren, Term::TV(lvl2ix(pren.dom, k)), sp),
+                    None => unreachable!("forced {x}"),
+                }
             }
-            Value::VΣ(_, _, _) => todo!(),
-            Value::Vσ(_, _) => todo!(),
-            Value::VU => Ok(Term::TU),
         }
     }
 
@@ -168,84 +1055,915 @@ pub fn rename(
         pren: &mut PartialRenaming,
         mut t: Term,
         sp: Spine,
-    ) -> Result<Term, Error> {
+    ) -> Result<Term, UnifyError> {
         if sp.is_empty() {
             return Ok(t);
         }
 
         for u in sp.into_iter() {
-            t = Term::TApp(t.into(), go(mcxt, m, pren, u)?.into());
+            t = match u {
+                Elim::EApp(u, i) => Term::TApp(t.into(), go(mcxt, m, pren, u)?.into(), i),
+                Elim::EProj1 => Term::TProj1(t.into()),
+                Elim::EProj2 => Term::TProj2(t.into()),
+                Elim::ENatElim(p, z, s) => Term::TNatElim(
+                    go(mcxt, m, pren, p)?.into(),
+                    go(mcxt, m, pren, z)?.into(),
+                    go(mcxt, m, pren, s)?.into(),
+                    t.into(),
+                ),
+                Elim::EBoolElim(p, t_, f) => Term::TBoolElim(
+                    go(mcxt, m, pren, p)?.into(),
+                    go(mcxt, m, pren, t_)?.into(),
+                    go(mcxt, m, pren, f)?.into(),
+                    t.into(),
+                ),
+                Elim::EAppend(u) => Term::TAppend(t.into(), go(mcxt, m, pren, u)?.into()),
+                Elim::EPrepend(s) => Term::TAppend(Term::TStrLit(s).into(), t.into()),
+                Elim::ELength => Term::TLength(t.into()),
+                Elim::EForce => Term::TForce(t.into()),
+                Elim::EIntOp(op, y) => Term::TIntOp(op, t.into(), go(mcxt, m, pren, y)?.into()),
+                Elim::EIntOpLit(op, x) => Term::TIntOp(op, Term::TIntLit(x).into(), t.into()),
+                Elim::EJ(a, x, p, pr, y) => Term::TJ(
+                    go(mcxt, m, pren, a)?.into(),
+                    go(mcxt, m, pren, x)?.into(),
+                    go(mcxt, m, pren, p)?.into(),
+                    go(mcxt, m, pren, pr)?.into(),
+                    go(mcxt, m, pren, y)?.into(),
+                    t.into(),
+                ),
+                Elim::EElim(d, args) => Term::TElim(
+                    d,
+                    args.into_iter()
+                        .map(|u| Ok(go(mcxt, m, pren, u)?.into()))
+                        .collect::<Result<_, _>>()?,
+                    t.into(),
+                ),
+                Elim::EField(r, f) => Term::TField(r, f, t.into()),
+            };
         }
 
         Ok(t)
     }
 
+    /// Rename `m_ sp`. Arguments of `m_` that are variables out of the scope of
+    /// `m` are pruned: `m_` is solved with a fresh meta that ignores them.
+    fn prune_flex(
+        metas: &mut MetaCxt,
+        m: MetaVar,
+        pren: &mut PartialRenaming,
+        m_: MetaVar,
+        sp: Spine,
+    ) -> Result<Term, UnifyError> {
+        if sp.iter().any(|e| !matches!(e, Elim::EApp(..))) {
+            return go_sp(metas, m, pren, Term::TMeta(m_), sp);
+        }
+
+        let mut args = Vec::new();
+        for e in sp {
+            let Elim::EApp(u, i) = e else { unreachable!() };
+            match force(metas, u) {
+                Value::VRigid(x, sp) if sp.is_empty() && !pren.ren.contains_key(&x) => {
+                    args.push((None, i))
+                }
+                u => args.push((Some(go(metas, m, pren, u)?), i)),
+            }
+        }
+
+        if args.iter().all(|(u, _)| u.is_some()) {
+            return Ok(args.into_iter().fold(Term::TMeta(m_), |t, (u, i)| {
+                Term::TApp(t.into(), u.unwrap().into(), i)
+            }));
+        }
+
+        let keep: Vec<(bool, Icit)> = args.iter().map(|(u, i)| (u.is_some(), *i)).collect();
+        let m__ = prune_meta(metas, m_, &keep)?;
+
+        Ok(args
+            .into_iter()
+            .filter_map(|(u, i)| Some((u?, i)))
+            .fold(Term::TMeta(m__), |t, (u, i)| {
+                Term::TApp(t.into(), u.into(), i)
+            }))
+    }
+
     go(mcxt, m, pren, v)
 }
 
-pub fn unify_sp(mcxt: &mut MetaCxt, lvl: Lvl, mut sp: Spine, mut sp_: Spine) -> Result<(), Error> {
+/// Solve `m`, then retry the problems that were waiting for it and search for
+/// instances again.
+fn assign(metas: &mut MetaCxt, m: MetaVar, solution: Value) -> Result<(), UnifyError> {
+    if metas.is_frozen(m) {
+        return Err(UnifyError::MetaFrozen(m));
+    }
+    metas.observe(|o| o.on_meta_solved(m, &solution));
+    if let Some(log) = metas.log.clone() {
+        let t = quote(metas, 0, solution.clone());
+        log.borrow_mut().step(UnifyStep::Solved(m, t));
+    }
+    metas.set_solution(m, solution);
+
+    let (woken, postponed) = std::mem::take(&mut metas.postponed)
+        .into_iter()
+        .partition(|p| p.blocker == m);
+    metas.postponed = postponed;
+
+    for p in woken {
+        let from = metas.postponed.len();
+        unify(metas, p.lvl, p.lhs, p.rhs)?;
+        if let Some(pos) = &p.pos {
+            metas.locate_postponed(from, &p.names, pos);
+        }
+    }
+    // the goals of instance arguments may be known now
+    instance::resolve(metas);
+
+    Ok(())
+}
+
+/// Solve `m` with `λ x1 .. xn. m_ (the kept xi)` for a fresh `m_`, which is
+/// returned.
+fn prune_meta(
+    metas: &mut MetaCxt,
+    m: MetaVar,
+    keep: &[(bool, Icit)],
+) -> Result<MetaVar, UnifyError> {
+    // the pruned type isn't known, so report with the original one
+    let origin = MetaOrigin {
+        pruned: Some((m, keep.iter().map(|(kept, _)| *kept).collect())),
+        ..metas.origin(m).clone()
+    };
+    let m_ = metas.new_meta(origin);
+    let n = keep.len();
+
+    let mut body = Term::TMeta(m_);
+    for (k, (kept, i)) in keep.iter().enumerate() {
+        if *kept {
+            body = Term::TApp(body.into(), Term::TV(Ix(n - 1 - k)).into(), *i);
+        }
+    }
+
+    let icits: Vec<Icit> = keep.iter().map(|(_, i)| *i).collect();
+    let solution = eval(metas, Cow::Owned(Env::default()), lams(&icits, body));
+    assign(metas, m, solution)?;
+
+    Ok(m_)
+}
+
+/// Unify `m sp =? m sp_`. When both spines are bound variables, `m` can only
+/// depend on the arguments where they agree, so the others are pruned.
+fn intersect(
+    mcxt: &mut MetaCxt,
+    lvl: Lvl,
+    m: MetaVar,
+    sp: Spine,
+    sp_: Spine,
+) -> Result<(), UnifyError> {
+    fn vars(metas: &mut MetaCxt, sp: &Spine) -> Option<Vec<(Lvl, Icit)>> {
+        sp.iter()
+            .map(|e| match e {
+                Elim::EApp(u, i) => match force(metas, u.clone()) {
+                    Value::VRigid(x, sp) if sp.is_empty() => Some((x, *i)),
+                    _ => None,
+                },
+                Elim::EProj1
+                | Elim::EProj2
+                | Elim::ENatElim(..)
+                | Elim::EBoolElim(..)
+                | Elim::EAppend(_)
+                | Elim::EPrepend(_)
+                | Elim::ELength
+                | Elim::EIntOp(..)
+                | Elim::EIntOpLit(..)
+                | Elim::EJ(..)
+                | Elim::EForce
+                | Elim::EElim(..)
+                | Elim::EField(..) => None,
+            })
+            .collect()
+    }
+
+    match (vars(mcxt, &sp), vars(mcxt, &sp_)) {
+        (Some(xs), Some(ys))
+            if xs.len() == ys.len() && xs.iter().zip(&ys).all(|((_, i), (_, i_))| i == i_) =>
+        {
+            if xs == ys {
+                return Ok(());
+            }
+
+            let keep: Vec<(bool, Icit)> = xs
+                .iter()
+                .zip(&ys)
+                .map(|((x, i), (y, _))| (x == y, *i))
+                .collect();
+            prune_meta(mcxt, m, &keep)?;
+            Ok(())
+        }
+        _ => unify_sp(mcxt, lvl, sp, sp_),
+    }
+}
+
+pub fn unify_sp(
+    mcxt: &mut MetaCxt,
+    lvl: Lvl,
+    mut sp: Spine,
+    mut sp_: Spine,
+) -> Result<(), UnifyError> {
     match (sp.pop(), sp_.pop()) {
         (None, None) => Ok(()),
-        (Some(t), Some(t_)) => {
+        (Some(Elim::EApp(t, i)), Some(Elim::EApp(t_, i_))) if i == i_ => {
+            unify_sp(mcxt, lvl, sp, sp_)?;
+            unify(mcxt, lvl, t, t_)
+        }
+        (Some(Elim::EProj1), Some(Elim::EProj1)) | (Some(Elim::EProj2), Some(Elim::EProj2)) => {
+            unify_sp(mcxt, lvl, sp, sp_)
+        }
+        (Some(Elim::ENatElim(p, z, s)), Some(Elim::ENatElim(p_, z_, s_))) => {
+            unify_sp(mcxt, lvl, sp, sp_)?;
+            unify(mcxt, lvl, p, p_)?;
+            unify(mcxt, lvl, z, z_)?;
+            unify(mcxt, lvl, s, s_)
+        }
+        (Some(Elim::EBoolElim(p, t, f)), Some(Elim::EBoolElim(p_, t_, f_))) => {
+            unify_sp(mcxt, lvl, sp, sp_)?;
+            unify(mcxt, lvl, p, p_)?;
+            unify(mcxt, lvl, t, t_)?;
+            unify(mcxt, lvl, f, f_)
+        }
+        (Some(Elim::EAppend(t)), Some(Elim::EAppend(t_))) => {
             unify_sp(mcxt, lvl, sp, sp_)?;
             unify(mcxt, lvl, t, t_)
         }
-        _ => error!(ErrorKind::MetaSpine(sp, sp_)),
+        (Some(Elim::EPrepend(s)), Some(Elim::EPrepend(s_))) if s == s_ => {
+            unify_sp(mcxt, lvl, sp, sp_)
+        }
+        (Some(Elim::ELength), Some(Elim::ELength)) | (Some(Elim::EForce), Some(Elim::EForce)) => {
+            unify_sp(mcxt, lvl, sp, sp_)
+        }
+        (Some(Elim::EIntOp(op, y)), Some(Elim::EIntOp(op_, y_))) if op == op_ => {
+            unify_sp(mcxt, lvl, sp, sp_)?;
+            unify(mcxt, lvl, y, y_)
+        }
+        (Some(Elim::EIntOpLit(op, x)), Some(Elim::EIntOpLit(op_, x_))) if op == op_ && x == x_ => {
+            unify_sp(mcxt, lvl, sp, sp_)
+        }
+        (Some(Elim::EJ(a, x, p, pr, y)), Some(Elim::EJ(a_, x_, p_, pr_, y_))) => {
+            unify_sp(mcxt, lvl, sp, sp_)?;
+            unify(mcxt, lvl, a, a_)?;
+            unify(mcxt, lvl, x, x_)?;
+            unify(mcxt, lvl, p, p_)?;
+            unify(mcxt, lvl, pr, pr_)?;
+            unify(mcxt, lvl, y, y_)
+        }
+        (Some(Elim::EElim(d, args)), Some(Elim::EElim(d_, args_))) if Rc::ptr_eq(&d, &d_) => {
+            unify_sp(mcxt, lvl, sp, sp_)?;
+            unify_args(mcxt, lvl, args, args_)
+        }
+        (Some(Elim::EField(r, f)), Some(Elim::EField(r_, f_)))
+            if Rc::ptr_eq(&r, &r_) && f == f_ =>
+        {
+            unify_sp(mcxt, lvl, sp, sp_)
+        }
+        _ => Err(UnifyError::MetaSpine(sp, sp_)),
     }
 }
 
-pub fn unify(mcxt: &mut MetaCxt, lvl: Lvl, l: Value, r: Value) -> Result<(), Error> {
-    let l = mcxt.force(l);
-    let r = mcxt.force(r);
+/// Outcome of [`is_def_eq`]
+#[derive(Debug, Clone)]
+pub enum Conversion {
+    /// the values are definitionally equal
+    Equal,
+    /// they aren't, however the metas are solved
+    NotEqual(UnifyError),
+    /// they are equal if these metas are solved, which unification would
+    /// have done
+    Blocked(Vec<MetaVar>),
+}
+
+/// Check whether `l` and `r` are definitionally equal like [`unify`], but
+/// without solving metas: whatever unification did is rolled back, and the
+/// metas it solved or postponed problems on are reported instead. Metas of
+/// earlier definitions count too, although they are frozen.
+pub fn is_def_eq(mcxt: &mut MetaCxt, lvl: Lvl, l: Value, r: Value) -> Conversion {
+    let snapshot = mcxt.snapshot();
+    let (len, trail) = (mcxt.len(), mcxt.trail.len());
+    // only the problems postponed from now on are left
+    mcxt.postponed.clear();
+    mcxt.frozen = 0;
+
+    let res = unify(mcxt, lvl, l.clone(), r.clone());
+    let levels = mcxt.solve_levels().is_some();
+    let solved = mcxt.trail[trail..].iter().filter_map(|(m, old)| match old {
+        MetaEntry::Unsolved => Some(*m),
+        MetaEntry::Solved(_) => None,
+    });
+    let mut blocked: Vec<MetaVar> = solved
+        .chain(mcxt.postponed.iter().map(|p| p.blocker))
+        .filter(|&m| m < len)
+        .collect();
+    mcxt.rollback(snapshot);
+
+    blocked.sort();
+    blocked.dedup();
+    match res {
+        Err(err) => Conversion::NotEqual(err),
+        Ok(()) if !levels => Conversion::NotEqual(UnifyError::MetaUnify(l.into(), r.into())),
+        Ok(()) if blocked.is_empty() => Conversion::Equal,
+        Ok(()) => Conversion::Blocked(blocked),
+    }
+}
+
+pub fn unify(mcxt: &mut MetaCxt, lvl: Lvl, l: Value, r: Value) -> Result<(), UnifyError> {
+    logged(mcxt, lvl, l, r, unify_)
+}
+
+/// Unify `expected` with `actual` up to cumulativity: a term of type `actual`
+/// has type `expected` too when the universes of `actual` are below the ones
+/// of `expected`, but in the domains of functions, where they are above.
+/// Unification itself only equates universes of the same level.
+pub fn subtype(
+    mcxt: &mut MetaCxt,
+    lvl: Lvl,
+    expected: Value,
+    actual: Value,
+) -> Result<(), UnifyError> {
+    logged(mcxt, lvl, expected, actual, subtype_)
+}
+
+/// Solve the problem `l =? r` with `f`, telling the observer and the log.
+fn logged(
+    mcxt: &mut MetaCxt,
+    lvl: Lvl,
+    l: Value,
+    r: Value,
+    f: fn(&mut MetaCxt, Lvl, Value, Value) -> Result<(), UnifyError>,
+) -> Result<(), UnifyError> {
+    mcxt.observe(|o| o.on_unify(&l, &r, lvl));
+    let Some(log) = mcxt.log.clone() else {
+        return f(mcxt, lvl, l, r);
+    };
+
+    let lhs = quote(mcxt, lvl, l.clone());
+    let rhs = quote(mcxt, lvl, r.clone());
+    log.borrow_mut().enter(lvl, lhs, rhs);
+    let res = f(mcxt, lvl, l, r);
+    log.borrow_mut().exit(&res);
+    res
+}
+
+fn subtype_(
+    mcxt: &mut MetaCxt,
+    lvl: Lvl,
+    expected: Value,
+    actual: Value,
+) -> Result<(), UnifyError> {
+    let former = |v: &Value| matches!(v, Value::VU(_) | Value::VΠ(..) | Value::VΣ(..));
+    let expected = force_metas(mcxt, expected);
+    let actual = force_metas(mcxt, actual);
+
+    match (expected, actual) {
+        (Value::VU(l), Value::VU(l_)) => {
+            mcxt.level_le(l_, l, None);
+            Ok(())
+        }
+        (Value::VΠ(_, i, a, b), Value::VΠ(_, i_, a_, b_)) if i == i_ => {
+            // the other way around in the domains
+            subtype(mcxt, lvl, Rc::unwrap_or_clone(a_), Rc::unwrap_or_clone(a))?;
+            let b = eval_closure(mcxt, b, Value::VRigid(lvl, vec![]));
+            let b_ = eval_closure(mcxt, b_, Value::VRigid(lvl, vec![]));
+            subtype(mcxt, lvl + 1, b, b_)
+        }
+        (Value::VΣ(_, a, b), Value::VΣ(_, a_, b_)) => {
+            subtype(mcxt, lvl, Rc::unwrap_or_clone(a), Rc::unwrap_or_clone(a_))?;
+            let b = eval_closure(mcxt, b, Value::VRigid(lvl, vec![]));
+            let b_ = eval_closure(mcxt, b_, Value::VRigid(lvl, vec![]));
+            subtype(mcxt, lvl + 1, b, b_)
+        }
+        // a definition of a universe or a type former against another one
+        (Value::VGlued(x, d, sp), r) if former(&r) && mcxt.opaque(&d).is_none() => {
+            let l = unfold(mcxt, Value::VGlued(x, d, sp));
+            subtype_(mcxt, lvl, l, r)
+        }
+        (l, Value::VGlued(x, d, sp)) if former(&l) && mcxt.opaque(&d).is_none() => {
+            let r = unfold(mcxt, Value::VGlued(x, d, sp));
+            subtype_(mcxt, lvl, l, r)
+        }
+        (l, r) => unify_(mcxt, lvl, l, r),
+    }
+}
+
+fn unify_(mcxt: &mut MetaCxt, lvl: Lvl, l: Value, r: Value) -> Result<(), UnifyError> {
+    // comparing values evaluates as much as quoting them
+    if !mcxt.take_node() {
+        return Err(UnifyError::MetaUnify(l.into(), r.into()));
+    }
+    // the same heads and spines need neither forcing nor unfolding
+    if same_value(&l, &r) {
+        return Ok(());
+    }
+    let l = force_metas(mcxt, l);
+    let r = force_metas(mcxt, r);
 
     match (l, r) {
-        (Value::VU, Value::VU) => Ok(()),
-        (Value::Vλ(_, t), Value::Vλ(_, t_)) => {
+        (Value::VFlex(m, sp), t_) if first_order(mcxt, lvl, m, &sp, &t_) => Ok(()),
+        (t, Value::VFlex(m_, sp_)) if first_order(mcxt, lvl, m_, &sp_, &t) => Ok(()),
+        // same definition: try the arguments before unfolding
+        (Value::VGlued(_, d, sp), Value::VGlued(_, d_, sp_))
+            if Rc::ptr_eq(&d, &d_) && mcxt.opaque(&d).is_some() =>
+        {
+            unify_sp(mcxt, lvl, sp, sp_)
+        }
+        (Value::VGlued(x, d, sp), Value::VGlued(x_, d_, sp_)) if Rc::ptr_eq(&d, &d_) => {
+            let snapshot = mcxt.snapshot();
+            match unify_sp(mcxt, lvl, sp.clone(), sp_.clone()) {
+                Ok(()) => Ok(()),
+                Err(_) => {
+                    mcxt.rollback(snapshot);
+                    let l = unfold(mcxt, Value::VGlued(x, d, sp));
+                    let r = unfold(mcxt, Value::VGlued(x_, d_, sp_));
+                    unify(mcxt, lvl, l, r)
+                }
+            }
+        }
+        (Value::VGlued(x, d, sp), r) if mcxt.opaque(&d).is_none() => {
+            let l = Value::VGlued(x, d, sp);
+            let l = unfold(mcxt, l);
+            unify(mcxt, lvl, l, r)
+        }
+        (l, Value::VGlued(x, d, sp)) if mcxt.opaque(&d).is_none() => {
+            let r = unfold(mcxt, Value::VGlued(x, d, sp));
+            unify(mcxt, lvl, l, r)
+        }
+        (Value::VFix(fix, i, env, sp), r) if fix_ready(mcxt, fix[i].1, &sp) => {
+            let l = unfold(mcxt, Value::VFix(fix, i, env, sp));
+            unify(mcxt, lvl, l, r)
+        }
+        (l, Value::VFix(fix, i, env, sp)) if fix_ready(mcxt, fix[i].1, &sp) => {
+            let r = unfold(mcxt, Value::VFix(fix, i, env, sp));
+            unify(mcxt, lvl, l, r)
+        }
+        // stuck recursive definitions are compared by the bodies of their blocks
+        (Value::VFix(fix, i, mut env, sp), Value::VFix(fix_, i_, mut env_, sp_))
+            if i == i_ && fix.len() == fix_.len() =>
+        {
+            if !(Rc::ptr_eq(&fix, &fix_) && env.ptr_eq(&env_)) {
+                let n = fix.len();
+                for j in 0..n {
+                    env.push(Value::VRigid(lvl + j, vec![]));
+                    env_.push(Value::VRigid(lvl + j, vec![]));
+                }
+                for ((_, _, t), (_, _, t_)) in fix.iter().zip(fix_.iter()) {
+                    let a = eval(mcxt, Cow::Borrowed(&env), Rc::unwrap_or_clone(t.clone()));
+                    let b = eval(mcxt, Cow::Borrowed(&env_), Rc::unwrap_or_clone(t_.clone()));
+                    unify(mcxt, lvl + n, a, b)?;
+                }
+            }
+            unify_sp(mcxt, lvl, sp, sp_)
+        }
+        // universes are equal at equal levels, cumulativity is up to `subtype`
+        (Value::VU(l), Value::VU(l_)) => {
+            mcxt.level_le(l, l_, None);
+            mcxt.level_le(l_, l, None);
+            Ok(())
+        }
+        (Value::Vλ(_, _, t), Value::Vλ(_, _, t_)) => {
+            if same_closure(&t, &t_) {
+                return Ok(());
+            }
+
             let a = eval_closure(mcxt, t, Value::VRigid(lvl, vec![]));
             let b = eval_closure(mcxt, t_, Value::VRigid(lvl, vec![]));
 
             unify(mcxt, lvl + 1, a, b)
         }
-        (t, Value::Vλ(_, t_)) => {
-            let a = v_app(mcxt, t, Value::VRigid(lvl, vec![]));
+        (t, Value::Vλ(_, i, t_)) => {
+            let a = v_app(mcxt, t, Value::VRigid(lvl, vec![]), i);
             let b = eval_closure(mcxt, t_, Value::VRigid(lvl, vec![]));
 
             unify(mcxt, lvl + 1, a, b)
         }
-        (Value::Vλ(_, t), t_) => {
+        (Value::Vλ(_, i, t), t_) => {
             let a = eval_closure(mcxt, t, Value::VRigid(lvl, vec![]));
-            let b = v_app(mcxt, t_, Value::VRigid(lvl, vec![]));
+            let b = v_app(mcxt, t_, Value::VRigid(lvl, vec![]), i);
 
             unify(mcxt, lvl + 1, a, b)
         }
-        (Value::VΠ(_, a, b), Value::VΠ(_, a_, b_)) => {
-            unify(mcxt, lvl, *a, *a_)?;
+        (Value::VΠ(_, i, a, b), Value::VΠ(_, i_, a_, b_)) if i == i_ => {
+            unify_shared(mcxt, lvl, a, a_)?;
+            if same_closure(&b, &b_) {
+                return Ok(());
+            }
+
             let b = eval_closure(mcxt, b, Value::VRigid(lvl, vec![]));
             let b_ = eval_closure(mcxt, b_, Value::VRigid(lvl, vec![]));
             unify(mcxt, lvl + 1, b, b_)
         }
+        (Value::VΣ(_, a, b), Value::VΣ(_, a_, b_)) => {
+            unify_shared(mcxt, lvl, a, a_)?;
+            if same_closure(&b, &b_) {
+                return Ok(());
+            }
+
+            let b = eval_closure(mcxt, b, Value::VRigid(lvl, vec![]));
+            let b_ = eval_closure(mcxt, b_, Value::VRigid(lvl, vec![]));
+            unify(mcxt, lvl + 1, b, b_)
+        }
+        (Value::Vσ(t, u), Value::Vσ(t_, u_)) => {
+            unify_shared(mcxt, lvl, t, t_)?;
+            unify_shared(mcxt, lvl, u, u_)
+        }
+        (Value::Vσ(t, u), t_ @ Value::VRigid(..)) => {
+            unify(mcxt, lvl, Rc::unwrap_or_clone(t), v_proj1(t_.clone()))?;
+            unify(mcxt, lvl, Rc::unwrap_or_clone(u), v_proj2(t_))
+        }
+        (t @ Value::VRigid(..), Value::Vσ(t_, u_)) => {
+            unify(mcxt, lvl, v_proj1(t.clone()), Rc::unwrap_or_clone(t_))?;
+            unify(mcxt, lvl, v_proj2(t), Rc::unwrap_or_clone(u_))
+        }
+        (Value::VNat, Value::VNat)
+        | (Value::VBool, Value::VBool)
+        | (Value::VTrue, Value::VTrue)
+        | (Value::VFalse, Value::VFalse)
+        | (Value::VString, Value::VString)
+        | (Value::VChar, Value::VChar)
+        | (Value::VInt, Value::VInt) => Ok(()),
+        (Value::VStrLit(s), Value::VStrLit(s_)) if s == s_ => Ok(()),
+        (Value::VCharLit(c), Value::VCharLit(c_)) if c == c_ => Ok(()),
+        (Value::VIntLit(n), Value::VIntLit(n_)) if n == n_ => Ok(()),
+        (Value::VEq(a, x, y), Value::VEq(a_, x_, y_)) => {
+            unify_shared(mcxt, lvl, a, a_)?;
+            unify_shared(mcxt, lvl, x, x_)?;
+            unify_shared(mcxt, lvl, y, y_)
+        }
+        // the arguments of both are determined by their type
+        (Value::VRefl(..), Value::VRefl(..)) => Ok(()),
+        (Value::VLazy(a), Value::VLazy(a_)) => unify_shared(mcxt, lvl, a, a_),
+        (Value::VDelay(t), Value::VDelay(t_)) => {
+            if same_closure(&t, &t_) {
+                return Ok(());
+            }
+            let a = v_force(mcxt, Value::VDelay(t));
+            let b = v_force(mcxt, Value::VDelay(t_));
+            unify(mcxt, lvl, a, b)
+        }
+        // eta for suspensions: a neutral one is the delay of its forcing
+        (Value::VDelay(t), t_ @ Value::VRigid(..)) => {
+            let a = v_force(mcxt, Value::VDelay(t));
+            let b = v_force(mcxt, t_);
+            unify(mcxt, lvl, a, b)
+        }
+        (t @ Value::VRigid(..), Value::VDelay(t_)) => {
+            let a = v_force(mcxt, t);
+            let b = v_force(mcxt, Value::VDelay(t_));
+            unify(mcxt, lvl, a, b)
+        }
+        (Value::VData(d, args), Value::VData(d_, args_)) if Rc::ptr_eq(&d, &d_) => {
+            unify_args(mcxt, lvl, args, args_)
+        }
+        (Value::VCon(d, c, args), Value::VCon(d_, c_, args_)) if Rc::ptr_eq(&d, &d_) && c == c_ => {
+            unify_args(mcxt, lvl, args, args_)
+        }
+        (Value::VRecord(r, args), Value::VRecord(r_, args_)) if Rc::ptr_eq(&r, &r_) => {
+            unify_args(mcxt, lvl, args, args_)
+        }
+        (Value::VRecordLit(r, fs), Value::VRecordLit(r_, fs_)) if Rc::ptr_eq(&r, &r_) => {
+            unify_args(mcxt, lvl, fs, fs_)
+        }
+        // eta for records: a neutral record is the literal of its projections
+        (Value::VRecordLit(r, fs), t_ @ Value::VRigid(..)) => {
+            let fs_ = (0..fs.len())
+                .map(|f| v_field(r.clone(), f, t_.clone()))
+                .collect();
+            unify_args(mcxt, lvl, fs, fs_)
+        }
+        (t @ Value::VRigid(..), Value::VRecordLit(r_, fs_)) => {
+            let fs = (0..fs_.len())
+                .map(|f| v_field(r_.clone(), f, t.clone()))
+                .collect();
+            unify_args(mcxt, lvl, fs, fs_)
+        }
+        (Value::VNatLit(n), Value::VNatLit(n_)) if n == n_ => Ok(()),
+        (Value::VSuc(t), Value::VSuc(t_)) => unify_shared(mcxt, lvl, t, t_),
+        (Value::VNatLit(n), Value::VSuc(t_)) if n > 0 => {
+            unify(mcxt, lvl, Value::VNatLit(n - 1), Rc::unwrap_or_clone(t_))
+        }
+        (Value::VSuc(t), Value::VNatLit(n_)) if n_ > 0 => {
+            unify(mcxt, lvl, Rc::unwrap_or_clone(t), Value::VNatLit(n_ - 1))
+        }
         (Value::VRigid(x, sp), Value::VRigid(x_, sp_)) if x == x_ => unify_sp(mcxt, lvl, sp, sp_),
-        (Value::VFlex(m, sp), Value::VFlex(m_, sp_)) if m == m_ => unify_sp(mcxt, lvl, sp, sp_),
-        (Value::VFlex(m, sp), t_) => solve(mcxt, lvl, m, sp, t_),
-        (t, Value::VFlex(m_, sp_)) => solve(mcxt, lvl, m_, sp_, t),
-        (l, r) => {
-            error!(ErrorKind::MetaUnify(l, r))
+        (Value::VFlex(m, sp), Value::VFlex(m_, sp_)) if m == m_ => intersect(mcxt, lvl, m, sp, sp_),
+        (Value::VFlex(m, sp), t_) => {
+            let problem = (Value::VFlex(m, sp.clone()), t_.clone());
+            solve_or_postpone(mcxt, lvl, problem, m, sp, t_)
+        }
+        (t, Value::VFlex(m_, sp_)) => {
+            let problem = (t.clone(), Value::VFlex(m_, sp_.clone()));
+            solve_or_postpone(mcxt, lvl, problem, m_, sp_, t)
+        }
+        // constructors are injective, which the cases above use, and distinct
+        (l, r) if con_name(&l).is_some() && con_name(&r).is_some() => {
+            Err(UnifyError::ConClash(l.into(), r.into()))
         }
+        (l, r) => Err(UnifyError::MetaUnify(l.into(), r.into())),
+    }
+}
+
+/// Name of the constructor a value is headed by, literals included.
+pub(crate) fn con_name(v: &Value) -> Option<Name> {
+    match v {
+        Value::VCon(d, c, _) => Some(d.cons[*c].name.clone()),
+        Value::VTrue => Some("true".into()),
+        Value::VFalse => Some("false".into()),
+        Value::VSuc(_) => Some("suc".into()),
+        Value::VNatLit(n) => Some(n.to_string().into()),
+        Value::VIntLit(n) => Some(n.to_string().into()),
+        Value::VStrLit(s) => Some(format!("{s:?}").into()),
+        Value::VCharLit(c) => Some(format!("{c:?}").into()),
+        _ => None,
     }
 }
 
-pub fn solve(metas: &mut MetaCxt, lvl: Lvl, m: MetaVar, sp: Spine, v: Value) -> Result<(), Error> {
+/// Solve `?m sp =? t` for a neutral `t` ending in the same arguments as
+/// `sp` by first-order approximation: `?m` with `t` without them. This is
+/// cheaper than inverting the spine, solves problems that aren't patterns
+/// without postponing them, and agrees with the solution of a pattern up to
+/// eta. Whatever it did is rolled back if it fails.
+fn first_order(mcxt: &mut MetaCxt, lvl: Lvl, m: MetaVar, sp: &Spine, t: &Value) -> bool {
+    let mut head = t.clone();
+    let sp_ = match &mut head {
+        Value::VFlex(m_, sp_) if *m_ != m => sp_,
+        Value::VRigid(_, sp_) | Value::VGlued(_, _, sp_) => sp_,
+        _ => return false,
+    };
+    let Some(k) = sp_.len().checked_sub(sp.len()) else {
+        return false;
+    };
+    let same_args = sp.iter().zip(&sp_[k..]).all(|args| match args {
+        (Elim::EApp(u, i), Elim::EApp(u_, i_)) => i == i_ && same_value(u, u_),
+        _ => false,
+    });
+    if sp.is_empty() || !same_args {
+        return false;
+    }
+    sp_.truncate(k);
+
+    let snapshot = mcxt.snapshot();
+    let solved = unify(mcxt, lvl, Value::VFlex(m, vec![]), head).is_ok();
+    if !solved {
+        mcxt.rollback(snapshot);
+    }
+    solved
+}
+
+/// Whether the `k`th of the `n` variables the meta `m` is applied to occurs
+/// in its solution, or may occur in it if it is unsolved.
+fn meta_uses(metas: &mut MetaCxt, m: MetaVar, k: usize, n: usize) -> bool {
+    let MetaEntry::Solved(v) = &metas[m] else {
+        return true;
+    };
+    let mut v = v.clone();
+    for x in 0..n {
+        v = v_app(metas, v, Value::VRigid(x, vec![]), Icit::Expl);
+    }
+    quote(metas, n, v).occurs(lvl2ix(n, k), &mut |_, _, _| true)
+}
+
+/// Unify the arguments of the same data type, constructor or eliminator, or
+/// the fields of the same record.
+fn unify_args(
+    mcxt: &mut MetaCxt,
+    lvl: Lvl,
+    args: Vec<Value>,
+    args_: Vec<Value>,
+) -> Result<(), UnifyError> {
+    for (t, t_) in args.into_iter().zip(args_) {
+        unify(mcxt, lvl, t, t_)?;
+    }
+    Ok(())
+}
+
+/// Unify shared values, which are trivially equal when they are the same node.
+fn unify_shared(
+    mcxt: &mut MetaCxt,
+    lvl: Lvl,
+    l: Rc<Value>,
+    r: Rc<Value>,
+) -> Result<(), UnifyError> {
+    if Rc::ptr_eq(&l, &r) {
+        return Ok(());
+    }
+
+    unify(mcxt, lvl, Rc::unwrap_or_clone(l), Rc::unwrap_or_clone(r))
+}
+
+/// Closures of the same term in the same environment. Interned terms, see
+/// [`TermStore`], are the same node when they are equal.
+fn same_closure((env, t): &Closure, (env_, t_): &Closure) -> bool {
+    env.ptr_eq(env_) && (Rc::ptr_eq(t, t_) || t.alpha_eq(t_))
+}
+
+/// How many nodes [`same_value`] compares before giving up, so that the
+/// nested calls of `unify` don't compare the same values over and over
+const SAME_VALUE_NODES: usize = 256;
+
+/// Whether two values are syntactically the same: the same heads applied to
+/// the same spines, with the same shared nodes and closures. Conservative,
+/// `false` only means that `unify` has to look further.
+fn same_value(l: &Value, r: &Value) -> bool {
+    let mut stack = vec![(l, r)];
+    let mut nodes = 0;
+
+    while let Some((l, r)) = stack.pop() {
+        nodes += 1;
+        if nodes > SAME_VALUE_NODES {
+            return false;
+        }
+        if std::ptr::eq(l, r) {
+            continue;
+        }
+        let same = match (l, r) {
+            (Value::VFlex(m, sp), Value::VFlex(m_, sp_)) => {
+                m == m_ && same_spine(sp, sp_, &mut stack)
+            }
+            (Value::VRigid(x, sp), Value::VRigid(x_, sp_)) => {
+                x == x_ && same_spine(sp, sp_, &mut stack)
+            }
+            (Value::VGlued(_, d, sp), Value::VGlued(_, d_, sp_)) => {
+                Rc::ptr_eq(d, d_) && same_spine(sp, sp_, &mut stack)
+            }
+            (Value::VFix(fix, i, env, sp), Value::VFix(fix_, i_, env_, sp_)) => {
+                Rc::ptr_eq(fix, fix_)
+                    && i == i_
+                    && env.ptr_eq(env_)
+                    && same_spine(sp, sp_, &mut stack)
+            }
+            (Value::Vλ(_, i, t), Value::Vλ(_, i_, t_)) => i == i_ && same_closure(t, t_),
+            (Value::VΠ(_, i, a, b), Value::VΠ(_, i_, a_, b_)) => {
+                stack.push((a, a_));
+                i == i_ && same_closure(b, b_)
+            }
+            (Value::VΣ(_, a, b), Value::VΣ(_, a_, b_)) => {
+                stack.push((a, a_));
+                same_closure(b, b_)
+            }
+            (Value::Vσ(t, u), Value::Vσ(t_, u_)) => {
+                stack.extend([(&**t, &**t_), (u, u_)]);
+                true
+            }
+            (Value::VU(l), Value::VU(l_)) => l == l_,
+            (Value::VNat, Value::VNat)
+            | (Value::VBool, Value::VBool)
+            | (Value::VTrue, Value::VTrue)
+            | (Value::VFalse, Value::VFalse)
+            | (Value::VString, Value::VString)
+            | (Value::VChar, Value::VChar)
+            | (Value::VInt, Value::VInt)
+            | (Value::VRefl(..), Value::VRefl(..)) => true,
+            (Value::VNatLit(n), Value::VNatLit(n_)) => n == n_,
+            (Value::VStrLit(s), Value::VStrLit(s_)) => s == s_,
+            (Value::VCharLit(c), Value::VCharLit(c_)) => c == c_,
+            (Value::VIntLit(n), Value::VIntLit(n_)) => n == n_,
+            (Value::VSuc(t), Value::VSuc(t_)) | (Value::VLazy(t), Value::VLazy(t_)) => {
+                stack.push((t, t_));
+                true
+            }
+            (Value::VDelay(t), Value::VDelay(t_)) => same_closure(t, t_),
+            (Value::VEq(a, x, y), Value::VEq(a_, x_, y_)) => {
+                stack.extend([(&**a, &**a_), (x, x_), (y, y_)]);
+                true
+            }
+            (Value::VData(d, args), Value::VData(d_, args_)) => {
+                Rc::ptr_eq(d, d_) && same_args(args, args_, &mut stack)
+            }
+            (Value::VCon(d, c, args), Value::VCon(d_, c_, args_)) => {
+                Rc::ptr_eq(d, d_) && c == c_ && same_args(args, args_, &mut stack)
+            }
+            (Value::VRecord(r, args), Value::VRecord(r_, args_))
+            | (Value::VRecordLit(r, args), Value::VRecordLit(r_, args_)) => {
+                Rc::ptr_eq(r, r_) && same_args(args, args_, &mut stack)
+            }
+            _ => false,
+        };
+        if !same {
+            return false;
+        }
+    }
+    true
+}
+
+/// Whether the spines have the same eliminators, leaving the values in them
+/// on `stack` to compare.
+fn same_spine<'a>(sp: &'a Spine, sp_: &'a Spine, stack: &mut Vec<(&'a Value, &'a Value)>) -> bool {
+    if sp.len() != sp_.len() {
+        return false;
+    }
+    for (e, e_) in sp.iter().zip(sp_) {
+        match (e, e_) {
+            (Elim::EApp(t, i), Elim::EApp(t_, i_)) if i == i_ => stack.push((t, t_)),
+            (Elim::EProj1, Elim::EProj1)
+            | (Elim::EProj2, Elim::EProj2)
+            | (Elim::ELength, Elim::ELength)
+            | (Elim::EForce, Elim::EForce) => {}
+            (Elim::ENatElim(p, z, s), Elim::ENatElim(p_, z_, s_))
+            | (Elim::EBoolElim(p, z, s), Elim::EBoolElim(p_, z_, s_)) => {
+                stack.extend([(p, p_), (z, z_), (s, s_)])
+            }
+            (Elim::EAppend(t), Elim::EAppend(t_)) => stack.push((t, t_)),
+            (Elim::EPrepend(s), Elim::EPrepend(s_)) if s == s_ => {}
+            (Elim::EIntOp(op, y), Elim::EIntOp(op_, y_)) if op == op_ => stack.push((y, y_)),
+            (Elim::EIntOpLit(op, x), Elim::EIntOpLit(op_, x_)) if op == op_ && x == x_ => {}
+            (Elim::EJ(a, x, p, pr, y), Elim::EJ(a_, x_, p_, pr_, y_)) => {
+                stack.extend([(a, a_), (x, x_), (p, p_), (pr, pr_), (y, y_)])
+            }
+            (Elim::EElim(d, args), Elim::EElim(d_, args_)) if Rc::ptr_eq(d, d_) => {
+                if !same_args(args, args_, stack) {
+                    return false;
+                }
+            }
+            (Elim::EField(r, f), Elim::EField(r_, f_)) if Rc::ptr_eq(r, r_) && f == f_ => {}
+            _ => return false,
+        }
+    }
+    true
+}
+
+fn same_args<'a>(
+    args: &'a [Value],
+    args_: &'a [Value],
+    stack: &mut Vec<(&'a Value, &'a Value)>,
+) -> bool {
+    stack.extend(args.iter().zip(args_));
+    args.len() == args_.len()
+}
+
+pub fn solve(
+    metas: &mut MetaCxt,
+    lvl: Lvl,
+    m: MetaVar,
+    sp: Spine,
+    v: Value,
+) -> Result<(), UnifyError> {
+    let icits: Vec<Icit> = sp
+        .iter()
+        .filter_map(|e| match e {
+            Elim::EApp(_, i) => Some(*i),
+            Elim::EProj1
+            | Elim::EProj2
+            | Elim::ENatElim(..)
+            | Elim::EBoolElim(..)
+            | Elim::EAppend(_)
+            | Elim::EPrepend(_)
+            | Elim::ELength
+            | Elim::EIntOp(..)
+            | Elim::EIntOpLit(..)
+            | Elim::EJ(..)
+            | Elim::EForce
+            | Elim::EElim(..)
+            | Elim::EField(..) => None,
+        })
+        .collect();
     let pren = PartialRenaming::invert(metas, lvl, sp)?;
     let rhs = rename(metas, m, &mut pren.clone(), v)?;
-    let solution = eval(metas, Cow::Owned(Env::default()), lams(pren.dom, rhs));
+    let rhs = metas.intern(&lams(&icits, rhs));
+    let env = metas.opaque_env();
+    let solution = eval(metas, Cow::Owned(env), Rc::unwrap_or_clone(rhs));
 
-    metas[m] = MetaEntry::Solved(solution);
-    Ok(())
+    assign(metas, m, solution)
+}
+
+/// Like [`solve`], but a problem that isn't a pattern is postponed until `m`
+/// is solved.
+fn solve_or_postpone(
+    metas: &mut MetaCxt,
+    lvl: Lvl,
+    (lhs, rhs): (Value, Value),
+    m: MetaVar,
+    sp: Spine,
+    v: Value,
+) -> Result<(), UnifyError> {
+    match solve(metas, lvl, m, sp, v) {
+        Err(UnifyError::MetaInvert(_)) => {
+            let problem = Postponed {
+                blocker: m,
+                lvl,
+                lhs,
+                rhs,
+                names: Vec::new(),
+                pos: None,
+            };
+            metas.observe(|o| o.on_constraint_postponed(&problem));
+            if let Some(log) = &metas.log {
+                log.borrow_mut().step(UnifyStep::Postponed(m));
+            }
+            metas.postponed.push(problem);
+            Ok(())
+        }
+        res => res,
+    }
 }
 
-pub fn lams(lvl: Lvl, mut t: Term) -> Term {
-    for i in 0..lvl {
-        t = Term::Tλ(format!("x{}", i + 1).into(), t.into());
+pub fn lams(icits: &[Icit], mut t: Term) -> Term {
+    for (x, i) in icits.iter().enumerate().rev() {
+        t = Term::Tλ(format!("x{}", x + 1).into(), *i, t.into());
     }
     t
 }
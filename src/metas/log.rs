@@ -0,0 +1,151 @@
+//! The log of what the solver did: every unification problem, the problems
+//! it was decomposed into, the metas it solved and how it ended.
+
+use std::fmt::{self, Display};
+
+use crate::{lsp::json::Json, Lvl, Name, TPrettyPrinter, Term};
+
+use super::{MetaVar, UnifyError};
+
+/// The problems `unify` was called with from outside, in order, with the
+/// ones tried and rolled back after. See [`super::MetaCxt::start_log`].
+#[derive(Debug, Clone, Default)]
+pub struct UnifyLog {
+    pub problems: Vec<UnifyProblem>,
+    /// the problems being solved, innermost last
+    open: Vec<UnifyProblem>,
+}
+
+/// `lhs =? rhs`, quoted with `lvl` variables bound, named `x0`, `x1`, …
+#[derive(Debug, Clone)]
+pub struct UnifyProblem {
+    pub lvl: Lvl,
+    pub lhs: Term,
+    pub rhs: Term,
+    pub steps: Vec<UnifyStep>,
+    /// why it failed, if it did
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum UnifyStep {
+    Subproblem(UnifyProblem),
+    /// the meta was solved with the closed term
+    Solved(MetaVar, Term),
+    /// a problem that isn't a pattern waits for the meta to be solved
+    Postponed(MetaVar),
+}
+
+impl UnifyLog {
+    pub(super) fn enter(&mut self, lvl: Lvl, lhs: Term, rhs: Term) {
+        self.open.push(UnifyProblem {
+            lvl,
+            lhs,
+            rhs,
+            steps: Vec::new(),
+            error: None,
+        });
+    }
+
+    pub(super) fn exit(&mut self, res: &Result<(), UnifyError>) {
+        let Some(mut problem) = self.open.pop() else {
+            return;
+        };
+        problem.error = res.as_ref().err().map(ToString::to_string);
+        match self.open.last_mut() {
+            Some(parent) => parent.steps.push(UnifyStep::Subproblem(problem)),
+            None => self.problems.push(problem),
+        }
+    }
+
+    /// Record a step of the innermost problem.
+    pub(super) fn step(&mut self, step: UnifyStep) {
+        if let Some(problem) = self.open.last_mut() {
+            problem.steps.push(step);
+        }
+    }
+
+    pub fn to_json(&self) -> Json {
+        Json::Array(self.problems.iter().map(UnifyProblem::to_json).collect())
+    }
+}
+
+impl UnifyProblem {
+    pub fn to_json(&self) -> Json {
+        let names = names(self.lvl);
+        Json::object([
+            ("lhs", TPrettyPrinter(&names, &self.lhs).to_string().into()),
+            ("rhs", TPrettyPrinter(&names, &self.rhs).to_string().into()),
+            (
+                "steps",
+                self.steps
+                    .iter()
+                    .map(|step| match step {
+                        UnifyStep::Subproblem(problem) => problem.to_json(),
+                        UnifyStep::Solved(m, t) => Json::object([
+                            ("solved", (*m).into()),
+                            ("solution", TPrettyPrinter(&[], t).to_string().into()),
+                        ]),
+                        UnifyStep::Postponed(m) => Json::object([("postponed", (*m).into())]),
+                    })
+                    .collect::<Vec<_>>()
+                    .into(),
+            ),
+            (
+                "error",
+                self.error
+                    .as_ref()
+                    .map_or(Json::Null, |e| e.as_str().into()),
+            ),
+        ])
+    }
+
+    fn fmt_indented(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        let names = names(self.lvl);
+        write!(
+            f,
+            "{:indent$}{} =? {}",
+            "",
+            TPrettyPrinter(&names, &self.lhs),
+            TPrettyPrinter(&names, &self.rhs),
+            indent = 2 * depth
+        )?;
+        match &self.error {
+            Some(e) => writeln!(f, "  -- failed: {e}")?,
+            None => writeln!(f)?,
+        }
+        for step in &self.steps {
+            match step {
+                UnifyStep::Subproblem(problem) => problem.fmt_indented(f, depth + 1)?,
+                UnifyStep::Solved(m, t) => writeln!(
+                    f,
+                    "{:indent$}?{m} := {}",
+                    "",
+                    TPrettyPrinter(&[], t),
+                    indent = 2 * depth + 2
+                )?,
+                UnifyStep::Postponed(m) => writeln!(
+                    f,
+                    "{:indent$}postponed until ?{m} is solved",
+                    "",
+                    indent = 2 * depth + 2
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn names(lvl: Lvl) -> Vec<Name> {
+    (0..lvl).map(|x| format!("x{x}").into()).collect()
+}
+
+/// One problem per line, each indented under the one it was decomposed from.
+impl Display for UnifyLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for problem in &self.problems {
+            problem.fmt_indented(f, 0)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,430 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{Debug, Display},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    check_axiom, check_definition, check_instance, check_mutual, check_opaque, check_pragmas,
+    data::{check_data, Data},
+    error::Error,
+    metas::{HoleReport, MetaCxt},
+    parser::parse_program_in,
+    pragma,
+    record::{check_record, Record},
+    span::{FileId, SourceMap},
+    Cxt, Decl, Definition, ElabOptions, Name, Program, Rc, Type, Value,
+};
+
+/// Dotted module name, `import A.B` loads `A/B.leonie`, and `import Prelude`
+/// the built-in [`PRELUDE`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ModulePath(pub Vec<Name>);
+
+impl ModulePath {
+    /// The module `A.B` of the file `A/B.leonie` under `root`, if it is one.
+    pub fn of_file(root: &Path, file: &Path) -> Option<ModulePath> {
+        let rel = file.strip_prefix(root).ok()?;
+        if rel.extension()? != "leonie" {
+            return None;
+        }
+        let rel = rel.with_extension("");
+        let path = rel.iter().map(|x| x.to_str().map(Name::from));
+        path.collect::<Option<_>>().map(ModulePath)
+    }
+}
+
+impl Display for ModulePath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut first = true;
+        for x in &self.0 {
+            if !first {
+                write!(f, ".")?;
+            }
+            write!(f, "{x}")?;
+            first = false;
+        }
+        Ok(())
+    }
+}
+
+/// Source of the built-in module `Prelude`, which `import Prelude` loads
+/// wherever the imports are resolved
+pub const PRELUDE: &str = include_str!("prelude.leonie");
+
+/// The source of the built-in module at `path`, if there is one. Files of the
+/// same name don't replace them.
+pub fn builtin_module(path: &ModulePath) -> Option<&'static str> {
+    match &path.0[..] {
+        [x] if &**x == "Prelude" => Some(PRELUDE),
+        _ => None,
+    }
+}
+
+/// Where a [`ModuleLoader`] reads the sources of the modules that aren't
+/// built in from
+pub trait SourceProvider: Debug {
+    /// The source of the module at `path`.
+    fn load(&self, path: &ModulePath) -> std::io::Result<Rc<str>>;
+}
+
+/// Reads `A/B.leonie` relative to a root directory for `import A.B`
+#[derive(Debug, Clone)]
+pub struct FileSystem {
+    pub root: PathBuf,
+}
+
+impl SourceProvider for FileSystem {
+    fn load(&self, path: &ModulePath) -> std::io::Result<Rc<str>> {
+        std::fs::read_to_string(module_file(&self.root, path)).map(Rc::from)
+    }
+}
+
+/// Sources held in memory, e.g. for embedding the elaborator where there
+/// are no files
+#[derive(Debug, Clone, Default)]
+pub struct MemorySources {
+    sources: HashMap<ModulePath, Rc<str>>,
+}
+
+impl MemorySources {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Give the module at `path` the source `src`, replacing the one it had.
+    pub fn insert(&mut self, path: ModulePath, src: impl Into<Rc<str>>) {
+        self.sources.insert(path, src.into());
+    }
+}
+
+impl SourceProvider for MemorySources {
+    fn load(&self, path: &ModulePath) -> std::io::Result<Rc<str>> {
+        self.sources.get(path).cloned().ok_or_else(|| {
+            let msg = format!("no source for the module {path}");
+            std::io::Error::new(std::io::ErrorKind::NotFound, msg)
+        })
+    }
+}
+
+/// The sources of `top` where it has them, and those of `base` otherwise, e.g.
+/// the unsaved buffers of an editor over the files
+#[derive(Debug, Clone)]
+pub struct Overlay<A, B> {
+    pub top: A,
+    pub base: B,
+}
+
+impl<A: SourceProvider, B: SourceProvider> SourceProvider for Overlay<A, B> {
+    fn load(&self, path: &ModulePath) -> std::io::Result<Rc<str>> {
+        self.top.load(path).or_else(|_| self.base.load(path))
+    }
+}
+
+/// A provider shared with what changes its sources, like the
+/// [`MemorySources`] of a session that modules are added to. Modules loaded
+/// already aren't loaded again once their sources change.
+impl<P: SourceProvider + ?Sized> SourceProvider for std::rc::Rc<RefCell<P>> {
+    fn load(&self, path: &ModulePath) -> std::io::Result<Rc<str>> {
+        self.borrow().load(path)
+    }
+}
+
+/// The file `A/B.leonie` of `import A.B` under `root`
+fn module_file(root: &Path, path: &ModulePath) -> PathBuf {
+    let mut file = root.to_path_buf();
+    for x in &path.0 {
+        file.push(&**x);
+    }
+    file.set_extension("leonie");
+    file
+}
+
+/// Elaborated module
+#[derive(Debug)]
+pub struct Module {
+    pub path: ModulePath,
+    pub defs: Vec<Definition>,
+    /// values and types of the module's own top-level definitions, in order
+    pub exports: Vec<(Name, Value, Type)>,
+    /// indices of the exports that are instances
+    pub instances: Vec<usize>,
+    /// the module's own data types
+    pub data: Vec<Rc<Data>>,
+    /// the module's own records
+    pub records: Vec<Rc<Record>>,
+    /// goals of the holes `?x` in the module
+    pub holes: Vec<HoleReport>,
+}
+
+#[derive(Debug)]
+pub enum ModuleError {
+    Io(PathBuf, std::io::Error),
+    /// parse or elaboration error in a file; [`Error`] is boxed, which keeps
+    /// this variant as small as the others
+    Check(PathBuf, Error),
+    Cycle(Vec<ModulePath>),
+}
+
+/// Resolves imports relative to a root directory, elaborating every module at
+/// most once. The sources are read from a [`SourceProvider`], the files under
+/// the root unless given another one, but for the built-in modules.
+#[derive(Debug)]
+pub struct ModuleLoader {
+    root: PathBuf,
+    provider: Box<dyn SourceProvider>,
+    modules: HashMap<ModulePath, Rc<Module>>,
+    /// modules currently being elaborated, used to detect import cycles
+    loading: Vec<ModulePath>,
+    /// sources of the files loaded, which the spans of their errors point into
+    sources: SourceMap,
+}
+
+impl ModuleLoader {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        let provider = FileSystem { root: root.clone() };
+        Self::with_provider(root, provider)
+    }
+
+    /// Loader reading the sources from `provider`. The files of the modules
+    /// are still named relative to `root`, for reporting errors.
+    pub fn with_provider(
+        root: impl Into<PathBuf>,
+        provider: impl SourceProvider + 'static,
+    ) -> Self {
+        Self {
+            root: root.into(),
+            provider: Box::new(provider),
+            modules: HashMap::new(),
+            loading: Vec::new(),
+            sources: SourceMap::new(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn sources(&self) -> &SourceMap {
+        &self.sources
+    }
+
+    /// Add the source of a file that isn't loaded as a module, e.g. the one
+    /// the imports are resolved for, to parse it with its spans in `sources`.
+    pub fn add_source(&mut self, file: &Path, src: impl Into<String>) -> FileId {
+        self.sources.add(file.display().to_string(), src)
+    }
+
+    /// The file of the module at `path`, `<builtin>/Prelude.leonie` for the
+    /// built-in ones.
+    pub fn resolve(&self, path: &ModulePath) -> PathBuf {
+        match builtin_module(path) {
+            Some(_) => module_file(Path::new("<builtin>"), path),
+            None => module_file(&self.root, path),
+        }
+    }
+
+    /// Load and elaborate a module and its dependencies with `options`, or
+    /// return the cached result.
+    pub fn load(
+        &mut self,
+        metas: &mut MetaCxt,
+        options: &ElabOptions,
+        path: &ModulePath,
+    ) -> Result<Rc<Module>, ModuleError> {
+        if let Some(module) = self.modules.get(path) {
+            return Ok(module.clone());
+        }
+
+        if self.loading.contains(path) {
+            let mut cycle = self.loading.clone();
+            cycle.push(path.clone());
+            return Err(ModuleError::Cycle(cycle));
+        }
+
+        let file = self.resolve(path);
+        let src = match builtin_module(path) {
+            Some(src) => Rc::from(src),
+            None => self
+                .provider
+                .load(path)
+                .map_err(|err| ModuleError::Io(file.clone(), err))?,
+        };
+        let id = self.add_source(&file, &*src);
+        let program = parse_program_in(id, self.sources.source(id))
+            .map_err(|errs| ModuleError::Check(file.clone(), Error::parse(errs)))?;
+
+        let from = metas.len();
+        self.loading.push(path.clone());
+        let mut cxt = Cxt::default();
+        cxt.set_options(options.clone());
+        let res = self.check_module(metas, &mut cxt, &file, program, None);
+        self.loading.pop();
+        let (defs, exports, instances) = res?;
+
+        let module = Rc::new(Module {
+            path: path.clone(),
+            defs,
+            exports,
+            instances,
+            data: cxt.data().to_vec(),
+            records: cxt.records().to_vec(),
+            holes: metas.take_holes(from),
+        });
+        self.modules.insert(path.clone(), module.clone());
+
+        Ok(module)
+    }
+
+    /// Bring the top-level definitions of the module at `path` into scope.
+    pub fn import(
+        &mut self,
+        metas: &mut MetaCxt,
+        cxt: &mut Cxt,
+        path: &ModulePath,
+    ) -> Result<(), ModuleError> {
+        let module = self.load(metas, cxt.options(), path)?;
+
+        let lvl = cxt.lvl();
+        for (x, val, ty) in &module.exports {
+            // opaque definitions and axioms stay so at the level they are
+            // imported at
+            if let Value::VGlued(x, def, _) = val {
+                if metas.axiom(def) {
+                    metas.set_axiom(x.clone(), def.clone(), cxt.lvl());
+                } else if metas.opaque(def).is_some() {
+                    metas.set_opaque(x.clone(), def.clone(), cxt.lvl());
+                }
+            }
+            cxt.define_global(x.clone(), val.clone(), ty.clone());
+        }
+        cxt.instances
+            .extend(module.instances.iter().map(|k| lvl + k));
+        for data in &module.data {
+            if !cxt.data.iter().any(|d| Rc::ptr_eq(d, data)) {
+                cxt.data.push(data.clone());
+            }
+        }
+        for record in &module.records {
+            if !cxt.records.iter().any(|r| Rc::ptr_eq(r, record)) {
+                cxt.records.push(record.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Elaborate a program whose imports are resolved by this loader. `file` is
+    /// only used for error reporting.
+    pub fn check_program(
+        &mut self,
+        metas: &mut MetaCxt,
+        cxt: &mut Cxt,
+        file: &Path,
+        program: Program,
+    ) -> Result<Vec<Definition>, ModuleError> {
+        // what the pragmas of the program set ends with it, even if it fails
+        let scope = cxt.begin_pragmas();
+        let res = self.check_module(metas, cxt, file, program, None);
+        cxt.end_pragmas(scope);
+        res.map(|(defs, _, _)| defs)
+    }
+
+    /// Like [`ModuleLoader::check_program`], but going on past the
+    /// declarations that fail, which are left out as if they weren't there,
+    /// with the errors of all of them.
+    pub fn check_program_recovering(
+        &mut self,
+        metas: &mut MetaCxt,
+        cxt: &mut Cxt,
+        file: &Path,
+        program: Program,
+    ) -> (Vec<Definition>, Vec<ModuleError>) {
+        let scope = cxt.begin_pragmas();
+        let mut errors = Vec::new();
+        let res = self.check_module(metas, cxt, file, program, Some(&mut errors));
+        cxt.end_pragmas(scope);
+        let (defs, _, _) = res.expect("errors are collected");
+        (defs, errors)
+    }
+
+    /// Elaborate the declarations of a module. With `errors`, a declaration
+    /// that fails is rolled back and its error collected instead.
+    #[allow(clippy::type_complexity)]
+    fn check_module(
+        &mut self,
+        metas: &mut MetaCxt,
+        cxt: &mut Cxt,
+        file: &Path,
+        program: Program,
+        mut errors: Option<&mut Vec<ModuleError>>,
+    ) -> Result<(Vec<Definition>, Vec<(Name, Value, Type)>, Vec<usize>), ModuleError> {
+        let mut defs = Vec::new();
+        let mut exports = Vec::new();
+        let mut instances = Vec::new();
+
+        for decl in program {
+            let from = cxt.lvl();
+            let snapshot = errors.is_some().then(|| (metas.snapshot(), cxt.snapshot()));
+            let (decl, scope) = check_pragmas(metas, cxt, decl);
+            let checked = match decl {
+                Decl::DDef(x, a, t) => check_definition(metas, cxt, x, a, t).map(|d| defs.push(d)),
+                Decl::DData(x, params, a, cons) => {
+                    check_data(metas, cxt, x, params, a, cons).map(|ds| defs.extend(ds))
+                }
+                Decl::DMutual(data, defs_) => {
+                    check_mutual(metas, cxt, data, defs_).map(|ds| defs.extend(ds))
+                }
+                Decl::DInstance(x, a, t) => {
+                    check_instance(metas, cxt, x, a, t).map(|d| defs.push(d))
+                }
+                Decl::DOpaque(x, a, t) => check_opaque(metas, cxt, x, a, t).map(|d| defs.push(d)),
+                Decl::DAxiom(x, a) => check_axiom(metas, cxt, x, a).map(|d| defs.push(d)),
+                Decl::DRecord(x, params, a, fields) => {
+                    check_record(metas, cxt, x, params, a, fields).map(|ds| defs.extend(ds))
+                }
+                Decl::DImport(path) => {
+                    let imported = self.import(metas, cxt, &path);
+                    cxt.end_pragmas(scope);
+                    // the modules loaded before the failure stay loaded, and
+                    // their metas with them
+                    match (imported, &mut errors) {
+                        (Err(err), Some(errors)) => errors.push(err),
+                        (imported, _) => imported?,
+                    }
+                    continue;
+                }
+                Decl::DPragma(..) => unreachable!("interpreted by check_pragmas"),
+                // which holds for the rest of the module
+                Decl::DFilePragma(p) => {
+                    pragma(metas, cxt, &p);
+                    continue;
+                }
+            };
+            cxt.end_pragmas(scope);
+            if let Err(err) = checked {
+                let err = ModuleError::Check(file.to_path_buf(), err);
+                let (Some(errors), Some((snapshot, cxt_snapshot))) = (&mut errors, snapshot) else {
+                    return Err(err);
+                };
+                metas.rollback(snapshot);
+                cxt.rollback(cxt_snapshot);
+                errors.push(err);
+                continue;
+            }
+
+            for lvl in from..cxt.lvl() {
+                if cxt.instances().contains(&lvl) {
+                    instances.push(exports.len());
+                }
+                let (x, ty) = cxt.types()[lvl].clone();
+                exports.push((x, cxt.env()[lvl].clone(), ty));
+            }
+        }
+
+        Ok((defs, exports, instances))
+    }
+}
@@ -1,8 +1,12 @@
-use std::{collections::HashSet, ops::Range, rc::Rc};
+use std::{collections::HashSet, fmt::Display, ops::Range};
 
-use chumsky::{prelude::*, BoxStream, Flat};
+use chumsky::{prelude::*, recovery::Strategy, BoxStream, Flat};
 
-use crate::Raw;
+use crate::{
+    modules::ModulePath,
+    span::{FileId, Span},
+    Decl, Icit, Name, Pattern, Pragma, Program, Raw, Rc,
+};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Token {
@@ -10,21 +14,47 @@ pub enum Token {
     Close(Delim),
     Ctrl(&'static str),
     Var(String),
+    Nat(u64),
+    Str(String),
+    Char(char),
+    Int(i64),
 }
 
-type Span = Range<usize>;
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Open(Delim::Paren) => write!(f, "("),
+            Token::Open(Delim::Brace) => write!(f, "{{"),
+            Token::Open(Delim::Block) => write!(f, "indented block"),
+            Token::Close(Delim::Paren) => write!(f, ")"),
+            Token::Close(Delim::Brace) => write!(f, "}}"),
+            Token::Close(Delim::Block) => write!(f, "end of block"),
+            Token::Ctrl("\n") => write!(f, "newline"),
+            Token::Ctrl(ctrl) => write!(f, "{ctrl}"),
+            Token::Var(x) => write!(f, "{x}"),
+            Token::Nat(n) => write!(f, "{n}"),
+            Token::Str(s) => write!(f, "{s:?}"),
+            Token::Char(c) => write!(f, "{c:?}"),
+            Token::Int(n) => write!(f, "{n}"),
+        }
+    }
+}
+
+/// Syntax error, with the span of the unexpected token
+pub type ParseError = Simple<Token, Span>;
 
 // Represents the different kinds of delimiters we care about
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Delim {
     Paren,
+    Brace,
     Block,
 }
 
 #[derive(Debug)]
 enum TokenTree {
     Token(Token),
-    Tree(Delim, Vec<(TokenTree, Span)>),
+    Tree(Delim, Vec<(TokenTree, Range<usize>)>),
 }
 
 #[must_use]
@@ -39,35 +69,99 @@ pub fn ident<C: chumsky::text::Character, E: chumsky::Error<C>>(
         .collect()
 }
 
-fn lexer() -> impl Parser<char, Vec<(TokenTree, Span)>, Error = Simple<char>> {
+fn lexer() -> impl Parser<char, Vec<(TokenTree, Range<usize>)>, Error = Simple<char>> {
     let tt = recursive(|tt| {
         // Define some atomic tokens
         let ident = ident().map(Token::Var);
         let ctrl = just("->")
+            .or(just("#!["))
+            .or(just("#["))
+            .or(just("]"))
+            .or(just("→"))
+            .or(just("=>"))
             .or(just("<-"))
             .or(just("=="))
             .or(just(":="))
             .or(just("λ"))
             .or(just("Π"))
+            .or(just(".1"))
+            .or(just(".2"))
             .or(just("."))
             .or(just("\\"))
             .or(just("_"))
+            .or(just("?"))
+            .or(just(","))
+            .or(just("×"))
+            .or(just("*"))
             .or(just(":"))
             .or(just("\n"))
             .or(just("="))
+            .or(just("|"))
             .map(Token::Ctrl);
 
-        let single_token = ctrl.or(ident).map(TokenTree::Token);
+        let nat = text::int(10).try_map(|n: String, span| {
+            n.parse()
+                .map(Token::Nat)
+                .map_err(|_| Simple::custom(span, "number literal too large"))
+        });
+
+        let int = just('-')
+            .chain::<char, String, _>(text::int(10))
+            .collect::<String>()
+            .try_map(|n: String, span| {
+                n.parse()
+                    .map(Token::Int)
+                    .map_err(|_| Simple::custom(span, "integer literal too large"))
+            });
+
+        // `\n`, `\t`, `\\`, `\"` and `\'` in string and character literals
+        let escape = just('\\').ignore_then(choice((
+            just('n').to('\n'),
+            just('t').to('\t'),
+            just('\\'),
+            just('"'),
+            just('\''),
+        )));
+        let str = filter(|c: &char| *c != '\\' && *c != '"' && *c != '\n')
+            .or(escape)
+            .repeated()
+            .delimited_by(just('"'), just('"'))
+            .collect::<String>()
+            .map(Token::Str);
+        let char = filter(|c: &char| *c != '\\' && *c != '\'' && *c != '\n')
+            .or(escape)
+            .delimited_by(just('\''), just('\''))
+            .map(Token::Char);
+
+        let single_token = ctrl
+            .or(ident)
+            .or(nat)
+            .or(int)
+            .or(str)
+            .or(char)
+            .map(TokenTree::Token);
 
         // Tokens surrounded by parentheses get turned into parenthesised token trees
         let token_tree = tt
+            .clone()
             .padded()
             .repeated()
             .delimited_by(just('('), just(')'))
             .map(|tts| TokenTree::Tree(Delim::Paren, tts));
 
+        // Tokens surrounded by braces are implicit binders or arguments
+        let brace_tree = tt
+            .padded()
+            .repeated()
+            .delimited_by(just('{'), just('}'))
+            .map(|tts| TokenTree::Tree(Delim::Brace, tts));
+
         single_token
             .or(token_tree)
+            .or(brace_tree)
+            // skip characters that start no token, but not the ends of lines
+            // and trees
+            .recover_with(skip_then_retry_until(['\n', ')', '}']))
             .map_with_span(|tt, span| (tt, span))
     });
 
@@ -79,109 +173,594 @@ fn lexer() -> impl Parser<char, Vec<(TokenTree, Span)>, Error = Simple<char>> {
 /// Flatten a series of token trees into a single token stream, ready for feeding into the main parser
 fn tts_to_stream(
     eoi: Span,
-    token_trees: Vec<(TokenTree, Span)>,
+    token_trees: Vec<(TokenTree, Range<usize>)>,
+    span: impl Fn(Range<usize>) -> Span + 'static,
 ) -> BoxStream<'static, Token, Span> {
     use std::iter::once;
 
-    BoxStream::from_nested(eoi, token_trees.into_iter(), |(tt, span)| match tt {
+    BoxStream::from_nested(eoi, token_trees.into_iter(), move |(tt, range)| match tt {
         // Single tokens remain unchanged
-        TokenTree::Token(token) => Flat::Single((token, span)),
+        TokenTree::Token(token) => Flat::Single((token, span(range))),
         // Nested token trees get flattened into their inner contents, surrounded by `Open` and `Close` tokens
         TokenTree::Tree(delim, tree) => Flat::Many(
-            once((TokenTree::Token(Token::Open(delim)), span.clone()))
+            once((TokenTree::Token(Token::Open(delim)), range.clone()))
                 .chain(tree.into_iter())
-                .chain(once((TokenTree::Token(Token::Close(delim)), span))),
+                .chain(once((TokenTree::Token(Token::Close(delim)), range))),
         ),
     })
 }
 
-pub fn parse(input: &str) -> Result<Option<Raw>, Vec<Simple<Token>>> {
-    let tts = lexer().parse(input).unwrap();
+/// Blank out `-- line comments` and `{- nested block comments -}`, keeping
+/// the newlines and the number of characters so that spans stay the same.
+/// Like in Haskell, `{-` always opens a comment, so a negative literal in
+/// braces needs a space: `{ -1}`.
+pub(crate) fn strip_comments(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+    let mut prev = ' ';
+    let blank = |c: char| if c == '\n' { '\n' } else { ' ' };
+
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('-', Some('-')) => {
+                out.push(' ');
+                while let Some(c) = chars.next_if(|c| *c != '\n') {
+                    out.push(blank(c));
+                }
+            }
+            ('{', Some('-')) => {
+                chars.next();
+                out.push_str("  ");
+                let mut depth = 1;
+                while let Some(c) = chars.next() {
+                    let delim = match (c, chars.peek()) {
+                        ('{', Some('-')) => Some(1),
+                        ('-', Some('}')) => Some(-1),
+                        _ => None,
+                    };
+                    let Some(delim) = delim else {
+                        out.push(blank(c));
+                        continue;
+                    };
+                    chars.next();
+                    out.push_str("  ");
+                    depth += delim;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+            }
+            // literals are copied as they are; a quote after an identifier
+            // is a prime
+            ('"', _) | ('\'', _) if !(prev.is_alphanumeric() || prev == '_' || prev == '\'') => {
+                out.push(c);
+                while let Some(d) = chars.next_if(|d| *d != '\n') {
+                    out.push(d);
+                    if d == '\\' {
+                        out.extend(chars.next_if(|d| *d != '\n'));
+                    } else if d == c {
+                        break;
+                    }
+                }
+            }
+            _ => out.push(c),
+        }
+        prev = out.chars().next_back().unwrap_or(' ');
+    }
+
+    out
+}
+
+/// The tokens of `input` in `file`, along with the errors of the characters
+/// that had to be skipped. Spans start at `offset`, for input that is part of
+/// a larger source.
+fn token_stream(
+    file: FileId,
+    input: &str,
+    offset: usize,
+) -> (BoxStream<'static, Token, Span>, Vec<ParseError>) {
+    let span = move |range: Range<usize>| Span::new(file, range.start + offset..range.end + offset);
+    let (tts, errors) = lexer().parse_recovery(strip_comments(input).as_str());
+    let errors = errors
+        .into_iter()
+        .map(|e| {
+            let msg = match e.found() {
+                Some(c) => format!("unexpected character {c:?}"),
+                None => "unexpected end of input".to_string(),
+            };
+            Simple::custom(span(e.span()), msg)
+        })
+        .collect();
 
     // Next, flatten
-    let eoi = 0..input.chars().count();
-    let token_stream = tts_to_stream(eoi, tts);
+    let eoi = span(0..input.chars().count());
+    (tts_to_stream(eoi, tts.unwrap_or_default(), span), errors)
+}
 
-    // At this point, we have a token stream that can be fed into the main parser! Because this is just an example,
-    // we're instead going to just collect the token stream into a vector and print it.
+pub fn parse(input: &str) -> Result<Option<Raw>, Vec<ParseError>> {
+    match parse_recovering(FileId::default(), input) {
+        (raw, errors) if errors.is_empty() => Ok(raw),
+        (_, errors) => Err(errors),
+    }
+}
 
-    //let flattened_trees = token_stream.fetch_tokens().collect::<Vec<_>>();
+/// Parse a term of `file`, recovering from syntax errors: the parts that
+/// failed to parse are holes in the term, and every error is returned.
+pub fn parse_recovering(file: FileId, input: &str) -> (Option<Raw>, Vec<ParseError>) {
+    let (token_stream, mut errors) = token_stream(file, input, 0);
+    let (raw, parse_errors) = parse_block().parse_recovery(token_stream);
+    errors.extend(parse_errors);
+    (raw, errors)
+}
 
-    let parser = parse_block();
-    let (raw, errors) = parser.parse_recovery(token_stream);
+/// Parse a whole source file of top-level declarations
+pub fn parse_program(input: &str) -> Result<Program, Vec<ParseError>> {
+    parse_program_in(FileId::default(), input)
+}
 
-    if !errors.is_empty() {
-        return Err(errors);
+/// Like [`parse_program`], with the spans pointing into `file`.
+pub fn parse_program_in(file: FileId, input: &str) -> Result<Program, Vec<ParseError>> {
+    match parse_program_recovering(file, input) {
+        (program, errors) if errors.is_empty() => Ok(program),
+        (_, errors) => Err(errors),
     }
+}
 
-    Ok(raw)
+/// Parse a source file, recovering from syntax errors: the declarations that
+/// failed to parse are skipped, and every error is returned.
+pub fn parse_program_recovering(file: FileId, input: &str) -> (Program, Vec<ParseError>) {
+    parse_program_at(file, input, 0)
 }
 
-pub fn parse_block() -> impl Parser<Token, Raw, Error = Simple<Token>> {
-    let keywords = HashSet::from(["let", "U"]);
+/// Like [`parse_program_recovering`], for the part of a source file that
+/// starts at the `offset`th character.
+pub(crate) fn parse_program_at(
+    file: FileId,
+    input: &str,
+    offset: usize,
+) -> (Program, Vec<ParseError>) {
+    let (token_stream, mut errors) = token_stream(file, input, offset);
+    let (program, parse_errors) = program().parse_recovery(token_stream);
+    errors.extend(parse_errors);
+    (program.unwrap_or_default(), errors)
+}
 
+fn program() -> impl Parser<Token, Program, Error = ParseError> {
+    let newlines = just(Token::Ctrl("\n")).repeated();
+
+    newlines
+        .clone()
+        .ignore_then(
+            parse_decl()
+                // retry at the next token, until the next declaration
+                .recover_with(skip_then_retry_until([]))
+                .then_ignore(newlines)
+                .repeated(),
+        )
+        .then_ignore(end())
+}
+
+pub fn parse_decl() -> impl Parser<Token, Decl, Error = ParseError> {
+    recursive(|p_decl| {
+        let ctrl = |ctrl: &'static str| just(Token::Ctrl(ctrl));
+
+        let p_def = just(Token::Var("def".to_string()))
+            .ignore_then(p_ident())
+            .then_ignore(ctrl(":"))
+            .then(parse_block())
+            .then_ignore(ctrl(":="))
+            .then(parse_block())
+            .map(|((x, a), t)| Decl::DDef(x, a, t));
+        let p_instance = just(Token::Var("instance".to_string()))
+            .ignore_then(p_ident())
+            .then_ignore(ctrl(":"))
+            .then(parse_block())
+            .then_ignore(ctrl(":="))
+            .then(parse_block())
+            .map(|((x, a), t)| Decl::DInstance(x, a, t));
+        let p_opaque = just(Token::Var("opaque".to_string()))
+            .ignore_then(p_ident())
+            .then_ignore(ctrl(":"))
+            .then(parse_block())
+            .then_ignore(ctrl(":="))
+            .then(parse_block())
+            .map(|((x, a), t)| Decl::DOpaque(x, a, t));
+        let p_axiom = just(Token::Var("axiom".to_string()))
+            .ignore_then(p_ident())
+            .then_ignore(ctrl(":"))
+            .then(parse_block())
+            .map(|(x, a)| Decl::DAxiom(x, a));
+        let p_import = just(Token::Var("import".to_string()))
+            .ignore_then(p_ident().separated_by(ctrl(".")).at_least(1))
+            .map(|path| Decl::DImport(ModulePath(path)));
+
+        let newlines = ctrl("\n").repeated();
+        let p_param = p_ident()
+            .then_ignore(ctrl(":"))
+            .then(parse_block())
+            .delimited_by(
+                just(Token::Open(Delim::Paren)),
+                just(Token::Close(Delim::Paren)),
+            );
+        let p_con = newlines
+            .clone()
+            .ignore_then(ctrl("|"))
+            .ignore_then(p_ident())
+            .then_ignore(ctrl(":"))
+            .then(parse_block());
+        let p_cons = p_con
+            .clone()
+            .repeated()
+            .then_ignore(newlines.clone())
+            .delimited_by(
+                just(Token::Open(Delim::Block)),
+                just(Token::Close(Delim::Block)),
+            )
+            .or(p_con.repeated());
+        let p_data = just(Token::Var("data".to_string()))
+            .ignore_then(p_ident())
+            .then(p_param.clone().repeated())
+            .then_ignore(ctrl(":"))
+            .then(parse_block())
+            .then(p_cons.clone())
+            .map(|(((x, params), a), cons)| Decl::DData(x, params, a, cons));
+        let p_record = just(Token::Var("record".to_string()))
+            .ignore_then(p_ident())
+            .then(p_param.repeated())
+            .then_ignore(ctrl(":"))
+            .then(parse_block())
+            .then(p_cons)
+            .map(|(((x, params), a), fields)| Decl::DRecord(x, params, a, fields));
+
+        let p_mutual = just(Token::Var("mutual".to_string()))
+            .ignore_then(
+                newlines
+                    .clone()
+                    .ignore_then(p_decl.then_ignore(newlines).repeated())
+                    .delimited_by(
+                        just(Token::Open(Delim::Block)),
+                        just(Token::Close(Delim::Block)),
+                    ),
+            )
+            .try_map(|decls, span| {
+                let mut data = Vec::new();
+                let mut defs = Vec::new();
+                for decl in decls {
+                    match decl {
+                        Decl::DData(x, params, a, cons) => data.push((x, params, a, cons)),
+                        Decl::DDef(x, a, t) => defs.push((x, a, t)),
+                        _ => {
+                            let msg = "only data types and definitions can be mutually recursive";
+                            return Err(Simple::custom(span, msg));
+                        }
+                    }
+                }
+                Ok(Decl::DMutual(data, defs))
+            });
+
+        // `name(arg, ..)` of a pragma
+        let p_pragma_body = p_ident()
+            .map_with_span(|name, pos| (name, pos))
+            .then(
+                p_ident()
+                    .separated_by(ctrl(","))
+                    .delimited_by(
+                        just(Token::Open(Delim::Paren)),
+                        just(Token::Close(Delim::Paren)),
+                    )
+                    .or_not(),
+            )
+            .map(|((name, pos), args)| (name, args.unwrap_or_default(), pos));
+        let p_pragma = ctrl("#[")
+            .ignore_then(p_pragma_body.clone())
+            .then_ignore(ctrl("]"))
+            .then_ignore(newlines.clone())
+            .then(p_decl.clone())
+            .map_with_span(|((name, args, pos), decl), scope| {
+                let p = Pragma {
+                    name,
+                    args,
+                    pos,
+                    scope,
+                };
+                Decl::DPragma(p, Box::new(decl))
+            });
+        let p_file_pragma = ctrl("#![")
+            .ignore_then(p_pragma_body)
+            .then_ignore(ctrl("]"))
+            .map(|(name, args, pos): (_, _, Span)| {
+                let scope = Span::new(pos.file, 0..usize::MAX);
+                Decl::DFilePragma(Pragma {
+                    name,
+                    args,
+                    pos,
+                    scope,
+                })
+            });
+
+        p_def
+            .or(p_pragma)
+            .or(p_file_pragma)
+            .or(p_instance)
+            .or(p_opaque)
+            .or(p_axiom)
+            .or(p_import)
+            .or(p_data)
+            .or(p_record)
+            .or(p_mutual)
+    })
+}
+
+fn p_ident() -> impl Parser<Token, Name, Error = ParseError> + Clone {
+    let keywords = HashSet::from([
+        "let", "def", "import", "if", "then", "else", "data", "match", "with", "record", "rec",
+        "mutual", "instance", "opaque", "axiom",
+    ]);
+
+    select! { Token::Var(name) if !keywords.contains(name.as_str()) && universe(&name).is_none() && !name.as_str().starts_with('_') => Into::<Rc<str>>::into(name) }
+}
+
+/// `U` is a universe of an inferred level, `U0`, `U1`, … of a given one
+fn universe(name: &str) -> Option<Option<u32>> {
+    match name.strip_prefix('U')? {
+        "" => Some(None),
+        n if n.chars().all(|c| c.is_ascii_digit()) => n.parse().ok().map(Some),
+        _ => None,
+    }
+}
+
+/// `{{t}}`, an instance binder or argument
+fn double_braces<O>(
+    p: impl Parser<Token, O, Error = ParseError> + Clone,
+) -> impl Parser<Token, O, Error = ParseError> + Clone {
+    braces(braces(p))
+}
+
+fn braces<O>(
+    p: impl Parser<Token, O, Error = ParseError> + Clone,
+) -> impl Parser<Token, O, Error = ParseError> + Clone {
+    p.delimited_by(
+        just(Token::Open(Delim::Brace)),
+        just(Token::Close(Delim::Brace)),
+    )
+}
+
+/// Recover from an error inside the delimiters `delim` by skipping to the
+/// matching closing one, leaving a hole in place of what they enclose.
+fn recover_delimited(delim: Delim) -> impl Strategy<Token, Raw, ParseError> + Clone {
+    let others = match delim {
+        Delim::Paren => [Delim::Brace, Delim::Block],
+        Delim::Brace => [Delim::Paren, Delim::Block],
+        Delim::Block => [Delim::Paren, Delim::Brace],
+    }
+    .map(|other| (Token::Open(other), Token::Close(other)));
+    nested_delimiters(Token::Open(delim), Token::Close(delim), others, |span| {
+        Raw::RSrcPos(span, Raw::RHole(None).into())
+    })
+}
+
+/// The binders of groups `(x y : A)`, one by one
+fn binders(groups: Vec<(Vec<Name>, Icit, Raw)>) -> Vec<(Name, Icit, Raw)> {
+    groups
+        .into_iter()
+        .flat_map(|(xs, i, a)| xs.into_iter().map(move |x| (x, i, a.clone())))
+        .collect()
+}
+
+pub fn parse_block() -> impl Parser<Token, Raw, Error = ParseError> + Clone {
     let ctrl = |ctrl: &'static str| just(Token::Ctrl(ctrl));
-    let p_ident = select! { Token::Var(name) if !keywords.contains(name.as_str()) && !name.as_str().starts_with('_') => Into::<Rc<str>>::into(name) };
+    let p_ident = p_ident();
     let p_var = p_ident.clone().map(Raw::RVar);
-    let p_hole = ctrl("_").map(|_| Raw::RHole);
-    let p_u = select! { Token::Var(name) if name.as_str() == "U" => Raw::RU };
+    let p_hole = ctrl("_").to(Raw::RHole(None)).or(ctrl("?")
+        .ignore_then(p_ident.clone().or_not())
+        .map(Raw::RHole));
+    let p_nat = select! { Token::Nat(n) => Raw::RNatLit(n) };
+    let p_str = select! {
+        Token::Str(s) => Raw::RStrLit(s.into()),
+        Token::Char(c) => Raw::RCharLit(c),
+        Token::Int(n) => Raw::RIntLit(n),
+    };
+    let p_u = select! { Token::Var(name) if universe(&name).is_some() => Raw::RU(universe(&name).flatten()) };
     let p_binder = p_ident.clone().or(ctrl("_").map(|_| "_".into()));
 
     let mut p_raw = Recursive::declare();
 
+    let p_record = just(Token::Var("record".to_string()))
+        .ignore_then(braces(
+            p_ident
+                .clone()
+                .then_ignore(ctrl("="))
+                .then(p_raw.clone())
+                .separated_by(ctrl(",")),
+        ))
+        .map(Raw::RRecordLit);
     let p_atom = p_var
+        .or(p_record)
         .or(p_u)
+        .or(p_nat)
+        .or(p_str)
         .or(p_hole)
-        .or(p_raw.clone().delimited_by(
-            just(Token::Open(Delim::Block)),
-            just(Token::Close(Delim::Block)),
-        ))
-        .or(p_raw.clone().delimited_by(
-            just(Token::Open(Delim::Paren)),
-            just(Token::Close(Delim::Paren)),
-        ));
-    let p_spine = p_atom.clone().then(p_atom.repeated()).map(|(head, spine)| {
-        spine
-            .into_iter()
-            .fold(head, |acc, arg| Raw::RApp(acc.into(), arg.into()))
+        .or(p_raw
+            .clone()
+            .delimited_by(
+                just(Token::Open(Delim::Block)),
+                just(Token::Close(Delim::Block)),
+            )
+            .recover_with(recover_delimited(Delim::Block)))
+        // `(a, b, c)` is `(a, (b, c))`
+        .or(p_raw
+            .clone()
+            .separated_by(ctrl(","))
+            .at_least(1)
+            .delimited_by(
+                just(Token::Open(Delim::Paren)),
+                just(Token::Close(Delim::Paren)),
+            )
+            .map(|mut ts| {
+                let last = ts.pop().unwrap();
+                ts.into_iter()
+                    .rev()
+                    .fold(last, |u, t| Raw::RPair(t.into(), u.into()))
+            })
+            .recover_with(recover_delimited(Delim::Paren)));
+    let p_proj = ctrl(".1")
+        .to(None)
+        .or(ctrl(".2").to(Some(None)))
+        .or(ctrl(".")
+            .ignore_then(p_ident.clone())
+            .map(|f| Some(Some(f))));
+    let p_atom = p_atom.then(p_proj.repeated()).foldl(|t, proj| match proj {
+        None => Raw::RProj1(t.into()),
+        Some(None) => Raw::RProj2(t.into()),
+        Some(Some(f)) => Raw::RField(t.into(), f),
     });
+    let p_arg = double_braces(p_raw.clone().map(|t| (None, t, Icit::Inst)))
+        .or(braces(
+            p_ident
+                .clone()
+                .then_ignore(ctrl("="))
+                .then(p_raw.clone())
+                .map(|(x, t)| (Some(x), t, Icit::Impl)),
+        ))
+        .or(braces(p_raw.clone().map(|t| (None, t, Icit::Impl))))
+        .or(p_atom.clone().map(|t| (None, t, Icit::Expl)));
+    let p_spine = p_atom
+        .then(p_arg.repeated())
+        .foldl(|acc, (x, arg, i)| match x {
+            Some(x) => Raw::RAppNamed(acc.into(), x, arg.into()),
+            None => Raw::RApp(acc.into(), arg.into(), i),
+        });
 
     let p_arrow_r = ctrl("→").or(ctrl("->"));
+    let p_times = ctrl("×").or(ctrl("*"));
 
     let fun_or_spine = p_spine
-        .then(p_arrow_r.clone().ignore_then(p_raw.clone()).or_not())
+        .then(
+            p_arrow_r
+                .clone()
+                .to(true)
+                .or(p_times.clone().to(false))
+                .then(p_raw.clone())
+                .or_not(),
+        )
         .map(|(x, y)| match y {
-            Some(y) => Raw::RPi("_".into(), x.into(), y.into()),
+            Some((true, y)) => Raw::RPi("_".into(), Icit::Expl, x.into(), y.into()),
+            Some((false, y)) => Raw::RSigma("_".into(), x.into(), y.into()),
             None => x,
         });
 
+    // `λ x {y}. t` is `λ x. λ {y}. t`
     let p_lam = ctrl("λ")
-        .ignore_then(p_binder.clone())
+        .ignore_then(
+            p_binder
+                .clone()
+                .map(|x| (x, Icit::Expl))
+                .or(double_braces(p_binder.clone()).map(|x| (x, Icit::Inst)))
+                .or(braces(p_binder.clone()).map(|x| (x, Icit::Impl)))
+                .repeated()
+                .at_least(1),
+        )
         .then_ignore(ctrl("."))
         .then(p_raw.clone())
-        .map(|(x, t)| Raw::RLam(x, t.into()));
+        .foldr(|(x, i), t| Raw::RLam(x, i, t.into()));
     let p_let = just(Token::Var("let".to_string()))
-        .ignore_then(p_binder.clone())
+        .ignore_then(just(Token::Var("rec".to_string())).or_not())
+        .then(p_binder.clone())
         .then_ignore(ctrl(":"))
         .then(p_raw.clone())
         .then_ignore(ctrl(":="))
-        .then(p_raw.clone())
+        // skip to the end of a definition that doesn't parse, keeping the body
+        .then(
+            p_raw
+                .clone()
+                .recover_with(skip_then_retry_until([Token::Ctrl("\n")])),
+        )
         .then_ignore(ctrl("\n"))
         .then(p_raw.clone())
-        .map(|(((x, e1), e2), e3)| Raw::RLet(x, e1.into(), e2.into(), e3.into()));
-    let p_pi = p_binder
-        .then_ignore(ctrl(":"))
+        .map(|((((rec, x), e1), e2), e3)| match rec {
+            Some(_) => Raw::RLetRec(x, e1.into(), e2.into(), e3.into()),
+            None => Raw::RLet(x, e1.into(), e2.into(), e3.into()),
+        });
+    let keyword = |x: &str| just(Token::Var(x.to_string()));
+    let p_if = keyword("if")
+        .ignore_then(p_raw.clone())
+        .then_ignore(keyword("then"))
+        .then(p_raw.clone())
+        .then_ignore(keyword("else"))
         .then(p_raw.clone())
-        .delimited_by(
-            just(Token::Open(Delim::Paren)),
-            just(Token::Close(Delim::Paren)),
+        .map(|((b, t), f)| Raw::RIf(b.into(), t.into(), f.into()));
+    let p_pattern = ctrl("_").to(Pattern::PWild).or(p_ident
+        .clone()
+        .then(
+            p_binder
+                .clone()
+                .map(|x| (x, Icit::Expl))
+                .or(braces(p_binder.clone()).map(|x| (x, Icit::Impl)))
+                .repeated(),
+        )
+        .map(|(c, xs)| Pattern::PCon(c, xs)));
+    let p_branch = ctrl("\n")
+        .repeated()
+        .ignore_then(ctrl("|"))
+        .ignore_then(p_pattern)
+        .then_ignore(ctrl("=>"))
+        .then(p_raw.clone());
+    let p_match = keyword("match")
+        .ignore_then(p_raw.clone())
+        .then_ignore(keyword("with"))
+        .then(
+            p_branch
+                .clone()
+                .repeated()
+                .then_ignore(ctrl("\n").repeated())
+                .delimited_by(
+                    just(Token::Open(Delim::Block)),
+                    just(Token::Close(Delim::Block)),
+                )
+                .or(p_branch.repeated()),
+        )
+        .map(|(t, branches)| Raw::RMatch(t.into(), branches));
+    // `x y : A`, the binders of a group of the same type
+    let p_binder_ty = p_binder
+        .repeated()
+        .at_least(1)
+        .then_ignore(ctrl(":"))
+        .then(p_raw.clone());
+    let p_paren_binder_ty = p_binder_ty.clone().delimited_by(
+        just(Token::Open(Delim::Paren)),
+        just(Token::Close(Delim::Paren)),
+    );
+    // `(A : U) {B : U} → A → B` is `(A : U) → {B : U} → A → B`, the way the
+    // printers write it, and `(x y : A) → B` is `(x : A) → (y : A) → B`
+    let p_pi_binder = p_paren_binder_ty
+        .clone()
+        .map(|(xs, a)| (xs, Icit::Expl, a))
+        .or(double_braces(
+            p_binder_ty
+                .clone()
+                .or(p_raw.clone().map(|a| (vec!["_".into()], a))),
         )
+        .map(|(xs, a)| (xs, Icit::Inst, a)))
+        .or(braces(p_binder_ty).map(|(xs, a)| (xs, Icit::Impl, a)));
+    let p_pi = p_pi_binder
+        .repeated()
+        .at_least(1)
         .then_ignore(p_arrow_r)
         .then(p_raw.clone())
-        .map(|((x, a), b)| Raw::RPi(x, a.into(), b.into()));
+        .map(|(groups, b)| (binders(groups), b))
+        .foldr(|(x, i, a), b| Raw::RPi(x, i, a.into(), b.into()))
+        .or(p_paren_binder_ty
+            .then_ignore(p_times)
+            .then(p_raw.clone())
+            .map(|((xs, a), b)| {
+                let xs = xs.into_iter().rev();
+                xs.fold(b, |b, x| Raw::RSigma(x, a.clone().into(), b.into()))
+            }));
 
     p_raw.define(
         p_let
+            .or(p_if)
+            .or(p_match)
             .or(p_lam)
             .or(p_pi)
             .or(fun_or_spine)
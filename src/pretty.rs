@@ -0,0 +1,170 @@
+//! Wadler-style layout of printed terms.
+//!
+//! A [`Doc`] is text with places where it may break across lines. Each group
+//! is laid out on one line when it fits in the width that is left, and with
+//! all of its own line breaks taken otherwise; nested groups get the same
+//! choice again.
+
+use std::fmt::Write;
+
+/// How many columns the broken lines of a block are indented by
+pub const INDENT: usize = 2;
+
+#[derive(Debug, Clone)]
+pub enum Doc {
+    Text(String),
+    /// a space, or a line break when the group is broken
+    Line,
+    /// nothing, or a line break when the group is broken
+    Break,
+    /// a line break, broken group or not
+    HardLine,
+    /// the line breaks inside indented further by this many columns
+    Nest(usize, Box<Doc>),
+    Group(Box<Doc>),
+    Concat(Vec<Doc>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Flat,
+    Broken,
+}
+
+impl Doc {
+    /// Lay the document out in lines of at most `width` columns where
+    /// possible.
+    pub fn render(&self, width: usize, out: &mut dyn Write) -> std::fmt::Result {
+        let mut col = 0;
+        let mut stack = vec![(0, Mode::Broken, self)];
+
+        while let Some((indent, mode, doc)) = stack.pop() {
+            match doc {
+                Doc::Text(s) => {
+                    out.write_str(s)?;
+                    col += s.chars().count();
+                }
+                Doc::Line if mode == Mode::Flat => {
+                    out.write_char(' ')?;
+                    col += 1;
+                }
+                Doc::Break if mode == Mode::Flat => {}
+                Doc::Line | Doc::Break | Doc::HardLine => {
+                    write!(out, "\n{:indent$}", "")?;
+                    col = indent;
+                }
+                Doc::Nest(n, doc) => stack.push((indent + n, mode, doc)),
+                // a group that starts past the width would only be broken
+                // into short pieces
+                Doc::Group(doc) => {
+                    let flat = mode == Mode::Flat || col >= width;
+                    let mode = match flat || fits(width - col, doc) {
+                        true => Mode::Flat,
+                        false => Mode::Broken,
+                    };
+                    stack.push((indent, mode, doc));
+                }
+                Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|doc| (indent, mode, doc))),
+            }
+        }
+        Ok(())
+    }
+
+    /// The document on as few lines as possible.
+    pub fn flat(&self) -> String {
+        let mut out = String::new();
+        self.render(usize::MAX, &mut out).unwrap();
+        out
+    }
+}
+
+/// Whether `doc` fits in `width` columns laid out flat, up to its first
+/// hard line break. What follows it is left for the groups after it to
+/// break.
+fn fits(width: usize, doc: &Doc) -> bool {
+    let mut width = Some(width);
+    let mut stack = vec![doc];
+
+    while let Some(doc) = stack.pop() {
+        match doc {
+            Doc::Text(s) => width = width.and_then(|w| w.checked_sub(s.chars().count())),
+            Doc::Line => width = width.and_then(|w| w.checked_sub(1)),
+            Doc::Break => {}
+            Doc::HardLine => return true,
+            Doc::Nest(_, doc) | Doc::Group(doc) => stack.push(doc),
+            Doc::Concat(docs) => stack.extend(docs.iter().rev()),
+        }
+        if width.is_none() {
+            return false;
+        }
+    }
+    true
+}
+
+/// Builds a [`Doc`] from text written to it, with `write!`, and the line
+/// breaks, groups and nesting between.
+#[derive(Debug, Default)]
+pub struct DocBuilder {
+    /// the documents of the groups and nestings not finished yet, outermost
+    /// first
+    stack: Vec<Vec<Doc>>,
+    docs: Vec<Doc>,
+}
+
+impl DocBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn line(&mut self) {
+        self.docs.push(Doc::Line);
+    }
+
+    pub fn line_break(&mut self) {
+        self.docs.push(Doc::Break);
+    }
+
+    pub fn hard_line(&mut self) {
+        self.docs.push(Doc::HardLine);
+    }
+
+    /// Write a group with `f`.
+    pub fn group<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        let (res, doc) = self.sub(f);
+        self.docs.push(Doc::Group(doc.into()));
+        res
+    }
+
+    /// Write with `f`, indenting its line breaks by `n` more columns.
+    pub fn nest<T>(&mut self, n: usize, f: impl FnOnce(&mut Self) -> T) -> T {
+        let (res, doc) = self.sub(f);
+        self.docs.push(Doc::Nest(n, doc.into()));
+        res
+    }
+
+    /// Write a group with `f`, indenting its line breaks by [`INDENT`].
+    pub fn block<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.group(|this| this.nest(INDENT, f))
+    }
+
+    fn sub<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> (T, Doc) {
+        self.stack.push(std::mem::take(&mut self.docs));
+        let res = f(self);
+        let docs = std::mem::replace(&mut self.docs, self.stack.pop().unwrap());
+        (res, Doc::Concat(docs))
+    }
+
+    pub fn finish(self) -> Doc {
+        Doc::Concat(self.docs)
+    }
+}
+
+impl Write for DocBuilder {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        match self.docs.last_mut() {
+            Some(Doc::Text(t)) => t.push_str(s),
+            _ => self.docs.push(Doc::Text(s.to_owned())),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,298 @@
+//! Records with named fields, declared with `record`.
+
+use std::borrow::Cow;
+
+use crate::{
+    check, check_levels,
+    data::{bind_params, binders, check_params, lams, vars},
+    error::{Error, ErrorKind},
+    eval, eval_closure, force, insert, instance,
+    metas::MetaCxt,
+    quote, zonk, Cxt, Definition, Elim, Env, Icit, Level, Name, Raw, Rc, Term, Tm, Type, Value,
+};
+
+/// Record type `R : (params) → U`
+#[derive(Debug)]
+pub struct Record {
+    pub name: Name,
+    pub params: usize,
+    /// type of the type former
+    pub ty: Type,
+    pub fields: Vec<Name>,
+    /// types of the fields as the telescope `(fields) → U`, in the environment
+    /// of the declaration followed by the parameters
+    pub tele: (Env, Tm),
+}
+
+impl Record {
+    /// Index of the field named `x`.
+    pub fn field(&self, x: &str) -> Option<usize> {
+        self.fields.iter().position(|f| &**f == x)
+    }
+
+    /// Types of the fields of `R params`, as a telescope.
+    pub fn telescope(&self, metas: &mut MetaCxt, params: Vec<Value>) -> Type {
+        let (mut env, t) = self.tele.clone();
+        for v in params {
+            env.push(v);
+        }
+        eval(metas, Cow::Owned(env), Rc::unwrap_or_clone(t))
+    }
+
+    /// Type of the field `f` of `r : R params`.
+    pub fn field_type(
+        self: &Rc<Self>,
+        metas: &mut MetaCxt,
+        params: Vec<Value>,
+        r: &Value,
+        f: usize,
+    ) -> Type {
+        let mut ty = self.telescope(metas, params);
+
+        for g in 0..f {
+            let Value::VΠ(_, _, _, b) = force(metas, ty) else {
+                unreachable!("record with fewer fields than its names")
+            };
+            ty = eval_closure(metas, b, v_field(self.clone(), g, r.clone()));
+        }
+        let Value::VΠ(_, _, a, _) = force(metas, ty) else {
+            unreachable!("record with fewer fields than its names")
+        };
+        Rc::unwrap_or_clone(a)
+    }
+}
+
+/// Project the field `f` out of `v`.
+pub(crate) fn v_field(record: Rc<Record>, f: usize, v: Value) -> Value {
+    match v {
+        Value::VFlex(m, mut sp) => {
+            sp.push(Elim::EField(record, f));
+            Value::VFlex(m, sp)
+        }
+        Value::VRigid(x, mut sp) => {
+            sp.push(Elim::EField(record, f));
+            Value::VRigid(x, sp)
+        }
+        Value::VGlued(x, def, mut sp) => {
+            sp.push(Elim::EField(record, f));
+            Value::VGlued(x, def, sp)
+        }
+        Value::VFix(x, k, t, mut sp) => {
+            sp.push(Elim::EField(record, f));
+            Value::VFix(x, k, t, sp)
+        }
+        Value::VRecordLit(_, mut fields) => fields.swap_remove(f),
+        _ => panic!(),
+    }
+}
+
+/// Elaborate `record x (params) : ty | field : A`, defining the type former.
+pub fn check_record(
+    metas: &mut MetaCxt,
+    cxt: &mut Cxt,
+    x: Name,
+    params: Vec<(Name, Raw)>,
+    ty: Raw,
+    fields: Vec<(Name, Raw)>,
+) -> Result<Vec<Definition>, Error> {
+    // left over by a definition that failed
+    metas.take_postponed();
+    metas.take_instances();
+    // the metas of earlier definitions are theirs alone
+    metas.freeze();
+
+    let lvl = cxt.lvl;
+    let p = params.len();
+    let a = check_params(metas, cxt, params, ty)?;
+    let va = eval(metas, Cow::Borrowed(&cxt.env), a.clone());
+
+    let level = {
+        let mut ty = va.clone();
+        for n in 0..p {
+            let Value::VΠ(_, _, _, b) = force(metas, ty) else {
+                unreachable!("fewer than {p} parameters")
+            };
+            ty = eval_closure(metas, b, Value::VRigid(lvl + n, vec![]));
+        }
+        match force(metas, ty) {
+            Value::VU(l) => l,
+            _ => {
+                let kind = ErrorKind::RecordType { record: x, ty: a };
+                return Err(Error::new(metas, cxt, kind));
+            }
+        }
+    };
+
+    for (k, (f, _)) in fields.iter().enumerate() {
+        if fields[..k].iter().any(|(g, _)| g == f) {
+            let kind = ErrorKind::DuplicateField { field: f.clone() };
+            return Err(Error::new(metas, cxt, kind));
+        }
+    }
+    let names = fields.iter().map(|(f, _)| f.clone()).collect();
+
+    // the fields are checked with the parameters bound
+    let env = cxt.env.clone();
+    let tele = bind_params(metas, cxt, va.clone(), p, |metas, cxt| {
+        let t = check_fields(metas, cxt, fields, level)?;
+        Ok(zonk(metas, cxt, t))
+    })?;
+
+    instance::check_found(metas)?;
+    if let Some(p) = metas.take_postponed().into_iter().next() {
+        return Err(Error::stuck(metas, p));
+    }
+    check_levels(metas, cxt)?;
+
+    let a = zonk(metas, cxt, a);
+    let record = Rc::new(Record {
+        name: x.clone(),
+        params: p,
+        ty: va.clone(),
+        fields: names,
+        tele: (env, tele.into()),
+    });
+
+    let t = lams(
+        binders(metas, va.clone(), lvl, p),
+        Term::TRecord(record.clone(), vars(p)),
+    );
+    let vt = eval(metas, Cow::Borrowed(&cxt.env), t.clone());
+    cxt.define_global(x.clone(), vt, va);
+    cxt.records.push(record);
+
+    Ok(vec![Definition {
+        name: x,
+        ty: a,
+        tm: Some(t),
+    }])
+}
+
+/// Elaborate the types of `fields` in `U level` to a telescope.
+fn check_fields(
+    metas: &mut MetaCxt,
+    cxt: &mut Cxt,
+    mut fields: Vec<(Name, Raw)>,
+    level: Level,
+) -> Result<Term, Error> {
+    if fields.is_empty() {
+        return Ok(Term::TU(level));
+    }
+
+    let (f, a) = fields.remove(0);
+    let a = check(metas, cxt, a, Value::VU(level))?;
+    let va = eval(metas, Cow::Borrowed(&cxt.env), a.clone());
+    let b = cxt
+        .bind(f.clone(), va, |cxt| check_fields(metas, cxt, fields, level))
+        .0?;
+    Ok(Term::TΠ(f, Icit::Expl, a.into(), b.into()))
+}
+
+/// Infer the type of the projection `t.f`.
+pub(crate) fn infer_field(
+    metas: &mut MetaCxt,
+    cxt: &mut Cxt,
+    t: Raw,
+    f: Name,
+) -> Result<(Term, Type), Error> {
+    let pos = cxt.pos.clone();
+    let (t, a) = insert(metas, cxt, t)?;
+    cxt.pos = pos;
+
+    match force(metas, a) {
+        Value::VRecord(record, params) => {
+            let Some(i) = record.field(&f) else {
+                let record = record.name.clone();
+                let kind = ErrorKind::NoSuchField { field: f, record };
+                return Err(Error::new(metas, cxt, kind));
+            };
+            let vt = eval(metas, Cow::Borrowed(&cxt.env), t.clone());
+            let ty = record.field_type(metas, params, &vt, i);
+            Ok((Term::TField(record, i, t.into()), ty))
+        }
+        a => {
+            let ty = quote(metas, cxt.lvl, a);
+            Err(Error::new(metas, cxt, ErrorKind::NotARecord { ty }))
+        }
+    }
+}
+
+/// Infer the type of a record literal, as the latest record with its fields.
+pub(crate) fn infer_record_lit(
+    metas: &mut MetaCxt,
+    cxt: &mut Cxt,
+    fields: Vec<(Name, Raw)>,
+) -> Result<(Term, Type), Error> {
+    let record = cxt.records.iter().rev().find(|r| {
+        r.fields.len() == fields.len() && fields.iter().all(|(f, _)| r.field(f).is_some())
+    });
+    let Some(record) = record.cloned() else {
+        let fields = fields.into_iter().map(|(f, _)| f).collect();
+        return Err(Error::new(metas, cxt, ErrorKind::UnknownRecord { fields }));
+    };
+
+    let mut ty = record.ty.clone();
+    let mut params = Vec::new();
+    for _ in 0..record.params {
+        let Value::VΠ(_, _, a, b) = force(metas, ty) else {
+            unreachable!("record with fewer parameters than its count")
+        };
+        let m = metas.fresh_meta(cxt, Rc::unwrap_or_clone(a));
+        let m = eval(metas, Cow::Borrowed(&cxt.env), m);
+        ty = eval_closure(metas, b, m.clone());
+        params.push(m);
+    }
+
+    let t = check_record_lit(metas, cxt, record.clone(), params.clone(), fields)?;
+    Ok((t, Value::VRecord(record, params)))
+}
+
+/// Check a record literal against `R params`, its fields in any order.
+pub(crate) fn check_record_lit(
+    metas: &mut MetaCxt,
+    cxt: &mut Cxt,
+    record: Rc<Record>,
+    params: Vec<Value>,
+    mut fields: Vec<(Name, Raw)>,
+) -> Result<Term, Error> {
+    let pos = cxt.pos.clone();
+
+    for (k, (f, _)) in fields.iter().enumerate() {
+        let kind = if record.field(f).is_none() {
+            let record = record.name.clone();
+            ErrorKind::NoSuchField {
+                field: f.clone(),
+                record,
+            }
+        } else if fields[..k].iter().any(|(g, _)| g == f) {
+            ErrorKind::DuplicateField { field: f.clone() }
+        } else {
+            continue;
+        };
+        return Err(Error::new(metas, cxt, kind));
+    }
+
+    let mut ty = record.telescope(metas, params);
+    let mut ts = Vec::new();
+    for f in &record.fields {
+        let Some(k) = fields.iter().position(|(g, _)| g == f) else {
+            let kind = ErrorKind::MissingField {
+                field: f.clone(),
+                record: record.name.clone(),
+            };
+            return Err(Error::new(metas, cxt, kind));
+        };
+        let (_, raw) = fields.swap_remove(k);
+
+        let Value::VΠ(_, _, a, b) = force(metas, ty) else {
+            unreachable!("record with fewer fields than its names")
+        };
+        let t = check(metas, cxt, raw, Rc::unwrap_or_clone(a))?;
+        cxt.pos = pos.clone();
+        let vt = eval(metas, Cow::Borrowed(&cxt.env), t.clone());
+        ty = eval_closure(metas, b, vt);
+        ts.push(Rc::new(t));
+    }
+
+    Ok(Term::TRecordLit(record, ts))
+}
@@ -0,0 +1,222 @@
+use std::{
+    io::{self, BufRead, Write},
+    path::Path,
+};
+
+use crate::{
+    diagnostics::{render_error, render_error_in, render_hole, render_warning},
+    elaborator::Elaborator,
+    error::Error,
+    modules::{ModuleError, ModuleLoader},
+    parser::{parse, parse_program_in},
+    Raw, TPrettyPrinter, Term, Unfold,
+};
+
+/// The file the inputs are named as in errors
+const REPL_FILE: &str = "<repl>";
+
+const HELP: &str = "\
+<def>          add a top-level definition
+<data>         add a data type with its constructors and eliminator
+<record>       add a record type with its fields
+<mutual>       add data types and definitions that refer to each other
+<instance>     add a definition that instance arguments are found among
+<opaque>       add a definition that isn't unfolded
+<axiom>        add a constant of a type, with no definition
+<import>       bring the definitions of a module into scope, like Prelude
+#[..]          set options or lints for the declaration after it, e.g.
+               #[type_in_type] or #[allow(unused_variable)]
+<expr>         elaborate and print an expression with its type
+:t <expr>      print the type of an expression
+:nf <expr>     print the normal form of an expression
+:elab <expr>   print the elaborated expression
+:metas         list the unsolved metas with their types
+:help          show this message
+:q             quit";
+
+/// Interactive session, definitions entered stay in scope for later inputs.
+/// Imports are resolved by its loader, relative to the current directory
+/// unless it is given another one.
+#[derive(Debug)]
+pub struct Repl {
+    elab: Elaborator,
+    loader: ModuleLoader,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::with_loader(ModuleLoader::new("."))
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Session resolving imports with `loader`, e.g. one reading the sources
+    /// from memory.
+    pub fn with_loader(loader: ModuleLoader) -> Self {
+        Self {
+            elab: Elaborator::new(),
+            loader,
+        }
+    }
+
+    pub fn elaborator(&mut self) -> &mut Elaborator {
+        &mut self.elab
+    }
+
+    /// Read and answer lines from `input` until it ends or `:q` is entered.
+    pub fn run(&mut self, mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        let mut line = String::new();
+
+        loop {
+            write!(output, "> ")?;
+            output.flush()?;
+
+            line.clear();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+
+            let line = line.trim();
+            if line == ":q" || line == ":quit" {
+                return Ok(());
+            }
+
+            let answer = self.eval_line(line);
+            if !answer.is_empty() {
+                writeln!(output, "{}", answer.trim_end())?;
+            }
+        }
+    }
+
+    /// Answer a single line of input.
+    pub fn eval_line(&mut self, line: &str) -> String {
+        let (cmd, arg) = match line.strip_prefix(':') {
+            Some(cmd) => match cmd.split_once(char::is_whitespace) {
+                Some((cmd, arg)) => (cmd, arg.trim()),
+                None => (cmd, ""),
+            },
+            None => ("", line),
+        };
+
+        match cmd {
+            "" if arg.is_empty() => String::new(),
+            "" if [
+                "def ",
+                "import ",
+                "data ",
+                "record ",
+                "mutual",
+                "instance ",
+                "opaque ",
+                "axiom ",
+                "#[",
+            ]
+            .iter()
+            .any(|kw| arg.starts_with(kw)) =>
+            {
+                self.declare(arg)
+            }
+            "" => self.expr(arg, |this, t, a| {
+                format!("{} : {}", this.print(&t), this.print(&a))
+            }),
+            "t" | "type" => self.expr(arg, |this, _, a| this.print(&a)),
+            "nf" | "normalize" => self.expr(arg, |this, t, _| {
+                let t = this.elab.normalize(t, Unfold::Always);
+                this.print(&t)
+            }),
+            "elab" | "elaborate" => self.expr(arg, |this, t, _| {
+                let t = this.elab.zonk(t);
+                this.print(&t)
+            }),
+            "metas" => {
+                let metas = self.elab.metas();
+                let mut out: Vec<String> = self
+                    .elab
+                    .unsolved_metas()
+                    .into_iter()
+                    .map(|m| {
+                        let origin = metas.origin(m);
+                        format!("?{m} : {}", TPrettyPrinter(&origin.names, &origin.ty))
+                    })
+                    .collect();
+                out.push(format!(
+                    "{} solved, {} unsolved",
+                    metas.solved_count(),
+                    metas.unsolved_count()
+                ));
+                out.join("\n")
+            }
+            "help" | "?" => HELP.to_string(),
+            cmd => format!("unknown command :{cmd}, try :help"),
+        }
+    }
+
+    fn declare(&mut self, src: &str) -> String {
+        let file = Path::new(REPL_FILE);
+        let id = self.loader.add_source(file, src);
+        let program = match parse_program_in(id, src) {
+            Ok(program) => program,
+            Err(errs) => return render_error(src, &Error::parse(errs)),
+        };
+
+        let res = self.elab.load_program(&mut self.loader, file, program);
+        let mut out: Vec<String> = match res {
+            Ok(defs) => defs
+                .iter()
+                .map(|def| format!("defined {}", def.name))
+                .collect(),
+            Err(ModuleError::Check(_, err)) if err.pos.file == id => vec![render_error(src, &err)],
+            Err(ModuleError::Check(_, err)) => vec![render_error_in(self.loader.sources(), &err)],
+            Err(ModuleError::Io(file, err)) => {
+                vec![format!("error: can't read {}: {err}", file.display())]
+            }
+            Err(ModuleError::Cycle(cycle)) => {
+                let cycle: Vec<String> = cycle.iter().map(ToString::to_string).collect();
+                vec![format!("error: import cycle {}", cycle.join(" -> "))]
+            }
+        };
+        for warning in self.elab.take_warnings() {
+            out.push(render_warning(src, &warning));
+        }
+        out.extend(self.goals(src));
+        out.join("\n")
+    }
+
+    /// Infer `src`, then show the term and its quoted type with `f`.
+    fn expr(&mut self, src: &str, f: impl FnOnce(&mut Self, Term, Term) -> String) -> String {
+        let raw = match parse(src) {
+            Ok(Some(raw)) => raw,
+            Ok(None) => return String::new(),
+            Err(errs) => return render_error(src, &Error::parse(errs)),
+        };
+
+        let mut out = match self.infer(raw) {
+            Ok((t, a)) => vec![f(self, t, a)],
+            Err(err) => vec![render_error(src, &err)],
+        };
+        out.extend(self.goals(src));
+        out.join("\n")
+    }
+
+    fn goals(&mut self, src: &str) -> Vec<String> {
+        self.elab
+            .take_holes()
+            .iter()
+            .map(|hole| render_hole(src, hole))
+            .collect()
+    }
+
+    fn infer(&mut self, raw: Raw) -> Result<(Term, Term), Error> {
+        let (t, a) = self.elab.infer(raw)?;
+        let a = self.elab.display_value(&a).term().clone();
+        Ok((t, a))
+    }
+
+    fn print(&self, t: &Term) -> String {
+        TPrettyPrinter(&self.elab.cxt().names(), t).to_string()
+    }
+}
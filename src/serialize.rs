@@ -0,0 +1,70 @@
+//! Serialization of syntax and elaboration results, with the `serde` feature.
+//!
+//! Data types and records carry their elaborated constructors and fields,
+//! which live outside of any term, so terms refer to them by name.
+//! Deserializing a term looks the names up among the data types and records
+//! of a context, made current with [`in_scope`]; outside of it only terms
+//! without them can be deserialized.
+
+use std::cell::RefCell;
+
+use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+use crate::{data::Data, record::Record, Cxt, Name, Rc};
+
+#[derive(Default)]
+struct Scope {
+    data: Vec<Rc<Data>>,
+    records: Vec<Rc<Record>>,
+}
+
+thread_local! {
+    static SCOPE: RefCell<Scope> = RefCell::default();
+}
+
+/// Run `f`, deserializing the data types and records of terms as those of
+/// `cxt`, the latest one of each name.
+pub fn in_scope<T>(cxt: &Cxt, f: impl FnOnce() -> T) -> T {
+    let scope = Scope {
+        data: cxt.data().to_vec(),
+        records: cxt.records().to_vec(),
+    };
+    let outer = SCOPE.with(|s| s.replace(scope));
+    let res = f();
+    SCOPE.with(|s| s.replace(outer));
+    res
+}
+
+pub(crate) mod data {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(data: &Rc<Data>, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&data.name)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Rc<Data>, D::Error> {
+        let name = Name::deserialize(d)?;
+        let data = SCOPE.with(|s| {
+            let s = s.borrow();
+            s.data.iter().rev().find(|data| data.name == name).cloned()
+        });
+        data.ok_or_else(|| D::Error::custom(format!("data type `{name}` not in scope")))
+    }
+}
+
+pub(crate) mod record {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(record: &Rc<Record>, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&record.name)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Rc<Record>, D::Error> {
+        let name = Name::deserialize(d)?;
+        let record = SCOPE.with(|s| {
+            let s = s.borrow();
+            s.records.iter().rev().find(|r| r.name == name).cloned()
+        });
+        record.ok_or_else(|| D::Error::custom(format!("record `{name}` not in scope")))
+    }
+}
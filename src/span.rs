@@ -0,0 +1,116 @@
+//! Source locations across files.
+
+use std::ops::Range;
+
+/// File of a [`SourceMap`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FileId(pub u32);
+
+/// Offset of a character in a file
+pub type Pos = usize;
+
+/// Range of characters in a file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub file: FileId,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+impl Span {
+    pub fn new(file: FileId, range: Range<Pos>) -> Self {
+        Span {
+            file,
+            start: range.start,
+            end: range.end,
+        }
+    }
+
+    pub fn range(&self) -> Range<Pos> {
+        self.start..self.end
+    }
+}
+
+impl chumsky::Span for Span {
+    type Context = FileId;
+    type Offset = Pos;
+
+    fn new(file: FileId, range: Range<Pos>) -> Self {
+        Span::new(file, range)
+    }
+
+    fn context(&self) -> FileId {
+        self.file
+    }
+
+    fn start(&self) -> Pos {
+        self.start
+    }
+
+    fn end(&self) -> Pos {
+        self.end
+    }
+}
+
+/// Names and sources of the files that spans point into
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+#[derive(Debug, Clone)]
+struct SourceFile {
+    name: String,
+    src: String,
+    /// offsets of the first characters of the lines
+    lines: Vec<Pos>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a file, e.g. named by its path.
+    pub fn add(&mut self, name: impl Into<String>, src: impl Into<String>) -> FileId {
+        let src = src.into();
+        let lines = [0]
+            .into_iter()
+            .chain(
+                src.chars()
+                    .enumerate()
+                    .filter(|(_, c)| *c == '\n')
+                    .map(|(i, _)| i + 1),
+            )
+            .collect();
+        self.files.push(SourceFile {
+            name: name.into(),
+            src,
+            lines,
+        });
+        FileId(self.files.len() as u32 - 1)
+    }
+
+    pub fn name(&self, file: FileId) -> &str {
+        &self.files[file.0 as usize].name
+    }
+
+    pub fn source(&self, file: FileId) -> &str {
+        &self.files[file.0 as usize].src
+    }
+
+    /// Zero-based line and column of the `pos`th character of `file`.
+    pub fn line_col(&self, file: FileId, pos: Pos) -> (usize, usize) {
+        let lines = &self.files[file.0 as usize].lines;
+        let line = lines.partition_point(|start| *start <= pos) - 1;
+        (line, pos - lines[line])
+    }
+
+    /// Line and column of the start of `span`, one-based for display.
+    pub fn location(&self, span: &Span) -> String {
+        let (line, col) = self.line_col(span.file, span.start);
+        format!("{}:{}:{}", self.name(span.file), line + 1, col + 1)
+    }
+}
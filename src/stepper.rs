@@ -0,0 +1,126 @@
+//! Elaboration one `check` or `infer` at a time, for front-ends that show how
+//! a term is elaborated.
+//!
+//! A [`Stepper`] stops before each node of the elaboration and shows its goal
+//! in a [`Snapshot`], with the context and the metas at that point. Data types
+//! and records elaborate their `match`es, fields and record literals in one
+//! step.
+
+use crate::{
+    error::Error, metas::MetaCxt, quote, span::Span, Cxt, ElabMachine, Elaborated, Name, Node, Raw,
+    Term, Type,
+};
+
+/// What the node about to be elaborated does
+#[derive(Debug, Clone)]
+pub enum Goal {
+    /// check the term against the type
+    Check(Raw, Term),
+    Infer(Raw),
+}
+
+/// The state of the elaboration before a node
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub goal: Goal,
+    /// the variables in scope with their types, innermost last, each type in
+    /// the context of the variables before it
+    pub cxt: Vec<(Name, Term)>,
+    pub pos: Span,
+    /// the metas so far
+    pub metas: MetaCxt,
+}
+
+/// `check` or `infer`, elaborating one node at each [`Stepper::step`]
+pub struct Stepper<'a> {
+    metas: &'a mut MetaCxt,
+    cxt: &'a mut Cxt,
+    machine: ElabMachine,
+    /// the type checked against, for a `check`
+    ty: Option<Type>,
+    /// whether the next node was shown by a step already
+    started: bool,
+    res: Option<Result<(Term, Type), Error>>,
+}
+
+impl<'a> Stepper<'a> {
+    /// Check `raw` against `ty` in `cxt`.
+    pub fn check(metas: &'a mut MetaCxt, cxt: &'a mut Cxt, raw: Raw, ty: Type) -> Self {
+        let machine = ElabMachine::new(cxt, Node::Check(raw, ty.clone()));
+        Self::new(metas, cxt, machine, Some(ty))
+    }
+
+    /// Infer the type of `raw` in `cxt`.
+    pub fn infer(metas: &'a mut MetaCxt, cxt: &'a mut Cxt, raw: Raw) -> Self {
+        let machine = ElabMachine::new(cxt, Node::Infer(raw));
+        Self::new(metas, cxt, machine, None)
+    }
+
+    fn new(
+        metas: &'a mut MetaCxt,
+        cxt: &'a mut Cxt,
+        machine: ElabMachine,
+        ty: Option<Type>,
+    ) -> Self {
+        Stepper {
+            metas,
+            cxt,
+            machine,
+            ty,
+            started: false,
+            res: None,
+        }
+    }
+
+    /// Elaborate up to the next node, and show it. Returns `None` once
+    /// elaboration is done.
+    pub fn step(&mut self) -> Option<Snapshot> {
+        if self.started {
+            self.advance();
+        }
+        self.started = true;
+        let node = self.machine.next()?.clone();
+
+        let goal = match node {
+            Node::Check(raw, a) => Goal::Check(raw, quote(self.metas, self.cxt.lvl(), a)),
+            Node::Infer(raw) => Goal::Infer(raw),
+        };
+        let cxt = self
+            .cxt
+            .types()
+            .iter()
+            .enumerate()
+            .map(|(lvl, (x, a))| (x.clone(), quote(self.metas, lvl, a.clone())))
+            .collect();
+
+        Some(Snapshot {
+            goal,
+            cxt,
+            pos: self.cxt.pos().clone(),
+            metas: self.metas.clone(),
+        })
+    }
+
+    /// Elaborate the node shown last, up to the next one.
+    fn advance(&mut self) {
+        if let Some(res) = self.machine.step(self.metas, self.cxt) {
+            self.res = Some(res.map(|res| match res {
+                Elaborated::Checked(t) => (t, self.ty.take().unwrap()),
+                Elaborated::Inferred(t, a) => (t, a),
+            }));
+        }
+    }
+
+    /// The metas as they are now.
+    pub fn metas(&self) -> &MetaCxt {
+        self.metas
+    }
+
+    /// Elaborate the rest, returning the elaborated term and its type.
+    pub fn finish(mut self) -> Result<(Term, Type), Error> {
+        while self.res.is_none() {
+            self.advance();
+        }
+        self.res.unwrap()
+    }
+}
@@ -0,0 +1,317 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{metas::MetaVar, span::Span, Icit, IntOp, Level, Name, Rc, Term, Tm, Unfolds, BD};
+
+/// Identity of a term interned in a [`TermStore`], equal ids mean
+/// syntactically equal terms. It is the address of the shared node, which the
+/// store keeps alive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TermId(usize);
+
+/// A term node whose children are already interned, compared by their ids.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Node {
+    V(usize),
+    Lam(Name, Icit, TermId),
+    Pi(Name, Icit, TermId, TermId),
+    Pair(TermId, TermId),
+    Sigma(Name, TermId, TermId),
+    Let(Name, TermId, TermId, TermId),
+    Meta(MetaVar),
+    InsertedMeta(MetaVar, Vec<BD>),
+    App(TermId, TermId, Icit),
+    Proj1(TermId),
+    Proj2(TermId),
+    U(Level),
+    Nat,
+    NatLit(u64),
+    Suc(TermId),
+    NatElim(TermId, TermId, TermId, TermId),
+    Bool,
+    True,
+    False,
+    BoolElim(TermId, TermId, TermId, TermId),
+    String,
+    StrLit(Rc<str>),
+    Char,
+    CharLit(char),
+    Append(TermId, TermId),
+    Length(TermId),
+    Int,
+    IntLit(i64),
+    IntOp(IntOp, TermId, TermId),
+    Eq(TermId, TermId, TermId),
+    Refl(TermId, TermId),
+    J([TermId; 6]),
+    Lazy(TermId),
+    Delay(TermId),
+    Force(TermId),
+    /// data types are told apart by their address
+    Data(usize, Vec<TermId>),
+    Con(usize, usize, Vec<TermId>),
+    Elim(usize, Vec<TermId>, TermId),
+    Fix(Vec<(Name, Unfolds, TermId)>, usize),
+    /// records are told apart by their address too
+    Record(usize, Vec<TermId>),
+    RecordLit(usize, Vec<TermId>),
+    Field(usize, usize, TermId),
+    /// terms at different spans are kept apart
+    SrcPos(Span, TermId),
+}
+
+/// Hash-consing store, structurally identical terms interned in the same store
+/// share a single allocation. So two interned terms are equal exactly when
+/// they are the same node, which unification compares closures by first.
+#[derive(Debug, Clone, Default)]
+pub struct TermStore {
+    nodes: HashMap<Node, Tm>,
+    interned: HashSet<TermId>,
+}
+
+impl TermStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// number of distinct nodes
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Id of `term` if it was returned by [`TermStore::intern`].
+    pub fn id(&self, term: &Rc<Term>) -> Option<TermId> {
+        Some(Self::key(term)).filter(|id| self.interned.contains(id))
+    }
+
+    fn key(term: &Tm) -> TermId {
+        TermId(Rc::as_ptr(term) as usize)
+    }
+
+    /// Share `term` and all of its subterms with the terms interned before.
+    pub fn intern(&mut self, term: &Term) -> Rc<Term> {
+        let mut go = |t: &Tm| self.intern(t);
+
+        let (node, term) = match term {
+            Term::TV(x) => (Node::V(x.0), Term::TV(*x)),
+            Term::Tλ(x, i, t) => {
+                let t = go(t);
+                (
+                    Node::Lam(x.clone(), *i, Self::key(&t)),
+                    Term::Tλ(x.clone(), *i, t),
+                )
+            }
+            Term::TΠ(x, i, a, b) => {
+                let (a, b) = (go(a), go(b));
+                (
+                    Node::Pi(x.clone(), *i, Self::key(&a), Self::key(&b)),
+                    Term::TΠ(x.clone(), *i, a, b),
+                )
+            }
+            Term::Tσ(t, u) => {
+                let (t, u) = (go(t), go(u));
+                (Node::Pair(Self::key(&t), Self::key(&u)), Term::Tσ(t, u))
+            }
+            Term::TΣ(x, a, b) => {
+                let (a, b) = (go(a), go(b));
+                (
+                    Node::Sigma(x.clone(), Self::key(&a), Self::key(&b)),
+                    Term::TΣ(x.clone(), a, b),
+                )
+            }
+            Term::TLet(x, a, t, u) => {
+                let (a, t, u) = (go(a), go(t), go(u));
+                (
+                    Node::Let(x.clone(), Self::key(&a), Self::key(&t), Self::key(&u)),
+                    Term::TLet(x.clone(), a, t, u),
+                )
+            }
+            Term::TMeta(m) => (Node::Meta(*m), Term::TMeta(*m)),
+            Term::TInsertedMeta(m, bds) => (
+                Node::InsertedMeta(*m, bds.clone()),
+                Term::TInsertedMeta(*m, bds.clone()),
+            ),
+            Term::TApp(t, u, i) => {
+                let (t, u) = (go(t), go(u));
+                (
+                    Node::App(Self::key(&t), Self::key(&u), *i),
+                    Term::TApp(t, u, *i),
+                )
+            }
+            Term::TProj1(t) => {
+                let t = go(t);
+                (Node::Proj1(Self::key(&t)), Term::TProj1(t))
+            }
+            Term::TProj2(t) => {
+                let t = go(t);
+                (Node::Proj2(Self::key(&t)), Term::TProj2(t))
+            }
+            Term::TU(l) => (Node::U(*l), Term::TU(*l)),
+            Term::TNat => (Node::Nat, Term::TNat),
+            Term::TNatLit(n) => (Node::NatLit(*n), Term::TNatLit(*n)),
+            Term::TSuc(t) => {
+                let t = go(t);
+                (Node::Suc(Self::key(&t)), Term::TSuc(t))
+            }
+            Term::TNatElim(p, z, s, n) => {
+                let (p, z, s, n) = (go(p), go(z), go(s), go(n));
+                (
+                    Node::NatElim(Self::key(&p), Self::key(&z), Self::key(&s), Self::key(&n)),
+                    Term::TNatElim(p, z, s, n),
+                )
+            }
+            Term::TBool => (Node::Bool, Term::TBool),
+            Term::TTrue => (Node::True, Term::TTrue),
+            Term::TFalse => (Node::False, Term::TFalse),
+            Term::TBoolElim(p, t, f, b) => {
+                let (p, t, f, b) = (go(p), go(t), go(f), go(b));
+                (
+                    Node::BoolElim(Self::key(&p), Self::key(&t), Self::key(&f), Self::key(&b)),
+                    Term::TBoolElim(p, t, f, b),
+                )
+            }
+            Term::TString => (Node::String, Term::TString),
+            Term::TStrLit(s) => (Node::StrLit(s.clone()), Term::TStrLit(s.clone())),
+            Term::TChar => (Node::Char, Term::TChar),
+            Term::TCharLit(c) => (Node::CharLit(*c), Term::TCharLit(*c)),
+            Term::TAppend(s, t) => {
+                let (s, t) = (go(s), go(t));
+                (
+                    Node::Append(Self::key(&s), Self::key(&t)),
+                    Term::TAppend(s, t),
+                )
+            }
+            Term::TLength(s) => {
+                let s = go(s);
+                (Node::Length(Self::key(&s)), Term::TLength(s))
+            }
+            Term::TInt => (Node::Int, Term::TInt),
+            Term::TIntLit(n) => (Node::IntLit(*n), Term::TIntLit(*n)),
+            Term::TIntOp(op, x, y) => {
+                let (x, y) = (go(x), go(y));
+                (
+                    Node::IntOp(*op, Self::key(&x), Self::key(&y)),
+                    Term::TIntOp(*op, x, y),
+                )
+            }
+            Term::TEq(a, x, y) => {
+                let (a, x, y) = (go(a), go(x), go(y));
+                (
+                    Node::Eq(Self::key(&a), Self::key(&x), Self::key(&y)),
+                    Term::TEq(a, x, y),
+                )
+            }
+            Term::TRefl(a, x) => {
+                let (a, x) = (go(a), go(x));
+                (Node::Refl(Self::key(&a), Self::key(&x)), Term::TRefl(a, x))
+            }
+            Term::TJ(a, x, p, pr, y, e) => {
+                let ts = [a, x, p, pr, y, e].map(go);
+                let [a, x, p, pr, y, e] = ts.clone();
+                (
+                    Node::J(ts.each_ref().map(Self::key)),
+                    Term::TJ(a, x, p, pr, y, e),
+                )
+            }
+            Term::TLazy(a) => {
+                let a = go(a);
+                (Node::Lazy(Self::key(&a)), Term::TLazy(a))
+            }
+            Term::TDelay(t) => {
+                let t = go(t);
+                (Node::Delay(Self::key(&t)), Term::TDelay(t))
+            }
+            Term::TForce(t) => {
+                let t = go(t);
+                (Node::Force(Self::key(&t)), Term::TForce(t))
+            }
+            Term::TData(d, args) => {
+                let args: Vec<_> = args.iter().map(&mut go).collect();
+                (
+                    Node::Data(Rc::as_ptr(d) as usize, args.iter().map(Self::key).collect()),
+                    Term::TData(d.clone(), args),
+                )
+            }
+            Term::TCon(d, c, args) => {
+                let args: Vec<_> = args.iter().map(&mut go).collect();
+                (
+                    Node::Con(
+                        Rc::as_ptr(d) as usize,
+                        *c,
+                        args.iter().map(Self::key).collect(),
+                    ),
+                    Term::TCon(d.clone(), *c, args),
+                )
+            }
+            Term::TElim(d, args, t) => {
+                let args: Vec<_> = args.iter().map(&mut go).collect();
+                let t = go(t);
+                (
+                    Node::Elim(
+                        Rc::as_ptr(d) as usize,
+                        args.iter().map(Self::key).collect(),
+                        Self::key(&t),
+                    ),
+                    Term::TElim(d.clone(), args, t),
+                )
+            }
+            Term::TRecord(r, args) => {
+                let args: Vec<_> = args.iter().map(&mut go).collect();
+                (
+                    Node::Record(Rc::as_ptr(r) as usize, args.iter().map(Self::key).collect()),
+                    Term::TRecord(r.clone(), args),
+                )
+            }
+            Term::TRecordLit(r, fields) => {
+                let fields: Vec<_> = fields.iter().map(&mut go).collect();
+                (
+                    Node::RecordLit(
+                        Rc::as_ptr(r) as usize,
+                        fields.iter().map(Self::key).collect(),
+                    ),
+                    Term::TRecordLit(r.clone(), fields),
+                )
+            }
+            Term::TField(r, f, t) => {
+                let t = go(t);
+                (
+                    Node::Field(Rc::as_ptr(r) as usize, *f, Self::key(&t)),
+                    Term::TField(r.clone(), *f, t),
+                )
+            }
+            Term::TSrcPos(pos, t) => {
+                let t = go(t);
+                (
+                    Node::SrcPos(pos.clone(), Self::key(&t)),
+                    Term::TSrcPos(pos.clone(), t),
+                )
+            }
+            Term::TFix(fix, i) => {
+                let bodies: Vec<_> = fix.iter().map(|(_, _, t)| go(t)).collect();
+                (
+                    Node::Fix(
+                        fix.iter()
+                            .zip(&bodies)
+                            .map(|((x, k, _), t)| (x.clone(), *k, Self::key(t)))
+                            .collect(),
+                        *i,
+                    ),
+                    Term::TFix(
+                        fix.iter()
+                            .zip(bodies)
+                            .map(|((x, k, _), t)| (x.clone(), *k, t))
+                            .collect(),
+                        *i,
+                    ),
+                )
+            }
+        };
+
+        let term = self.nodes.entry(node).or_insert_with(|| Rc::new(term));
+        self.interned.insert(Self::key(term));
+        term.clone()
+    }
+}
@@ -0,0 +1,143 @@
+//! Substitution and shifting of the de Bruijn indices of elaborated terms,
+//! for transformations of terms outside of evaluation.
+//!
+//! The variables a term refers to without binding them are its context,
+//! innermost first. Inserted metas are applied to the variables of their
+//! context by a mask over it, see [`BD`], which is kept in step with it: a
+//! meta is applied to no variable added to its context, and is applied
+//! explicitly to the ones a substitution replaces.
+
+use crate::{
+    inserted_meta_app,
+    visit::{fold_term, TermFolder},
+    Ix, Term, BD,
+};
+
+impl Term {
+    /// The term under `by` more binders, which it doesn't refer to.
+    pub fn weaken(&self, by: usize) -> Term {
+        self.shift(Ix(0), by)
+    }
+
+    /// The term with `by` more variables in its context just outside the
+    /// `from` innermost ones, which it doesn't refer to.
+    pub fn shift(&self, from: Ix, by: usize) -> Term {
+        Shift { from: from.0, by }.fold(0, self.clone())
+    }
+
+    /// The term with `u` for the variable `x`, which is gone from its
+    /// context: `u` lives in the context without `x`, like the result.
+    pub fn subst(&self, x: Ix, u: &Term) -> Term {
+        Subst { x: x.0, u }.fold(0, self.clone())
+    }
+
+    /// The term in its context without the variable `x`, unless it refers to
+    /// it, or a meta in it is applied to it.
+    pub fn strengthen(&self, x: Ix) -> Option<Term> {
+        let mut strengthen = Strengthen {
+            x: x.0,
+            occurs: false,
+        };
+        let t = strengthen.fold(0, self.clone());
+        (!strengthen.occurs).then_some(t)
+    }
+}
+
+/// Level in the mask `bds` of the variable of index `x`, if it is in it
+fn level(bds: &[BD], x: usize) -> Option<usize> {
+    bds.len().checked_sub(x + 1)
+}
+
+struct Shift {
+    from: usize,
+    by: usize,
+}
+
+impl TermFolder for Shift {
+    fn var(&mut self, depth: usize, x: Ix) -> Term {
+        match x.0 >= self.from + depth {
+            true => Term::TV(Ix(x.0 + self.by)),
+            false => Term::TV(x),
+        }
+    }
+
+    fn meta(&mut self, depth: usize, t: Term) -> Term {
+        match t {
+            Term::TInsertedMeta(m, mut bds) => {
+                if let Some(lvl) = bds.len().checked_sub(self.from + depth) {
+                    bds.splice(lvl..lvl, vec![BD::Defined; self.by]);
+                }
+                Term::TInsertedMeta(m, bds)
+            }
+            t => t,
+        }
+    }
+}
+
+struct Subst<'a> {
+    x: usize,
+    u: &'a Term,
+}
+
+impl TermFolder for Subst<'_> {
+    fn var(&mut self, depth: usize, x: Ix) -> Term {
+        let y = self.x + depth;
+        match x.0 {
+            x if x == y => self.u.weaken(depth),
+            x if x > y => Term::TV(Ix(x - 1)),
+            x => Term::TV(Ix(x)),
+        }
+    }
+
+    fn meta(&mut self, depth: usize, t: Term) -> Term {
+        match t {
+            Term::TInsertedMeta(m, mut bds) => match level(&bds, self.x + depth) {
+                Some(lvl) if bds[lvl] == BD::Bound => {
+                    let t = inserted_meta_app(m, &bds, bds.len());
+                    fold_term(self, depth, t)
+                }
+                Some(lvl) => {
+                    bds.remove(lvl);
+                    Term::TInsertedMeta(m, bds)
+                }
+                None => Term::TInsertedMeta(m, bds),
+            },
+            t => t,
+        }
+    }
+}
+
+struct Strengthen {
+    x: usize,
+    occurs: bool,
+}
+
+impl TermFolder for Strengthen {
+    fn var(&mut self, depth: usize, x: Ix) -> Term {
+        let y = self.x + depth;
+        match x.0 {
+            x if x == y => {
+                self.occurs = true;
+                Term::TV(Ix(x))
+            }
+            x if x > y => Term::TV(Ix(x - 1)),
+            x => Term::TV(Ix(x)),
+        }
+    }
+
+    fn meta(&mut self, depth: usize, t: Term) -> Term {
+        match t {
+            Term::TInsertedMeta(m, mut bds) => {
+                match level(&bds, self.x + depth) {
+                    Some(lvl) if bds[lvl] == BD::Bound => self.occurs = true,
+                    Some(lvl) => {
+                        bds.remove(lvl);
+                    }
+                    None => {}
+                }
+                Term::TInsertedMeta(m, bds)
+            }
+            t => t,
+        }
+    }
+}
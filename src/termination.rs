@@ -0,0 +1,496 @@
+//! Structural termination checking of recursive definitions.
+//!
+//! A recursive definition is accepted when one of its arguments decreases in
+//! every recursive call: the call has to pass a variable bound by a `match` on
+//! that argument, or on a variable bound by such a `match` in turn.
+//!
+//! Failing that, a corecursive one is accepted when it is productive: every
+//! recursive call is under a `delay`, reached from the top of the definition
+//! through constructors, pairs, record literals and the branches of matches
+//! only, so that forcing the delay yields a constructor before calling the
+//! block again.
+
+use crate::{
+    error::{Error, ErrorKind},
+    eval_closure, force,
+    metas::MetaCxt,
+    Cxt, Env, Fix, Ix, Lvl, Name, Pattern, Raw, Term, Tm, Unfolds, Value,
+};
+
+/// Whether `x` occurs free in `t`, which makes a definition of `x` recursive.
+pub fn mentions(t: &Raw, x: &str) -> bool {
+    let under = |y: &str, t: &Raw| y != x && mentions(t, x);
+
+    match t {
+        Raw::RVar(y) => &**y == x,
+        Raw::RLam(y, _, t) => under(y, t),
+        Raw::RApp(t, u, _) | Raw::RAppNamed(t, _, u) | Raw::RPair(t, u) => {
+            mentions(t, x) || mentions(u, x)
+        }
+        Raw::RPi(y, _, a, b) | Raw::RSigma(y, a, b) => mentions(a, x) || under(y, b),
+        Raw::RLet(y, a, t, u) => mentions(a, x) || mentions(t, x) || under(y, u),
+        Raw::RLetRec(y, a, t, u) => mentions(a, x) || under(y, t) || under(y, u),
+        Raw::RSrcPos(_, t) | Raw::RProj1(t) | Raw::RProj2(t) | Raw::RField(t, _) => mentions(t, x),
+        Raw::RRecordLit(fields) => fields.iter().any(|(_, t)| mentions(t, x)),
+        Raw::RIf(b, t, f) => [b, t, f].iter().any(|t| mentions(t, x)),
+        Raw::RMatch(t, branches) => {
+            mentions(t, x)
+                || branches.iter().any(|(pat, u)| match pat {
+                    Pattern::PCon(_, ys) => ys.iter().all(|(y, _)| &**y != x) && mentions(u, x),
+                    Pattern::PWild => mentions(u, x),
+                })
+        }
+        Raw::RU(_)
+        | Raw::RHole(_)
+        | Raw::RNatLit(_)
+        | Raw::RStrLit(_)
+        | Raw::RCharLit(_)
+        | Raw::RIntLit(_) => false,
+    }
+}
+
+/// Check that the elaborated definitions `t` terminate, if they are a block of
+/// recursive ones, and record the argument each one is structurally recursive
+/// on, or that they are productive ones unfolding once eliminated. Unless the
+/// options of `cxt` turn the check off, calls on no smaller argument are an
+/// error.
+pub fn check(metas: &mut MetaCxt, cxt: &Cxt, t: Term) -> Result<Term, Error> {
+    let Term::TFix(fix, i) = t else {
+        return Ok(t);
+    };
+    let with = |unfolds: Vec<Unfolds>| {
+        fix.iter()
+            .zip(unfolds)
+            .map(|((x, _, t), unfolds)| (x.clone(), unfolds, t.clone()))
+            .collect()
+    };
+
+    match structural_args(metas, cxt.env(), &fix) {
+        Ok(ks) => {
+            let unfolds = ks
+                .into_iter()
+                .map(|k| k.map_or(Unfolds::Now, Unfolds::OnArg));
+            Ok(Term::TFix(with(unfolds.collect()), i))
+        }
+        Err(_) if productive(metas, cxt.env(), &fix) => {
+            Ok(Term::TFix(with(vec![Unfolds::OnElim; fix.len()]), i))
+        }
+        Err(_) if cxt.options().no_termination_check => {
+            Ok(Term::TFix(with(vec![Unfolds::Now; fix.len()]), i))
+        }
+        Err((j, names, call)) => {
+            let block = fix.iter().map(|(x, _, _)| x.clone()).collect();
+            let names = [cxt.names(), block, names].concat();
+            let pos = match &call {
+                Term::TSrcPos(pos, _) => Some(pos.clone()),
+                _ => None,
+            };
+            let kind = ErrorKind::Termination {
+                def: fix[j].0.clone(),
+                names,
+                call,
+            };
+            let mut err = Error::new(metas, cxt, kind);
+            if let Some(pos) = pos {
+                err.pos = pos;
+            }
+            Err(err)
+        }
+    }
+}
+
+/// The parameters and the body under them of each definition of the block,
+/// under the spans around the lambdas
+fn bodies(fix: &Fix) -> Vec<(Vec<Name>, &Tm)> {
+    fix.iter()
+        .map(|(_, _, t)| {
+            let mut params = Vec::new();
+            let mut body = t;
+            loop {
+                match &**body {
+                    Term::Tλ(x, _, t) => {
+                        params.push(x.clone());
+                        body = t;
+                    }
+                    Term::TSrcPos(_, t) if matches!(t.unspanned(), Term::Tλ(..)) => body = t,
+                    _ => break,
+                }
+            }
+            (params, body)
+        })
+        .collect()
+}
+
+/// Whether every call of the block `fix` bound after `env` in its bodies is
+/// guarded by a `delay`.
+fn productive(metas: &mut MetaCxt, env: &Env, fix: &Fix) -> bool {
+    let (lvl, n) = (env.len(), fix.len());
+    bodies(fix).into_iter().all(|(params, body)| {
+        let mut checker = Checker {
+            metas,
+            env,
+            fix: lvl,
+            args: &vec![Vec::new(); n],
+            leaves: &[],
+            arg: None,
+            smaller: Vec::new(),
+            names: params.clone(),
+            corecursive: true,
+            guarding: true,
+            delayed: false,
+        };
+        checker.go(lvl + n + params.len(), body).is_ok()
+    })
+}
+
+/// The arguments on which the bodies of the block `fix` bound after `env` are
+/// structurally recursive, `None` for those that don't call the block after
+/// all. Otherwise the first call that isn't on a smaller argument, with the
+/// definition it is in and the names bound between the block and the call.
+///
+/// Rather than trying every choice of arguments, the candidates of each
+/// definition are narrowed down to those its calls can decrease on given the
+/// candidates left to the others, then settled on one definition at a time.
+#[allow(clippy::type_complexity)]
+fn structural_args(
+    metas: &mut MetaCxt,
+    env: &Env,
+    fix: &Fix,
+) -> Result<Vec<Option<usize>>, (usize, Vec<Name>, Term)> {
+    let (lvl, n) = (env.len(), fix.len());
+    let bodies = bodies(fix);
+
+    let check = |metas: &mut MetaCxt, j: usize, k: Option<usize>, args: &[Vec<usize>], leaves| {
+        let (params, body) = &bodies[j];
+        let mut checker = Checker {
+            metas,
+            env,
+            fix: lvl,
+            args,
+            leaves,
+            arg: k.map(|k| lvl + n + k),
+            smaller: Vec::new(),
+            names: params.clone(),
+            corecursive: false,
+            guarding: true,
+            delayed: false,
+        };
+        // the names are those in scope of the call
+        checker
+            .go(lvl + n + params.len(), body)
+            .map_err(|call| (j, checker.names, call))
+    };
+
+    // definitions that don't call the block unfold right away
+    let none = vec![Vec::new(); n];
+    let leaves: Vec<bool> = (0..n)
+        .map(|j| check(metas, j, None, &none, &[]).is_ok())
+        .collect();
+    if leaves.iter().all(|leaf| *leaf) {
+        return Ok(vec![None; n]);
+    }
+    let mut first = None;
+
+    // drop the candidates some call can't decrease on until none is dropped,
+    // failing with the definition left without any
+    let narrow = |metas: &mut MetaCxt,
+                  args: &mut Vec<Vec<usize>>,
+                  first: &mut Option<(usize, Vec<Name>, Term)>| loop {
+        let mut dropped = false;
+        for j in (0..n).filter(|j| !leaves[*j]) {
+            let mut ks = args[j].clone();
+            ks.retain(|k| match check(metas, j, Some(*k), args, &leaves) {
+                Ok(()) => true,
+                Err(err) => {
+                    first.get_or_insert(err);
+                    false
+                }
+            });
+            if ks.is_empty() {
+                return Err(j);
+            }
+            dropped |= ks.len() < args[j].len();
+            args[j] = ks;
+        }
+        if !dropped {
+            return Ok(());
+        }
+    };
+
+    let mut args: Vec<Vec<usize>> = (0..n)
+        .map(|j| match leaves[j] {
+            true => Vec::new(),
+            false => (0..bodies[j].0.len()).collect(),
+        })
+        .collect();
+    if let Err(j) = narrow(metas, &mut args, &mut first) {
+        // without parameters, the definition fails without any candidate
+        return Err(first.unwrap_or_else(|| check(metas, j, None, &args, &leaves).unwrap_err()));
+    }
+
+    // the candidates left may still not go together: settle on the first one
+    // leaving the others some
+    for j in (0..n).filter(|j| !leaves[*j]) {
+        let settled = args[j].iter().find_map(|k| {
+            let mut args = args.clone();
+            args[j] = vec![*k];
+            narrow(metas, &mut args, &mut first).is_ok().then_some(args)
+        });
+        match settled {
+            Some(settled) => args = settled,
+            None => return Err(first.unwrap()),
+        }
+    }
+    Ok(args.into_iter().map(|ks| ks.first().copied()).collect())
+}
+
+struct Checker<'a> {
+    metas: &'a mut MetaCxt,
+    /// values of the variables bound outside the block
+    env: &'a Env,
+    /// level of the block of recursive definitions
+    fix: Lvl,
+    /// arguments each definition of the block may be called on a smaller one
+    /// of
+    args: &'a [Vec<usize>],
+    /// definitions of the block that don't call it, and may be called on any
+    /// arguments
+    leaves: &'a [bool],
+    /// level of the argument that has to decrease, if any
+    arg: Option<Lvl>,
+    /// variables structurally smaller than the argument
+    smaller: Vec<Lvl>,
+    /// names of the variables bound inside the definition
+    names: Vec<Name>,
+    /// whether the calls have to be guarded by a `delay` instead
+    corecursive: bool,
+    /// whether only constructors and the like are between the top of the
+    /// definition and here
+    guarding: bool,
+    /// whether a `delay` reached while guarding is
+    delayed: bool,
+}
+
+impl Checker<'_> {
+    /// Whether `t` is the argument or a variable smaller than it.
+    fn decreases(&self, lvl: Lvl, t: &Term) -> bool {
+        match t.unspanned() {
+            Term::TV(x) => {
+                let x = lvl - 1 - x.0;
+                self.arg == Some(x) || self.smaller.contains(&x)
+            }
+            _ => false,
+        }
+    }
+
+    /// Check the recursive calls in `t`, returning the first one that isn't
+    /// on a smaller argument, or guarded by a `delay` for corecursive ones.
+    fn go(&mut self, lvl: Lvl, t: &Term) -> Result<(), Term> {
+        match t {
+            Term::TV(_) | Term::TApp(..) => {
+                let mut head = t;
+                let mut args = Vec::new();
+                while let Term::TApp(t, u, _) = head {
+                    args.push(u);
+                    head = t.unspanned();
+                }
+                args.reverse();
+
+                if let Term::TV(x) = head {
+                    let j = (lvl - 1 - x.0).wrapping_sub(self.fix);
+                    if j < self.args.len() && !self.leaves.get(j).is_some_and(|leaf| *leaf) {
+                        let smaller = |u: &&Tm| match u.unspanned() {
+                            Term::TV(y) => self.smaller.contains(&(lvl - 1 - y.0)),
+                            _ => false,
+                        };
+                        let ok = match self.corecursive {
+                            true => self.guarding && self.delayed,
+                            false => self.args[j]
+                                .iter()
+                                .any(|k| args.get(*k).is_some_and(smaller)),
+                        };
+                        if !ok {
+                            return Err(t.clone());
+                        }
+                    }
+                    // the arguments of constructors stay guarded
+                    let x = lvl - 1 - x.0;
+                    if x < self.fix && self.constructor(x, args.len()) {
+                        return args.into_iter().try_for_each(|u| self.go(lvl, u));
+                    }
+                } else {
+                    self.unguarded(lvl, head)?;
+                }
+                args.into_iter().try_for_each(|u| self.unguarded(lvl, u))
+            }
+            Term::Tλ(x, _, t) => self.under(lvl, x, t),
+            // the call gets the innermost span around it, if any
+            Term::TSrcPos(pos, t) => self.go(lvl, t).map_err(|call| match call {
+                Term::TSrcPos(..) => call,
+                call => Term::TSrcPos(pos.clone(), call.into()),
+            }),
+            Term::TFix(fix, _) => fix.iter().try_for_each(|(_, _, t)| {
+                let names = self.names.len();
+                self.names.extend(fix.iter().map(|(x, _, _)| x.clone()));
+                self.unguarded(lvl + fix.len(), t)?;
+                self.names.truncate(names);
+                Ok(())
+            }),
+            Term::TΠ(x, _, a, b) | Term::TΣ(x, a, b) => {
+                self.unguarded(lvl, a)?;
+                let guarding = std::mem::replace(&mut self.guarding, false);
+                self.under(lvl, x, b)?;
+                self.guarding = guarding;
+                Ok(())
+            }
+            Term::TLet(x, a, t, u) => {
+                self.unguarded(lvl, a)?;
+                self.unguarded(lvl, t)?;
+                self.under(lvl, x, u)
+            }
+            Term::Tσ(t, u) => {
+                self.go(lvl, t)?;
+                self.go(lvl, u)
+            }
+            Term::TRefl(t, u) | Term::TAppend(t, u) | Term::TIntOp(_, t, u) => {
+                self.unguarded(lvl, t)?;
+                self.unguarded(lvl, u)
+            }
+            Term::TDelay(t) => {
+                let delayed = self.delayed;
+                self.delayed |= self.guarding;
+                self.go(lvl, t)?;
+                self.delayed = delayed;
+                Ok(())
+            }
+            Term::TProj1(t)
+            | Term::TProj2(t)
+            | Term::TSuc(t)
+            | Term::TLength(t)
+            | Term::TLazy(t)
+            | Term::TForce(t)
+            | Term::TField(_, _, t) => self.unguarded(lvl, t),
+            // the predecessor bound by the successor method is smaller
+            Term::TNatElim(p, z, s, n) => {
+                [p, n]
+                    .into_iter()
+                    .try_for_each(|t| self.unguarded(lvl, t))?;
+                self.go(lvl, z)?;
+                match self.decreases(lvl, n) {
+                    true => self.method(lvl, s, &[true, false]),
+                    false => self.go(lvl, s),
+                }
+            }
+            Term::TBoolElim(p, t, f, b) => {
+                [p, b]
+                    .into_iter()
+                    .try_for_each(|t| self.unguarded(lvl, t))?;
+                [t, f].into_iter().try_for_each(|t| self.go(lvl, t))
+            }
+            Term::TEq(a, t, u) => [a, t, u]
+                .into_iter()
+                .try_for_each(|t| self.unguarded(lvl, t)),
+            Term::TJ(a, t, p, pr, u, e) => [a, t, p, pr, u, e]
+                .into_iter()
+                .try_for_each(|t| self.unguarded(lvl, t)),
+            Term::TCon(_, _, args) | Term::TRecordLit(_, args) => {
+                args.iter().try_for_each(|t| self.go(lvl, t))
+            }
+            Term::TData(_, args) | Term::TRecord(_, args) => {
+                args.iter().try_for_each(|t| self.unguarded(lvl, t))
+            }
+            // the arguments bound by the methods are smaller, their induction
+            // hypotheses aren't
+            Term::TElim(d, args, t) => {
+                self.unguarded(lvl, t)?;
+                let decreases = self.decreases(lvl, t);
+                let methods = d.params + 1..d.params + 1 + d.cons.len();
+
+                for (j, u) in args.iter().enumerate() {
+                    if !methods.contains(&j) {
+                        self.unguarded(lvl, u)?;
+                        continue;
+                    }
+                    if !decreases {
+                        self.go(lvl, u)?;
+                        continue;
+                    }
+
+                    let rec = d.recursive(self.metas, j - methods.start);
+                    let binders: Vec<bool> = rec
+                        .into_iter()
+                        .flat_map(|rec| [Some(true), rec.then_some(false)])
+                        .flatten()
+                        .collect();
+                    self.method(lvl, u, &binders)?;
+                }
+                Ok(())
+            }
+            Term::TMeta(_)
+            | Term::TInsertedMeta(..)
+            | Term::TU(_)
+            | Term::TNat
+            | Term::TNatLit(_)
+            | Term::TBool
+            | Term::TTrue
+            | Term::TFalse
+            | Term::TString
+            | Term::TStrLit(_)
+            | Term::TChar
+            | Term::TCharLit(_)
+            | Term::TInt
+            | Term::TIntLit(_) => Ok(()),
+        }
+    }
+
+    /// Whether the variable `x` bound outside the block is a constructor,
+    /// applied to `n` arguments.
+    fn constructor(&mut self, x: Lvl, n: usize) -> bool {
+        let mut v = self.env[Ix(self.fix - 1 - x)].clone();
+        for k in 0..n {
+            v = match force(self.metas, v) {
+                Value::Vλ(_, _, t) => {
+                    eval_closure(self.metas, t, Value::VRigid(self.fix + k, vec![]))
+                }
+                _ => return false,
+            };
+        }
+        matches!(force(self.metas, v), Value::VCon(..))
+    }
+
+    /// Check `t` where its calls aren't guarded, being eliminated or passed on.
+    fn unguarded(&mut self, lvl: Lvl, t: &Term) -> Result<(), Term> {
+        let guarding = std::mem::replace(&mut self.guarding, false);
+        self.go(lvl, t)?;
+        self.guarding = guarding;
+        Ok(())
+    }
+
+    /// Check a method `t`, whose leading lambdas bind variables that are
+    /// smaller than the argument where `binders` says so.
+    fn method(&mut self, lvl: Lvl, t: &Term, binders: &[bool]) -> Result<(), Term> {
+        match (binders, t) {
+            (_, Term::TSrcPos(_, t)) => self.method(lvl, t, binders),
+            ([smaller, binders @ ..], Term::Tλ(x, _, t)) => {
+                self.names.push(x.clone());
+                if *smaller {
+                    self.smaller.push(lvl);
+                }
+                self.method(lvl + 1, t, binders)?;
+                if *smaller {
+                    self.smaller.pop();
+                }
+                self.names.pop();
+                Ok(())
+            }
+            _ => self.go(lvl, t),
+        }
+    }
+
+    fn under(&mut self, lvl: Lvl, x: &Name, t: &Term) -> Result<(), Term> {
+        self.names.push(x.clone());
+        self.go(lvl + 1, t)?;
+        self.names.pop();
+        Ok(())
+    }
+}
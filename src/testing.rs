@@ -0,0 +1,406 @@
+//! Generators of random terms and the round trips they should survive, for
+//! fuzzing the parser, the printers and evaluation, with the `testing`
+//! feature.
+//!
+//! A [`Gen`] is seeded, so that a failing case can be reproduced from its
+//! seed. [`Raw`] terms are generated well-scoped but not well-typed, for the
+//! parser and the printer of surface syntax. [`Typed`] terms are closed and
+//! simply typed over the base types, with shadowed names and `_` binders
+//! whose variable is used, for the printer of core terms and evaluation.
+//! [`golden`] compares the elaboration of example files with snapshots.
+
+use std::fmt::Display;
+
+use chumsky::prelude::Simple;
+
+use crate::{
+    elaborator::Elaborator,
+    error::Error,
+    parser::{parse, ParseError},
+    span::Span,
+    Icit, Ix, Name, Pattern, Raw, TPrettyPrinter, Term, Unfold,
+};
+
+pub mod golden;
+
+/// Source of randomness of the generators: a SplitMix64 generator and the
+/// size of the terms to generate
+#[derive(Debug, Clone)]
+pub struct Gen {
+    state: u64,
+    size: usize,
+}
+
+impl Gen {
+    pub fn new(seed: u64) -> Self {
+        Gen {
+            state: seed,
+            size: 6,
+        }
+    }
+
+    /// Generate terms nested at most `size` deep.
+    pub fn with_size(self, size: usize) -> Self {
+        Gen { size, ..self }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number below `n`, which must be positive.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    pub fn choose<'a, T>(&mut self, xs: &'a [T]) -> &'a T {
+        &xs[self.below(xs.len())]
+    }
+
+    fn icit(&mut self) -> Icit {
+        *self.choose(&[Icit::Expl, Icit::Expl, Icit::Impl, Icit::Inst])
+    }
+
+    fn name(&mut self, names: &[&str]) -> Name {
+        (*self.choose(names)).into()
+    }
+}
+
+/// Values that can be generated at random, like `arbitrary::Arbitrary`
+pub trait Arbitrary: Sized {
+    fn arbitrary(g: &mut Gen) -> Self;
+}
+
+/// names of variables; `suc` shadows a built-in
+const NAMES: &[&str] = &["x", "y", "f", "A", "n", "suc"];
+const FIELDS: &[&str] = &["fst", "snd", "val"];
+const CONS: &[&str] = &["zero", "suc", "nil", "cons"];
+const BUILTINS: &[&str] = &["Nat", "zero", "Bool", "true", "String", "append"];
+
+impl Arbitrary for Raw {
+    fn arbitrary(g: &mut Gen) -> Self {
+        raw(g, g.size, &mut Vec::new())
+    }
+}
+
+/// A raw term of depth at most `size` whose variables are bound in `scope`
+/// or built in.
+fn raw(g: &mut Gen, size: usize, scope: &mut Vec<Name>) -> Raw {
+    // `_` binds no variable that can be referred to
+    let var = |g: &mut Gen, scope: &[Name]| {
+        let scope: Vec<&Name> = scope.iter().filter(|x| &***x != "_").collect();
+        match scope.is_empty() || g.below(4) == 0 {
+            true => Raw::RVar(g.name(BUILTINS)),
+            false => Raw::RVar((*g.choose(&scope)).clone()),
+        }
+    };
+    if size == 0 {
+        return match g.below(7) {
+            0 => Raw::RU(*g.choose(&[None, Some(1)])),
+            1 => Raw::RNatLit(g.below(100) as u64),
+            2 => Raw::RStrLit(g.name(NAMES)),
+            3 => Raw::RCharLit(*g.choose(&['a', 'z', '0'])),
+            4 => Raw::RIntLit(-(g.below(100) as i64) - 1),
+            5 => Raw::RHole(g.choose(&[None, Some("h".into())]).clone()),
+            _ => var(g, scope),
+        };
+    }
+
+    let size = size - 1;
+    let sub = |g: &mut Gen, scope: &mut Vec<Name>| {
+        let size = g.below(size + 1);
+        Box::new(raw(g, size, scope))
+    };
+    let under = |g: &mut Gen, scope: &mut Vec<Name>, xs: &[Name]| {
+        scope.extend(xs.iter().cloned());
+        let size = g.below(size + 1);
+        let t = raw(g, size, scope);
+        scope.truncate(scope.len() - xs.len());
+        Box::new(t)
+    };
+    let binder = |g: &mut Gen| match g.below(5) {
+        0 => Name::from("_"),
+        _ => g.name(NAMES),
+    };
+
+    match g.below(16) {
+        0 => var(g, scope),
+        1 => {
+            let x = binder(g);
+            Raw::RLam(x.clone(), g.icit(), under(g, scope, &[x]))
+        }
+        2 | 3 => Raw::RApp(sub(g, scope), sub(g, scope), g.icit()),
+        4 => Raw::RAppNamed(sub(g, scope), g.name(NAMES), sub(g, scope)),
+        5 | 6 => {
+            let x = binder(g);
+            let a = sub(g, scope);
+            Raw::RPi(x.clone(), g.icit(), a, under(g, scope, &[x]))
+        }
+        7 => {
+            let x = binder(g);
+            let a = sub(g, scope);
+            Raw::RSigma(x.clone(), a, under(g, scope, &[x]))
+        }
+        8 => Raw::RPair(sub(g, scope), sub(g, scope)),
+        9 => {
+            let x = binder(g);
+            let (a, t) = (sub(g, scope), sub(g, scope));
+            Raw::RLet(x.clone(), a, t, under(g, scope, &[x]))
+        }
+        10 => {
+            let x = g.name(NAMES);
+            let a = sub(g, scope);
+            let t = under(g, scope, std::slice::from_ref(&x));
+            Raw::RLetRec(x.clone(), a, t, under(g, scope, &[x]))
+        }
+        11 => match g.below(2) {
+            0 => Raw::RProj1(sub(g, scope)),
+            _ => Raw::RProj2(sub(g, scope)),
+        },
+        12 => Raw::RIf(sub(g, scope), sub(g, scope), sub(g, scope)),
+        13 => {
+            let t = sub(g, scope);
+            let mut branches = Vec::new();
+            for _ in 0..=g.below(2) {
+                let xs: Vec<(Name, Icit)> = (0..g.below(3))
+                    .map(|_| (binder(g), *g.choose(&[Icit::Expl, Icit::Impl])))
+                    .collect();
+                let names: Vec<Name> = xs.iter().map(|(x, _)| x.clone()).collect();
+                let u = under(g, scope, &names);
+                branches.push((Pattern::PCon(g.name(CONS), xs), *u));
+            }
+            if g.below(2) == 0 {
+                branches.push((Pattern::PWild, *sub(g, scope)));
+            }
+            Raw::RMatch(t, branches)
+        }
+        14 => Raw::RField(sub(g, scope), g.name(FIELDS)),
+        _ => {
+            let fields = (0..g.below(3))
+                .map(|i| (FIELDS[i].into(), *sub(g, scope)))
+                .collect();
+            Raw::RRecordLit(fields)
+        }
+    }
+}
+
+/// A closed term with its type
+#[derive(Debug, Clone)]
+pub struct Typed {
+    pub term: Term,
+    pub ty: Term,
+}
+
+impl Arbitrary for Typed {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let ty = simple_type(g, g.size / 2);
+        let term = typed(g, g.size, &mut Vec::new(), &ty);
+        Typed { term, ty }
+    }
+}
+
+/// A closed function type of depth at most `size` over the base types.
+fn simple_type(g: &mut Gen, size: usize) -> Term {
+    match size == 0 || g.below(2) == 0 {
+        true => g
+            .choose(&[Term::TNat, Term::TBool, Term::TString, Term::TChar])
+            .clone(),
+        false => {
+            let a = simple_type(g, size - 1);
+            let b = simple_type(g, size - 1);
+            Term::TΠ("_".into(), Icit::Expl, a.into(), b.into())
+        }
+    }
+}
+
+/// A term of the closed type `ty` in the context of the variables of the
+/// types `cxt`, the last one bound innermost.
+fn typed(g: &mut Gen, size: usize, cxt: &mut Vec<Term>, ty: &Term) -> Term {
+    // the variables that return `ty` applied to arguments of the types
+    let heads: Vec<(usize, Vec<Term>)> = cxt
+        .iter()
+        .rev()
+        .enumerate()
+        .filter_map(|(ix, a)| {
+            let mut args = Vec::new();
+            let mut a = a;
+            loop {
+                if a.alpha_eq(ty) {
+                    break Some((ix, args));
+                }
+                let Term::TΠ(_, _, dom, cod) = a else {
+                    break None;
+                };
+                args.push((**dom).clone());
+                a = cod;
+            }
+        })
+        .filter(|(_, args)| size > 0 || args.is_empty())
+        .collect();
+
+    let choice = match g.below(4) {
+        0 | 1 if !heads.is_empty() => 0,
+        2 if size > 0 => 1,
+        _ => 2,
+    };
+    let binder = |g: &mut Gen| match g.below(4) {
+        0 => Name::from("_"),
+        _ => g.name(NAMES),
+    };
+
+    match choice {
+        0 => {
+            let (ix, args) = g.choose(&heads).clone();
+            args.iter().fold(Term::TV(Ix(ix)), |t, a| {
+                let u = typed(g, size.saturating_sub(1), cxt, a);
+                Term::TApp(t.into(), u.into(), Icit::Expl)
+            })
+        }
+        1 => {
+            let a = simple_type(g, 1);
+            let t = typed(g, size - 1, cxt, &a);
+            cxt.push(a.clone());
+            let u = typed(g, size - 1, cxt, ty);
+            cxt.pop();
+            Term::TLet(binder(g), a.into(), t.into(), u.into())
+        }
+        _ => match ty {
+            Term::TΠ(_, _, a, b) => {
+                cxt.push((**a).clone());
+                let t = typed(g, size.saturating_sub(1), cxt, b);
+                cxt.pop();
+                Term::Tλ(binder(g), Icit::Expl, t.into())
+            }
+            Term::TNat => Term::TNatLit(g.below(100) as u64),
+            Term::TBool => g.choose(&[Term::TTrue, Term::TFalse]).clone(),
+            Term::TString => Term::TStrLit(g.name(NAMES)),
+            _ => Term::TCharLit(*g.choose(&['a', 'z', '0'])),
+        },
+    }
+}
+
+/// A round trip that changed its input
+#[derive(Debug)]
+pub enum RoundTripError {
+    /// the printed term doesn't parse
+    Parse {
+        printed: String,
+        errors: Vec<ParseError>,
+    },
+    /// the printed term parses, but doesn't elaborate
+    Elab { printed: String, error: Error },
+    /// parsing the printed term and printing it again gives `reprinted`, or
+    /// elaborating it gives a different term that prints as `reprinted`
+    Changed { printed: String, reprinted: String },
+    /// the normal form `normal` normalizes further to `again`
+    NotIdempotent { normal: String, again: String },
+}
+
+impl Display for RoundTripError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RoundTripError::Parse { printed, errors } => {
+                write!(f, "`{printed}` doesn't parse: {errors:?}")
+            }
+            RoundTripError::Elab { printed, error } => {
+                write!(f, "`{printed}` doesn't elaborate: {:?}", error.kind)
+            }
+            RoundTripError::Changed { printed, reprinted } => {
+                write!(f, "`{printed}` comes back as `{reprinted}`")
+            }
+            RoundTripError::NotIdempotent { normal, again } => {
+                write!(f, "the normal form `{normal}` normalizes to `{again}`")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RoundTripError {}
+
+fn parse_printed(printed: &str) -> Result<Raw, RoundTripError> {
+    let parse_error = |errors| RoundTripError::Parse {
+        printed: printed.to_string(),
+        errors,
+    };
+    match parse(printed) {
+        Ok(Some(raw)) => Ok(raw),
+        Ok(None) => Err(parse_error(vec![Simple::custom(
+            Span::default(),
+            "expected a term",
+        )])),
+        Err(errors) => Err(parse_error(errors)),
+    }
+}
+
+/// Check that `raw` prints to source that parses back to a term printed the
+/// same way.
+pub fn check_raw(raw: &Raw) -> Result<(), RoundTripError> {
+    let printed = raw.to_string();
+    let reprinted = parse_printed(&printed)?.to_string();
+    match printed == reprinted {
+        true => Ok(()),
+        false => Err(RoundTripError::Changed { printed, reprinted }),
+    }
+}
+
+/// Check that the closed `term` prints to source that elaborates back to
+/// `term`, up to the names of binders, against the closed type `ty`.
+pub fn check_term(term: &Term, ty: &Term) -> Result<(), RoundTripError> {
+    let printed = TPrettyPrinter(&[], term).to_string();
+    let raw = parse_printed(&printed)?;
+
+    let mut elab = Elaborator::new();
+    let ty = elab.eval(ty.clone());
+    let t = match elab.check(raw, ty) {
+        Ok(t) => elab.zonk(t),
+        Err(error) => return Err(RoundTripError::Elab { printed, error }),
+    };
+
+    match t.alpha_eq(term) {
+        true => Ok(()),
+        false => Err(RoundTripError::Changed {
+            reprinted: TPrettyPrinter(&[], &t).to_string(),
+            printed,
+        }),
+    }
+}
+
+/// Check that the normal form of the closed `term`, `quote` of its `eval`,
+/// is its own normal form.
+pub fn check_normal_form(term: &Term) -> Result<(), RoundTripError> {
+    let mut elab = Elaborator::new();
+    let normal = elab.normalize(term.clone(), Unfold::Always);
+    let again = elab.normalize(normal.clone(), Unfold::Always);
+
+    match normal.alpha_eq(&again) {
+        true => Ok(()),
+        false => Err(RoundTripError::NotIdempotent {
+            normal: TPrettyPrinter(&[], &normal).to_string(),
+            again: TPrettyPrinter(&[], &again).to_string(),
+        }),
+    }
+}
+
+/// Run the round trips on `cases` terms of each kind, the `i`th generated
+/// from the seed `seed + i`, returning the seed of the first that fails.
+pub fn fuzz(seed: u64, cases: u64) -> Result<(), (u64, RoundTripError)> {
+    for seed in seed..seed + cases {
+        let fail = |err| (seed, err);
+
+        let raw = Raw::arbitrary(&mut Gen::new(seed));
+        check_raw(&raw).map_err(fail)?;
+
+        let Typed { term, ty } = Typed::arbitrary(&mut Gen::new(seed));
+        check_term(&term, &ty).map_err(fail)?;
+        check_normal_form(&term).map_err(fail)?;
+    }
+    Ok(())
+}
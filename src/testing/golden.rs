@@ -0,0 +1,224 @@
+//! Golden tests: each `.leonie` file of a directory is elaborated and what
+//! comes out, the zonked definitions with their types and the diagnostics, is
+//! compared with the snapshot checked in next to it, `file.snap`. Setting the
+//! environment variable [`UPDATE_VAR`] writes the snapshots instead.
+//!
+//! The definitions are printed with fixed [`PrintOptions`], so that a
+//! snapshot only changes with the output of elaboration.
+
+use std::{
+    fmt::{Display, Write},
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    diagnostics::{render_error_in, render_hole, render_warning},
+    elaborator::Elaborator,
+    error::Error,
+    modules::{ModuleError, ModuleLoader},
+    parser::parse_program_in,
+    ElabOptions, PrintOptions, TPrettyPrinter,
+};
+
+/// Environment variable that makes [`Golden::check_file`] write snapshots
+/// rather than compare with them
+pub const UPDATE_VAR: &str = "LEONIE_UPDATE_GOLDEN";
+
+/// How the files of golden tests are elaborated and printed
+#[derive(Debug, Clone, Default)]
+pub struct Golden {
+    pub elab: ElabOptions,
+    pub print: PrintOptions,
+}
+
+#[derive(Debug)]
+pub enum GoldenError {
+    Io(PathBuf, io::Error),
+    /// the file has no snapshot yet
+    Missing(PathBuf),
+    /// the snapshot of the file is `expected`, but elaborating it gives
+    /// `actual`
+    Mismatch {
+        file: PathBuf,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl Display for GoldenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GoldenError::Io(file, err) => write!(f, "{}: {err}", file.display()),
+            GoldenError::Missing(file) => write!(
+                f,
+                "{} has no snapshot, set {UPDATE_VAR}=1 to write it",
+                file.display()
+            ),
+            GoldenError::Mismatch {
+                file,
+                expected,
+                actual,
+            } => {
+                let (expected, actual): (Vec<&str>, Vec<&str>) =
+                    (expected.lines().collect(), actual.lines().collect());
+                let lines = expected.len().max(actual.len());
+                // the lines are the same when only their endings differ
+                let Some(n) = (0..lines).find(|n| expected.get(*n) != actual.get(*n)) else {
+                    return write!(
+                        f,
+                        "{} differs from its snapshot in its line endings or final newline, \
+                         set {UPDATE_VAR}=1 to update it",
+                        file.display()
+                    );
+                };
+                writeln!(
+                    f,
+                    "{} differs from its snapshot at line {}, set {UPDATE_VAR}=1 to update it",
+                    file.display(),
+                    n + 1
+                )?;
+                writeln!(f, "- {}", expected.get(n).unwrap_or(&""))?;
+                write!(f, "+ {}", actual.get(n).unwrap_or(&""))
+            }
+        }
+    }
+}
+
+impl std::error::Error for GoldenError {}
+
+/// The `.leonie` files of `dir`, sorted by name.
+pub fn example_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "leonie") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Where the snapshot of `file` is kept: next to it, with the extension
+/// `snap`.
+pub fn snapshot_path(file: &Path) -> PathBuf {
+    file.with_extension("snap")
+}
+
+impl Golden {
+    /// Elaborate `file`, with its imports relative to its directory, and
+    /// print the definitions, then the warnings, the goals of the holes and
+    /// the error that stopped elaboration, if any.
+    pub fn snapshot(&self, file: &Path) -> Result<String, GoldenError> {
+        let src = std::fs::read_to_string(file).map_err(|err| GoldenError::Io(file.into(), err))?;
+
+        let root = file.parent().unwrap_or(Path::new("."));
+        Ok(self.snapshot_with(ModuleLoader::new(root), file, &src))
+    }
+
+    /// Like [`Golden::snapshot`], with the source `src` of `file` given and
+    /// its imports resolved by `loader`, e.g. from
+    /// [`crate::modules::MemorySources`] in tests that write no files.
+    pub fn snapshot_with(&self, mut loader: ModuleLoader, file: &Path, src: &str) -> String {
+        let id = loader.add_source(file, src);
+
+        let mut out = String::new();
+        let program = match parse_program_in(id, src) {
+            Ok(program) => program,
+            Err(errs) => return render_error_in(loader.sources(), &Error::parse(errs)),
+        };
+
+        let mut elab = Elaborator::new();
+        elab.set_options(self.elab.clone());
+
+        // one declaration at a time, so that each definition is printed in the
+        // scope it was elaborated in
+        let mut error = None;
+        for decl in program {
+            let mut names = elab.cxt().names();
+            let defs = match elab.load_program(&mut loader, file, vec![decl]) {
+                Ok(defs) => defs,
+                Err(err) => {
+                    error = Some(err);
+                    break;
+                }
+            };
+            for def in defs {
+                let print = |t| TPrettyPrinter(&names, t).with_options(self.print);
+                let _ = writeln!(out, "{} : {}", def.name, print(&def.ty));
+                if let Some(tm) = &def.tm {
+                    let _ = writeln!(out, "{} := {}", def.name, print(tm));
+                }
+                names.push(def.name);
+            }
+        }
+
+        for warning in elab.take_warnings() {
+            if warning.pos.file == id {
+                out.push_str(&render_warning(src, &warning));
+            }
+        }
+        for hole in elab.take_holes() {
+            if hole.pos.file == id {
+                out.push_str(&render_hole(src, &hole));
+            }
+        }
+        match error {
+            None => {}
+            Some(ModuleError::Io(file, err)) => {
+                let _ = writeln!(out, "error: can't read {}: {err}", file.display());
+            }
+            Some(ModuleError::Check(_, err)) => {
+                out.push_str(&render_error_in(loader.sources(), &err));
+            }
+            Some(ModuleError::Cycle(cycle)) => {
+                let cycle: Vec<String> = cycle.iter().map(ToString::to_string).collect();
+                let _ = writeln!(out, "error: import cycle {}", cycle.join(" -> "));
+            }
+        }
+
+        out
+    }
+
+    /// Compare the snapshot of `file` with the one checked in, or write it
+    /// with [`UPDATE_VAR`] set.
+    pub fn check_file(&self, file: &Path) -> Result<(), GoldenError> {
+        let actual = self.snapshot(file)?;
+        let path = snapshot_path(file);
+
+        if std::env::var_os(UPDATE_VAR).is_some() {
+            return std::fs::write(&path, actual).map_err(|err| GoldenError::Io(path, err));
+        }
+
+        let expected = match std::fs::read_to_string(&path) {
+            Ok(expected) => expected,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(GoldenError::Missing(file.into()))
+            }
+            Err(err) => return Err(GoldenError::Io(path, err)),
+        };
+        match expected == actual {
+            true => Ok(()),
+            false => Err(GoldenError::Mismatch {
+                file: file.into(),
+                expected,
+                actual,
+            }),
+        }
+    }
+
+    /// Check every `.leonie` file of `dir`, returning how many there are, or
+    /// the failures.
+    pub fn check_dir(&self, dir: &Path) -> Result<usize, Vec<GoldenError>> {
+        let files = example_files(dir).map_err(|err| vec![GoldenError::Io(dir.into(), err)])?;
+        let errors: Vec<GoldenError> = files
+            .iter()
+            .filter_map(|file| self.check_file(file).err())
+            .collect();
+        match errors.is_empty() {
+            true => Ok(files.len()),
+            false => Err(errors),
+        }
+    }
+}
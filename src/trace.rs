@@ -0,0 +1,126 @@
+use std::{cell::RefCell, fmt::Debug};
+
+use crate::{span::Span, Name, Raw, TPrettyPrinter, Term};
+
+/// Observes the recursive calls of `check` and `infer`. Types and terms are
+/// given quoted, in a context with the given `names`.
+pub trait ElabTracer: Debug {
+    /// entering `check raw : ty`
+    fn check(&mut self, names: &[Name], raw: &Raw, ty: &Term);
+
+    /// entering `infer raw`
+    fn infer(&mut self, names: &[Name], raw: &Raw);
+
+    /// leaving the innermost `check` or `infer`
+    fn exit(&mut self);
+
+    /// the `infer` that was just exited produced `term : ty`
+    fn inferred(&mut self, names: &[Name], term: &Term, ty: &Term);
+
+    /// the `check` or `infer` of the term at `pos` that was just exited
+    /// produced `term : ty`, the type checked against for a `check`
+    fn typed(&mut self, _names: &[Name], _pos: &Span, _term: &Term, _ty: &Term) {}
+}
+
+/// Prints an indented trace of the elaboration to stderr.
+#[derive(Debug, Default)]
+pub struct PrintTracer {
+    depth: usize,
+}
+
+impl ElabTracer for PrintTracer {
+    fn check(&mut self, names: &[Name], raw: &Raw, ty: &Term) {
+        eprintln!(
+            "{}check {raw}: {}",
+            " ".repeat(self.depth),
+            TPrettyPrinter(names, ty)
+        );
+        self.depth += 1;
+    }
+
+    fn infer(&mut self, _names: &[Name], raw: &Raw) {
+        eprintln!("{}infer {raw}", " ".repeat(self.depth));
+        self.depth += 1;
+    }
+
+    fn exit(&mut self) {
+        self.depth -= 1;
+    }
+
+    fn inferred(&mut self, names: &[Name], term: &Term, ty: &Term) {
+        eprintln!(
+            "{}|- {}: {}",
+            " ".repeat(self.depth),
+            TPrettyPrinter(names, term),
+            TPrettyPrinter(names, ty)
+        );
+    }
+}
+
+/// Elaborated subterm at a source span, with its type
+#[derive(Debug, Clone)]
+pub struct TypeInfo {
+    pub pos: Span,
+    /// the names in scope of the subterm, innermost last
+    pub names: Vec<Name>,
+    pub term: Term,
+    /// the type inferred, or the one checked against
+    pub ty: Term,
+}
+
+/// Records the types of the subterms at source spans, in the order their
+/// elaboration finished, passing everything on to the tracer it wraps.
+#[derive(Debug, Default)]
+pub struct TypeRecorder {
+    pub types: Vec<TypeInfo>,
+    inner: Option<std::rc::Rc<RefCell<dyn ElabTracer>>>,
+}
+
+impl TypeRecorder {
+    pub fn new(inner: Option<std::rc::Rc<RefCell<dyn ElabTracer>>>) -> Self {
+        Self {
+            types: Vec::new(),
+            inner,
+        }
+    }
+}
+
+impl ElabTracer for TypeRecorder {
+    fn check(&mut self, names: &[Name], raw: &Raw, ty: &Term) {
+        if let Some(inner) = &self.inner {
+            inner.borrow_mut().check(names, raw, ty);
+        }
+    }
+
+    fn infer(&mut self, names: &[Name], raw: &Raw) {
+        if let Some(inner) = &self.inner {
+            inner.borrow_mut().infer(names, raw);
+        }
+    }
+
+    fn exit(&mut self) {
+        if let Some(inner) = &self.inner {
+            inner.borrow_mut().exit();
+        }
+    }
+
+    fn inferred(&mut self, names: &[Name], term: &Term, ty: &Term) {
+        if let Some(inner) = &self.inner {
+            inner.borrow_mut().inferred(names, term, ty);
+        }
+    }
+
+    fn typed(&mut self, names: &[Name], pos: &Span, term: &Term, ty: &Term) {
+        if let Some(inner) = &self.inner {
+            inner.borrow_mut().typed(names, pos, term, ty);
+        }
+        if pos.start < pos.end {
+            self.types.push(TypeInfo {
+                pos: pos.clone(),
+                names: names.to_vec(),
+                term: term.clone(),
+                ty: ty.clone(),
+            });
+        }
+    }
+}
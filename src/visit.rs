@@ -0,0 +1,257 @@
+//! Traversals of raw and elaborated terms, for analyses and transformations
+//! that would otherwise repeat the recursion over every node.
+//!
+//! A [`RawVisitor`] walks a raw term, told about its variables, holes and the
+//! names bound on the way, and a [`TermFolder`] rebuilds an elaborated term,
+//! told how many binders it is under. Both recurse by default, through
+//! [`walk_raw`] and [`fold_term`], so an implementation only overrides the
+//! nodes it cares about, calling back into those to go on below them.
+
+use crate::{metas::MetaVar, Ix, Name, Pattern, Raw, Rc, Term, Tm};
+
+/// Walk over a raw term
+pub trait RawVisitor {
+    /// Visit `raw`, by default its subterms with [`walk_raw`].
+    fn visit(&mut self, raw: &Raw) {
+        walk_raw(self, raw);
+    }
+
+    /// The variable `x`, bound or free.
+    fn var(&mut self, _x: &Name) {}
+
+    /// The hole `?x`, or `_` for `None`.
+    fn hole(&mut self, _x: Option<&Name>) {}
+
+    /// `x` is bound in what is visited until the matching [`RawVisitor::unbind`].
+    fn bind(&mut self, _x: &Name) {}
+
+    fn unbind(&mut self, _x: &Name) {}
+}
+
+/// Visit the subterms of `raw` from left to right, binding the names of its
+/// binders around the subterms they scope over.
+pub fn walk_raw<V: RawVisitor + ?Sized>(v: &mut V, raw: &Raw) {
+    let under = |v: &mut V, x: &Name, t: &Raw| {
+        v.bind(x);
+        v.visit(t);
+        v.unbind(x);
+    };
+
+    match raw {
+        Raw::RVar(x) => v.var(x),
+        Raw::RHole(x) => v.hole(x.as_ref()),
+        Raw::RLam(x, _, t) => under(v, x, t),
+        Raw::RApp(t, u, _) | Raw::RAppNamed(t, _, u) | Raw::RPair(t, u) => {
+            v.visit(t);
+            v.visit(u);
+        }
+        Raw::RPi(x, _, a, b) | Raw::RSigma(x, a, b) => {
+            v.visit(a);
+            under(v, x, b);
+        }
+        Raw::RLet(x, a, t, u) => {
+            v.visit(a);
+            v.visit(t);
+            under(v, x, u);
+        }
+        Raw::RLetRec(x, a, t, u) => {
+            v.visit(a);
+            v.bind(x);
+            v.visit(t);
+            v.visit(u);
+            v.unbind(x);
+        }
+        Raw::RSrcPos(_, t) | Raw::RProj1(t) | Raw::RProj2(t) | Raw::RField(t, _) => v.visit(t),
+        Raw::RIf(b, t, f) => [b, t, f].into_iter().for_each(|t| v.visit(t)),
+        Raw::RMatch(t, branches) => {
+            v.visit(t);
+            for (pat, u) in branches {
+                let xs = match pat {
+                    Pattern::PCon(_, xs) => &xs[..],
+                    Pattern::PWild => &[],
+                };
+                xs.iter().for_each(|(x, _)| v.bind(x));
+                v.visit(u);
+                xs.iter().rev().for_each(|(x, _)| v.unbind(x));
+            }
+        }
+        Raw::RRecordLit(fields) => fields.iter().for_each(|(_, t)| v.visit(t)),
+        Raw::RU(_) | Raw::RNatLit(_) | Raw::RStrLit(_) | Raw::RCharLit(_) | Raw::RIntLit(_) => {}
+    }
+}
+
+/// Rebuilding of an elaborated term
+pub trait TermFolder {
+    /// Fold `t`, under `depth` binders more than the term the fold started
+    /// with, by default its subterms with [`fold_term`].
+    fn fold(&mut self, depth: usize, t: Term) -> Term {
+        fold_term(self, depth, t)
+    }
+
+    /// The variable `x`, kept by default.
+    fn var(&mut self, _depth: usize, x: Ix) -> Term {
+        Term::TV(x)
+    }
+
+    /// The meta `t`, a `TMeta` or a `TInsertedMeta`, kept by default. The
+    /// variables an inserted meta is applied to are given by their levels,
+    /// unlike the others.
+    fn meta(&mut self, _depth: usize, t: Term) -> Term {
+        t
+    }
+}
+
+/// Fold the subterms of `t` from left to right, each under the binders of
+/// `t` it is under, and put it back together.
+pub fn fold_term<F: TermFolder + ?Sized>(f: &mut F, depth: usize, t: Term) -> Term {
+    let mut go = |t: Tm, depth: usize| Rc::new(f.fold(depth, Rc::unwrap_or_clone(t)));
+
+    match t {
+        Term::TV(x) => f.var(depth, x),
+        t @ (Term::TMeta(_) | Term::TInsertedMeta(..)) => f.meta(depth, t),
+        Term::Tλ(x, i, t) => Term::Tλ(x, i, go(t, depth + 1)),
+        Term::TFix(fix, i) => {
+            let n = fix.len();
+            let fix = fix
+                .iter()
+                .map(|(x, k, t)| (x.clone(), *k, go(t.clone(), depth + n)))
+                .collect();
+            Term::TFix(fix, i)
+        }
+        Term::TΠ(x, i, a, b) => {
+            let a = go(a, depth);
+            Term::TΠ(x, i, a, go(b, depth + 1))
+        }
+        Term::Tσ(t, u) => {
+            let t = go(t, depth);
+            Term::Tσ(t, go(u, depth))
+        }
+        Term::TΣ(x, a, b) => {
+            let a = go(a, depth);
+            Term::TΣ(x, a, go(b, depth + 1))
+        }
+        Term::TLet(x, a, t, u) => {
+            let [a, t] = [a, t].map(|t| go(t, depth));
+            Term::TLet(x, a, t, go(u, depth + 1))
+        }
+        Term::TApp(t, u, i) => {
+            let t = go(t, depth);
+            Term::TApp(t, go(u, depth), i)
+        }
+        Term::TProj1(t) => Term::TProj1(go(t, depth)),
+        Term::TProj2(t) => Term::TProj2(go(t, depth)),
+        Term::TSuc(t) => Term::TSuc(go(t, depth)),
+        Term::TNatElim(p, z, s, n) => {
+            let [p, z, s, n] = [p, z, s, n].map(|t| go(t, depth));
+            Term::TNatElim(p, z, s, n)
+        }
+        Term::TBoolElim(p, t, f, b) => {
+            let [p, t, f, b] = [p, t, f, b].map(|t| go(t, depth));
+            Term::TBoolElim(p, t, f, b)
+        }
+        Term::TAppend(s, t) => {
+            let s = go(s, depth);
+            Term::TAppend(s, go(t, depth))
+        }
+        Term::TLength(s) => Term::TLength(go(s, depth)),
+        Term::TIntOp(op, x, y) => {
+            let x = go(x, depth);
+            Term::TIntOp(op, x, go(y, depth))
+        }
+        Term::TEq(a, x, y) => {
+            let [a, x, y] = [a, x, y].map(|t| go(t, depth));
+            Term::TEq(a, x, y)
+        }
+        Term::TRefl(a, x) => {
+            let a = go(a, depth);
+            Term::TRefl(a, go(x, depth))
+        }
+        Term::TJ(a, x, p, pr, y, e) => {
+            let [a, x, p, pr, y, e] = [a, x, p, pr, y, e].map(|t| go(t, depth));
+            Term::TJ(a, x, p, pr, y, e)
+        }
+        Term::TLazy(a) => Term::TLazy(go(a, depth)),
+        Term::TDelay(t) => Term::TDelay(go(t, depth)),
+        Term::TForce(t) => Term::TForce(go(t, depth)),
+        Term::TData(d, args) => Term::TData(d, args.into_iter().map(|t| go(t, depth)).collect()),
+        Term::TCon(d, c, args) => {
+            Term::TCon(d, c, args.into_iter().map(|t| go(t, depth)).collect())
+        }
+        Term::TElim(d, args, t) => {
+            let args = args.into_iter().map(|t| go(t, depth)).collect();
+            Term::TElim(d, args, go(t, depth))
+        }
+        Term::TRecord(r, args) => {
+            Term::TRecord(r, args.into_iter().map(|t| go(t, depth)).collect())
+        }
+        Term::TRecordLit(r, fields) => {
+            Term::TRecordLit(r, fields.into_iter().map(|t| go(t, depth)).collect())
+        }
+        Term::TField(r, f, t) => Term::TField(r, f, go(t, depth)),
+        Term::TSrcPos(pos, t) => Term::TSrcPos(pos, go(t, depth)),
+        t @ (Term::TU(_)
+        | Term::TNat
+        | Term::TNatLit(_)
+        | Term::TBool
+        | Term::TTrue
+        | Term::TFalse
+        | Term::TString
+        | Term::TStrLit(_)
+        | Term::TChar
+        | Term::TCharLit(_)
+        | Term::TInt
+        | Term::TIntLit(_)) => t,
+    }
+}
+
+/// The names `raw` refers to without binding them, each once, in the order
+/// they first occur. Globals and builtins are among them.
+pub fn free_vars(raw: &Raw) -> Vec<Name> {
+    let mut v = FreeVars::default();
+    v.visit(raw);
+    v.free
+}
+
+#[derive(Default)]
+struct FreeVars {
+    bound: Vec<Name>,
+    free: Vec<Name>,
+}
+
+impl RawVisitor for FreeVars {
+    fn var(&mut self, x: &Name) {
+        if !self.bound.contains(x) && !self.free.contains(x) {
+            self.free.push(x.clone());
+        }
+    }
+
+    fn bind(&mut self, x: &Name) {
+        self.bound.push(x.clone());
+    }
+
+    fn unbind(&mut self, _x: &Name) {
+        self.bound.pop();
+    }
+}
+
+/// Every occurrence of a meta in `t` from left to right, with the number of
+/// binders of `t` it is under.
+pub fn meta_occurrences(t: &Term) -> Vec<(MetaVar, usize)> {
+    let mut f = MetaOccurrences::default();
+    f.fold(0, t.clone());
+    f.occurrences
+}
+
+#[derive(Default)]
+struct MetaOccurrences {
+    occurrences: Vec<(MetaVar, usize)>,
+}
+
+impl TermFolder for MetaOccurrences {
+    fn meta(&mut self, depth: usize, t: Term) -> Term {
+        if let Term::TMeta(m) | Term::TInsertedMeta(m, _) = t {
+            self.occurrences.push((m, depth));
+        }
+        t
+    }
+}
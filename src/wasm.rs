@@ -0,0 +1,246 @@
+//! Bindings for an in-browser playground through `wasm-bindgen`, with the
+//! `wasm` feature.
+//!
+//! Sources go in as strings, and results come out as structs of strings. Each
+//! error comes both rendered against its source, as on the command line, and
+//! as a message with the characters it is about, for an editor to show.
+
+use std::{cell::RefCell, path::Path};
+
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    diagnostics::{
+        error_message, hole_message, render_error, render_error_in, render_hole, render_warning,
+    },
+    elaborator::Elaborator,
+    error::Error,
+    metas::HoleReport,
+    modules::{MemorySources, ModuleError, ModuleLoader, ModulePath},
+    parser::{self, parse_program_in},
+    ElabOptions, TPrettyPrinter, Unfold,
+};
+
+/// The file the sources of a session are named as in errors
+const PLAYGROUND_FILE: &str = "<playground>";
+
+#[wasm_bindgen]
+impl ElabOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Error, warning or goal of a hole in a source
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    /// `error`, `warning` or `goal`
+    pub severity: String,
+    /// the message with its notes, without the source
+    pub message: String,
+    /// the message rendered against the source
+    pub rendered: String,
+    /// the characters of the source it is about
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Diagnostic {
+    fn error(src: &str, err: &Error) -> Self {
+        Diagnostic {
+            severity: "error".into(),
+            message: error_message(err),
+            rendered: render_error(src, err),
+            start: err.pos.start,
+            end: err.pos.end,
+        }
+    }
+
+    fn warning(src: &str, err: &Error) -> Self {
+        Diagnostic {
+            severity: "warning".into(),
+            rendered: render_warning(src, err),
+            ..Diagnostic::error(src, err)
+        }
+    }
+
+    /// An error that isn't in `src`, put at its start.
+    fn elsewhere(message: String, rendered: String) -> Self {
+        Diagnostic {
+            severity: "error".into(),
+            message,
+            rendered,
+            start: 0,
+            end: 0,
+        }
+    }
+
+    fn goal(src: &str, hole: &HoleReport) -> Self {
+        Diagnostic {
+            severity: "goal".into(),
+            message: hole_message(hole),
+            rendered: render_hole(src, hole),
+            start: hole.pos.start,
+            end: hole.pos.end,
+        }
+    }
+}
+
+/// Declarations elaborated by [`Session::declare`]
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone)]
+pub struct Declared {
+    /// the names defined, in order
+    pub names: Vec<String>,
+    /// warnings, and the goals of the holes
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Term elaborated by a [`Session`], printed with its type
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone)]
+pub struct Elaborated {
+    pub term: String,
+    pub ty: String,
+    /// warnings, and the goals of the holes
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Parse a term, returning it printed as it parsed.
+#[wasm_bindgen]
+pub fn parse(src: &str) -> Result<String, Diagnostic> {
+    match parser::parse(src) {
+        Ok(raw) => Ok(raw.map(|raw| raw.to_string()).unwrap_or_default()),
+        Err(errs) => Err(Diagnostic::error(src, &Error::parse(errs))),
+    }
+}
+
+/// Playground session, the declarations elaborated stay in scope for later
+/// sources. They import the modules added with [`Session::add_module`], and
+/// the built-in `Prelude`.
+#[wasm_bindgen]
+#[derive(Debug)]
+pub struct Session {
+    elab: Elaborator,
+    modules: std::rc::Rc<RefCell<MemorySources>>,
+    loader: ModuleLoader,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new(ElabOptions::default())
+    }
+}
+
+#[wasm_bindgen]
+impl Session {
+    #[wasm_bindgen(constructor)]
+    pub fn new(options: ElabOptions) -> Self {
+        let mut elab = Elaborator::new();
+        elab.set_options(options);
+        let modules = std::rc::Rc::new(RefCell::new(MemorySources::new()));
+        let loader = ModuleLoader::with_provider("", modules.clone());
+        Session {
+            elab,
+            modules,
+            loader,
+        }
+    }
+
+    /// Give the module `path`, like `A.B`, the source `src` for the sources
+    /// that import it. A module imported already keeps the source it had.
+    pub fn add_module(&mut self, path: &str, src: &str) {
+        let path = ModulePath(path.split('.').map(Into::into).collect());
+        self.modules.borrow_mut().insert(path, src);
+    }
+
+    /// Elaborate the declarations of `src`, keeping them in scope.
+    pub fn declare(&mut self, src: &str) -> Result<Declared, Diagnostic> {
+        let file = Path::new(PLAYGROUND_FILE);
+        let id = self.loader.add_source(file, src);
+        let program = parse_program_in(id, src)
+            .map_err(|errs| Diagnostic::error(src, &Error::parse(errs)))?;
+
+        let res = self.elab.load_program(&mut self.loader, file, program);
+        let goals = self.goals(src);
+        let defs = res.map_err(|err| match err {
+            ModuleError::Check(_, err) if err.pos.file == id => Diagnostic::error(src, &err),
+            ModuleError::Check(_, err) => {
+                let location = self.loader.sources().location(&err.pos);
+                let message = format!("{location}: {}", error_message(&err));
+                Diagnostic::elsewhere(message, render_error_in(self.loader.sources(), &err))
+            }
+            ModuleError::Io(file, err) => {
+                let message = format!("can't read {}: {err}", file.display());
+                Diagnostic::elsewhere(message.clone(), format!("error: {message}\n"))
+            }
+            ModuleError::Cycle(cycle) => {
+                let cycle: Vec<String> = cycle.iter().map(ToString::to_string).collect();
+                let message = format!("import cycle {}", cycle.join(" -> "));
+                Diagnostic::elsewhere(message.clone(), format!("error: {message}\n"))
+            }
+        })?;
+
+        let mut diagnostics: Vec<Diagnostic> = self
+            .elab
+            .take_warnings()
+            .iter()
+            .map(|warning| Diagnostic::warning(src, warning))
+            .collect();
+        diagnostics.extend(goals);
+
+        Ok(Declared {
+            names: defs.iter().map(|def| def.name.to_string()).collect(),
+            diagnostics,
+        })
+    }
+
+    /// Elaborate the term `src` in the scope of the declarations.
+    pub fn elaborate(&mut self, src: &str) -> Result<Elaborated, Diagnostic> {
+        self.expr(src, false)
+    }
+
+    /// Like [`Session::elaborate`], with the term in normal form.
+    pub fn normalize(&mut self, src: &str) -> Result<Elaborated, Diagnostic> {
+        self.expr(src, true)
+    }
+}
+
+impl Session {
+    fn expr(&mut self, src: &str, normalize: bool) -> Result<Elaborated, Diagnostic> {
+        let res = self.elab.elaborate(src);
+        let goals = self.goals(src);
+        let res = res.map_err(|err| Diagnostic::error(src, &err))?;
+
+        let term = match normalize {
+            true => self
+                .elab
+                .normalize_limited(res.term, Unfold::Always)
+                .map_err(|err| Diagnostic::error(src, &err))?,
+            false => res.term,
+        };
+        let names = self.elab.cxt().names();
+        let mut diagnostics: Vec<Diagnostic> = res
+            .warnings
+            .iter()
+            .map(|warning| Diagnostic::warning(src, warning))
+            .collect();
+        diagnostics.extend(goals);
+
+        Ok(Elaborated {
+            term: TPrettyPrinter(&names, &term).to_string(),
+            ty: TPrettyPrinter(&names, &res.ty).to_string(),
+            diagnostics,
+        })
+    }
+
+    fn goals(&mut self, src: &str) -> Vec<Diagnostic> {
+        self.elab
+            .take_holes()
+            .iter()
+            .map(|hole| Diagnostic::goal(src, hole))
+            .collect()
+    }
+}
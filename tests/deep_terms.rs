@@ -0,0 +1,54 @@
+//! Evaluation and quotation of terms nested far deeper than the native stack
+//! would allow if they recursed.
+
+use leonie::{elaborator::Elaborator, Icit, Ix, Rc, Term};
+
+const DEPTH: usize = 100_000;
+
+/// Drop a term without the recursion of its drop glue.
+fn drop_deep(t: Term) {
+    let mut stack = vec![t];
+    while let Some(t) = stack.pop() {
+        if let Term::TApp(t, u, _) = t {
+            stack.extend([t, u].into_iter().filter_map(|t| Rc::try_unwrap(t).ok()));
+        }
+    }
+}
+
+#[test]
+fn deep_spine() {
+    let mut elab = Elaborator::with_axioms(&[("f", "Nat → Nat")]).unwrap();
+    // f 0 0 … 0
+    let mut t = Term::TV(Ix(0));
+    for n in 0..DEPTH {
+        t = Term::TApp(t.into(), Term::TNatLit(n as u64).into(), Icit::Expl);
+    }
+
+    let v = elab.eval(t.clone());
+    let t_ = elab.quote(v);
+
+    let (mut head, mut args) = (&t_, 0);
+    while let Term::TApp(t, u, _) = head {
+        assert!(matches!(**u, Term::TNatLit(n) if n as usize == DEPTH - 1 - args));
+        (head, args) = (t, args + 1);
+    }
+    assert!(matches!(head, Term::TV(Ix(0))));
+    assert_eq!(args, DEPTH);
+    drop_deep(t);
+    drop_deep(t_);
+}
+
+#[test]
+fn deep_arguments() {
+    let mut elab = Elaborator::new();
+    let id = Rc::new(Term::Tλ("x".into(), Icit::Expl, Term::TV(Ix(0)).into()));
+    // id (id (… (id 0)))
+    let mut t = Term::TNatLit(0);
+    for _ in 0..DEPTH {
+        t = Term::TApp(id.clone(), t.into(), Icit::Expl);
+    }
+
+    let v = elab.eval(t.clone());
+    assert!(matches!(elab.quote(v), Term::TNatLit(0)));
+    drop_deep(t);
+}
@@ -0,0 +1,19 @@
+//! The golden tests of `examples/`, see [`leonie::testing::golden`].
+
+#![cfg(feature = "testing")]
+
+use std::path::Path;
+
+use leonie::testing::golden::Golden;
+
+#[test]
+fn examples() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+    match Golden::default().check_dir(&dir) {
+        Ok(files) => assert!(files > 0, "no examples in {}", dir.display()),
+        Err(errs) => {
+            let errs: Vec<String> = errs.iter().map(ToString::to_string).collect();
+            panic!("{}", errs.join("\n"));
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! Elaboration of programs going on past the declarations that fail, which
+//! are left out of scope for the rest of the program.
+
+use std::path::Path;
+
+use leonie::{
+    elaborator::Elaborator,
+    error::ErrorKind,
+    modules::{MemorySources, ModuleError, ModuleLoader, ModulePath},
+    Decl, Icit, Raw,
+};
+
+fn v(x: &str) -> Raw {
+    Raw::RVar(x.into())
+}
+
+fn def(x: &str, a: Raw, t: Raw) -> Decl {
+    Decl::DDef(x.into(), a, t)
+}
+
+fn suc(t: Raw) -> Raw {
+    Raw::RApp(v("suc").into(), t.into(), Icit::Expl)
+}
+
+#[test]
+fn every_error() {
+    let mut loader = ModuleLoader::with_provider(".", MemorySources::new());
+    let mut elab = Elaborator::new();
+    let arrow = Raw::RPi("_".into(), Icit::Expl, v("Nat").into(), v("Nat").into());
+    let program = vec![
+        def("one", v("Nat"), Raw::RNatLit(1)),
+        def("bad", v("Nat"), Raw::RU(None)),
+        Decl::DImport(ModulePath(vec!["Missing".into()])),
+        def("two", v("Nat"), suc(v("one"))),
+        def("worse", v("Nat"), suc(v("bad"))),
+        def("three", v("Nat"), suc(v("two"))),
+        def(
+            "k",
+            arrow.clone(),
+            Raw::RLam("x".into(), Icit::Expl, v("one").into()),
+        ),
+    ];
+    let errs = elab
+        .load_program_recovering(&mut loader, Path::new("main.leonie"), program)
+        .unwrap_err();
+
+    let [ModuleError::Check(_, mismatch), ModuleError::Io(..), ModuleError::Check(_, unbound)] =
+        &errs[..]
+    else {
+        panic!("{errs:?}")
+    };
+    assert!(matches!(mismatch.kind, ErrorKind::Mismatch { .. }));
+    assert!(matches!(&unbound.kind, ErrorKind::UnboundVariable(x) if &**x == "bad"));
+    // the declarations after the failing ones are still in scope, and warned
+    // about
+    elab.infer(v("three")).unwrap();
+    let warnings = elab.take_warnings();
+    let [warning] = &warnings[..] else {
+        panic!("{warnings:?}")
+    };
+    assert!(matches!(&warning.kind, ErrorKind::UnusedBinder { name } if &**name == "x"));
+}
+
+#[test]
+fn no_error() {
+    let mut loader = ModuleLoader::with_provider(".", MemorySources::new());
+    let mut elab = Elaborator::new();
+    let program = vec![
+        def("one", v("Nat"), Raw::RNatLit(1)),
+        def("two", v("Nat"), suc(v("one"))),
+    ];
+    let defs = elab
+        .load_program_recovering(&mut loader, Path::new("main.leonie"), program)
+        .unwrap();
+    assert_eq!(defs.len(), 2);
+}
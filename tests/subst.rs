@@ -0,0 +1,114 @@
+//! Index arithmetic of substitution, shifting and strengthening, and how they
+//! keep the masks of inserted metas in step with the context.
+
+use leonie::{Icit, Ix, Term, BD};
+
+fn var(x: usize) -> Term {
+    Term::TV(Ix(x))
+}
+
+fn lam(t: Term) -> Term {
+    Term::Tλ("y".into(), Icit::Expl, t.into())
+}
+
+fn app(t: Term, u: Term) -> Term {
+    Term::TApp(t.into(), u.into(), Icit::Expl)
+}
+
+fn meta(bds: &[BD]) -> Term {
+    Term::TInsertedMeta(0, bds.to_vec())
+}
+
+fn assert_eq(t: Term, u: Term) {
+    assert!(t.alpha_eq(&u), "{t:?} != {u:?}");
+}
+
+#[test]
+fn subst_under_binder() {
+    // λ y. x [x := z], with `z` weakened under `y`
+    assert_eq(lam(var(1)).subst(Ix(0), &var(3)), lam(var(4)));
+    assert_eq(lam(var(0)).subst(Ix(0), &var(3)), lam(var(0)));
+}
+
+#[test]
+fn subst_past_cut_off() {
+    // the variables outside `x` are one closer once it is gone
+    assert_eq(var(2).subst(Ix(1), &var(5)), var(1));
+    assert_eq(var(1).subst(Ix(1), &var(5)), var(5));
+    assert_eq(var(0).subst(Ix(1), &var(5)), var(0));
+}
+
+#[test]
+fn subst_meta() {
+    use BD::*;
+    // the meta is applied to `x` explicitly, and then to what replaces it
+    let t = app(app(Term::TMeta(0), var(0)), var(7));
+    assert_eq(meta(&[Bound, Bound]).subst(Ix(0), &var(7)), t);
+    assert_eq(
+        meta(&[Bound, Defined]).subst(Ix(0), &var(7)),
+        meta(&[Bound]),
+    );
+    // under a binder, the mask covers the context of the meta
+    assert_eq(
+        lam(meta(&[Bound, Defined, Bound])).subst(Ix(0), &var(7)),
+        lam(meta(&[Bound, Bound])),
+    );
+}
+
+#[test]
+fn shift_under_binder() {
+    assert_eq(lam(var(1)).shift(Ix(0), 2), lam(var(3)));
+    assert_eq(lam(var(0)).shift(Ix(0), 2), lam(var(0)));
+}
+
+#[test]
+fn shift_past_cut_off() {
+    assert_eq(var(0).shift(Ix(1), 1), var(0));
+    assert_eq(var(1).shift(Ix(1), 1), var(2));
+    assert_eq(var(1).weaken(3), var(4));
+}
+
+#[test]
+fn shift_meta() {
+    use BD::*;
+    // the meta is applied to none of the new variables, wherever they go
+    assert_eq(
+        meta(&[Bound, Bound]).shift(Ix(1), 1),
+        meta(&[Bound, Defined, Bound]),
+    );
+    assert_eq(
+        meta(&[Defined, Bound]).shift(Ix(0), 2),
+        meta(&[Defined, Bound, Defined, Defined]),
+    );
+    assert_eq(
+        lam(meta(&[Bound, Bound])).shift(Ix(0), 1),
+        lam(meta(&[Bound, Defined, Bound])),
+    );
+}
+
+#[test]
+fn strengthen_under_binder() {
+    let t = lam(var(2)).strengthen(Ix(0)).unwrap();
+    assert_eq(t, lam(var(1)));
+    assert!(lam(var(1)).strengthen(Ix(0)).is_none());
+    let t = lam(var(0)).strengthen(Ix(0)).unwrap();
+    assert_eq(t, lam(var(0)));
+}
+
+#[test]
+fn strengthen_past_cut_off() {
+    assert_eq(var(2).strengthen(Ix(1)).unwrap(), var(1));
+    assert_eq(var(0).strengthen(Ix(1)).unwrap(), var(0));
+    assert!(var(1).strengthen(Ix(1)).is_none());
+}
+
+#[test]
+fn strengthen_meta() {
+    use BD::*;
+    // a meta applied to `x` refers to it
+    assert!(meta(&[Bound, Bound]).strengthen(Ix(0)).is_none());
+    let t = meta(&[Bound, Defined]).strengthen(Ix(0)).unwrap();
+    assert_eq(t, meta(&[Bound]));
+    let t = lam(meta(&[Defined, Bound, Bound])).strengthen(Ix(1));
+    assert_eq(t.unwrap(), lam(meta(&[Bound, Bound])));
+}
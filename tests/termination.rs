@@ -0,0 +1,77 @@
+//! Termination of blocks of mutually recursive definitions with many
+//! parameters, each of which could be the one decreasing.
+
+use leonie::{elaborator::Elaborator, error::ErrorKind, Decl, Icit, Pattern, Raw, Term, Unfolds};
+
+const DEFS: usize = 8;
+const PARAMS: usize = 8;
+
+fn v(x: &str) -> Raw {
+    Raw::RVar(x.into())
+}
+
+fn app(t: Raw, u: Raw) -> Raw {
+    Raw::RApp(t.into(), u.into(), Icit::Expl)
+}
+
+fn f(i: usize) -> Raw {
+    v(&format!("f{}", i % DEFS))
+}
+
+/// `data N : U | z : N | s : N → N`
+fn nat() -> Decl {
+    let arrow = Raw::RPi("_".into(), Icit::Expl, v("N").into(), v("N").into());
+    let cons = vec![("z".into(), v("N")), ("s".into(), arrow)];
+    Decl::DData("N".into(), vec![], Raw::RU(None), cons)
+}
+
+/// `f0`, .., each matching on its last parameter and calling the next one on
+/// `args` of the parameters and the predecessor `k`
+fn block(args: impl Fn(&[Raw], Raw) -> Vec<Raw>) -> Decl {
+    let xs: Vec<Raw> = (0..PARAMS).map(|x| v(&format!("x{x}"))).collect();
+    let ty = (0..PARAMS).fold(v("N"), |b, _| {
+        Raw::RPi("_".into(), Icit::Expl, v("N").into(), b.into())
+    });
+    let defs = (0..DEFS).map(|i| {
+        let call = args(&xs[..PARAMS - 1], v("k"))
+            .into_iter()
+            .fold(f(i + 1), app);
+        let branches = vec![
+            (Pattern::PCon("z".into(), vec![]), v("z")),
+            (
+                Pattern::PCon("s".into(), vec![("k".into(), Icit::Expl)]),
+                call,
+            ),
+        ];
+        let body = Raw::RMatch(xs[PARAMS - 1].clone().into(), branches);
+        let t = (0..PARAMS).rev().fold(body, |t, x| {
+            Raw::RLam(format!("x{x}").as_str().into(), Icit::Expl, t.into())
+        });
+        (format!("f{i}").as_str().into(), ty.clone(), t)
+    });
+    Decl::DMutual(vec![], defs.collect())
+}
+
+#[test]
+fn decreasing_last() {
+    let block = block(|xs, k| [xs, &[k]].concat());
+    let defs = Elaborator::new().check_program(vec![nat(), block]).unwrap();
+    for def in &defs[defs.len() - DEFS..] {
+        let Some(Term::TFix(fix, _)) = def.tm.as_ref().map(Term::unspanned) else {
+            panic!("{:?}", def.tm)
+        };
+        assert!(fix
+            .iter()
+            .all(|(_, unfolds, _)| *unfolds == Unfolds::OnArg(PARAMS - 1)));
+    }
+}
+
+#[test]
+fn decreasing_none() {
+    // the predecessor goes first, and nothing decreases in the next call
+    let block = block(|xs, k| [&[k], xs].concat());
+    let err = Elaborator::new()
+        .check_program(vec![nat(), block])
+        .unwrap_err();
+    assert!(matches!(err.kind, ErrorKind::Termination { .. }));
+}
@@ -0,0 +1,78 @@
+//! Call-by-need: a thunk forced while metas are solved speculatively is
+//! suspended again when they are rolled back, rather than keeping the
+//! solutions inlined into its value.
+
+use std::borrow::Cow;
+
+use leonie::{
+    eval, eval_closure,
+    metas::{is_def_eq, unify, Conversion, MetaCxt, MetaVar},
+    Cxt, Icit, Ix, Level, Strategy, Term, Value,
+};
+
+fn u() -> Value {
+    Value::VU(Level::LConst(0))
+}
+
+/// `?m` and `(λ x z. x) ?m`, whose closure has `?m` suspended in its
+/// environment.
+fn setup(metas: &mut MetaCxt) -> (MetaVar, Value) {
+    metas.set_strategy(Strategy::CallByNeed);
+    let m = match metas.fresh_meta(&Cxt::default(), u()) {
+        Term::TInsertedMeta(m, _) => m,
+        t => unreachable!("{t:?}"),
+    };
+    let lam = |t: Term| Term::Tλ("x".into(), Icit::Expl, t.into());
+    let k = lam(lam(Term::TV(Ix(1))));
+    let t = Term::TApp(k.into(), Term::TMeta(m).into(), Icit::Expl);
+    (m, eval(metas, Cow::Owned(Default::default()), t))
+}
+
+/// The body of `f`, which forces the thunk of `?m`.
+fn body(metas: &mut MetaCxt, f: &Value) -> Value {
+    let Value::Vλ(_, _, clos) = f else {
+        panic!("{f:?}")
+    };
+    eval_closure(metas, clos.clone(), u())
+}
+
+#[test]
+fn forced_then_rolled_back() {
+    let mut metas = MetaCxt::default();
+    let (m, f) = setup(&mut metas);
+
+    let snapshot = metas.snapshot();
+    unify(&mut metas, 0, Value::VFlex(m, vec![]), u()).unwrap();
+    assert!(matches!(body(&mut metas, &f), Value::VU(_)));
+    metas.rollback(snapshot);
+
+    let v = body(&mut metas, &f);
+    assert!(
+        matches!(&v, Value::VFlex(m_, sp) if *m_ == m && sp.is_empty()),
+        "{v:?}"
+    );
+}
+
+/// Checking conversion solves `?m` before comparing the bodies, and rolls
+/// it back after.
+#[test]
+fn forced_by_conversion() {
+    let mut metas = MetaCxt::default();
+    let (m, f) = setup(&mut metas);
+
+    let const_u = eval(
+        &mut metas,
+        Cow::Owned(Default::default()),
+        Term::Tλ("z".into(), Icit::Expl, Term::TU(Level::LConst(0)).into()),
+    );
+    let l = Value::Vσ(Value::VFlex(m, vec![]).into(), f.clone().into());
+    let r = Value::Vσ(u().into(), const_u.into());
+    let conv = is_def_eq(&mut metas, 0, l, r);
+    assert!(
+        matches!(&conv, Conversion::Blocked(ms) if ms == &[m]),
+        "{conv:?}"
+    );
+
+    let v = body(&mut metas, &f);
+    assert!(matches!(&v, Value::VFlex(m_, _) if *m_ == m), "{v:?}");
+}
@@ -0,0 +1,111 @@
+//! Problems that unification solves by pruning the arguments of metas, and
+//! the ones it can't solve still.
+
+use leonie::{
+    elaborator::Elaborator,
+    error::{Error, ErrorKind},
+    metas::{unify, MetaCxt, MetaVar},
+    parser::parse,
+    quote, Cxt, Definition, Elim, Icit, Ix, Level, Term, Value,
+};
+
+fn define(a: &str, t: &str) -> Result<Definition, Error> {
+    let [a, t] = [a, t].map(|s| parse(s).unwrap().unwrap());
+    Elaborator::new().define("d".into(), a, t)
+}
+
+/// The type of `g` is a meta of the scope of `f`, but its domain is first a
+/// meta of the scope of `g` too.
+#[test]
+fn prune_nested_meta() {
+    define("(U → U) → U", "λ f. (λ g. g U) f").unwrap();
+    define("(A : U) → A → A", "λ A a. (λ f x. f x) (λ y. y) a").unwrap();
+    define("(A : U) → A → U", "λ A a. (λ f. f a) (λ x. A)").unwrap();
+}
+
+/// The domain of `f` pruned of `A` and `a` would have to be `A`.
+#[test]
+fn prune_then_escape() {
+    let err = define("_ → (A : U) → A → _", "λ f A a. f a").unwrap_err();
+    assert!(matches!(&err.kind, ErrorKind::Scope { var, .. } if &**var == "A"));
+}
+
+fn fresh_meta(metas: &mut MetaCxt) -> MetaVar {
+    match metas.fresh_meta(&Cxt::default(), Value::VU(Level::LConst(0))) {
+        Term::TInsertedMeta(m, _) => m,
+        t => unreachable!("{t:?}"),
+    }
+}
+
+/// `?m` applied to `args`
+fn flex(m: MetaVar, args: impl IntoIterator<Item = Value>) -> Value {
+    let sp = args.into_iter().map(|u| Elim::EApp(u, Icit::Expl));
+    Value::VFlex(m, sp.collect())
+}
+
+fn var(x: usize) -> Value {
+    Value::VRigid(x, vec![])
+}
+
+/// The solution of `m`, under as many lambdas as it has arguments.
+fn solution(metas: &mut MetaCxt, m: MetaVar, n: usize) -> Option<Term> {
+    let mut t = quote(metas, 0, metas.solution(m)?.clone());
+    for _ in 0..n {
+        let Term::Tλ(_, Icit::Expl, u) = t else {
+            panic!("{t:?}")
+        };
+        t = (*u).clone();
+    }
+    Some(t)
+}
+
+/// `?m x y =? ?m y x`: `?m` depends on neither.
+#[test]
+fn intersect_swapped() {
+    let mut metas = MetaCxt::default();
+    let m = fresh_meta(&mut metas);
+    let (l, r) = (flex(m, [var(0), var(1)]), flex(m, [var(1), var(0)]));
+    unify(&mut metas, 2, l, r).unwrap();
+    let t = solution(&mut metas, m, 2).unwrap();
+    assert!(matches!(t, Term::TMeta(m_) if m_ != m), "{t:?}");
+}
+
+/// `?m x y =? ?m x z`: `?m` depends on its first argument alone.
+#[test]
+fn intersect_partially() {
+    let mut metas = MetaCxt::default();
+    let m = fresh_meta(&mut metas);
+    let (l, r) = (flex(m, [var(0), var(1)]), flex(m, [var(0), var(2)]));
+    unify(&mut metas, 3, l, r).unwrap();
+    let t = solution(&mut metas, m, 2).unwrap();
+    assert!(
+        matches!(&t, Term::TApp(f, x, Icit::Expl)
+            if matches!(**f, Term::TMeta(m_) if m_ != m) && matches!(**x, Term::TV(Ix(1)))),
+        "{t:?}"
+    );
+}
+
+/// `?m x y =? ?m x y` holds whatever `?m` is.
+#[test]
+fn intersect_same() {
+    let mut metas = MetaCxt::default();
+    let m = fresh_meta(&mut metas);
+    let (l, r) = (flex(m, [var(0), var(1)]), flex(m, [var(0), var(1)]));
+    unify(&mut metas, 2, l, r).unwrap();
+    assert!(metas.solution(m).is_none());
+}
+
+/// `?m ?n =? ?m U` isn't a pattern: the arguments are unified instead.
+#[test]
+fn intersect_non_variables() {
+    let mut metas = MetaCxt::default();
+    let [m, n] = [(); 2].map(|_| fresh_meta(&mut metas));
+    let (l, r) = (
+        flex(m, [flex(n, [])]),
+        flex(m, [Value::VU(Level::LConst(0))]),
+    );
+    unify(&mut metas, 0, l, r).unwrap();
+    assert!(metas.solution(m).is_none());
+    let t = solution(&mut metas, n, 0);
+    assert!(matches!(t, Some(Term::TU(Level::LConst(0)))), "{t:?}");
+}
@@ -0,0 +1,40 @@
+//! Cumulativity of the universes, which a term of a type may be used at a
+//! larger type for, but which doesn't make the universes equal.
+
+use leonie::{
+    elaborator::Elaborator,
+    error::{Error, ErrorKind},
+    parser::parse,
+    Definition, Level,
+};
+
+fn define(a: &str, t: &str) -> Result<Definition, Error> {
+    let [a, t] = [a, t].map(|s| parse(s).unwrap().unwrap());
+    Elaborator::new().define("d".into(), a, t)
+}
+
+fn inconsistent(a: &str, t: &str) -> bool {
+    let err = define(a, t).unwrap_err();
+    matches!(
+        err.kind,
+        ErrorKind::Universe {
+            lhs: Level::LConst(1),
+            rhs: Level::LConst(0),
+        }
+    )
+}
+
+#[test]
+fn cumulative() {
+    define("U2", "U0").unwrap();
+    define("U0 → U1", "λ A. A").unwrap();
+    define("(U1 → U0) → U0 → U1", "λ k. k").unwrap();
+}
+
+#[test]
+fn equal_levels() {
+    assert!(inconsistent("U0", "U0"));
+    assert!(inconsistent("Eq U2 U0 U1", "refl"));
+    // the domains are the other way around
+    assert!(inconsistent("(U0 → U0) → U1 → U0", "λ k. k"));
+}